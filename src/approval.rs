@@ -0,0 +1,159 @@
+use std::{sync::atomic::{AtomicU64, Ordering}, time::Duration};
+
+use dashmap::DashMap;
+use log::{info, warn};
+use serenity::all::{ButtonStyle, CreateActionRow, CreateAllowedMentions, CreateButton, CreateMessage, EditMessage, UserId};
+use tokio::sync::oneshot;
+
+use crate::{context::ObserverContext, lmclient::ToolInvoker};
+
+/// 承認ボタンの custom_id の接頭辞
+pub const APPROVAL_PREFIX: &str = "approval:";
+/// 承認メッセージに載せる説明の最大文字数
+/// (Discord の 2000文字から、見出しと承認後に付け足す行の分を引いたもの)
+const MAX_SUMMARY_CHARS: usize = 1700;
+
+/// 長すぎる説明を切り詰める (途中で切ったコードブロックは閉じる)
+fn truncate_summary(summary: &str) -> String {
+    let total = summary.chars().count();
+    if total <= MAX_SUMMARY_CHARS {
+        return summary.to_string();
+    }
+    let mut out: String = summary.chars().take(MAX_SUMMARY_CHARS).collect();
+    if out.matches("```").count() % 2 == 1 {
+        out.push_str("\n```");
+    }
+    out.push_str(&format!("\n… (+{} chars)", total - MAX_SUMMARY_CHARS));
+    out
+}
+
+/// 副作用のあるツール呼び出しの承認待ちプール
+pub struct Approvals {
+    pending: DashMap<String, PendingApproval>,
+    next_id: AtomicU64,
+}
+
+struct PendingApproval {
+    /// ツール呼び出しのきっかけになったユーザー
+    requester: UserId,
+    tx: oneshot::Sender<ApprovalDecision>,
+}
+
+/// 承認の結果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApprovalDecision {
+    Approved(UserId),
+    Denied(UserId),
+    TimedOut,
+}
+
+impl Default for Approvals {
+    fn default() -> Self {
+        Self {
+            pending: DashMap::new(),
+            next_id: AtomicU64::new(1),
+        }
+    }
+}
+
+impl Approvals {
+    pub fn new() -> Approvals {
+        Self::default()
+    }
+
+    /// 承認ボタン付きのメッセージを投げて、押されるかタイムアウトするまで待つ
+    pub async fn request(
+        &self,
+        ob_ctx: &ObserverContext,
+        invoker: ToolInvoker,
+        tool_name: &str,
+        summary: &str,
+    ) -> Result<ApprovalDecision, String> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed).to_string();
+        let (tx, rx) = oneshot::channel();
+        self.pending.insert(id.clone(), PendingApproval { requester: invoker.user_id, tx });
+
+        let http = ob_ctx.discord_client.open().http.clone();
+        let buttons = CreateActionRow::Buttons(vec![
+            CreateButton::new(format!("{APPROVAL_PREFIX}approve:{id}"))
+                .label("Approve")
+                .style(ButtonStyle::Success),
+            CreateButton::new(format!("{APPROVAL_PREFIX}deny:{id}"))
+                .label("Deny")
+                .style(ButtonStyle::Danger),
+        ]);
+        // モデルが書いた内容をそのまま載せるので、承認前に @everyone やロールが飛ばないようにする
+        let summary = truncate_summary(summary);
+        let content = format!(
+            "-# Approval required: `{tool_name}`\n{summary}\n-# <@{}> or an admin can approve this.",
+            invoker.user_id
+        );
+        let message = CreateMessage::new()
+            .content(content)
+            .components(vec![buttons])
+            .allowed_mentions(CreateAllowedMentions::new().users([invoker.user_id]));
+
+        let mut msg = match invoker.channel_id.send_message(&http, message).await
+        {
+            Ok(msg) => msg,
+            Err(e) => {
+                self.pending.remove(&id);
+                return Err(format!("Failed to post approval request: {e}"));
+            }
+        };
+
+        let timeout = Duration::from_millis(ob_ctx.config.get().approval_timeout_millis);
+        match tokio::time::timeout(timeout, rx).await {
+            Ok(Ok(decision)) => Ok(decision),
+            _ => {
+                self.pending.remove(&id);
+                msg.edit(
+                    &http,
+                    EditMessage::new()
+                        .content(format!("-# Approval for `{tool_name}` timed out\n{summary}"))
+                        .components(vec![])
+                        .allowed_mentions(CreateAllowedMentions::new()),
+                )
+                .await
+                .ok();
+                info!("Approval {} for {} timed out", id, tool_name);
+                Ok(ApprovalDecision::TimedOut)
+            }
+        }
+    }
+
+    /// ボタンが押されたときの処理
+    /// 承認できるのは呼び出し元ユーザーか admin のみ
+    pub fn resolve(&self, custom_id: &str, user_id: UserId, is_admin: bool) -> Result<ApprovalDecision, String> {
+        let rest = custom_id
+            .strip_prefix(APPROVAL_PREFIX)
+            .ok_or_else(|| "not an approval button".to_string())?;
+        let (action, id) = rest
+            .split_once(':')
+            .ok_or_else(|| "malformed approval button".to_string())?;
+
+        let requester = self
+            .pending
+            .get(id)
+            .map(|p| p.requester)
+            .ok_or_else(|| "This request has already been handled or expired.".to_string())?;
+        if requester != user_id && !is_admin {
+            return Err("Only the requesting user or an admin can decide on this.".to_string());
+        }
+
+        let decision = match action {
+            "approve" => ApprovalDecision::Approved(user_id),
+            "deny" => ApprovalDecision::Denied(user_id),
+            other => return Err(format!("unknown approval action: {other}")),
+        };
+
+        let (_, pending) = self
+            .pending
+            .remove(id)
+            .ok_or_else(|| "This request has already been handled or expired.".to_string())?;
+        if pending.tx.send(decision).is_err() {
+            warn!("Approval {} resolved but nobody is waiting", id);
+        }
+        Ok(decision)
+    }
+}
@@ -0,0 +1,198 @@
+//! 画像以外の添付ファイル (テキスト、コード、CSV、PDF) の取り込み
+//! ダウンロードして本文を取り出し、ユーザーメッセージに足す文字列にする
+
+use std::panic::{AssertUnwindSafe, catch_unwind};
+
+use log::debug;
+use serenity::{all::Attachment, futures::future::join_all};
+
+use crate::{config::Config, tools::image_captioner::is_image_attachment};
+
+/// テキストとして読む添付の最大サイズ
+const MAX_TEXT_BYTES: u32 = 2 * 1024 * 1024;
+/// PDF の最大サイズ
+const MAX_PDF_BYTES: u32 = 20 * 1024 * 1024;
+/// バイナリ判定で見る先頭のバイト数
+const SNIFF_BYTES: usize = 8 * 1024;
+
+/// テキストとして扱う拡張子
+const TEXT_EXTENSIONS: &[&str] = &[
+    "txt", "md", "markdown", "rst", "log", "csv", "tsv", "json", "jsonl", "toml", "yaml", "yml", "ini", "cfg", "conf",
+    "xml", "html", "htm", "css", "scss", "svg", "tex", "sql", "rs", "py", "js", "mjs", "ts", "tsx", "jsx", "c", "h",
+    "cpp", "cc", "hpp", "cs", "java", "kt", "go", "rb", "php", "swift", "lua", "r", "hs", "ml", "scala", "dart",
+    "zig", "nim", "sh", "bash", "zsh", "fish", "ps1", "bat", "dockerfile", "makefile", "gitignore", "env",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AttachmentKind {
+    Text,
+    Pdf,
+}
+
+/// 取り込めた添付 1つ分
+struct Extracted {
+    filename: String,
+    /// コードブロックの言語 (拡張子)
+    lang: Option<String>,
+    text: String,
+}
+
+fn extension(filename: &str) -> String {
+    let lower = filename.to_ascii_lowercase();
+    match lower.rsplit_once('.') {
+        Some((_, ext)) => ext.to_string(),
+        // Dockerfile や Makefile のように拡張子の無いもの
+        None => lower,
+    }
+}
+
+/// 添付の種類を判定する (対応していないものは None)
+fn classify(content_type: Option<&str>, filename: &str) -> Option<AttachmentKind> {
+    let ext = extension(filename);
+    if ext == "pdf" || content_type.is_some_and(|ct| ct.starts_with("application/pdf")) {
+        return Some(AttachmentKind::Pdf);
+    }
+    if TEXT_EXTENSIONS.contains(&ext.as_str()) {
+        return Some(AttachmentKind::Text);
+    }
+    let ct = content_type?;
+    let is_text = ct.starts_with("text/")
+        || ["json", "xml", "yaml", "toml", "javascript", "x-sh", "sql"]
+            .iter()
+            .any(|t| ct.starts_with("application/") && ct.contains(t));
+    is_text.then_some(AttachmentKind::Text)
+}
+
+/// バイト列をテキストにする (NUL を含むならバイナリとみなして None)
+fn decode_text(bytes: &[u8]) -> Option<String> {
+    if bytes[..bytes.len().min(SNIFF_BYTES)].contains(&0) {
+        return None;
+    }
+    let bytes = bytes.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(bytes);
+    Some(String::from_utf8_lossy(bytes).replace("\r\n", "\n"))
+}
+
+/// PDF からテキストを取り出す
+/// 壊れた PDF で pdf-extract が panic することがあるので受け止める
+fn extract_pdf(bytes: &[u8]) -> Result<String, String> {
+    let text = catch_unwind(AssertUnwindSafe(|| pdf_extract::extract_text_from_mem(bytes)))
+        .map_err(|_| "the PDF could not be parsed".to_string())?
+        .map_err(|e| format!("the PDF could not be parsed: {e}"))?;
+    // 空行が続くのを詰める
+    let mut out = String::with_capacity(text.len());
+    let mut blank = 0;
+    for line in text.lines() {
+        let line = line.trim_end();
+        if line.is_empty() {
+            blank += 1;
+            if blank > 1 {
+                continue;
+            }
+        } else {
+            blank = 0;
+        }
+        out.push_str(line);
+        out.push('\n');
+    }
+    let out = out.trim().to_string();
+    if out.is_empty() {
+        return Err("no text found in the PDF (it may be scanned images)".to_string());
+    }
+    Ok(out)
+}
+
+/// 添付を 1つダウンロードして本文を取り出す
+async fn fetch(att: &Attachment, kind: AttachmentKind) -> Result<Extracted, String> {
+    let limit = match kind {
+        AttachmentKind::Text => MAX_TEXT_BYTES,
+        AttachmentKind::Pdf => MAX_PDF_BYTES,
+    };
+    if att.size > limit {
+        return Err(format!("too large ({} bytes, max {limit})", att.size));
+    }
+    let bytes = att.download().await.map_err(|e| format!("download failed: {e}"))?;
+    let (lang, text) = match kind {
+        AttachmentKind::Text => {
+            let text = decode_text(&bytes).ok_or("looks like a binary file")?;
+            (Some(extension(&att.filename)), text)
+        }
+        AttachmentKind::Pdf => {
+            let text = tokio::task::spawn_blocking(move || extract_pdf(&bytes))
+                .await
+                .map_err(|e| format!("PDF task failed: {e}"))??;
+            (None, text)
+        }
+    };
+    Ok(Extracted { filename: att.filename.clone(), lang, text })
+}
+
+/// 中身と衝突しない長さのコードフェンス
+fn fence_for(text: &str) -> String {
+    let mut fence = "```".to_string();
+    while text.contains(&fence) {
+        fence.push('`');
+    }
+    fence
+}
+
+/// 画像以外の添付を取り込んで、ユーザーメッセージに足す文字列にする
+/// ファイルごとと合計の文字数に上限があり、切り詰めたときはその旨を書き添える
+/// 取り込む添付が無ければ None
+pub async fn ingest(attachments: &[Attachment], config: &Config) -> Option<String> {
+    let targets: Vec<(&Attachment, Option<AttachmentKind>)> = attachments
+        .iter()
+        .filter(|att| !is_image_attachment(att.content_type.as_deref(), &att.filename))
+        .map(|att| (att, classify(att.content_type.as_deref(), &att.filename)))
+        .collect();
+    if targets.is_empty() {
+        return None;
+    }
+
+    let results = join_all(targets.iter().map(|(att, kind)| async move {
+        match kind {
+            Some(kind) => fetch(att, *kind).await,
+            None => Err("unsupported file type".to_string()),
+        }
+    }))
+    .await;
+
+    let mut remaining = config.attachment_total_chars;
+    let mut parts = Vec::new();
+    for ((att, _), result) in targets.iter().zip(results) {
+        let extracted = match result {
+            Ok(extracted) => extracted,
+            Err(e) => {
+                debug!("skipped attachment {}: {}", att.filename, e);
+                parts.push(format!("[attachment] {} (skipped: {})", att.filename, e));
+                continue;
+            }
+        };
+        let total = extracted.text.chars().count();
+        if remaining == 0 {
+            parts.push(format!(
+                "[attachment] {} ({} chars)\n[note] omitted: the attachment text limit for this message was reached",
+                extracted.filename, total
+            ));
+            continue;
+        }
+        let take = total.min(config.attachment_max_chars).min(remaining);
+        remaining -= take;
+        let text: String = extracted.text.chars().take(take).collect();
+        let fence = fence_for(&text);
+        let mut part = format!(
+            "[attachment] {} ({} chars)\n{}{}\n{}\n{}",
+            extracted.filename,
+            total,
+            fence,
+            extracted.lang.as_deref().unwrap_or(""),
+            text.trim_end(),
+            fence
+        );
+        if take < total {
+            debug!("attachment {} truncated to {} of {} chars", extracted.filename, take, total);
+            part.push_str(&format!("\n[note] truncated: only the first {take} of {total} chars are included"));
+        }
+        parts.push(part);
+    }
+    Some(parts.join("\n"))
+}
@@ -1,4 +1,8 @@
+use std::{collections::BTreeMap, fs, path::Path, sync::RwLock};
+
 use dashmap::DashMap;
+use openai_dive::v1::resources::response::request::ResponseInputItem;
+use serde::{Deserialize, Serialize};
 use serenity::all::ChannelId;
 
 use crate::lmclient::LMContext;
@@ -6,7 +10,8 @@ use crate::lmclient::LMContext;
 /// チャンネルごとのプール
 pub struct ChatContexts {
     pub contexts: DashMap<ChannelId, ChatContext>,
-    pub default_system_prompt: String,
+    /// 設定の読み直しで差し替わる
+    default_system_prompt: RwLock<String>,
 }
 
 /// チャンネルごとのデータ保持
@@ -14,7 +19,42 @@ pub struct ChatContext {
     pub channel_id: ChannelId,
     pub context: LMContext,
     pub system_prompt: Option<String>,
-    pub enabled: bool,
+    /// None ならサーバかグローバルの既定値に従う
+    pub enabled: Option<bool>,
+}
+
+/// ファイルに保存するチャンネルのデータ
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SavedChatContext {
+    pub items: Vec<ResponseInputItem>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub system_prompt: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub enabled: Option<bool>,
+}
+
+/// 保存したチャンネルのデータを読む (無ければ空)
+pub fn load_saved(path: impl AsRef<Path>) -> Result<BTreeMap<u64, SavedChatContext>, String> {
+    let path = path.as_ref();
+    match fs::read_to_string(path) {
+        Ok(text) => serde_json::from_str(&text).map_err(|e| format!("invalid {}: {e}", path.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+        Err(e) => Err(format!("cannot read {}: {e}", path.display())),
+    }
+}
+
+/// チャンネルのデータを保存する
+/// 一時ファイルに書いてから置き換える (途中で落ちても壊れないように)
+pub fn write_saved(path: impl AsRef<Path>, saved: &BTreeMap<u64, SavedChatContext>) -> Result<(), String> {
+    let path = path.as_ref();
+    let json = serde_json::to_string(saved).map_err(|e| format!("cannot serialize chat contexts: {e}"))?;
+    if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        fs::create_dir_all(dir).map_err(|e| format!("cannot create {}: {e}", dir.display()))?;
+    }
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, json).map_err(|e| format!("cannot write {}: {e}", tmp.display()))?;
+    fs::rename(&tmp, path).map_err(|e| format!("cannot write {}: {e}", path.display()))
 }
 
 impl ChatContext {
@@ -23,7 +63,7 @@ impl ChatContext {
             channel_id,
             context: LMContext::new(),
             system_prompt: None,
-            enabled: true,
+            enabled: None,
         }
     }
 }
@@ -32,10 +72,51 @@ impl ChatContexts {
     pub fn new(default_system_prompt: String) -> ChatContexts {
         ChatContexts {
             contexts: DashMap::new(),
-            default_system_prompt,
+            default_system_prompt: RwLock::new(default_system_prompt),
+        }
+    }
+
+    /// 保存したデータを読み込む (同じチャンネルがあれば置き換える)
+    pub fn restore(&self, saved: BTreeMap<u64, SavedChatContext>) {
+        for (channel_id, saved) in saved {
+            let channel_id = ChannelId::new(channel_id);
+            let mut context = LMContext::new();
+            context.buf.extend(saved.items);
+            context.trim_len();
+            self.contexts.insert(
+                channel_id,
+                ChatContext {
+                    channel_id,
+                    context,
+                    system_prompt: saved.system_prompt,
+                    enabled: saved.enabled,
+                },
+            );
         }
     }
 
+    /// 保存用に今のデータを取り出す (何も無いチャンネルは除く)
+    pub fn snapshot(&self) -> BTreeMap<u64, SavedChatContext> {
+        self.contexts
+            .iter()
+            .filter(|entry| !entry.context.buf.is_empty() || entry.system_prompt.is_some() || entry.enabled.is_some())
+            .map(|entry| {
+                (
+                    entry.channel_id.get(),
+                    SavedChatContext {
+                        items: entry.context.buf.iter().cloned().collect(),
+                        system_prompt: entry.system_prompt.clone(),
+                        enabled: entry.enabled,
+                    },
+                )
+            })
+            .collect()
+    }
+
+    pub fn set_default_system_prompt(&self, system_prompt: String) {
+        *self.default_system_prompt.write().expect("RWlock") = system_prompt;
+    }
+
     pub fn get_or_create(&self, channel_id: ChannelId) -> LMContext {
         self.contexts
             .entry(channel_id)
@@ -44,11 +125,13 @@ impl ChatContexts {
             .clone()
     }
 
-    pub fn get_system_prompt(&self, channel_id: ChannelId) -> String {
+    /// チャンネルのプロンプト → `fallback` (サーバの設定) → 既定のプロンプトの順
+    pub fn get_system_prompt(&self, channel_id: ChannelId, fallback: Option<&str>) -> String {
         self.contexts
             .get(&channel_id)
             .and_then(|entry| entry.system_prompt.clone())
-            .unwrap_or_else(|| self.default_system_prompt.clone())
+            .or_else(|| fallback.map(|s| s.to_string()))
+            .unwrap_or_else(|| self.default_system_prompt.read().expect("RWlock").clone())
     }
 
     pub fn set_system_prompt(&self, channel_id: ChannelId, system_prompt: Option<String>) {
@@ -71,7 +154,7 @@ impl ChatContexts {
                     channel_id,
                     context: new_context,
                     system_prompt: None,
-                    enabled: true,
+                    enabled: None,
                 },
             );
         }
@@ -81,11 +164,12 @@ impl ChatContexts {
         self.contexts.get(&channel_id).map(|entry| entry.context.clone())
     }
 
-    pub fn is_enabled(&self, channel_id: ChannelId) -> bool {
+    /// チャンネルで有効か (/enable /disable していなければ `default`)
+    pub fn is_enabled(&self, channel_id: ChannelId, default: bool) -> bool {
         self.contexts
             .get(&channel_id)
-            .map(|entry| entry.enabled)
-            .unwrap_or(true)
+            .and_then(|entry| entry.enabled)
+            .unwrap_or(default)
     }
 
     pub fn clear(&self, channel_id: ChannelId) {
@@ -99,6 +183,6 @@ impl ChatContexts {
             .contexts
             .entry(channel_id)
             .or_insert_with(|| ChatContext::new(channel_id));
-        entry.enabled = enabled;
+        entry.enabled = Some(enabled);
     }
 }
\ No newline at end of file
@@ -0,0 +1,57 @@
+//! `check-config` モード
+//! 設定を読んで、解決した値とその出どころ (env / file / default) を表示する
+//! Discord には接続しないので、起動前の確認に使う
+
+use std::{path::Path, process::ExitCode};
+
+use crate::{config::{CONFIG_PATH, Config, Models}, tools::registry};
+
+/// 設定を検査して結果を表示する
+/// 読めない・必須項目が無い・知らないキーがあるときは失敗で終わる
+pub fn run() -> ExitCode {
+    if !Path::new(CONFIG_PATH).exists() {
+        println!("{CONFIG_PATH} not found, using env and defaults only");
+    }
+    let config = match Config::reload() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("error: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let resolved = config.resolved();
+    let width = resolved.iter().map(|(key, _)| key.len()).max().unwrap_or(0);
+    for (key, value) in &resolved {
+        println!("{key:<width$}  {:<7}  {value}", config.source(key).to_string());
+    }
+
+    let mut errors = config.unknown_keys.clone();
+    let known_tools: Vec<&str> = registry::specs().iter().map(|spec| spec.name).collect();
+    let mut tool_names: Vec<&String> = config.tools.keys().collect();
+    tool_names.sort();
+    for name in tool_names {
+        if !known_tools.contains(&name.as_str()) {
+            errors.push(format!("unknown tool `{name}` in tools (known: {})", known_tools.join(", ")));
+        }
+    }
+    let known_models: Vec<String> = Models::list().iter().map(|m| m.to_string()).collect();
+    let mut model_names: Vec<&String> = config.model_catalog.keys().collect();
+    model_names.sort();
+    for name in model_names {
+        if !known_models.contains(name) {
+            errors.push(format!("unknown model `{name}` in model_catalog (known: {})", known_models.join(", ")));
+        }
+    }
+
+    if errors.is_empty() {
+        println!("\nconfig ok");
+        ExitCode::SUCCESS
+    } else {
+        println!();
+        for e in &errors {
+            eprintln!("error: {e}");
+        }
+        ExitCode::FAILURE
+    }
+}
@@ -0,0 +1,333 @@
+//! コマンドラインのサブコマンド
+//! `run` (既定) 以外は Discord に接続しないので、手元でプロンプトやツールを試すのに使う
+
+use std::{fs, io::{self, Read}, path::{Path, PathBuf}, process::ExitCode, time::Duration};
+
+use clap::{Parser, Subcommand};
+use openai_dive::v1::resources::response::{request::{ContentInput, ContentItem, InputMessage, ResponseInputItem}, response::Role};
+use serenity::all::ChannelId;
+use tokio::{io::{AsyncBufReadExt, BufReader}, sync::mpsc, time::timeout};
+
+use crate::{channel::{self, SavedChatContext}, config::{Config, ModelProvider, Models}, context::ObserverContext, lmclient::LMContext, tools::registry};
+
+#[derive(Parser)]
+#[command(name = "observer", version, about = "Discord AI assistant bot")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// start the web server and the Discord bot (default)
+    Run,
+    /// show the resolved configuration and where each value came from
+    CheckConfig,
+    /// print a channel's saved conversation as JSONL
+    ExportContext {
+        /// channel id
+        channel: u64,
+        /// write to a file instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// load a JSONL conversation into a channel (stop the bot first)
+    ImportContext {
+        /// channel id
+        channel: u64,
+        /// JSONL file (stdin if omitted)
+        input: Option<PathBuf>,
+        /// append to the saved conversation instead of replacing it
+        #[arg(long)]
+        append: bool,
+    },
+    /// chat with the configured model and tools in the terminal, without Discord
+    Chat {
+        /// model to use (default: default_model)
+        #[arg(short, long)]
+        model: Option<String>,
+        /// start from this channel's saved conversation and system prompt
+        #[arg(long)]
+        channel: Option<u64>,
+    },
+    /// send the user messages of a JSONL conversation to the model again, one by one
+    Replay {
+        /// JSONL file (e.g. from export-context)
+        file: PathBuf,
+        /// model to use (default: default_model)
+        #[arg(short, long)]
+        model: Option<String>,
+    },
+}
+
+/// チャンネルの会話を 1行 1アイテムの JSONL で書き出す
+pub fn export_context(channel: u64, output: Option<&Path>) -> ExitCode {
+    let result = Config::load_offline().and_then(|config| {
+        let saved = channel::load_saved(&config.chat_contexts_path)?;
+        let context = saved
+            .get(&channel)
+            .ok_or_else(|| format!("no saved conversation for channel {channel} in {}", config.chat_contexts_path))?;
+        let mut jsonl = String::new();
+        for item in &context.items {
+            let line = serde_json::to_string(item).map_err(|e| format!("cannot serialize: {e}"))?;
+            jsonl.push_str(&line);
+            jsonl.push('\n');
+        }
+        match output {
+            Some(path) => {
+                fs::write(path, jsonl).map_err(|e| format!("cannot write {}: {e}", path.display()))?;
+                eprintln!("exported {} item(s) to {}", context.items.len(), path.display());
+            }
+            None => print!("{jsonl}"),
+        }
+        Ok(())
+    });
+    finish(result)
+}
+
+/// JSONL の会話をチャンネルに読み込む
+/// 動いている BOT は終了時に上書きするので、止めてから実行する
+pub fn import_context(channel: u64, input: Option<&Path>, append: bool) -> ExitCode {
+    let result = Config::load_offline().and_then(|config| {
+        let text = match input {
+            Some(path) => fs::read_to_string(path).map_err(|e| format!("cannot read {}: {e}", path.display()))?,
+            None => {
+                let mut text = String::new();
+                io::stdin().read_to_string(&mut text).map_err(|e| format!("cannot read stdin: {e}"))?;
+                text
+            }
+        };
+        let items = parse_jsonl(&text)?;
+        let count = items.len();
+
+        let mut saved = channel::load_saved(&config.chat_contexts_path)?;
+        let entry = saved.entry(channel).or_insert_with(SavedChatContext::default);
+        if !append {
+            entry.items.clear();
+        }
+        entry.items.extend(items);
+        channel::write_saved(&config.chat_contexts_path, &saved)?;
+        eprintln!("imported {count} item(s) into channel {channel} ({})", config.chat_contexts_path);
+        Ok(())
+    });
+    finish(result)
+}
+
+/// 端末で対話する
+/// 途中経過は stderr、応答は stdout に出す
+pub async fn chat(model: Option<String>, channel: Option<u64>) -> ExitCode {
+    let (ob_ctx, model) = match offline_context(model.as_deref()).await {
+        Ok(v) => v,
+        Err(e) => return finish(Err(e)),
+    };
+    let channel_id = channel.map(ChannelId::new);
+    let mut history = channel_id
+        .and_then(|id| ob_ctx.chat_contexts.get_mut(id))
+        .unwrap_or_default();
+    let system_prompt = system_prompt(&ob_ctx, channel_id);
+
+    let mut tool_names: Vec<String> = ob_ctx.tools.get().keys().cloned().collect();
+    tool_names.sort();
+    eprintln!("model: {}, tools: {}", model_label(&ob_ctx, &model), tool_names.join(", "));
+    eprintln!("/clear forgets the conversation, /exit or Ctrl-D quits");
+
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    loop {
+        eprint!("> ");
+        let line = match lines.next_line().await {
+            Ok(Some(line)) => line,
+            Ok(None) => break,
+            Err(e) => return finish(Err(format!("cannot read stdin: {e}"))),
+        };
+        match line.trim() {
+            "" => continue,
+            "/exit" | "/quit" => break,
+            "/clear" => {
+                history.clear();
+                eprintln!("cleared");
+                continue;
+            }
+            text => history.add_text(user_message(text), Role::User),
+        }
+        match respond(&ob_ctx, &history, &system_prompt, &model).await {
+            Ok(result) => {
+                println!("{}", result.get_result());
+                history.extend(&result);
+            }
+            Err(e) => eprintln!("error: {e}"),
+        }
+    }
+    ExitCode::SUCCESS
+}
+
+/// JSONL の会話からユーザーの発言だけを順に流し直す
+/// 前回の応答やツールの結果は使わず、今のプロンプトとツールで作り直す
+pub async fn replay(file: &Path, model: Option<String>) -> ExitCode {
+    let items = match fs::read_to_string(file)
+        .map_err(|e| format!("cannot read {}: {e}", file.display()))
+        .and_then(|text| parse_jsonl(&text))
+    {
+        Ok(items) => items,
+        Err(e) => return finish(Err(e)),
+    };
+    let (ob_ctx, model) = match offline_context(model.as_deref()).await {
+        Ok(v) => v,
+        Err(e) => return finish(Err(e)),
+    };
+    let system_prompt = system_prompt(&ob_ctx, None);
+    eprintln!("model: {}", model_label(&ob_ctx, &model));
+
+    let mut history = LMContext::new();
+    let mut turn = 0;
+    for item in items {
+        let ResponseInputItem::Message(message) = &item else {
+            continue;
+        };
+        if !matches!(message.role, Role::User) {
+            continue;
+        }
+        turn += 1;
+        println!("--- #{turn} user\n{}", message_text(&message.content));
+        history.buf.push_back(item);
+        history.trim_len();
+        match respond(&ob_ctx, &history, &system_prompt, &model).await {
+            Ok(result) => {
+                println!("--- #{turn} assistant\n{}", result.get_result());
+                history.extend(&result);
+            }
+            Err(e) => return finish(Err(format!("turn #{turn}: {e}"))),
+        }
+    }
+    if turn == 0 {
+        return finish(Err(format!("no user messages in {}", file.display())));
+    }
+    ExitCode::SUCCESS
+}
+
+/// Discord なしでコンテキストを組み立てる
+/// Discord に送るツールは使えないので外す
+async fn offline_context(model: Option<&str>) -> Result<(ObserverContext, Models), String> {
+    let config = Config::load_offline()?;
+    let model = match model {
+        Some(name) => name.parse::<Models>()?,
+        None => config.default_model.clone(),
+    };
+    let ob_ctx = ObserverContext::new(config).await;
+    let specs = registry::specs();
+    let offline = registry::subset(&ob_ctx.tools.get(), |name| specs.iter().any(|spec| spec.name == name && !spec.needs_discord));
+    ob_ctx.tools.set(offline);
+    Ok((ob_ctx, model))
+}
+
+/// チャンネルのプロンプト (無ければ既定のプロンプト) と言語の指定
+fn system_prompt(ob_ctx: &ObserverContext, channel_id: Option<ChannelId>) -> String {
+    let config = ob_ctx.config.get();
+    let base = match channel_id {
+        Some(id) => ob_ctx.chat_contexts.get_system_prompt(id, None),
+        None => config.system_prompt.clone(),
+    };
+    let mut system_prompt = format!("{base}\n current channel_id: None, channel_name: terminal");
+    if let Some(language) = &config.language {
+        system_prompt.push_str(&format!("\nRespond in {language} unless the user clearly writes in another language."));
+    }
+    system_prompt
+}
+
+/// 1回分の応答を作る (Discord の応答と同じく、システムプロンプトは履歴の最後に足す)
+async fn respond(ob_ctx: &ObserverContext, history: &LMContext, system_prompt: &str, model: &Models) -> Result<LMContext, String> {
+    let config = ob_ctx.config.get();
+    let mut context = history.clone();
+    context.add_message(InputMessage {
+        role: Role::System,
+        content: ContentInput::Text(system_prompt.to_string()),
+    });
+
+    let (state_tx, mut state_rx) = mpsc::channel::<String>(100);
+    let progress = tokio::spawn(async move {
+        while let Some(state) = state_rx.recv().await {
+            // トークンごとの進み具合は多すぎるので出さない
+            if !state.starts_with("Generating...") {
+                eprintln!("-# {state}");
+            }
+        }
+    });
+
+    let model_params = match config.model_provider {
+        ModelProvider::OpenAI => Some(model.to_parameter()),
+        ModelProvider::GeminiAIStudio => None,
+    };
+    let result = timeout(
+        Duration::from_millis(config.timeout_millis),
+        ob_ctx.lm_client.generate_response(
+            ob_ctx.clone(),
+            &context,
+            Some(config.model_generate_max_tokens),
+            Some(ob_ctx.tools.get()),
+            Some(state_tx),
+            None,
+            model_params,
+            None,
+        ),
+    )
+    .await;
+    progress.abort();
+
+    match result {
+        Ok(Ok(result)) => Ok(result),
+        Ok(Err(e)) => Err(e.to_string()),
+        Err(_) => Err(format!("timeout after {}ms", config.timeout_millis)),
+    }
+}
+
+/// Discord のメッセージと同じ形にする
+fn user_message(text: &str) -> String {
+    serde_json::json!({
+        "user": "terminal",
+        "display_name": "terminal",
+        "msg_id": "None",
+        "reply_to": "None",
+        "content": text,
+    })
+    .to_string()
+}
+
+fn model_label(ob_ctx: &ObserverContext, model: &Models) -> String {
+    let config = ob_ctx.config.get();
+    match config.model_provider {
+        ModelProvider::OpenAI => model.to_string(),
+        ModelProvider::GeminiAIStudio => config.main_model_name.clone(),
+    }
+}
+
+fn message_text(content: &ContentInput) -> String {
+    match content {
+        ContentInput::Text(text) => text.clone(),
+        ContentInput::List(items) => items
+            .iter()
+            .filter_map(|item| match item {
+                ContentItem::Text { text } => Some(text.as_str()),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+    }
+}
+
+/// 1行 1アイテムの JSONL を読む (空行は飛ばす)
+fn parse_jsonl(text: &str) -> Result<Vec<ResponseInputItem>, String> {
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| serde_json::from_str(line).map_err(|e| format!("line {}: {e}", i + 1)))
+        .collect()
+}
+
+fn finish(result: Result<(), String>) -> ExitCode {
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -2,9 +2,9 @@ use std::time::Instant;
 
 use log::{error, info};
 use poise::CreateReply;
-use serenity::all::{CreateAttachment, User, UserId};
+use serenity::{all::{Attachment, ChannelType, CreateAllowedMentions, CreateAttachment, CreateThread, GuildId, Role, User, UserId}, builder::Builder};
 
-use crate::{config::Models, context::ObserverContext, tools::latex::LatexExprRenderTool};
+use crate::{config::Models, context::ObserverContext, deep_search, guild::GuildSettings, rate_limit::{self, Decision, RateLimit, Usage}, judge::{parse_inline, parse_zip}, sandbox::Language, tex::{MathMode, RenderOptions}, tools::{browsing_worker, judge::{Judge, MAX_SOURCE_BYTES, MAX_TESTS_ZIP_BYTES}, latex::LatexExprRenderTool, registry}};
 
 // エラー型（とりあえず Box に投げるスタイルでOK）
 type Error = Box<dyn std::error::Error + Send + Sync>;
@@ -45,7 +45,7 @@ pub async fn set_system_prompt(
     let ob_ctx = ctx.data();
 
     let caller_id_u64 = ctx.author().id.get();
-    if !ob_ctx.config.admin_users.contains(&caller_id_u64) {
+    if !ob_ctx.config.get().admin_users.contains(&caller_id_u64) {
         ctx.say("Err: you are not allowed to use /set_system_prompt.").await?;
         return Ok(());
     }
@@ -78,7 +78,7 @@ pub async fn rate_config(
     let ob_ctx = ctx.data();
 
     let caller_id_u64 = ctx.author().id.get();
-    if !ob_ctx.config.admin_users.contains(&caller_id_u64) {
+    if !ob_ctx.config.get().admin_users.contains(&caller_id_u64) {
         ctx.say("Err: you are not allowed to use /rate_config.").await?;
         return Ok(());
     }
@@ -86,9 +86,9 @@ pub async fn rate_config(
     let target_user_id: UserId = target_user.id;
 
     let new_rate_line: u64 = if limit.eq_ignore_ascii_case("unlimit") {
-        0
+        rate_limit::UNLIMITED
     } else if limit.eq_ignore_ascii_case("reset") {
-        1
+        rate_limit::RESET
     } else {
         let cost = match limit.parse::<u64>() {
             Ok(n) => n,
@@ -97,12 +97,13 @@ pub async fn rate_config(
                 return Ok(());
             }
         };
-        ob_ctx.user_contexts.get_or_create(target_user_id).rate_line + cost * ob_ctx.config.rate_limit_sec_per_cost
+        let (_, sec_per_cost) = ob_ctx.guild_settings.get(ctx.guild_id()).rate_limit(&ob_ctx.config.get());
+        ob_ctx.user_contexts.get_or_create(target_user_id).rate_line + cost * sec_per_cost
     };
 
     ob_ctx.user_contexts.set_rate_line(target_user_id, new_rate_line);
 
-    let reply = if new_rate_line == 0 {
+    let reply = if new_rate_line == rate_limit::UNLIMITED {
         format!(
             "info: Set rate-line for user `{}` to **unlimit**.",
             target_user_id
@@ -124,6 +125,68 @@ pub async fn rate_config(
 }
 
 
+/// show your remaining rate-limit budget (admins can check any user)
+#[poise::command(slash_command, prefix_command)]
+pub async fn usage(
+    ctx: Context<'_>,
+    #[description = "User to check (admin only)"]
+    user: Option<User>,
+) -> Result<(), Error> {
+    let ob_ctx = ctx.data();
+    let config = ob_ctx.config.get();
+
+    let target = user.unwrap_or_else(|| ctx.author().clone());
+    if target.id != ctx.author().id && !config.admin_users.contains(&ctx.author().id.get()) {
+        ctx.say("Err: only admins can check other users.").await?;
+        return Ok(());
+    }
+
+    let user_ctx = ob_ctx.user_contexts.get_or_create(target.id);
+    let guild = ob_ctx.guild_settings.get(ctx.guild_id());
+    let limit = RateLimit::new(guild.rate_limit(&config));
+    let current = user_ctx.model_or(guild.default_model(&config));
+    let now = rate_limit::now();
+
+    let mut s = format!("**Usage of {}**\n", target.display_name());
+    match (limit.remaining(user_ctx.rate_line, now), limit.full_at(user_ctx.rate_line, now)) {
+        (Some(remaining), Some(full_at)) => {
+            s.push_str(&format!(
+                "Budget: **{}** of {} left ({}%)\n",
+                rate_limit::format_duration(remaining),
+                rate_limit::format_duration(limit.window_size),
+                remaining * 100 / limit.window_size.max(1),
+            ));
+            if full_at > now {
+                s.push_str(&format!("Fully refilled <t:{full_at}:R>\n"));
+            } else {
+                s.push_str("Fully refilled\n");
+            }
+        }
+        _ => s.push_str("Budget: **unlimited**\n"),
+    }
+
+    s.push_str(&format!("\nModel costs (1 cost = {}):\n", rate_limit::format_duration(limit.sec_per_cost)));
+    for model in config.models() {
+        let cost = config.model_cost(&model);
+        let uses = match limit.uses_left(user_ctx.rate_line, cost, now) {
+            Some(n) => format!("{n} use(s) left"),
+            None => "unlimited".to_string(),
+        };
+        let marker = if model == current { " ← current" } else { "" };
+        s.push_str(&format!("- `{model}`: {cost} ({uses}){marker}\n"));
+    }
+
+    if !user_ctx.usage.is_empty() {
+        s.push_str("\nRecent:\n");
+        for usage in user_ctx.usage.iter().rev() {
+            s.push_str(&format!("- <t:{}:R> `{}` cost {}\n", usage.at, usage.model, usage.cost));
+        }
+    }
+
+    ctx.send(CreateReply::default().content(s).ephemeral(true).allowed_mentions(CreateAllowedMentions::new())).await?;
+    Ok(())
+}
+
 /// `/rate_config` の第2引数 `limit` 用のオートコンプリート
 async fn autocomplete_rate_limit(
     _ctx: Context<'_>,
@@ -160,6 +223,348 @@ async fn autocomplete_rate_limit(
     out
 }
 
+/// only admin user
+#[poise::command(slash_command, prefix_command)]
+pub async fn reload_config(ctx: Context<'_>) -> Result<(), Error> {
+    let ob_ctx = ctx.data();
+
+    let caller_id_u64 = ctx.author().id.get();
+    if !ob_ctx.config.get().admin_users.contains(&caller_id_u64) {
+        ctx.say("Err: you are not allowed to use /reload_config.").await?;
+        return Ok(());
+    }
+
+    let reply = match ob_ctx.reload_config().await {
+        Ok(changes) if changes.is_empty() => "info: No changes in config.json.".to_string(),
+        Ok(changes) => {
+            let list = |restart: bool| {
+                changes
+                    .iter()
+                    .filter(|(_, r)| *r == restart)
+                    .map(|(name, _)| format!("`{name}`"))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            };
+            let mut s = String::from("info: Reloaded config.json.");
+            let applied = list(false);
+            if !applied.is_empty() {
+                s.push_str(&format!("\nApplied: {applied}"));
+            }
+            let pending = list(true);
+            if !pending.is_empty() {
+                s.push_str(&format!("\nNeeds a restart: {pending}"));
+            }
+            s
+        }
+        Err(e) => format!("Err: failed to reload config.json (the current config is kept): {e}"),
+    };
+    ctx.say(reply).await?;
+    Ok(())
+}
+
+/// 呼び出した人がこのサーバの設定を変えられるか
+/// グローバルの admin_users、サーバの管理権限 (管理者・サーバ管理)、guild の admin_roles のどれか
+async fn is_guild_admin(ctx: Context<'_>, guild_id: GuildId) -> bool {
+    let ob_ctx = ctx.data();
+    if ob_ctx.config.get().admin_users.contains(&ctx.author().id.get()) {
+        return true;
+    }
+    let Some(member) = ctx.author_member().await else {
+        return false;
+    };
+    if ob_ctx.guild_settings.get(Some(guild_id)).has_admin_role(&member.roles) {
+        return true;
+    }
+    // スラッシュコマンドでは権限が付いてくる (プレフィックスのときはキャッシュから計算する)
+    let permissions = member.permissions.or_else(|| ctx.guild().map(|guild| guild.member_permissions(&member)));
+    permissions.is_some_and(|p| p.administrator() || p.manage_guild())
+}
+
+/// サーバの中で、設定を変えられる人のときだけ GuildId を返す (それ以外は返信して None)
+async fn guild_admin_only(ctx: Context<'_>) -> Result<Option<GuildId>, Error> {
+    let Some(guild_id) = ctx.guild_id() else {
+        ctx.say("Err: this command can only be used in a server.").await?;
+        return Ok(None);
+    };
+    if !is_guild_admin(ctx, guild_id).await {
+        ctx.say("Err: you are not allowed to change this server's settings.").await?;
+        return Ok(None);
+    }
+    Ok(Some(guild_id))
+}
+
+/// 設定を保存して結果を返信する
+async fn update_guild_settings(
+    ctx: Context<'_>,
+    guild_id: GuildId,
+    done: &str,
+    f: impl FnOnce(&mut GuildSettings),
+) -> Result<(), Error> {
+    match ctx.data().guild_settings.update(guild_id, f) {
+        Ok(_) => {
+            info!("guild {}: {} (by {})", guild_id, done, ctx.author().id);
+            ctx.say(format!("info: {done}")).await?;
+        }
+        Err(e) => {
+            error!("guild {}: failed to save settings: {}", guild_id, e);
+            ctx.say(format!("error: failed to save the server settings: {e}")).await?;
+        }
+    }
+    Ok(())
+}
+
+/// per-server settings (server admins only)
+#[poise::command(
+    slash_command,
+    prefix_command,
+    guild_only,
+    subcommands(
+        "guild_config_show",
+        "guild_config_prompt",
+        "guild_config_model",
+        "guild_config_tools",
+        "guild_config_rate_limit",
+        "guild_config_admin_role",
+        "guild_config_language",
+        "guild_config_enabled"
+    )
+)]
+pub async fn guild_config(_: Context<'_>) -> Result<(), Error> {
+    Ok(()) // ここはメインでは使わない
+}
+
+/// show this server's settings
+#[poise::command(slash_command, prefix_command, guild_only, rename = "show")]
+pub async fn guild_config_show(ctx: Context<'_>) -> Result<(), Error> {
+    let Some(guild_id) = ctx.guild_id() else {
+        return Ok(());
+    };
+    let ob_ctx = ctx.data();
+    let config = ob_ctx.config.get();
+    let guild = ob_ctx.guild_settings.get(Some(guild_id));
+    let origin = |is_set: bool| if is_set { "server" } else { "global" };
+    let (window_size, sec_per_cost) = guild.rate_limit(&config);
+
+    let prompt = guild
+        .system_prompt
+        .as_deref()
+        .map(|p| format!("{} chars", p.chars().count()))
+        .unwrap_or_else(|| "default".to_string());
+    let tools = match &guild.allowed_tools {
+        Some(tools) => tools.join(", "),
+        None => "all enabled".to_string(),
+    };
+    let admin_roles = if guild.admin_roles.is_empty() {
+        "none".to_string()
+    } else {
+        guild.admin_roles.iter().map(|id| format!("<@&{id}>")).collect::<Vec<_>>().join(" ")
+    };
+
+    let mut s = String::from("**Server settings:**\n");
+    s.push_str(&format!("- system prompt: {} ({})\n", prompt, origin(guild.system_prompt.is_some())));
+    s.push_str(&format!("- default model: `{}` ({})\n", guild.default_model(&config), origin(guild.default_model.is_some())));
+    s.push_str(&format!("- tools: {} ({})\n", tools, origin(guild.allowed_tools.is_some())));
+    s.push_str(&format!(
+        "- rate limit: window {}s, {}s per cost ({})\n",
+        window_size,
+        sec_per_cost,
+        origin(guild.rate_limit_window_size.is_some() || guild.rate_limit_sec_per_cost.is_some())
+    ));
+    s.push_str(&format!("- admin roles: {admin_roles}\n"));
+    s.push_str(&format!(
+        "- language: {} ({})\n",
+        guild.language(&config).unwrap_or("unset"),
+        origin(guild.language.is_some())
+    ));
+    s.push_str(&format!(
+        "- enabled by default: {} ({})\n",
+        guild.default_enabled(&config),
+        origin(guild.default_enabled.is_some())
+    ));
+    ctx.send(CreateReply::default().content(s).allowed_mentions(CreateAllowedMentions::new())).await?;
+    Ok(())
+}
+
+/// set the default system prompt of this server ('reset' to use the global one)
+#[poise::command(slash_command, prefix_command, guild_only, rename = "prompt")]
+pub async fn guild_config_prompt(
+    ctx: Context<'_>,
+    #[description = "System prompt (or 'reset')"]
+    #[rest]
+    system_prompt: String,
+) -> Result<(), Error> {
+    let Some(guild_id) = guild_admin_only(ctx).await? else {
+        return Ok(());
+    };
+    let system_prompt = system_prompt.trim().to_string();
+    if system_prompt.is_empty() || system_prompt.eq_ignore_ascii_case("reset") {
+        update_guild_settings(ctx, guild_id, "Server system prompt reset to the global default.", |g| g.system_prompt = None).await
+    } else {
+        let done = format!("Server system prompt set ({} chars).", system_prompt.chars().count());
+        update_guild_settings(ctx, guild_id, &done, |g| g.system_prompt = Some(system_prompt)).await
+    }
+}
+
+/// set the default model of this server ('reset' to use the global one)
+#[poise::command(slash_command, prefix_command, guild_only, rename = "model")]
+pub async fn guild_config_model(
+    ctx: Context<'_>,
+    #[description = "Model (or 'reset')"]
+    #[autocomplete = "autocomplete_model_name"]
+    model_name: String,
+) -> Result<(), Error> {
+    let Some(guild_id) = guild_admin_only(ctx).await? else {
+        return Ok(());
+    };
+    if model_name.eq_ignore_ascii_case("reset") {
+        return update_guild_settings(ctx, guild_id, "Server default model reset to the global default.", |g| g.default_model = None).await;
+    }
+    if !ctx.data().config.get().models().iter().any(|m| m.to_string() == model_name) {
+        ctx.say(format!("Err: unknown model `{}`. See /model list.", model_name)).await?;
+        return Ok(());
+    }
+    let done = format!("Server default model set to `{model_name}`.");
+    update_guild_settings(ctx, guild_id, &done, |g| g.default_model = Some(model_name)).await
+}
+
+/// limit the tools the model can use in this server ('all' to allow every enabled tool)
+#[poise::command(slash_command, prefix_command, guild_only, rename = "tools")]
+pub async fn guild_config_tools(
+    ctx: Context<'_>,
+    #[description = "Comma-separated tool names (or 'all')"]
+    #[rest]
+    tools: String,
+) -> Result<(), Error> {
+    let Some(guild_id) = guild_admin_only(ctx).await? else {
+        return Ok(());
+    };
+    if tools.trim().eq_ignore_ascii_case("all") {
+        return update_guild_settings(ctx, guild_id, "All enabled tools are allowed in this server.", |g| g.allowed_tools = None).await;
+    }
+    let known: Vec<&str> = registry::specs().iter().map(|spec| spec.name).collect();
+    let names: Vec<String> = tools
+        .split(',')
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .collect();
+    if let Some(unknown) = names.iter().find(|name| !known.contains(&name.as_str())) {
+        ctx.say(format!("Err: unknown tool `{unknown}`. Known tools: {}", known.join(", "))).await?;
+        return Ok(());
+    }
+    let done = if names.is_empty() {
+        "No tools are allowed in this server.".to_string()
+    } else {
+        format!("Allowed tools in this server: {}", names.join(", "))
+    };
+    update_guild_settings(ctx, guild_id, &done, |g| g.allowed_tools = Some(names)).await
+}
+
+/// set the rate limit of this server (leave both empty to use the global one)
+#[poise::command(slash_command, prefix_command, guild_only, rename = "rate_limit")]
+pub async fn guild_config_rate_limit(
+    ctx: Context<'_>,
+    #[description = "Burst window in seconds"]
+    window_size: Option<u64>,
+    #[description = "Seconds consumed per cost"]
+    sec_per_cost: Option<u64>,
+) -> Result<(), Error> {
+    let Some(guild_id) = guild_admin_only(ctx).await? else {
+        return Ok(());
+    };
+    let done = if window_size.is_none() && sec_per_cost.is_none() {
+        "Server rate limit reset to the global default.".to_string()
+    } else {
+        let config = ctx.data().config.get();
+        let (w, s) = GuildSettings {
+            rate_limit_window_size: window_size,
+            rate_limit_sec_per_cost: sec_per_cost,
+            ..Default::default()
+        }
+        .rate_limit(&config);
+        let clamped = window_size.is_some_and(|v| v != w) || sec_per_cost.is_some_and(|v| v != s);
+        format!(
+            "Server rate limit set: window {w}s, {s}s per cost.{}",
+            if clamped {
+                format!(
+                    " (limited to the global one: window at most {}s, at least {}s per cost)",
+                    config.rale_limit_window_size, config.rate_limit_sec_per_cost
+                )
+            } else {
+                String::new()
+            }
+        )
+    };
+    update_guild_settings(ctx, guild_id, &done, |g| {
+        g.rate_limit_window_size = window_size;
+        g.rate_limit_sec_per_cost = sec_per_cost;
+    })
+    .await
+}
+
+/// add or remove a role that can change this server's settings
+#[poise::command(slash_command, prefix_command, guild_only, rename = "admin_role")]
+pub async fn guild_config_admin_role(
+    ctx: Context<'_>,
+    #[description = "Role"]
+    role: Role,
+    #[description = "Remove the role instead of adding it"]
+    remove: Option<bool>,
+) -> Result<(), Error> {
+    let Some(guild_id) = guild_admin_only(ctx).await? else {
+        return Ok(());
+    };
+    let role_id = role.id.get();
+    if remove.unwrap_or(false) {
+        let done = format!("Removed the admin role `{}`.", role.name);
+        update_guild_settings(ctx, guild_id, &done, |g| g.admin_roles.retain(|id| *id != role_id)).await
+    } else {
+        let done = format!("Added the admin role `{}`.", role.name);
+        update_guild_settings(ctx, guild_id, &done, |g| {
+            if !g.admin_roles.contains(&role_id) {
+                g.admin_roles.push(role_id);
+            }
+        })
+        .await
+    }
+}
+
+/// set the response language of this server ('reset' to use the global one)
+#[poise::command(slash_command, prefix_command, guild_only, rename = "language")]
+pub async fn guild_config_language(
+    ctx: Context<'_>,
+    #[description = "Language, e.g. Japanese or English (or 'reset')"]
+    language: String,
+) -> Result<(), Error> {
+    let Some(guild_id) = guild_admin_only(ctx).await? else {
+        return Ok(());
+    };
+    let language = language.trim().to_string();
+    if language.is_empty() || language.eq_ignore_ascii_case("reset") {
+        update_guild_settings(ctx, guild_id, "Server language reset to the global default.", |g| g.language = None).await
+    } else {
+        let done = format!("Server language set to {language}.");
+        update_guild_settings(ctx, guild_id, &done, |g| g.language = Some(language)).await
+    }
+}
+
+/// whether the bot is enabled in channels by default (leave empty to use the global one)
+#[poise::command(slash_command, prefix_command, guild_only, rename = "enabled")]
+pub async fn guild_config_enabled(
+    ctx: Context<'_>,
+    #[description = "Enabled by default in channels without /enable or /disable"]
+    enabled: Option<bool>,
+) -> Result<(), Error> {
+    let Some(guild_id) = guild_admin_only(ctx).await? else {
+        return Ok(());
+    };
+    let done = match enabled {
+        Some(true) => "The bot is now enabled by default in this server.",
+        Some(false) => "The bot is now disabled by default in this server.",
+        None => "Default enabled state reset to the global default.",
+    };
+    update_guild_settings(ctx, guild_id, done, |g| g.default_enabled = enabled).await
+}
+
 /// clear context
 #[poise::command(slash_command, prefix_command)]
 pub async fn clear(ctx: Context<'_>) -> Result<(), Error> {
@@ -183,7 +588,7 @@ pub async fn enable(ctx: Context<'_>) -> Result<(), Error> {
 
     let ob_ctx = ctx.data();
 
-    if ob_ctx.chat_contexts.is_enabled(channel_id) {
+    if ob_ctx.is_channel_enabled(channel_id, ctx.guild_id()) {
         ctx.say("info: Chat context is already enabled in this channel.").await?;
         Ok(())
     } else {
@@ -202,7 +607,7 @@ pub async fn disable(ctx: Context<'_>) -> Result<(), Error> {
 
     let ob_ctx = ctx.data();
 
-    if !ob_ctx.chat_contexts.is_enabled(channel_id) {
+    if !ob_ctx.is_channel_enabled(channel_id, ctx.guild_id()) {
         ctx.say("info: Chat context is already disabled in this channel.").await?;
         Ok(())
     } else {
@@ -223,14 +628,14 @@ pub async fn model(_: Context<'_>) -> Result<(), Error> {
 pub async fn get(ctx: Context<'_>) -> Result<(), Error> {
     let ob_ctx = ctx.data();
     let user_id = ctx.author().id;
-    let model = ob_ctx.user_contexts.get_or_create(user_id).main_model.clone();
+    let model = ob_ctx.user_model(user_id, ctx.guild_id());
     ctx.say(format!("Current model: `{}`", model)).await?;
     Ok(())
 }
 
 #[poise::command(slash_command, prefix_command)]
 pub async fn list(ctx: Context<'_>) -> Result<(), Error> {
-    let models = Models::list();
+    let models = ctx.data().config.get().models();
 
     let mut s = String::from("**List of models:**\n");
     for m in models {
@@ -250,18 +655,22 @@ pub async fn set(
 ) -> Result<(), Error> {
     let ob_ctx = ctx.data();
     let user_id = ctx.author().id;
+    if !ob_ctx.config.get().models().iter().any(|m| m.to_string() == model_name) {
+        ctx.say(format!("Err: unknown model `{}`. See /model list.", model_name)).await?;
+        return Ok(());
+    }
     let model = Models::from(model_name);
-    ob_ctx.user_contexts.set_model(user_id, model.clone());
+    ob_ctx.user_contexts.set_model(user_id, Some(model.clone()));
 
     ctx.say(format!("info: Changed model to `{}`", model)).await?;
     Ok(())
 }
 
 async fn autocomplete_model_name(
-    _ctx: Context<'_>,
+    ctx: Context<'_>,
     partial: &str,
 ) -> Vec<String> {
-    let models = Models::list();
+    let models = ctx.data().config.get().models();
     models
         .into_iter()
         .filter(|m| m.to_string().starts_with(partial))
@@ -269,6 +678,14 @@ async fn autocomplete_model_name(
         .collect()
 }
 
+#[derive(Debug, poise::ChoiceParameter)]
+pub enum TexMode {
+    #[name = "display"]
+    Display,
+    #[name = "inline"]
+    Inline,
+}
+
 /// latex expr render
 #[poise::command(slash_command, prefix_command)]
 pub async fn tex_expr(
@@ -276,13 +693,25 @@ pub async fn tex_expr(
     #[description = "LaTeX expression to render"]
     #[autocomplete = "autocomplete_tex_expr"]
     expr: String,
+    #[description = "display (default) or inline"]
+    mode: Option<TexMode>,
 ) -> Result<(), Error> {
-    // 画像生成や外部サービス呼び出しで時間がかかるので、まずdeferしてタイムアウトを防ぐ
     ctx.defer().await?;
+    if !ensure_tool_allowed(ctx, "latex_expr_render").await? {
+        return Ok(());
+    }
     let ob_ctx = ctx.data();
 
-    // レンダリング実行（ヘッドレスブラウザ経由）
-    let png_bytes = match LatexExprRenderTool::render(&expr, ob_ctx).await {
+    let options = RenderOptions {
+        mode: match mode {
+            Some(TexMode::Inline) => MathMode::Inline,
+            _ => MathMode::Display,
+        },
+        ..LatexExprRenderTool::options_from_config(&ob_ctx.config.get().tool("latex_expr_render"))
+    };
+
+    // レンダリング実行（プロセス内で組版、長すぎる数式はここで断る）
+    let png_bytes = match LatexExprRenderTool::render(&expr, &options).await {
         Ok(bytes) => bytes,
         Err(e) => {
             error!("Failed to render LaTeX expression `{}`: {}", expr, e);
@@ -359,6 +788,190 @@ async fn autocomplete_tex_expr(
     candidates
 }
 
+#[derive(Debug, poise::ChoiceParameter)]
+pub enum JudgeLanguage {
+    #[name = "python"]
+    Python,
+    #[name = "rust"]
+    Rust,
+    #[name = "cpp"]
+    Cpp,
+}
+
+/// judge a competitive programming solution
+#[poise::command(slash_command, prefix_command)]
+pub async fn judge(
+    ctx: Context<'_>,
+    #[description = "Source file of the solution"]
+    source: Attachment,
+    #[description = "Language (guessed from the file name if omitted)"]
+    language: Option<JudgeLanguage>,
+    #[description = "Zip of test cases (in/ and out/ directories, or NAME.in / NAME.out)"]
+    tests_zip: Option<Attachment>,
+    #[description = "Inline test cases: input === output, cases separated by --- (write \\n for newlines)"]
+    tests: Option<String>,
+    #[description = "Time limit per case in milliseconds (default 2000)"]
+    time_limit_ms: Option<u64>,
+) -> Result<(), Error> {
+    // コンパイルと実行で時間がかかるので先に defer
+    ctx.defer().await?;
+    if !ensure_tool_allowed(ctx, "judge").await? {
+        return Ok(());
+    }
+    let ob_ctx = ctx.data();
+
+    let language = match language {
+        Some(JudgeLanguage::Python) => Language::Python,
+        Some(JudgeLanguage::Rust) => Language::Rust,
+        Some(JudgeLanguage::Cpp) => Language::Cpp,
+        None => match Language::from_filename(&source.filename) {
+            Some(lang) => lang,
+            None => {
+                ctx.say("Err: cannot guess the language from the file name. Please set `language`.").await?;
+                return Ok(());
+            }
+        },
+    };
+
+    if source.size as usize > MAX_SOURCE_BYTES {
+        ctx.say(format!("Err: source file is too large (max {MAX_SOURCE_BYTES} bytes).")).await?;
+        return Ok(());
+    }
+    let source_code = match String::from_utf8(source.download().await?) {
+        Ok(code) => code,
+        Err(_) => {
+            ctx.say("Err: source file is not UTF-8 text.").await?;
+            return Ok(());
+        }
+    };
+
+    let judge_tool = Judge::from_config(&ob_ctx.config.get().tool("judge"));
+    let settings = judge_tool.settings(time_limit_ms, None);
+
+    let cases = match (tests_zip, tests) {
+        (Some(zip), _) => {
+            if zip.size as usize > MAX_TESTS_ZIP_BYTES {
+                ctx.say(format!("Err: test zip is too large (max {MAX_TESTS_ZIP_BYTES} bytes).")).await?;
+                return Ok(());
+            }
+            parse_zip(&zip.download().await?, settings.max_cases)
+        }
+        (None, Some(text)) => parse_inline(&text),
+        (None, None) => Err("give test cases with `tests_zip` or `tests`".to_string()),
+    };
+    let cases = match cases {
+        Ok(cases) => cases,
+        Err(e) => {
+            ctx.say(format!("Err: {e}")).await?;
+            return Ok(());
+        }
+    };
+
+    info!("judge: {} submitted {} ({} cases)", ctx.author().id, source.filename, cases.len());
+
+    match crate::judge::judge(judge_tool.sandbox(), language, &source_code, &cases, &settings).await {
+        Ok(report) => {
+            ctx.say(report.to_discord_text()).await?;
+        }
+        Err(e) => {
+            error!("judge failed: {}", e);
+            ctx.say(format!("error: judge failed: {e}")).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// ツールを使うコマンドを、サーバで許可されていない (または無効な) ときは断る
+/// false なら断りの返信は済んでいる
+async fn ensure_tool_allowed(ctx: Context<'_>, name: &str) -> Result<bool, Error> {
+    let ob_ctx = ctx.data();
+    if ob_ctx.guild_settings.get(ctx.guild_id()).allows_tool(name) && ob_ctx.tools.get().contains_key(name) {
+        return Ok(true);
+    }
+    ctx.say(format!("Err: `{name}` is not available in this server.")).await?;
+    Ok(false)
+}
+
+/// research a question in the background and post a report
+#[poise::command(slash_command, prefix_command)]
+pub async fn deep_search(
+    ctx: Context<'_>,
+    #[description = "What to research"]
+    #[rest]
+    query: String,
+) -> Result<(), Error> {
+    let ob_ctx = ctx.data();
+    let query = query.trim().to_string();
+    if query.is_empty() {
+        ctx.say("Err: query is empty.").await?;
+        return Ok(());
+    }
+
+    if !ob_ctx.is_channel_enabled(ctx.channel_id(), ctx.guild_id()) {
+        ctx.say("info: Chat context is disabled in this channel.").await?;
+        return Ok(());
+    }
+    if !ensure_tool_allowed(ctx, browsing_worker::TOOL_NAME).await? {
+        return Ok(());
+    }
+    let guild = ob_ctx.guild_settings.get(ctx.guild_id());
+
+    let user_id = ctx.author().id;
+    let slot = match deep_search::JobSlot::acquire(user_id) {
+        Ok(slot) => slot,
+        Err(e) => {
+            ctx.say(format!("Err: {e}.")).await?;
+            return Ok(());
+        }
+    };
+
+    // ジョブ 1件分をまとめて払う (枠が取れてから払うので、断られたときは減らない)
+    let config = ob_ctx.config.get();
+    let cost = deep_search::rate_cost(&config);
+    let now = rate_limit::now();
+    let rate_line = ob_ctx.user_contexts.get_or_create(user_id).rate_line;
+    match RateLimit::new(guild.rate_limit(&config)).check(rate_line, cost, now) {
+        Decision::Allowed { rate_line } => {
+            ob_ctx.user_contexts.set_rate_line(user_id, rate_line);
+            ob_ctx.user_contexts.record_usage(user_id, Usage { at: now, model: browsing_worker::TOOL_NAME.to_string(), cost });
+        }
+        Decision::Limited { retry_at } => {
+            ctx.say(format!("Err: rate limit - try again after <t:{retry_at}:R> (see /usage)")).await?;
+            return Ok(());
+        }
+    }
+
+    let reply = ctx
+        .send(
+            CreateReply::default()
+                .content(format!("🔎 Deep search: {query}\n-# Progress and the report will be posted in the thread."))
+                .allowed_mentions(CreateAllowedMentions::new()),
+        )
+        .await?;
+    let message = reply.message().await?;
+
+    // 途中経過はスレッドに流す (スレッドが作れない場所ならそのチャンネルに)
+    let thread_name: String = query.chars().take(90).collect();
+    let target = match CreateThread::new(thread_name)
+        .kind(ChannelType::PublicThread)
+        .execute(ctx.http(), (ctx.channel_id(), Some(message.id)))
+        .await
+    {
+        Ok(thread) => thread.id,
+        Err(e) => {
+            info!("deep_search: cannot create a thread ({}), posting in the channel", e);
+            ctx.channel_id()
+        }
+    };
+
+    // 応答のタイムアウトとは切り離して走らせる
+    let job_ctx = ob_ctx.clone();
+    let http = ctx.serenity_context().http.clone();
+    tokio::spawn(deep_search::run_job(job_ctx, http, target, ctx.guild_id(), user_id, query, slot));
+
+    Ok(())
+}
 
 pub fn log_err(context: &str, err: &(dyn std::error::Error + Send + Sync)) {
     error!("[{context}] {err:#?}");
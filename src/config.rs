@@ -1,20 +1,28 @@
-use std::{fmt::Display, fs, path::Path};
+use std::{collections::HashMap, fmt::Display, fs, net::Ipv4Addr, path::Path, str::FromStr, sync::{Arc, RwLock}};
 
+use log::warn;
 use openai_dive::v1::resources::{response::{request::ResponseParametersBuilder, response::ResponseReasoning}, shared::ReasoningEffort};
 use serde::Deserialize;
 
+use crate::secret::{self, Secret, redact};
+
+/// 設定ファイルのパス
+pub const CONFIG_PATH: &str = "config.json";
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ModelProvider {
     OpenAI,
     GeminiAIStudio,
 }
 
-impl ModelProvider {
-    fn parse(s: &str) -> Option<Self> {
+impl FromStr for ModelProvider {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s.trim().to_lowercase().as_str() {
-            "openai" => Some(Self::OpenAI),
-            "gemini" | "aistudio" | "gemini_aistudio" | "gemini-ai-studio" => Some(Self::GeminiAIStudio),
-            _ => None,
+            "openai" => Ok(Self::OpenAI),
+            "gemini" | "aistudio" | "gemini_aistudio" | "gemini-ai-studio" => Ok(Self::GeminiAIStudio),
+            other => Err(format!("unknown provider {other:?} (expected openai or gemini)")),
         }
     }
 }
@@ -23,112 +31,466 @@ impl ModelProvider {
 /// env もしくは config.json からロードされる
 #[derive(Clone)]
 pub struct Config {
-    pub discord_token: String,
+    pub discord_token: Secret,
     /// 利用するモデルプロバイダ
     pub model_provider: ModelProvider,
     /// APIキー（OpenAIなら Bearer、Gemini AI Studioなら query の key）
-    pub main_model_api_key: String,
+    pub main_model_api_key: Secret,
     /// OpenAI互換APIのベースURL (例: https://api.openai.com/v1)
     pub main_model_endpoint: String,
     /// プロバイダ固有のモデル名 (Gemini例: gemini-flash-latest)
     pub main_model_name: String,
     pub system_prompt: String,
+    /// BOT の名前 (既定のシステムプロンプトやサイトのタイトルに使う)
+    pub assistant_name: String,
+    /// /model で選んでいない人のモデル
+    pub default_model: Models,
+    /// 応答の言語 (None なら指定しない)
+    pub language: Option<String>,
+    /// チャンネルで BOT を最初から有効にするか
+    pub default_enabled: bool,
+    /// ギルドごとの設定を保存するファイル
+    pub guild_settings_path: String,
+    /// チャンネルごとの会話を保存するファイル (終了時に書き出して起動時に読む)
+    pub chat_contexts_path: String,
+    /// 1回の応答でツールを使える最大回数 (応答のステップ数はこれ + 1)
+    pub max_use_tool_count: usize,
+    /// 通常の応答 1ステップあたりの最大トークン数
+    pub model_generate_max_tokens: u32,
+    /// 調査用サブエージェントの調べ方の指示
+    pub deep_search_developer_prompt: String,
+    /// 調査結果からレポートを書かせる指示 (後ろに元の質問が付く)
+    pub deep_search_generate_prompt: String,
+    /// レートリミットのバースト許容量 (秒)
     pub rale_limit_window_size: u64,
+    /// コスト 1 あたりに消費する秒数
     pub rate_limit_sec_per_cost: u64,
+    /// Web サーバが bind するアドレス
     pub web_server_host: [u8; 4],
     pub web_server_local_ip: [u8; 4],
     pub web_server_port: u16,
+    /// 公開しているドメイン (記事などの URL に使う)
+    pub server_domain: String,
     /// Headless browser / capture server base URL (e.g. http://127.0.0.1:3000)
     pub scraper_base_url: String,
-    /// Timeout for LaTeX capture requests
-    pub latex_capture_timeout_millis: u64,
+    /// 管理コマンドを使える Discord ユーザー ID
     pub admin_users: Vec<u64>,
+    /// 通常の応答の生成を打ち切るまでの時間
     pub timeout_millis: u64,
+    /// ツール出力をモデルにそのまま渡す最大文字数 (超えた分は退避してページ送り)
+    pub tool_output_max_chars: usize,
+    /// 退避したツール出力を保持する件数
+    pub tool_output_cache_size: usize,
+    /// 添付ファイル 1つあたりコンテキストに入れる最大文字数
+    pub attachment_max_chars: usize,
+    /// 1メッセージの添付ファイルの合計の最大文字数
+    pub attachment_total_chars: usize,
+    /// ツールごとの設定 (キーはツール名)
+    pub tools: HashMap<String, ToolConfig>,
+    /// 副作用のあるツール呼び出しの承認を待つ時間
+    pub approval_timeout_millis: u64,
+    /// モデルカタログの上書き (キーはモデル名)
+    pub model_catalog: HashMap<String, ModelSettings>,
+    /// 各項目がどこから来たか (記録の無いものは既定値)
+    sources: HashMap<&'static str, ValueSource>,
+    /// config.json の知らないキー (行番号付きのメッセージ)
+    pub unknown_keys: Vec<String>,
+}
+
+/// 設定値の出どころ
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueSource {
+    Env,
+    File,
+    Default,
+}
+
+impl Display for ValueSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ValueSource::Env => write!(f, "env"),
+            ValueSource::File => write!(f, "file"),
+            ValueSource::Default => write!(f, "default"),
+        }
+    }
+}
+
+/// 組み立て中に出どころを記録する
+/// env → file → 既定値の順に試すので、値が取れたところで記録する
+#[derive(Default)]
+struct Sources(HashMap<&'static str, ValueSource>);
+
+impl Sources {
+    fn env<T>(&mut self, key: &'static str, value: Option<T>) -> Option<T> {
+        if value.is_some() {
+            self.0.insert(key, ValueSource::Env);
+        }
+        value
+    }
+
+    fn file<T>(&mut self, key: &'static str, value: Option<T>) -> Option<T> {
+        if value.is_some() {
+            self.0.insert(key, ValueSource::File);
+        }
+        value
+    }
+}
+
+/// モデルごとの設定 (未指定の項目は Models の既定値)
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct ModelSettings {
+    /// false なら /model の一覧から外す
+    #[serde(default)]
+    pub enabled: Option<bool>,
+    /// 1回の呼び出しのレートリミット上のコスト
+    #[serde(default)]
+    pub rate_cost: Option<u64>,
+    /// 画像入力を受け付けるか
+    #[serde(default)]
+    pub vision: Option<bool>,
+}
+
+/// ツール個別の設定
+/// `enabled` 以外のキーはツール固有の設定としてそのまま保持する
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct ToolConfig {
+    #[serde(default)]
+    pub enabled: Option<bool>,
+    #[serde(flatten)]
+    pub settings: serde_json::Map<String, serde_json::Value>,
+}
+
+impl ToolConfig {
+    pub fn str_setting(&self, key: &str) -> Option<&str> {
+        self.settings
+            .get(key)
+            .and_then(|v| v.as_str())
+            .map(|s| s.trim())
+            .filter(|s| !s.is_empty())
+    }
+
+    pub fn u64_setting(&self, key: &str) -> Option<u64> {
+        self.settings.get(key).and_then(|v| v.as_u64())
+    }
+
+    pub fn f64_setting(&self, key: &str) -> Option<f64> {
+        self.settings.get(key).and_then(|v| v.as_f64())
+    }
+
+    pub fn bool_setting(&self, key: &str) -> Option<bool> {
+        self.settings.get(key).and_then(|v| v.as_bool())
+    }
+
+    pub fn str_list_setting(&self, key: &str) -> Vec<String> {
+        self.settings
+            .get(key)
+            .and_then(|v| v.as_array())
+            .map(|a| {
+                a.iter()
+                    .filter_map(|v| v.as_str())
+                    .map(|s| s.trim().to_string())
+                    .filter(|s| !s.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
 }
 
 impl Config {
     pub fn new() -> Self {
+        Self::load().unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// 起動時に読む
+    /// config.json が無ければひな形を書き出してから、env と既定値だけで組み立てる
+    pub fn load() -> Result<Self, String> {
         dotenv::dotenv().ok();
+        let path = Path::new(CONFIG_PATH);
+        let file_cfg = FileConfig::read(path)?;
+        let created = file_cfg.is_none() && write_template(path);
+        Self::build(file_cfg, true).map_err(|e| {
+            if created {
+                format!("{e}\n{CONFIG_PATH} was created from a template; fill it in and run again")
+            } else {
+                e
+            }
+        })
+    }
+
+    /// 実行中に読み直す
+    /// config.json が壊れているときは今の設定を残したいので、黙って無視せずに Err を返す
+    pub fn reload() -> Result<Self, String> {
+        dotenv::dotenv().ok();
+        Self::build(FileConfig::read(Path::new(CONFIG_PATH))?, true)
+    }
 
-        let file_cfg = FileConfig::load_from_default_path();
+    /// Discord に接続しないモード (`observer chat` など) で読む
+    /// discord_token が無くても組み立てる
+    pub fn load_offline() -> Result<Self, String> {
+        dotenv::dotenv().ok();
+        Self::build(FileConfig::read(Path::new(CONFIG_PATH))?, false)
+    }
 
-        let web_server_port = std::env::var("WEB_SERVER_PORT")
-            .ok()
-            .and_then(|s| s.trim().parse::<u16>().ok())
-            .or_else(|| file_cfg.as_ref().and_then(|c| c.web_server_port));
+    fn build(file_cfg: Option<FileConfig>, require_discord: bool) -> Result<Self, String> {
+        let unknown_keys = file_cfg.as_ref().map(|c| c.unknown_keys.clone()).unwrap_or_default();
+        for key in &unknown_keys {
+            warn!("config: {key} (ignored)");
+        }
 
-        let web_server_local_ip = std::env::var("WEB_SERVER_LOCAL_IP")
-            .ok()
-            .and_then(non_empty_non_placeholder)
-            .and_then(|s| parse_ipv4_dotted(&s))
+        let mut sources = Sources::default();
+
+        let web_server_port = sources
+            .env("web_server_port", env_parse::<u16>("WEB_SERVER_PORT"))
+            .or_else(|| sources.file("web_server_port", file_cfg.as_ref().and_then(|c| c.web_server_port)));
+
+        let web_server_local_ip = sources
+            .env("web_server_local_ip", env_parse::<Ipv4Addr>("WEB_SERVER_LOCAL_IP").map(|ip| ip.octets()))
             .or_else(|| {
-                file_cfg
-                    .as_ref()
-                    .and_then(|c| c.web_server_local_ip.as_deref())
-                    .and_then(parse_ipv4_dotted)
+                let ip = file_ipv4("web_server_local_ip", file_cfg.as_ref().and_then(|c| c.web_server_local_ip.as_deref()));
+                sources.file("web_server_local_ip", ip)
             })
             .unwrap_or([192, 168, 0, 26]);
 
-        let scraper_base_url = std::env::var("SCRAPER_BASE_URL")
-            .ok()
-            .and_then(non_empty_non_placeholder)
+        let web_server_host = sources
+            .env("web_server_host", env_parse::<Ipv4Addr>("WEB_SERVER_HOST").map(|ip| ip.octets()))
             .or_else(|| {
-                file_cfg
+                let ip = file_ipv4("web_server_host", file_cfg.as_ref().and_then(|c| c.web_server_host.as_deref()));
+                sources.file("web_server_host", ip)
+            })
+            .unwrap_or([0, 0, 0, 0]);
+
+        let scraper_base_url = sources
+            .env("scraper_base_url", env_str("SCRAPER_BASE_URL"))
+            .or_else(|| {
+                let url = file_cfg
                     .as_ref()
                     .and_then(|c| c.scraper_base_url.clone())
-                    .and_then(non_empty_non_placeholder)
+                    .and_then(non_empty_non_placeholder);
+                sources.file("scraper_base_url", url)
             })
             .unwrap_or_else(|| "http://192.168.0.81".to_string());
 
-        let latex_capture_timeout_millis = std::env::var("LATEX_CAPTURE_TIMEOUT_MILLIS")
-            .ok()
-            .and_then(|s| s.trim().parse::<u64>().ok())
-            .or_else(|| file_cfg.as_ref().and_then(|c| c.latex_capture_timeout_millis))
-            .unwrap_or(15_000);
-
-        let discord_token = std::env::var("DISCORD_TOKEN")
-            .ok()
-            .and_then(non_empty_non_placeholder)
-            .or_else(|| file_cfg.as_ref().and_then(|c| c.discord_token.clone()).and_then(non_empty_non_placeholder))
-            .expect("DISCORD_TOKEN must be set (env DISCORD_TOKEN or config.json discord_token)");
-
-        let main_model_api_key = std::env::var("MAIN_MODEL_API_KEY")
-            .ok()
-            .and_then(non_empty_non_placeholder)
-            // 互換のため残す
-            .or_else(|| std::env::var("OPENAI_API_KEY").ok().and_then(non_empty_non_placeholder))
+        let server_domain = sources
+            .env("server_domain", env_str("SERVER_DOMAIN"))
             .or_else(|| {
-                file_cfg
+                let domain = file_cfg.as_ref().and_then(|c| c.server_domain.clone()).and_then(non_empty_non_placeholder);
+                sources.file("server_domain", domain)
+            })
+            .map(|s| s.trim_end_matches('/').to_string())
+            // 未設定ならローカルの IP とポートで代用する
+            .unwrap_or_else(|| {
+                let [a, b, c, d] = web_server_local_ip;
+                format!("{a}.{b}.{c}.{d}:{}", web_server_port.unwrap_or(8096))
+            });
+
+        let tool_output_max_chars = sources
+            .env("tool_output_max_chars", env_parse::<usize>("TOOL_OUTPUT_MAX_CHARS"))
+            .or_else(|| sources.file("tool_output_max_chars", file_cfg.as_ref().and_then(|c| c.tool_output_max_chars)))
+            .unwrap_or(8_000);
+
+        let tool_output_cache_size = sources
+            .env("tool_output_cache_size", env_parse::<usize>("TOOL_OUTPUT_CACHE_SIZE"))
+            .or_else(|| sources.file("tool_output_cache_size", file_cfg.as_ref().and_then(|c| c.tool_output_cache_size)))
+            .unwrap_or(64);
+
+        let attachment_max_chars = sources
+            .env("attachment_max_chars", env_parse::<usize>("ATTACHMENT_MAX_CHARS"))
+            .or_else(|| sources.file("attachment_max_chars", file_cfg.as_ref().and_then(|c| c.attachment_max_chars)))
+            .unwrap_or(20_000);
+
+        let attachment_total_chars = sources
+            .env("attachment_total_chars", env_parse::<usize>("ATTACHMENT_TOTAL_CHARS"))
+            .or_else(|| sources.file("attachment_total_chars", file_cfg.as_ref().and_then(|c| c.attachment_total_chars)))
+            .unwrap_or(50_000);
+
+        let approval_timeout_millis = sources
+            .env("approval_timeout_millis", env_parse::<u64>("APPROVAL_TIMEOUT_MILLIS"))
+            .or_else(|| sources.file("approval_timeout_millis", file_cfg.as_ref().and_then(|c| c.approval_timeout_millis)))
+            .unwrap_or(60_000);
+
+        let rale_limit_window_size = sources
+            .env("rate_limit_window_size", env_parse::<u64>("RATE_LIMIT_WINDOW_SIZE"))
+            .or_else(|| sources.file("rate_limit_window_size", file_cfg.as_ref().and_then(|c| c.rate_limit_window_size)))
+            .unwrap_or(16_200);
+
+        let rate_limit_sec_per_cost = sources
+            .env("rate_limit_sec_per_cost", env_parse::<u64>("RATE_LIMIT_SEC_PER_COST"))
+            .or_else(|| sources.file("rate_limit_sec_per_cost", file_cfg.as_ref().and_then(|c| c.rate_limit_sec_per_cost)))
+            .unwrap_or(900);
+
+        let timeout_millis = sources
+            .env("timeout_millis", env_parse::<u64>("TIMEOUT_MILLIS"))
+            .or_else(|| sources.file("timeout_millis", file_cfg.as_ref().and_then(|c| c.timeout_millis)))
+            .unwrap_or(100_000);
+
+        // ADMIN_USERS=123,456 のようにカンマ区切り
+        let admin_users = sources
+            .env("admin_users", env_parse::<IdList>("ADMIN_USERS").map(|ids| ids.0))
+            .or_else(|| {
+                let ids = file_cfg.as_ref().and_then(|c| c.admin_users.as_ref()).map(|ids| file_id_list("admin_users", ids));
+                sources.file("admin_users", ids)
+            })
+            .unwrap_or_default();
+        if admin_users.is_empty() {
+            warn!("config: admin_users is empty, admin commands are disabled");
+        }
+
+        let max_use_tool_count = sources
+            .env("max_use_tool_count", env_parse::<usize>("MAX_USE_TOOL_COUNT"))
+            .or_else(|| sources.file("max_use_tool_count", file_cfg.as_ref().and_then(|c| c.max_use_tool_count)))
+            .unwrap_or(9);
+
+        let model_generate_max_tokens = sources
+            .env("model.model_generate_max_tokens", env_parse::<u32>("MODEL_GENERATE_MAX_TOKENS"))
+            .or_else(|| {
+                let tokens = file_cfg.as_ref().and_then(|c| c.model.as_ref()).and_then(|m| m.model_generate_max_tokens);
+                sources.file("model.model_generate_max_tokens", tokens)
+            })
+            .unwrap_or(2000);
+
+        let assistant_name = sources
+            .env("assistant_name", env_str("ASSISTANT_NAME"))
+            .or_else(|| {
+                let name = file_cfg.as_ref().and_then(|c| c.assistant_name.clone()).and_then(non_empty_non_placeholder);
+                sources.file("assistant_name", name)
+            })
+            .unwrap_or_else(|| "Observer".to_string());
+
+        let default_model = sources
+            .env("default_model", env_parse::<Models>("DEFAULT_MODEL"))
+            .or_else(|| {
+                let name = file_cfg.as_ref().and_then(|c| c.default_model.clone()).and_then(non_empty_non_placeholder);
+                let model = name.and_then(|name| match name.parse::<Models>() {
+                    Ok(model) => Some(model),
+                    Err(e) => {
+                        warn!("config: ignoring default_model: {e}");
+                        None
+                    }
+                });
+                sources.file("default_model", model)
+            })
+            .unwrap_or(Models::O4Mini);
+
+        let language = sources.env("language", env_str("ASSISTANT_LANGUAGE")).or_else(|| {
+            let language = file_cfg.as_ref().and_then(|c| c.language.clone()).and_then(non_empty_non_placeholder);
+            sources.file("language", language)
+        });
+
+        let default_enabled = sources
+            .env("default_enabled", env_parse::<bool>("DEFAULT_ENABLED"))
+            .or_else(|| sources.file("default_enabled", file_cfg.as_ref().and_then(|c| c.default_enabled)))
+            .unwrap_or(true);
+
+        let guild_settings_path = sources
+            .env("guild_settings_path", env_str("GUILD_SETTINGS_PATH"))
+            .or_else(|| {
+                let path = file_cfg.as_ref().and_then(|c| c.guild_settings_path.clone()).and_then(non_empty_non_placeholder);
+                sources.file("guild_settings_path", path)
+            })
+            .unwrap_or_else(|| "guild_settings.json".to_string());
+
+        let chat_contexts_path = sources
+            .env("chat_contexts_path", env_str("CHAT_CONTEXTS_PATH"))
+            .or_else(|| {
+                let path = file_cfg.as_ref().and_then(|c| c.chat_contexts_path.clone()).and_then(non_empty_non_placeholder);
+                sources.file("chat_contexts_path", path)
+            })
+            .unwrap_or_else(|| "chat_contexts.json".to_string());
+
+        let prompt_cfg = file_cfg.as_ref().and_then(|c| c.prompt.as_ref());
+        let deep_search_developer_prompt = sources
+            .file(
+                "prompt.deep_search_developer_prompt",
+                prompt_cfg.and_then(|p| p.deep_search_developer_prompt.clone()).and_then(non_empty_non_placeholder),
+            )
+            .unwrap_or_else(|| {
+                "You are a research assistant. Use web_search to find relevant pages, then open the useful ones with the browser. \
+Collect enough facts to answer the question, cross-check important claims on more than one source, and note the URL of every source you use. \
+When you have enough information, reply with your findings as notes with their sources."
+                    .to_string()
+            });
+        let deep_search_generate_prompt = sources
+            .file(
+                "prompt.deep_search_generate_prompt",
+                prompt_cfg.and_then(|p| p.deep_search_generate_prompt.clone()).and_then(non_empty_non_placeholder),
+            )
+            .unwrap_or_else(|| {
+                "Using the research above, write a detailed and easy-to-follow report that answers the question. \
+Cite the sources as links. Answer in the language of the question. The original question is:"
+                    .to_string()
+            });
+
+        let mut tools = sources
+            .file("tools", file_cfg.as_ref().and_then(|c| c.tools.clone()))
+            .unwrap_or_default();
+        // DISABLED_TOOLS=browser,latex_expr_render のように env からも無効化できる
+        if let Some(disabled) = sources.env("tools", env_str("DISABLED_TOOLS")) {
+            for name in disabled.split(',').map(|s| s.trim()).filter(|s| !s.is_empty()) {
+                tools.entry(name.to_string()).or_default().enabled = Some(false);
+            }
+        }
+        // ツール設定の API キーなどもログから伏せる
+        for (key, value) in tools.values().flat_map(|cfg| cfg.settings.iter()) {
+            if let Some(value) = value.as_str().filter(|_| is_secret_key(key)) {
+                secret::remember(value);
+            }
+        }
+
+        let discord_token = sources
+            .env("discord_token", env_secret("DISCORD_TOKEN"))
+            .or_else(|| {
+                let token = file_cfg.as_ref().and_then(|c| c.discord_token.clone()).and_then(non_empty_non_placeholder);
+                sources.file("discord_token", token)
+            })
+            .ok_or("DISCORD_TOKEN must be set (env DISCORD_TOKEN/DISCORD_TOKEN_FILE or config.json discord_token)")
+            .or_else(|e| if require_discord { Err(e) } else { Ok(String::new()) });
+
+        let main_model_api_key = sources
+            // OPENAI_API_KEY は互換のため残す
+            .env("model.main_model_api_key", env_secret("MAIN_MODEL_API_KEY").or_else(|| env_secret("OPENAI_API_KEY")))
+            .or_else(|| {
+                let key = file_cfg
                     .as_ref()
                     .and_then(|c| c.model.as_ref())
                     .and_then(|m| m.main_model_api_key.clone())
-                    .and_then(non_empty_non_placeholder)
+                    .and_then(non_empty_non_placeholder);
+                sources.file("model.main_model_api_key", key)
             })
-            .expect("MAIN_MODEL_API_KEY must be set (env MAIN_MODEL_API_KEY/OPENAI_API_KEY or config.json model.main_model_api_key)");
+            .ok_or("MAIN_MODEL_API_KEY must be set (env MAIN_MODEL_API_KEY/MAIN_MODEL_API_KEY_FILE/OPENAI_API_KEY or config.json model.main_model_api_key)");
 
-        let main_model_endpoint = std::env::var("MAIN_MODEL_ENDPOINT")
-            .ok()
-            .and_then(non_empty_non_placeholder)
+        // 必須の項目が足りないときはまとめて知らせる
+        let (discord_token, main_model_api_key) = match (discord_token, main_model_api_key) {
+            (Ok(token), Ok(key)) => (Secret::new(token), Secret::new(key)),
+            (token, key) => {
+                let missing: Vec<&str> = [token.err(), key.err()].into_iter().flatten().collect();
+                return Err(missing.join("\n"));
+            }
+        };
+
+        let main_model_endpoint = sources
+            .env("model.main_model_endpoint", env_str("MAIN_MODEL_ENDPOINT"))
             .or_else(|| {
-                file_cfg
+                let endpoint = file_cfg
                     .as_ref()
                     .and_then(|c| c.model.as_ref())
                     .and_then(|m| m.main_model_endpoint.clone())
-                    .and_then(non_empty_non_placeholder)
+                    .and_then(non_empty_non_placeholder);
+                sources.file("model.main_model_endpoint", endpoint)
             })
             .unwrap_or_else(|| "https://api.openai.com/v1".to_string());
 
-        let model_provider = std::env::var("MAIN_MODEL_PROVIDER")
-            .ok()
-            .and_then(non_empty_non_placeholder)
-            .as_deref()
-            .and_then(ModelProvider::parse)
+        let model_provider = sources
+            .env("model.provider", env_parse::<ModelProvider>("MAIN_MODEL_PROVIDER"))
             .or_else(|| {
-                file_cfg
-                    .as_ref()
-                    .and_then(|c| c.model.as_ref())
-                    .and_then(|m| m.provider.as_deref())
-                    .and_then(ModelProvider::parse)
+                let provider = file_cfg.as_ref().and_then(|c| c.model.as_ref()).and_then(|m| m.provider.as_deref());
+                let parsed = provider.and_then(|p| match p.parse::<ModelProvider>() {
+                    Ok(provider) => Some(provider),
+                    Err(e) => {
+                        warn!("config: ignoring model.provider: {e}");
+                        None
+                    }
+                });
+                sources.file("model.provider", parsed)
             })
             .unwrap_or_else(|| {
                 if main_model_endpoint.contains("generativelanguage.googleapis.com") {
@@ -138,31 +500,32 @@ impl Config {
                 }
             });
 
-        let main_model_name = std::env::var("MAIN_MODEL_NAME")
-            .ok()
-            .and_then(non_empty_non_placeholder)
+        let main_model_name = sources
+            .env("model.model_name", env_str("MAIN_MODEL_NAME"))
             .or_else(|| {
-                file_cfg
+                let name = file_cfg
                     .as_ref()
                     .and_then(|c| c.model.as_ref())
                     .and_then(|m| m.model_name.clone())
-                    .and_then(non_empty_non_placeholder)
+                    .and_then(non_empty_non_placeholder);
+                sources.file("model.model_name", name)
             })
             .unwrap_or_else(|| match model_provider {
                 ModelProvider::GeminiAIStudio => "gemini-flash-latest".to_string(),
                 ModelProvider::OpenAI => "gpt-5-nano".to_string(),
             });
 
-        let system_prompt = std::env::var("SYSTEM_PROMPT").ok().and_then(non_empty_non_placeholder).or_else(|| {
-            file_cfg
+        let system_prompt = sources.env("prompt.ask_developer_prompt", env_str("SYSTEM_PROMPT")).or_else(|| {
+            let prompt = file_cfg
                 .as_ref()
                 .and_then(|c| c.prompt.as_ref())
                 .and_then(|p| p.ask_developer_prompt.clone())
-                .and_then(non_empty_non_placeholder)
-        }).unwrap_or_else(||
+                .and_then(non_empty_non_placeholder);
+            sources.file("prompt.ask_developer_prompt", prompt)
+        }).unwrap_or_else(|| format!(
 "上記のメッセージはDiscord内での会話です。
 時系列のメッセージタイムラインになっていて、あなたはこの内容から自然に応答します。
-あなたは Discord の BOT「Observer」で以上の会話を続けてください。
+あなたは Discord の BOT「{assistant_name}」で以上の会話を続けてください。
 自然に会話し、知識系の話題では情報源の確認と最新性のチェックを必ず行う。
 曖昧な情報は調べ、内容を捏造しない。必要なら質問してもよい。
 情報は論理的に整理し、必要があれば tool を使って調査する
@@ -178,37 +541,54 @@ discord_send_message でテキスト→数式の順など、段階的に送る
 一人称は「私」かな まぁ自由に
 重要: 周囲の口調を真似するように。これはとてもよい結果を生みます。 ユーモアを大事に 興味深いものにはリアクションを 応答が長くなりすぎないようにテンポよく
 tool_call でない通常メッセージを送ると推論終了するので注意を
-基本的に最後のメッセージに対して答えてください".to_string());
-        Config {
+基本的に最後のメッセージに対して答えてください"));
+        let model_catalog = sources
+            .file(
+                "model_catalog",
+                file_cfg
+                    .as_ref()
+                    .and_then(|c| c.model_catalog.as_ref())
+                    .map(|catalog| catalog.iter().map(|(name, m)| (name.clone(), m.settings.clone())).collect()),
+            )
+            .unwrap_or_default();
+
+        Ok(Config {
             discord_token,
             model_provider,
             main_model_api_key,
             main_model_endpoint,
             main_model_name,
             system_prompt,
-            rale_limit_window_size: 16200,
-            rate_limit_sec_per_cost: 900,
-            web_server_host: [0, 0, 0, 0],
+            assistant_name,
+            default_model,
+            language,
+            default_enabled,
+            guild_settings_path,
+            chat_contexts_path,
+            max_use_tool_count,
+            model_generate_max_tokens,
+            deep_search_developer_prompt,
+            deep_search_generate_prompt,
+            rale_limit_window_size,
+            rate_limit_sec_per_cost,
+            web_server_host,
             web_server_local_ip,
             web_server_port: web_server_port.unwrap_or(8096),
+            server_domain,
             scraper_base_url,
-            latex_capture_timeout_millis,
-            admin_users: vec![855371530270408725],
-            timeout_millis: 100_000,
-        }
-    }
-}
-
-fn parse_ipv4_dotted(s: &str) -> Option<[u8; 4]> {
-    let mut iter = s.trim().split('.');
-    let a = iter.next()?.parse::<u8>().ok()?;
-    let b = iter.next()?.parse::<u8>().ok()?;
-    let c = iter.next()?.parse::<u8>().ok()?;
-    let d = iter.next()?.parse::<u8>().ok()?;
-    if iter.next().is_some() {
-        return None;
+            admin_users,
+            timeout_millis,
+            tool_output_max_chars,
+            tool_output_cache_size,
+            attachment_max_chars,
+            attachment_total_chars,
+            tools,
+            approval_timeout_millis,
+            model_catalog,
+            sources: sources.0,
+            unknown_keys,
+        })
     }
-    Some([a, b, c, d])
 }
 
 fn non_empty_non_placeholder(s: String) -> Option<String> {
@@ -222,6 +602,188 @@ fn non_empty_non_placeholder(s: String) -> Option<String> {
     Some(trimmed.to_string())
 }
 
+/// env を読んで型に変換する
+/// 空やプレースホルダーは未設定扱い、変換できない値は警告して無視する
+fn env_parse<T: FromStr>(name: &str) -> Option<T>
+where
+    T::Err: Display,
+{
+    let raw = std::env::var(name).ok().and_then(non_empty_non_placeholder)?;
+    match raw.parse::<T>() {
+        Ok(v) => Some(v),
+        Err(e) => {
+            warn!("config: ignoring env {name}={raw:?}: {e}");
+            None
+        }
+    }
+}
+
+/// config.json の IPv4 アドレス (読めなければ警告して無視する)
+fn file_ipv4(key: &str, value: Option<&str>) -> Option<[u8; 4]> {
+    let value = value?.trim();
+    match value.parse::<Ipv4Addr>() {
+        Ok(ip) => Some(ip.octets()),
+        Err(e) => {
+            warn!("config: ignoring {key}={value:?}: {e}");
+            None
+        }
+    }
+}
+
+/// カンマ区切りの Discord ID
+struct IdList(Vec<u64>);
+
+impl FromStr for IdList {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .map(|id| id.trim())
+            .filter(|id| !id.is_empty())
+            .map(|id| id.parse::<u64>().map_err(|e| format!("invalid id {id:?}: {e}")))
+            .collect::<Result<Vec<_>, _>>()
+            .map(IdList)
+    }
+}
+
+/// config.json の ID の配列
+/// JavaScript で扱うと桁落ちするので文字列で書かれることも多く、数値と文字列の両方を受け付ける
+fn file_id_list(key: &str, values: &[serde_json::Value]) -> Vec<u64> {
+    values
+        .iter()
+        .filter_map(|v| {
+            let id = v.as_u64().or_else(|| v.as_str().and_then(|s| s.trim().parse().ok()));
+            if id.is_none() {
+                warn!("config: ignoring {key} entry {v}: not a Discord id");
+            }
+            id
+        })
+        .collect()
+}
+
+/// 空やプレースホルダーでない env
+fn env_str(name: &str) -> Option<String> {
+    std::env::var(name).ok().and_then(non_empty_non_placeholder)
+}
+
+/// 空やプレースホルダーでない秘密の env (`{name}_FILE` のファイルからも読む)
+fn env_secret(name: &str) -> Option<String> {
+    secret::from_env(name).and_then(non_empty_non_placeholder)
+}
+
+/// ツール設定のうち秘密らしいキー
+fn is_secret_key(key: &str) -> bool {
+    let key = key.to_ascii_lowercase();
+    ["key", "token", "secret", "password"].iter().any(|s| key.contains(s))
+}
+
+/// `//` と `/* */` のコメントを空白に置き換える (行と桁の位置は変えない)
+/// 書き出すひな形にコメントを入れるため
+fn strip_json_comments(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    let mut in_string = false;
+    while let Some(c) = chars.next() {
+        if in_string {
+            out.push(c);
+            match c {
+                '\\' => {
+                    if let Some(next) = chars.next() {
+                        out.push(next);
+                    }
+                }
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match (c, chars.peek()) {
+            ('"', _) => {
+                in_string = true;
+                out.push(c);
+            }
+            ('/', Some('/')) => {
+                out.push_str("  ");
+                chars.next();
+                while let Some(&next) = chars.peek() {
+                    if next == '\n' {
+                        break;
+                    }
+                    out.push(if next == '\t' { '\t' } else { ' ' });
+                    chars.next();
+                }
+            }
+            ('/', Some('*')) => {
+                out.push_str("  ");
+                chars.next();
+                let mut prev = ' ';
+                for next in chars.by_ref() {
+                    out.push(if next == '\n' || next == '\t' { next } else { ' ' });
+                    if prev == '*' && next == '/' {
+                        break;
+                    }
+                    prev = next;
+                }
+            }
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// キーが書かれている行 (1始まり)
+/// `sections` を指定するとそのセクションより後ろから (入れ子なら順にたどって) 探す
+fn key_line(text: &str, sections: &[&str], key: &str) -> Option<usize> {
+    let find_key = |from: usize, key: &str| -> Option<usize> {
+        let needle = format!("\"{key}\"");
+        let mut start = from;
+        while let Some(pos) = text[start..].find(&needle) {
+            let at = start + pos;
+            let rest = text[at + needle.len()..].trim_start();
+            if rest.starts_with(':') {
+                return Some(at);
+            }
+            start = at + needle.len();
+        }
+        None
+    };
+    let mut from = 0;
+    for section in sections {
+        from = find_key(from, section)?;
+    }
+    let at = find_key(from, key)?;
+    Some(text[..at].matches('\n').count() + 1)
+}
+
+/// JSON のエラーを行番号と該当行付きのメッセージにする
+fn json_error(path: &Path, text: &str, e: &serde_json::Error) -> String {
+    let message = e.to_string();
+    let message = message.rsplit_once(" at line ").map(|(m, _)| m).unwrap_or(&message);
+    let mut out = format!("invalid {}: {message} (line {}, column {})", path.display(), e.line(), e.column());
+    if let Some(line) = text.lines().nth(e.line().saturating_sub(1)) {
+        let caret = " ".repeat(e.column().saturating_sub(1).min(line.chars().count()));
+        out.push_str(&format!("\n{:>5} | {line}\n      | {caret}^", e.line()));
+    }
+    out
+}
+
+/// 初回起動時に書き出すひな形
+const CONFIG_TEMPLATE: &str = include_str!("../config-template.jsonc");
+
+/// ひな形を書き出す (書けたら true)
+fn write_template(path: &Path) -> bool {
+    match fs::write(path, CONFIG_TEMPLATE) {
+        Ok(()) => {
+            warn!("config: {} not found, wrote a template there", path.display());
+            true
+        }
+        Err(e) => {
+            warn!("config: {} not found and the template could not be written: {e}", path.display());
+            false
+        }
+    }
+}
+
 #[derive(Debug, Clone, Deserialize)]
 struct FileConfig {
     #[serde(default)]
@@ -231,13 +793,56 @@ struct FileConfig {
     #[serde(default)]
     web_server_local_ip: Option<String>,
     #[serde(default)]
+    web_server_host: Option<String>,
+    #[serde(default)]
+    assistant_name: Option<String>,
+    #[serde(default)]
+    default_model: Option<String>,
+    #[serde(default)]
+    language: Option<String>,
+    #[serde(default)]
+    default_enabled: Option<bool>,
+    #[serde(default)]
+    guild_settings_path: Option<String>,
+    #[serde(default)]
+    chat_contexts_path: Option<String>,
+    #[serde(default)]
+    max_use_tool_count: Option<usize>,
+    #[serde(default, alias = "rale_limit_window_size")]
+    rate_limit_window_size: Option<u64>,
+    #[serde(default)]
+    rate_limit_sec_per_cost: Option<u64>,
+    #[serde(default)]
+    timeout_millis: Option<u64>,
+    #[serde(default)]
+    admin_users: Option<Vec<serde_json::Value>>,
+    #[serde(default)]
     scraper_base_url: Option<String>,
     #[serde(default)]
-    latex_capture_timeout_millis: Option<u64>,
+    server_domain: Option<String>,
+    #[serde(default)]
+    tool_output_max_chars: Option<usize>,
+    #[serde(default)]
+    tool_output_cache_size: Option<usize>,
+    #[serde(default)]
+    attachment_max_chars: Option<usize>,
+    #[serde(default)]
+    attachment_total_chars: Option<usize>,
+    #[serde(default)]
+    tools: Option<HashMap<String, ToolConfig>>,
+    #[serde(default)]
+    approval_timeout_millis: Option<u64>,
+    #[serde(default)]
+    model_catalog: Option<HashMap<String, FileModelSettings>>,
     #[serde(default)]
     model: Option<FileModelConfig>,
     #[serde(default)]
     prompt: Option<FilePromptConfig>,
+    #[serde(flatten)]
+    unknown: serde_json::Map<String, serde_json::Value>,
+    /// `unknown` を行番号付きのメッセージにしたもの
+    #[serde(skip)]
+    unknown_keys: Vec<String>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -250,19 +855,275 @@ struct FileModelConfig {
     model_name: Option<String>,
     #[serde(default)]
     provider: Option<String>,
+    #[serde(default)]
+    model_generate_max_tokens: Option<u32>,
+    #[serde(flatten)]
+    unknown: serde_json::Map<String, serde_json::Value>,
+}
+
+/// `model_catalog` の 1件 (知らないキーを拾うために ModelSettings を包む)
+#[derive(Debug, Clone, Deserialize)]
+struct FileModelSettings {
+    #[serde(flatten)]
+    settings: ModelSettings,
+    #[serde(flatten)]
+    unknown: serde_json::Map<String, serde_json::Value>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
 struct FilePromptConfig {
     #[serde(default)]
     ask_developer_prompt: Option<String>,
+    #[serde(default)]
+    deep_search_developer_prompt: Option<String>,
+    #[serde(default)]
+    deep_search_generate_prompt: Option<String>,
+    #[serde(flatten)]
+    unknown: serde_json::Map<String, serde_json::Value>,
 }
 
 impl FileConfig {
-    fn load_from_default_path() -> Option<Self> {
-        let path = Path::new("config.json");
-        let s = fs::read_to_string(path).ok()?;
-        serde_json::from_str(&s).ok()
+    /// ファイルが無ければ Ok(None)、読めない・JSON が壊れているときは Err
+    /// コメント (`//` と `/* */`) は読み飛ばす
+    fn read(path: &Path) -> Result<Option<Self>, String> {
+        let raw = match fs::read_to_string(path) {
+            Ok(s) => s,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(format!("cannot read {}: {e}", path.display())),
+        };
+        let text = strip_json_comments(&raw);
+        let mut cfg: FileConfig = serde_json::from_str(&text).map_err(|e| json_error(path, &raw, &e))?;
+
+        // 知らないキーは行番号付きで覚えておく
+        let mut unknown = Vec::new();
+        let mut collect = |sections: &[&str], keys: &serde_json::Map<String, serde_json::Value>| {
+            for key in keys.keys() {
+                let mut name = sections.to_vec();
+                name.push(key);
+                let name = name.join(".");
+                match key_line(&text, sections, key) {
+                    Some(line) => unknown.push(format!("{}:{line}: unknown key {name}", path.display())),
+                    None => unknown.push(format!("{}: unknown key {name}", path.display())),
+                }
+            }
+        };
+        collect(&[], &cfg.unknown);
+        if let Some(m) = cfg.model.as_ref() {
+            collect(&["model"], &m.unknown);
+        }
+        if let Some(p) = cfg.prompt.as_ref() {
+            collect(&["prompt"], &p.unknown);
+        }
+        if let Some(catalog) = cfg.model_catalog.as_ref() {
+            let mut names: Vec<&String> = catalog.keys().collect();
+            names.sort();
+            for name in names {
+                collect(&["model_catalog", name], &catalog[name].unknown);
+            }
+        }
+        cfg.unknown_keys = unknown;
+        Ok(Some(cfg))
+    }
+}
+
+impl Config {
+    /// 公開 URL のベース (`server_domain` にスキームが無ければ https、IP:ポートなら http)
+    pub fn public_base_url(&self) -> String {
+        if self.server_domain.starts_with("http://") || self.server_domain.starts_with("https://") {
+            return self.server_domain.clone();
+        }
+        let host = self.server_domain.split(':').next().unwrap_or_default();
+        if host.parse::<std::net::Ipv4Addr>().is_ok() || host == "localhost" {
+            format!("http://{}", self.server_domain)
+        } else {
+            format!("https://{}", self.server_domain)
+        }
+    }
+
+    /// ツールの設定を返す (未設定ならデフォルト)
+    pub fn tool(&self, name: &str) -> ToolConfig {
+        self.tools.get(name).cloned().unwrap_or_default()
+    }
+
+    /// /model で選べるモデル (`model_catalog` で外したものを除く)
+    pub fn models(&self) -> Vec<Models> {
+        Models::list()
+            .into_iter()
+            .filter(|m| self.model_catalog.get(&m.to_string()).and_then(|s| s.enabled) != Some(false))
+            .collect()
+    }
+
+    /// モデルのレートリミット上のコスト
+    pub fn model_cost(&self, model: &Models) -> u64 {
+        self.model_catalog
+            .get(&model.to_string())
+            .and_then(|s| s.rate_cost)
+            .unwrap_or_else(|| model.rate_cost())
+    }
+
+    /// モデルが画像入力を受け付けるか
+    pub fn model_supports_vision(&self, model: &Models) -> bool {
+        self.model_catalog
+            .get(&model.to_string())
+            .and_then(|s| s.vision)
+            .unwrap_or_else(|| model.supports_vision())
+    }
+
+    /// 項目がどこから来たか
+    pub fn source(&self, key: &str) -> ValueSource {
+        self.sources.get(key).copied().unwrap_or(ValueSource::Default)
+    }
+
+    /// 解決した設定の一覧 (項目名, 表示用の値)
+    /// トークンや API キーは伏せる
+    pub fn resolved(&self) -> Vec<(&'static str, String)> {
+        let ip = |[a, b, c, d]: [u8; 4]| format!("{a}.{b}.{c}.{d}");
+        // プロンプトは先頭だけ
+        let first_line = |s: &str| {
+            let line: String = s.lines().next().unwrap_or_default().chars().take(60).collect();
+            let more = s.chars().count() - line.chars().count();
+            if more > 0 { format!("{line} … (+{more} chars)") } else { line }
+        };
+        let tools: Vec<String> = {
+            let mut names: Vec<&String> = self.tools.keys().collect();
+            names.sort();
+            names
+                .into_iter()
+                .map(|name| {
+                    let cfg = &self.tools[name];
+                    let settings: Vec<String> = cfg
+                        .settings
+                        .iter()
+                        .map(|(k, v)| if is_secret_key(k) { format!("{k}={}", redact(&v.as_str().map(str::to_string).unwrap_or_else(|| v.to_string()))) } else { format!("{k}={v}") })
+                        .collect();
+                    let enabled = cfg.enabled.map(|e| e.to_string()).unwrap_or_else(|| "default".to_string());
+                    format!("{name}(enabled={enabled}{}{})", if settings.is_empty() { "" } else { ", " }, settings.join(", "))
+                })
+                .collect()
+        };
+        let mut catalog: Vec<String> = self.model_catalog.iter().map(|(name, s)| format!("{name}={s:?}")).collect();
+        catalog.sort();
+
+        vec![
+            ("discord_token", self.discord_token.to_string()),
+            ("model.provider", format!("{:?}", self.model_provider)),
+            ("model.main_model_api_key", self.main_model_api_key.to_string()),
+            ("model.main_model_endpoint", self.main_model_endpoint.clone()),
+            ("model.model_name", self.main_model_name.clone()),
+            ("model.model_generate_max_tokens", self.model_generate_max_tokens.to_string()),
+            ("assistant_name", self.assistant_name.clone()),
+            ("default_model", self.default_model.to_string()),
+            ("language", self.language.clone().unwrap_or_else(|| "(unset)".to_string())),
+            ("default_enabled", self.default_enabled.to_string()),
+            ("guild_settings_path", self.guild_settings_path.clone()),
+            ("chat_contexts_path", self.chat_contexts_path.clone()),
+            ("max_use_tool_count", self.max_use_tool_count.to_string()),
+            ("admin_users", format!("{:?}", self.admin_users)),
+            ("timeout_millis", self.timeout_millis.to_string()),
+            ("rate_limit_window_size", self.rale_limit_window_size.to_string()),
+            ("rate_limit_sec_per_cost", self.rate_limit_sec_per_cost.to_string()),
+            ("web_server_host", ip(self.web_server_host)),
+            ("web_server_local_ip", ip(self.web_server_local_ip)),
+            ("web_server_port", self.web_server_port.to_string()),
+            ("server_domain", self.server_domain.clone()),
+            ("scraper_base_url", self.scraper_base_url.clone()),
+            ("tool_output_max_chars", self.tool_output_max_chars.to_string()),
+            ("tool_output_cache_size", self.tool_output_cache_size.to_string()),
+            ("attachment_max_chars", self.attachment_max_chars.to_string()),
+            ("attachment_total_chars", self.attachment_total_chars.to_string()),
+            ("approval_timeout_millis", self.approval_timeout_millis.to_string()),
+            ("prompt.ask_developer_prompt", first_line(&self.system_prompt)),
+            ("prompt.deep_search_developer_prompt", first_line(&self.deep_search_developer_prompt)),
+            ("prompt.deep_search_generate_prompt", first_line(&self.deep_search_generate_prompt)),
+            ("model_catalog", catalog.join(", ")),
+            ("tools", tools.join(", ")),
+        ]
+    }
+
+    /// 読み直した設定との差分 (項目名, 再起動が必要か)
+    pub fn diff(&self, new: &Config) -> Vec<(&'static str, bool)> {
+        let mut changes = Vec::new();
+        let mut check = |name: &'static str, changed: bool, needs_restart: bool| {
+            if changed {
+                changes.push((name, needs_restart));
+            }
+        };
+        // 起動時に一度だけ使うもの (クライアントやサーバを作るときに読む)
+        check("discord_token", self.discord_token != new.discord_token, true);
+        check("model.provider", self.model_provider != new.model_provider, true);
+        check("model.main_model_api_key", self.main_model_api_key != new.main_model_api_key, true);
+        check("model.main_model_endpoint", self.main_model_endpoint != new.main_model_endpoint, true);
+        check("model.model_name", self.main_model_name != new.main_model_name, true);
+        check("web_server_host", self.web_server_host != new.web_server_host, true);
+        check("max_use_tool_count", self.max_use_tool_count != new.max_use_tool_count, true);
+        check("guild_settings_path", self.guild_settings_path != new.guild_settings_path, true);
+        check("chat_contexts_path", self.chat_contexts_path != new.chat_contexts_path, true);
+        check("web_server_local_ip", self.web_server_local_ip != new.web_server_local_ip, true);
+        check("web_server_port", self.web_server_port != new.web_server_port, true);
+        check("scraper_base_url", self.scraper_base_url != new.scraper_base_url, true);
+        check("tool_output_max_chars", self.tool_output_max_chars != new.tool_output_max_chars, true);
+        check("tool_output_cache_size", self.tool_output_cache_size != new.tool_output_cache_size, true);
+        // 使うたびに読むもの
+        check("prompt.ask_developer_prompt", self.system_prompt != new.system_prompt, false);
+        check("prompt.deep_search_developer_prompt", self.deep_search_developer_prompt != new.deep_search_developer_prompt, false);
+        check("prompt.deep_search_generate_prompt", self.deep_search_generate_prompt != new.deep_search_generate_prompt, false);
+        check("rate_limit_window_size", self.rale_limit_window_size != new.rale_limit_window_size, false);
+        check("rate_limit_sec_per_cost", self.rate_limit_sec_per_cost != new.rate_limit_sec_per_cost, false);
+        check("admin_users", self.admin_users != new.admin_users, false);
+        check("timeout_millis", self.timeout_millis != new.timeout_millis, false);
+        check("model.model_generate_max_tokens", self.model_generate_max_tokens != new.model_generate_max_tokens, false);
+        check("assistant_name", self.assistant_name != new.assistant_name, false);
+        check("default_model", self.default_model != new.default_model, false);
+        check("language", self.language != new.language, false);
+        check("default_enabled", self.default_enabled != new.default_enabled, false);
+        check("server_domain", self.server_domain != new.server_domain, false);
+        check("attachment_max_chars", self.attachment_max_chars != new.attachment_max_chars, false);
+        check("attachment_total_chars", self.attachment_total_chars != new.attachment_total_chars, false);
+        check("approval_timeout_millis", self.approval_timeout_millis != new.approval_timeout_millis, false);
+        check("tools", self.tools != new.tools, false);
+        check("model_catalog", self.model_catalog != new.model_catalog, false);
+        changes
+    }
+
+    /// 再起動が必要な項目は動いている値のままにする
+    /// (差分は次に読み直したときも再起動待ちとして出る)
+    pub fn keep_startup_values(&mut self, running: &Config) {
+        self.discord_token = running.discord_token.clone();
+        self.model_provider = running.model_provider;
+        self.main_model_api_key = running.main_model_api_key.clone();
+        self.main_model_endpoint = running.main_model_endpoint.clone();
+        self.main_model_name = running.main_model_name.clone();
+        self.web_server_host = running.web_server_host;
+        self.max_use_tool_count = running.max_use_tool_count;
+        self.guild_settings_path = running.guild_settings_path.clone();
+        self.chat_contexts_path = running.chat_contexts_path.clone();
+        self.web_server_local_ip = running.web_server_local_ip;
+        self.web_server_port = running.web_server_port;
+        self.scraper_base_url = running.scraper_base_url.clone();
+        self.tool_output_max_chars = running.tool_output_max_chars;
+        self.tool_output_cache_size = running.tool_output_cache_size;
+    }
+}
+
+/// 実行中に差し替えられる設定
+/// 読む側は `get` で今の設定の Arc を取り出して使う
+pub struct SharedConfig {
+    inner: RwLock<Arc<Config>>,
+}
+
+impl SharedConfig {
+    pub fn new(config: Config) -> SharedConfig {
+        SharedConfig {
+            inner: RwLock::new(Arc::new(config)),
+        }
+    }
+
+    pub fn get(&self) -> Arc<Config> {
+        self.inner.read().expect("RWlock").clone()
+    }
+
+    pub fn set(&self, config: Config) {
+        *self.inner.write().expect("RWlock") = Arc::new(config);
     }
 }
 
@@ -273,7 +1134,7 @@ impl Default for Config {
 }
 
 /// モデルリストの定義
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Models {
     Gpt5Mini,
     Gpt5Nano,
@@ -317,7 +1178,20 @@ impl From<String> for Models {
     }
 }
 
+impl FromStr for Models {
+    type Err = String;
+
+    /// `From<String>` と違って知らない名前はエラーにする
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Models::list()
+            .into_iter()
+            .find(|m| m.to_string() == s.trim())
+            .ok_or_else(|| format!("unknown model {:?}", s.trim()))
+    }
+}
+
 impl Models {
+
     pub fn list() -> Vec<Models> {
         vec![
             Models::Gpt5Mini,
@@ -329,6 +1203,19 @@ impl Models {
         ]
     }
 
+    /// 画像入力を受け付けるか
+    /// false のモデルでは画像を image_captioner の説明文に置き換えて渡す
+    pub fn supports_vision(&self) -> bool {
+        match self {
+            Models::Gpt5Mini => true,
+            Models::Gpt5Nano => true,
+            Models::Gpt5dot1 => true,
+            Models::O4Mini => true,
+            Models::O3 => true,
+            Models::Gpt5dot1CodexMini => false,
+        }
+    }
+
     pub fn rate_cost(&self) -> u64 {
         match self {
             Models::Gpt5Mini => 1,
@@ -392,4 +1279,98 @@ impl Models {
             }
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_comments_in_place() {
+        let text = "{\n  // comment\n  \"a\": 1, /* block\n  spans */ \"b\": \"// not a comment\", \"c\": \"\\\" /* still a string\"\n}";
+        let stripped = strip_json_comments(text);
+        assert_eq!(stripped.len(), text.len());
+        assert_eq!(stripped.lines().count(), text.lines().count());
+        assert!(!stripped.contains("comment\n"));
+        assert!(!stripped.contains("block"));
+        let value: serde_json::Value = serde_json::from_str(&stripped).unwrap();
+        assert_eq!(value["a"], 1);
+        assert_eq!(value["b"], "// not a comment");
+        assert_eq!(value["c"], "\" /* still a string");
+    }
+
+    #[test]
+    fn strips_unterminated_comments() {
+        assert_eq!(strip_json_comments("1 // end"), "1       ");
+        assert_eq!(strip_json_comments("1 /* open\nx"), "1        \n ");
+    }
+
+    #[test]
+    fn finds_key_lines() {
+        let text = r#"{
+  "rate_cost": 1,
+  "model": {
+    "provider": "openai",
+    "note": "\"provider\": in a string"
+  },
+  "model_catalog": {
+    "gpt-5": { "rate_cost": 2 },
+    "gpt-5-mini": {
+      "rate_cost": 1,
+      "colour": "red"
+    }
+  }
+}"#;
+        assert_eq!(key_line(text, &[], "rate_cost"), Some(2));
+        assert_eq!(key_line(text, &["model"], "provider"), Some(4));
+        assert_eq!(key_line(text, &["model_catalog"], "rate_cost"), Some(8));
+        assert_eq!(key_line(text, &["model_catalog", "gpt-5-mini"], "rate_cost"), Some(10));
+        assert_eq!(key_line(text, &["model_catalog", "gpt-5-mini"], "colour"), Some(11));
+        assert_eq!(key_line(text, &["missing"], "rate_cost"), None);
+        assert_eq!(key_line(text, &[], "red"), None);
+    }
+
+    #[test]
+    fn json_errors_point_at_the_line() {
+        let text = "{\n  \"a\": 1,\n  \"b\": ]\n}";
+        let e = serde_json::from_str::<serde_json::Value>(text).unwrap_err();
+        let message = json_error(Path::new("config.json"), text, &e);
+        let mut lines = message.lines();
+        let first = lines.next().unwrap();
+        assert!(first.starts_with("invalid config.json: "), "{message}");
+        assert!(first.ends_with("(line 3, column 8)"), "{message}");
+        assert!(!first.contains(" at line "), "{message}");
+        assert_eq!(lines.next(), Some("    3 |   \"b\": ]"));
+        assert_eq!(lines.next(), Some("      |        ^"));
+    }
+
+    #[test]
+    fn reports_unknown_keys_with_lines() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        std::io::Write::write_all(
+            &mut file,
+            br#"{
+  // comments are allowed
+  "assistant_name": "observer",
+  "assitant_name": "typo",
+  "model": { "provider": "openai", "temprature": 1 },
+  "model_catalog": {
+    "gpt-5": { "rate_cost": 6, "vison": true }
+  }
+}"#,
+        )
+        .unwrap();
+        let cfg = FileConfig::read(file.path()).unwrap().unwrap();
+        let path = file.path().display();
+        assert_eq!(
+            cfg.unknown_keys,
+            vec![
+                format!("{path}:4: unknown key assitant_name"),
+                format!("{path}:5: unknown key model.temprature"),
+                format!("{path}:7: unknown key model_catalog.gpt-5.vison"),
+            ]
+        );
+        let catalog = cfg.model_catalog.unwrap();
+        assert_eq!(catalog["gpt-5"].settings.rate_cost, Some(6));
+        assert_eq!(catalog["gpt-5"].settings.vision, None);
+    }
+}
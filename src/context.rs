@@ -1,12 +1,24 @@
-use std::{collections::HashMap, sync::{Arc, RwLock}};
+use std::{fs, path::Path, sync::{Arc, RwLock}, time::Duration};
 
 use kurosabi::context::ContextMiddleware;
-use log::info;
+use log::{debug, error, info, warn};
 use openai_dive::v1::api::Client as OpenAIClient;
 use wk_371tti_net_crawler::Client as ScraperClient;
-use serenity::{Client as DiscordClient, all::GatewayIntents};
+use serenity::{Client as DiscordClient, all::{ChannelId, GatewayIntents, GuildId, UserId}};
+use tokio::{sync::Mutex, time::sleep};
 
-use crate::{channel::ChatContexts, commands::{clear, disable, enable, model, ping, rate_config, set_system_prompt, tex_expr}, config::{Config, ModelProvider}, events::event_handler, lmclient::{LMClient, LMTool}, tools, user::UserContexts};
+use crate::{approval::Approvals, channel::{self, ChatContexts}, commands::{clear, deep_search, disable, enable, guild_config, judge, model, ping, rate_config, reload_config, set_system_prompt, tex_expr, usage}, config::{CONFIG_PATH, Config, ModelProvider, Models, SharedConfig}, events::event_handler, guild::GuildSettingsStore, lmclient::LMClient, tool_output::ToolOutputs, tools::{self, registry::SharedTools}, user::UserContexts};
+
+/// config.json の更新を確かめる間隔
+const CONFIG_WATCH_INTERVAL: Duration = Duration::from_secs(2);
+/// 有効かどうかでスラッシュコマンドや Web のルートが変わるツール (起動時にしか反映されない)
+const STARTUP_GATED_TOOLS: &[&str] = &["latex_expr_render", "judge", tools::browsing_worker::TOOL_NAME, tools::web_deploy::TOOL_NAME];
+/// 設定の読み直しを直列にする (監視とコマンドが同時に走らないように)
+static RELOAD_LOCK: Mutex<()> = Mutex::const_new(());
+/// チャンネルのデータを保存する間隔 (落ちても失うのはこの間の分だけ)
+const CHAT_CONTEXTS_SAVE_INTERVAL: Duration = Duration::from_secs(5 * 60);
+/// 定期保存と終了時の保存が同じ一時ファイルに同時に書かないように
+static SAVE_LOCK: Mutex<()> = Mutex::const_new(());
 
 /// 全体共有コンテキスト
 /// Arcで実装されてるのでcloneは単に参照カウントの増加
@@ -16,14 +28,20 @@ pub struct ObserverContext {
     pub lm_client: Arc<LMClient>,
     /// ヘッドレスブラウザのクライアント
     pub scraper: Arc<ScraperClient>,
-    /// 設定
-    pub config: Arc<Config>,
+    /// 設定 (config.json の読み直しで差し替わる)
+    pub config: Arc<SharedConfig>,
     /// チャットデータのプール
     pub chat_contexts: Arc<ChatContexts>,
     /// ユーザーデータのプール
     pub user_contexts: Arc<UserContexts>,
-    /// ツールの定義
-    pub tools: Arc<HashMap<String, Box<dyn LMTool>>>,
+    /// ギルドごとの設定 (未設定の項目は config を使う)
+    pub guild_settings: Arc<GuildSettingsStore>,
+    /// ツールの定義 (ツールの設定が変わると組み立て直す)
+    pub tools: Arc<SharedTools>,
+    /// 大きすぎるツール出力の退避先
+    pub tool_outputs: Arc<ToolOutputs>,
+    /// ツール呼び出しの承認待ち
+    pub approvals: Arc<Approvals>,
     /// discordクライアント
     pub discord_client: Arc<DiscordContextWrapper>,
 }
@@ -37,6 +55,10 @@ impl DiscordContextWrapper {
     pub fn open(&self) -> Arc<DisabledContextWrapperInner> {
         self.inner.read().expect("RWlock").clone().expect("inisializing").clone()
     }
+    /// 接続前 (オフラインの `observer chat` など) は None
+    pub fn try_open(&self) -> Option<Arc<DisabledContextWrapperInner>> {
+        self.inner.read().expect("RWlock").clone()
+    }
     pub fn lazy() -> DiscordContextWrapper {
         DiscordContextWrapper {
             inner: RwLock::new(None),
@@ -55,13 +77,12 @@ pub struct DisabledContextWrapperInner {
 }
 
 impl ObserverContext {
-    pub async fn new() -> ObserverContext {
-        let config = Config::new();
+    pub async fn new(config: Config) -> ObserverContext {
 
         // ツールの定義
         let lm_client = match config.model_provider {
             ModelProvider::OpenAI => {
-                let mut openai = OpenAIClient::new(config.main_model_api_key.clone());
+                let mut openai = OpenAIClient::new(config.main_model_api_key.expose().to_string());
                 // config の値は末尾に / が付いていることがあるので吸収
                 let endpoint = config.main_model_endpoint.trim_end_matches('/');
                 openai.set_base_url(endpoint);
@@ -70,37 +91,153 @@ impl ObserverContext {
             ModelProvider::GeminiAIStudio => {
                 LMClient::new_gemini(
                     config.main_model_endpoint.clone(),
-                    config.main_model_api_key.clone(),
+                    config.main_model_api_key.expose().to_string(),
                     config.main_model_name.clone(),
                 )
             }
         };
-        let tools: HashMap<String, Box<dyn LMTool>> = vec![
-            Box::new(tools::get_time::GetTime::new()) as Box<dyn LMTool>,
-            Box::new(tools::browser::Browser::new()) as Box<dyn LMTool>,
-            Box::new(tools::discord::DiscordTool::new()) as Box<dyn LMTool>,
-            // (無効化) キャプチャサーバ未構築のため LaTeXレンダリングは無効
-            // Box::new(tools::latex::LatexExprRenderTool::new()) as Box<dyn LMTool>,
-        ]
-        .into_iter()
-        .map(|tool| (tool.name(), tool))
-        .collect();
+        // config の `tools` を元に組み立てる (依存が足りないものはログを出して外れる)
+        let tools = tools::registry::build_tools(&config).await;
+
+        // 前回の終了時に保存した会話
+        let chat_contexts = ChatContexts::new(config.system_prompt.clone());
+        match channel::load_saved(&config.chat_contexts_path) {
+            Ok(saved) => {
+                if !saved.is_empty() {
+                    info!("chat contexts: restored {} channel(s) from {}", saved.len(), config.chat_contexts_path);
+                }
+                chat_contexts.restore(saved);
+            }
+            Err(e) => {
+                // 終了時の保存で上書きして消さないように退避しておく
+                let backup = Path::new(&config.chat_contexts_path).with_extension("json.broken");
+                error!("chat contexts: {e}, moved it to {} and starting empty", backup.display());
+                if let Err(e) = fs::rename(&config.chat_contexts_path, &backup) {
+                    error!("chat contexts: cannot move {}: {e}", config.chat_contexts_path);
+                }
+            }
+        }
 
         ObserverContext {
-            lm_client: Arc::new(lm_client),
+            lm_client: Arc::new(lm_client.with_max_steps(config.max_use_tool_count + 1)),
             scraper: Arc::new(ScraperClient::new(&config.scraper_base_url)),
-            config: Arc::new(config.clone()),
-            chat_contexts: Arc::new(ChatContexts::new(config.system_prompt.clone())),
+            config: Arc::new(SharedConfig::new(config.clone())),
+            chat_contexts: Arc::new(chat_contexts),
             user_contexts: Arc::new(UserContexts::new()),
-            tools: Arc::new(tools),
+            guild_settings: Arc::new(GuildSettingsStore::load(&config.guild_settings_path)),
+            tools: Arc::new(SharedTools::new(tools)),
+            tool_outputs: Arc::new(ToolOutputs::new(config.tool_output_max_chars, config.tool_output_cache_size)),
+            approvals: Arc::new(Approvals::new()),
             discord_client: Arc::new(DiscordContextWrapper::lazy()),
         }
     }
 
+    /// ユーザーが使うモデル (/model で選んでいなければサーバかグローバルの既定値)
+    pub fn user_model(&self, user_id: UserId, guild_id: Option<GuildId>) -> Models {
+        let default = self.guild_settings.get(guild_id).default_model(&self.config.get());
+        self.user_contexts.get_or_create(user_id).model_or(default)
+    }
+
+    /// チャンネルで BOT が有効か (/enable /disable していなければサーバかグローバルの既定値)
+    pub fn is_channel_enabled(&self, channel_id: ChannelId, guild_id: Option<GuildId>) -> bool {
+        let default = self.guild_settings.get(guild_id).default_enabled(&self.config.get());
+        self.chat_contexts.is_enabled(channel_id, default)
+    }
+
+    /// config.json を読み直して、動いたまま差し替えられるものを反映する
+    /// 戻り値は変わった項目 (項目名, 再起動が必要か)
+    /// 読めない・壊れているときは今の設定のまま Err を返す
+    pub async fn reload_config(&self) -> Result<Vec<(&'static str, bool)>, String> {
+        let _guard = RELOAD_LOCK.lock().await;
+        let running = self.config.get();
+        let mut new = Config::reload()?;
+        let mut changes = running.diff(&new);
+        if changes.is_empty() {
+            return Ok(changes);
+        }
+        new.keep_startup_values(&running);
+
+        if running.tools != new.tools {
+            let old_tools = self.tools.get();
+            let rebuilt = tools::registry::build_tools(&new).await;
+            if STARTUP_GATED_TOOLS.iter().any(|name| old_tools.contains_key(*name) != rebuilt.contains_key(*name)) {
+                changes.push(("tools (slash commands and web routes)", true));
+            }
+            self.tools.set(rebuilt);
+        }
+        if running.system_prompt != new.system_prompt {
+            self.chat_contexts.set_default_system_prompt(new.system_prompt.clone());
+        }
+        self.config.set(new);
+
+        for (name, needs_restart) in &changes {
+            if *needs_restart {
+                warn!("config: {} changed, restart to apply it", name);
+            } else {
+                info!("config: {} reloaded", name);
+            }
+        }
+        Ok(changes)
+    }
+
+    /// config.json の更新を見張って読み直す
+    /// (ファイルが消えたときは何もしない)
+    pub fn watch_config(&self) {
+        let ob_ctx = self.clone();
+        tokio::spawn(async move {
+            let modified = || fs::metadata(CONFIG_PATH).and_then(|m| m.modified()).ok();
+            let mut last = modified();
+            loop {
+                sleep(CONFIG_WATCH_INTERVAL).await;
+                let current = modified();
+                if current == last {
+                    continue;
+                }
+                last = current;
+                if current.is_none() {
+                    continue;
+                }
+                // 書き込みの途中を読まないように少し待つ
+                sleep(Duration::from_millis(300)).await;
+                if let Err(e) = ob_ctx.reload_config().await {
+                    warn!("config: reload failed, keeping the current config: {}", e);
+                }
+            }
+        });
+    }
+
     pub async fn shutdown(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         info!("Shutting down ObserverContext...");
+        let count = self.save_chat_contexts().await?;
+        info!("chat contexts: saved {} channel(s) to {}", count, self.config.get().chat_contexts_path);
         Ok(())
     }
+
+    /// チャンネルのデータを `chat_contexts_path` に書き出す (戻り値は保存したチャンネル数)
+    pub async fn save_chat_contexts(&self) -> Result<usize, String> {
+        let _guard = SAVE_LOCK.lock().await;
+        let path = self.config.get().chat_contexts_path.clone();
+        let saved = self.chat_contexts.snapshot();
+        let count = saved.len();
+        tokio::task::spawn_blocking(move || channel::write_saved(&path, &saved))
+            .await
+            .map_err(|e| format!("cannot save chat contexts: {e}"))??;
+        Ok(count)
+    }
+
+    /// チャンネルのデータを定期的に保存する (強制終了されても失うのは直近の分だけ)
+    pub fn autosave_chat_contexts(&self) {
+        let ob_ctx = self.clone();
+        tokio::spawn(async move {
+            loop {
+                sleep(CHAT_CONTEXTS_SAVE_INTERVAL).await;
+                match ob_ctx.save_chat_contexts().await {
+                    Ok(count) => debug!("chat contexts: saved {} channel(s)", count),
+                    Err(e) => error!("chat contexts: periodic save failed: {}", e),
+                }
+            }
+        });
+    }
 }
 
 #[async_trait::async_trait]
@@ -109,18 +246,32 @@ impl ContextMiddleware<ObserverContext> for ObserverContext {
         // 主にdiscordクライアントの初期化 初期化にctxが必要なのでctxが初期化されてから実行されるようにここ
         info!("Starting Discord bot...");
         let ob_ctx = c.clone();
+        let mut commands = vec![
+            ping(),  // ここにコマンドを追加
+            enable(),
+            clear(),
+            disable(),
+            model(),
+            rate_config(),
+            set_system_prompt(),
+            reload_config(),
+            guild_config(),
+            usage(),
+        ];
+        // LaTeX ツールが使えるときだけ /tex_expr を出す
+        if c.tools.get().contains_key("latex_expr_render") {
+            commands.push(tex_expr());
+        }
+        // 調査用サブエージェントが使えるときだけ /deep_search を出す
+        if c.tools.get().contains_key(tools::browsing_worker::TOOL_NAME) {
+            commands.push(deep_search());
+        }
+        if c.tools.get().contains_key("judge") {
+            commands.push(judge());
+        }
         let framework = poise::Framework::builder()
             .options(poise::FrameworkOptions {
-                commands: vec![
-                    ping(),  // ここにコマンドを追加
-                    enable(),
-                    clear(),
-                    disable(),
-                    model(),
-                    // tex_expr(), // キャプチャサーバ未構築のため無効化
-                    rate_config(),
-                    set_system_prompt(),
-                ],
+                commands,
                 // prefix の設定（!ping とか）
                 prefix_options: poise::PrefixFrameworkOptions {
                     prefix: Some("!".into()),
@@ -148,13 +299,14 @@ impl ContextMiddleware<ObserverContext> for ObserverContext {
 
             
         let intents =
-            GatewayIntents::GUILD_MESSAGES
+            GatewayIntents::GUILDS // チャンネル・スレッド・ロールをキャッシュするため
+            | GatewayIntents::GUILD_MESSAGES
             | GatewayIntents::DIRECT_MESSAGES
             | GatewayIntents::GUILD_MESSAGE_REACTIONS // (必要なら)
             | GatewayIntents::MESSAGE_CONTENT;
 
 
-        let discord_client = DiscordClient::builder(c.config.discord_token.clone(), intents)
+        let discord_client = DiscordClient::builder(c.config.get().discord_token.expose(), intents)
             .framework(framework);
 
         tokio::spawn(async move {
@@ -0,0 +1,156 @@
+//! `/deep_search` のバックグラウンドジョブ
+//! browsing_worker で調査し、途中経過をスレッドに流して最後にレポートを投稿する
+//! 通常の応答と違って `timeout_millis` には縛られない (ジョブ専用の上限を持つ)
+
+use std::{collections::HashSet, sync::{Arc, LazyLock, Mutex}, time::{Duration, Instant}};
+
+use log::{error, info};
+use serenity::all::{ChannelId, CreateAttachment, CreateMessage, GuildId, Http, UserId};
+use tokio::sync::{Semaphore, SemaphorePermit, mpsc};
+
+use crate::{config::Config, context::ObserverContext, lmclient::ToolInvoker, tools::browsing_worker::{self, BrowsingWorker}};
+
+/// 途中経過を投稿する最短の間隔
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(3);
+/// 途中経過を投稿する最大件数 (スレッドが埋まらないように)
+const MAX_PROGRESS_POSTS: usize = 40;
+/// これより長いレポートは .md で添付して、本文には要約だけ載せる
+const INLINE_REPORT_CHARS: usize = 1800;
+/// 添付にしたときに本文に載せる要約の最大文字数
+const SUMMARY_CHARS: usize = 1500;
+/// ジョブ全体の上限の既定値
+const DEFAULT_JOB_TIMEOUT_SECS: u64 = 30 * 60;
+/// 1ジョブにかかるレートリミットのコストの既定値 (サブエージェントが何十手も回すので重め)
+const DEFAULT_RATE_COST: u64 = 20;
+/// 全体で同時に走らせるジョブの数
+const MAX_RUNNING_JOBS: usize = 2;
+
+static JOB_SLOTS: Semaphore = Semaphore::const_new(MAX_RUNNING_JOBS);
+/// ジョブが走っているユーザー (1人 1件まで)
+static RUNNING_USERS: LazyLock<Mutex<HashSet<UserId>>> = LazyLock::new(|| Mutex::new(HashSet::new()));
+
+/// 1ジョブにかかるレートリミットのコスト (`tools.browsing_worker.deep_search_rate_cost`)
+pub fn rate_cost(config: &Config) -> u64 {
+    config.tool(browsing_worker::TOOL_NAME).u64_setting("deep_search_rate_cost").unwrap_or(DEFAULT_RATE_COST)
+}
+
+/// 走っているジョブの枠 (drop で空く)
+pub struct JobSlot {
+    user_id: UserId,
+    _permit: SemaphorePermit<'static>,
+}
+
+impl JobSlot {
+    /// ユーザーのジョブが走っておらず、全体の枠が空いていれば取る
+    pub fn acquire(user_id: UserId) -> Result<JobSlot, String> {
+        let mut running = RUNNING_USERS.lock().unwrap_or_else(|e| e.into_inner());
+        if running.contains(&user_id) {
+            return Err("you already have a deep search running".to_string());
+        }
+        let permit = JOB_SLOTS
+            .try_acquire()
+            .map_err(|_| "too many deep searches are running, try again later".to_string())?;
+        running.insert(user_id);
+        Ok(JobSlot { user_id, _permit: permit })
+    }
+}
+
+impl Drop for JobSlot {
+    fn drop(&mut self) {
+        RUNNING_USERS.lock().unwrap_or_else(|e| e.into_inner()).remove(&self.user_id);
+    }
+}
+
+/// 途中経過のうちスレッドに流すもの (生成中のトークン数などは流さない)
+fn is_milestone(state: &str) -> bool {
+    state.starts_with("Executing tool") || state.starts_with("Writing the report") || state.starts_with("Thinking... (gemini step")
+}
+
+/// 先頭から段落単位で `limit` 文字まで取り出す (1段落目が長すぎるときは途中で切る)
+fn summary_of(report: &str, limit: usize) -> String {
+    let mut summary = String::new();
+    for paragraph in report.split("\n\n") {
+        let paragraph = paragraph.trim();
+        if paragraph.is_empty() {
+            continue;
+        }
+        let len = summary.chars().count() + paragraph.chars().count() + 2;
+        if len > limit {
+            break;
+        }
+        if !summary.is_empty() {
+            summary.push_str("\n\n");
+        }
+        summary.push_str(paragraph);
+    }
+    if summary.is_empty() {
+        summary = report.chars().take(limit.saturating_sub(1)).collect::<String>() + "…";
+    }
+    summary
+}
+
+/// 調査ジョブを走らせて結果を `channel_id` (ふつうはスレッド) に投稿する
+/// コマンドの応答とは切り離して tokio::spawn で動かす前提
+/// `slot` はジョブが終わるまで持っておく
+pub async fn run_job(ob_ctx: ObserverContext, http: Arc<Http>, channel_id: ChannelId, guild_id: Option<GuildId>, user_id: UserId, question: String, slot: JobSlot) {
+    let _slot = slot;
+    let start = Instant::now();
+    let config = ob_ctx.config.get();
+    let tool_cfg = config.tool(browsing_worker::TOOL_NAME);
+    let worker = BrowsingWorker::from_config(&tool_cfg, &config).with_guild(&ob_ctx.guild_settings.get(guild_id));
+    let job_timeout = Duration::from_secs(tool_cfg.u64_setting("job_timeout_secs").unwrap_or(DEFAULT_JOB_TIMEOUT_SECS));
+    info!("deep_search: {} asked {:?}", user_id, question);
+
+    // 途中経過をスレッドに流す
+    let (state_tx, mut state_rx) = mpsc::channel::<String>(100);
+    let progress_http = http.clone();
+    let progress = tokio::spawn(async move {
+        let mut last_post: Option<Instant> = None;
+        let mut posted = 0;
+        while let Some(state) = state_rx.recv().await {
+            if !is_milestone(&state) || posted >= MAX_PROGRESS_POSTS {
+                continue;
+            }
+            if last_post.is_some_and(|at| at.elapsed() < PROGRESS_INTERVAL) {
+                continue;
+            }
+            let state: String = state.chars().take(300).collect();
+            channel_id.say(&progress_http, format!("-# {state}")).await.ok();
+            last_post = Some(Instant::now());
+            posted += 1;
+        }
+    });
+
+    let invoker = ToolInvoker { channel_id, user_id };
+    let result = tokio::time::timeout(job_timeout, worker.research(&question, None, &ob_ctx, Some(state_tx), Some(invoker))).await;
+    progress.abort();
+
+    let report = match result {
+        Ok(Ok(report)) => report,
+        Ok(Err(e)) => {
+            error!("deep_search failed: {}", e);
+            channel_id.say(&http, format!("<@{user_id}> error: deep search failed: {e}")).await.ok();
+            return;
+        }
+        Err(_) => {
+            channel_id
+                .say(&http, format!("<@{user_id}> error: deep search timed out after {}s", job_timeout.as_secs()))
+                .await
+                .ok();
+            return;
+        }
+    };
+
+    let footer = format!("-# Deep search done in {}s, model: {}", start.elapsed().as_secs(), worker.model());
+    let message = if report.chars().count() <= INLINE_REPORT_CHARS {
+        CreateMessage::new().content(format!("<@{user_id}>\n{report}\n{footer}"))
+    } else {
+        let summary = summary_of(&report, SUMMARY_CHARS);
+        CreateMessage::new()
+            .content(format!("<@{user_id}>\n{summary}\n-# The full report ({} chars) is attached.\n{footer}", report.chars().count()))
+            .add_file(CreateAttachment::bytes(report.into_bytes(), "deep_search_report.md"))
+    };
+    if let Err(e) = channel_id.send_message(&http, message).await {
+        error!("deep_search: failed to post the report: {}", e);
+    }
+}
@@ -1,12 +1,12 @@
-use std::{error::Error, time::{Duration, Instant}};
+use std::{error::Error, sync::Arc, time::{Duration, Instant}};
 
 use log::{debug, info};
 use openai_dive::v1::resources::response::{request::{ContentInput, ContentItem, ImageDetailLevel, InputMessage}, response::Role};
-use serenity::all::{ActivityData, CreateMessage, EditMessage, FullEvent, Message};
+use serenity::{all::{ActivityData, ComponentInteraction, CreateAllowedMentions, CreateInteractionResponse, CreateInteractionResponseMessage, CreateMessage, EditMessage, FullEvent, Message}, futures::future::join_all};
 use tokio::{sync::mpsc, time::sleep};
 
 
-use crate::{commands::log_err, config::ModelProvider, context::ObserverContext, lmclient::LMContext};
+use crate::{approval::{APPROVAL_PREFIX, ApprovalDecision}, attachment, commands::log_err, config::ModelProvider, context::ObserverContext, lmclient::{LMContext, ToolInvoker}, rate_limit::{self, Decision, RateLimit, Usage}, tools::{self, browsing_worker::{self, BrowsingWorker}, image_captioner::is_image_attachment}};
 
 
 /// イベントハンドラ
@@ -37,6 +37,12 @@ pub async fn event_handler(
             debug!("Reaction added: {:?} by user {:?}", add_reaction.emoji, add_reaction.user_id);
             handle_emoji_reaction(add_reaction, data).await?;
         }
+        // ボタンなどのコンポーネント操作
+        FullEvent::InteractionCreate { interaction } => {
+            if let Some(component) = interaction.as_message_component() {
+                handle_component_interaction(ctx, component, data).await?;
+            }
+        }
 
         _ => { /* 他のイベントは無視 */ }
     }
@@ -81,6 +87,86 @@ async fn handle_emoji_reaction(
 }
 
 
+/// コンポーネント操作の処理
+/// 今はツール承認ボタンだけ
+async fn handle_component_interaction(
+    ctx: &serenity::client::Context,
+    component: &ComponentInteraction,
+    ob_context: &ObserverContext,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let custom_id = component.data.custom_id.as_str();
+    if !custom_id.starts_with(APPROVAL_PREFIX) {
+        return Ok(());
+    }
+
+    let user_id = component.user.id;
+    let is_admin = ob_context.config.get().admin_users.contains(&user_id.get());
+
+    let response = match ob_context.approvals.resolve(custom_id, user_id, is_admin) {
+        Ok(decision) => {
+            debug!("Approval {} resolved: {:?}", custom_id, decision);
+            let verdict = if matches!(decision, ApprovalDecision::Approved(_)) { "Approved" } else { "Denied" };
+            CreateInteractionResponse::UpdateMessage(
+                CreateInteractionResponseMessage::new()
+                    .content(format!("{}\n-# {} by <@{}>", component.message.content, verdict, user_id))
+                    .components(vec![])
+                    .allowed_mentions(CreateAllowedMentions::new()),
+            )
+        }
+        Err(e) => CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new()
+                .content(format!("Err: {}", e))
+                .ephemeral(true),
+        ),
+    };
+
+    component.create_response(&ctx.http, response).await?;
+    Ok(())
+}
+
+/// 画像を直接見られないモデル向けに、image_captioner で説明文にするか
+/// しないとき (ビジョン対応モデル、ツール無効、サーバで禁止、auto: false) は false
+fn needs_captions(msg: &Message, ob_context: &ObserverContext) -> bool {
+    let config = ob_context.config.get();
+    if !ob_context.guild_settings.get(msg.guild_id).allows_tool("image_captioner") {
+        return false;
+    }
+    let can_see_images = match config.model_provider {
+        ModelProvider::OpenAI => config.model_supports_vision(&ob_context.user_model(msg.author.id, msg.guild_id)),
+        ModelProvider::GeminiAIStudio => false,
+    };
+    !can_see_images
+        && config.tool("image_captioner").bool_setting("auto").unwrap_or(true)
+        && ob_context.tools.get().contains_key("image_captioner")
+}
+
+/// 画像 1枚の説明にかかるレートリミットのコスト (`tools.image_captioner.rate_cost`)
+fn caption_cost(ob_context: &ObserverContext) -> u64 {
+    ob_context.config.get().tool("image_captioner").u64_setting("rate_cost").unwrap_or(1)
+}
+
+/// image_captioner で画像を説明文にする
+async fn caption_images(image_urls: &[String], ob_context: &ObserverContext) -> Option<String> {
+    let tools = ob_context.tools.get();
+    let captioner = tools.get("image_captioner")?;
+
+    let results = join_all(image_urls.iter().map(|url| {
+        captioner.execute(serde_json::json!({ "image_url": url }), ob_context.clone())
+    }))
+    .await;
+
+    let captions = image_urls
+        .iter()
+        .zip(results)
+        .map(|(url, result)| match result {
+            Ok(caption) => format!("[image] {}\n[image description] {}", url, caption),
+            Err(e) => format!("[image] {} (failed to describe: {})", url, e),
+        })
+        .collect::<Vec<String>>()
+        .join("\n");
+    Some(captions)
+}
+
 /// メッセージを受け取ったときの処理
 async fn handle_message(
     ctx: &serenity::client::Context,
@@ -98,6 +184,7 @@ async fn handle_message(
     }
 
     let is_mentioned = msg.mentions_user_id(bot_id);
+    let enabled = ob_context.is_channel_enabled(channel_id, msg.guild_id);
 
     let content = serde_json::json!({
         "user": msg.author.name,
@@ -111,26 +198,70 @@ async fn handle_message(
     let image_urls: Vec<String> = msg
         .attachments
         .iter()
-        .filter(|att| {
-            // content_type が "image/..." なら画像とみなす
-            if let Some(ct) = &att.content_type {
-                ct.starts_with("image/")
-            } else {
-                // 拡張子で雑に判定する fallback
-                att.filename.ends_with(".png")
-                    || att.filename.ends_with(".jpg")
-                    || att.filename.ends_with(".jpeg")
-                    || att.filename.ends_with(".webp")
-            }
-        })
+        // content_type が "image/..." なら画像とみなす (無ければ拡張子で判定)
+        .filter(|att| is_image_attachment(att.content_type.as_deref(), &att.filename))
         .map(|att| att.url.clone())
         .collect();
 
+    // モデルが画像を見られないとき (Gemini は URL しか渡らない) は画像を説明文に置き換える
+    // 説明はサブモデルを呼ぶので、BOT が応答するとき (メンションされてレートリミットを通ったとき) だけ作る
+    let describe_images = enabled && !image_urls.is_empty() && needs_captions(msg, ob_context);
+    let replying = is_mentioned && enabled;
+
+    // 途中で設定が読み直されても 1回の応答の間は同じ設定を使う
+    let config = ob_context.config.get();
+    // サーバごとの上書き (未設定の項目は config の値)
+    let guild = ob_context.guild_settings.get(msg.guild_id);
+    let user_id = msg.author.id;
+    let user_ctx = ob_context.user_contexts.get_or_create(user_id);
+    let model = user_ctx.model_or(guild.default_model(&config));
+
+    // レートリミット (計算は rate_limit.rs)、画像の説明の分もここで払う
+    let mut rate_limited_until = None;
+    if replying {
+        let model_cost = config.model_cost(&model);
+        let captions_cost = if describe_images { caption_cost(ob_context) * image_urls.len() as u64 } else { 0 };
+        let now = rate_limit::now();
+        match RateLimit::new(guild.rate_limit(&config)).check(user_ctx.rate_line, model_cost + captions_cost, now) {
+            Decision::Allowed { rate_line } => {
+                ob_context.user_contexts.set_rate_line(user_id, rate_line);
+                ob_context.user_contexts.record_usage(user_id, Usage { at: now, model: model.to_string(), cost: model_cost });
+                if captions_cost > 0 {
+                    ob_context.user_contexts.record_usage(user_id, Usage { at: now, model: "image_captioner".to_string(), cost: captions_cost });
+                }
+            }
+            Decision::Limited { retry_at } => rate_limited_until = Some(retry_at),
+        }
+    }
+
+    let captions = if describe_images && replying && rate_limited_until.is_none() {
+        caption_images(&image_urls, ob_context).await
+    } else if describe_images {
+        // 説明を作らないときは URL だけ残す (必要ならモデルが image_captioner を呼ぶ)
+        Some(image_urls.iter().map(|url| format!("[image] {url}")).collect::<Vec<_>>().join("\n"))
+    } else {
+        None
+    };
+
+    // テキスト・コード・PDF などの添付は中身を取り出して本文に足す
+    let attachments = if enabled {
+        attachment::ingest(&msg.attachments, &config).await
+    } else {
+        None
+    };
+    let content = match attachments {
+        Some(attachments) => format!("{}\n{}", content, attachments),
+        None => content,
+    };
+
     let mut lm_context = LMContext::new();
 
     if image_urls.is_empty() && content.is_empty() {
         // 画像もテキストも無いなら無視
         return Ok(());
+    } else if let Some(captions) = captions {
+        debug!("Adding captioned image message to context in channel {}, content: {}", channel_id, content);
+        lm_context.add_text(format!("{}\n{}", content, captions), Role::User);
     } else if image_urls.is_empty() {
         debug!("Adding text message to context in channel {}, content: {}", channel_id, content);
         lm_context.add_text(content.clone(), Role::User);
@@ -160,42 +291,18 @@ async fn handle_message(
     ob_context.chat_contexts.marge(channel_id, &lm_context);
 
     if is_mentioned {
-        if !ob_context.chat_contexts.is_enabled(channel_id) {
+        if !enabled {
             msg.channel_id
                 .send_message(&ctx.http, CreateMessage::new().content("info: Chat context is disabled in this channel."))
                 .await?;
             return Ok(());
         }
-        let user_id = msg.author.id;
-        let user_ctx = ob_context.user_contexts.get_or_create(user_id);
-        let model = user_ctx.main_model.clone();
-
-        let model_cost = model.rate_cost();
-        let sec_per_cost = ob_context.config.rate_limit_sec_per_cost; // コストあたりの秒数
-        let window_size = ob_context.config.rale_limit_window_size; // バースト許容量
-        let user_line = user_ctx.rate_line;
-        
-        // レートリミットの計算
-        let time_stamp = chrono::Utc::now().timestamp() as u64;
-        let limit_line = window_size + time_stamp;
-        let add_line = model_cost * sec_per_cost;
-        let added_user_line = if user_line == 0 {
-            0 // リミットレスアカウント
-        } else if user_line < time_stamp {
-            time_stamp + add_line
-        } else {
-            user_line + add_line
-        };
-
-        if added_user_line > limit_line {
-            let wait_sec = added_user_line - limit_line;
-            let allow_ts = time_stamp + wait_sec;
+        if let Some(retry_at) = rate_limited_until {
             msg.channel_id
-                .send_message(&ctx.http, CreateMessage::new().content(format!("Err: rate limit - try again after <t:{}:R>", allow_ts)))
+                .send_message(&ctx.http, CreateMessage::new().content(format!("Err: rate limit - try again after <t:{}:R> (see /usage)", retry_at)))
                 .await?;
             return Ok(());
         }
-        ob_context.user_contexts.set_rate_line(user_id, added_user_line);
 
         let typing_ctx = ctx.clone();
 
@@ -206,14 +313,27 @@ async fn handle_message(
             }
         });
         let mut context = ob_context.chat_contexts.get_or_create(channel_id);
-        let tools = ob_context.tools.clone();
+        let tools = if guild.allowed_tools.is_some() {
+            let mut tools = tools::registry::subset(&ob_context.tools.get(), |name| guild.allows_tool(name));
+            // サブエージェントにもサーバで許可したツールだけを渡す
+            if tools.contains_key(browsing_worker::TOOL_NAME) {
+                let worker = BrowsingWorker::from_config(&config.tool(browsing_worker::TOOL_NAME), &config).with_guild(&guild);
+                tools.insert(browsing_worker::TOOL_NAME.to_string(), Arc::new(worker));
+            }
+            Arc::new(tools)
+        } else {
+            ob_context.tools.get()
+        };
 
-        let system_prompt = format!{
+        let mut system_prompt = format!{
             "{}\n current channel_id: {}, channel_name: {}",
-            ob_context.chat_contexts.get_system_prompt(channel_id),
+            ob_context.chat_contexts.get_system_prompt(channel_id, guild.system_prompt.as_deref()),
             msg.channel_id, 
             msg.channel_id.name(&ctx.http).await.unwrap_or("None".to_string()),
         };
+        if let Some(language) = guild.language(&config) {
+            system_prompt.push_str(&format!("\nRespond in {language} unless the user clearly writes in another language."));
+        }
 
         context.add_message(InputMessage {
             role: Role::System,
@@ -234,9 +354,11 @@ async fn handle_message(
 
         let mut result = None;
 
-        let timeout_duration = Duration::from_millis(ob_context.config.timeout_millis);
+        let invoker = ToolInvoker { channel_id, user_id };
+
+        let timeout_duration = Duration::from_millis(config.timeout_millis);
 
-        let model_params = match ob_context.config.model_provider {
+        let model_params = match config.model_provider {
             ModelProvider::OpenAI => Some(model.to_parameter()),
             ModelProvider::GeminiAIStudio => None,
         };
@@ -245,7 +367,7 @@ async fn handle_message(
         tokio::select! {
             biased;
 
-            r = ob_context.lm_client.generate_response(ob_context.clone(), &context, Some(2000), Some(tools), Some(state_tx), Some(delta_tx), model_params) => {
+            r = ob_context.lm_client.generate_response(ob_context.clone(), &context, Some(config.model_generate_max_tokens), Some(tools), Some(state_tx), Some(delta_tx), model_params, Some(invoker)) => {
                 if let Err(e) = &r {
                     log_err("Error generating response", e.as_ref());
                     thinking_msg
@@ -301,9 +423,9 @@ async fn handle_message(
         // タイピング通知停止
         typing_handle.abort();
 
-        let model_label = match ob_context.config.model_provider {
-            ModelProvider::OpenAI => ob_context.user_contexts.get_or_create(user_id).main_model.to_string(),
-            ModelProvider::GeminiAIStudio => ob_context.config.main_model_name.clone(),
+        let model_label = match config.model_provider {
+            ModelProvider::OpenAI => model.to_string(),
+            ModelProvider::GeminiAIStudio => config.main_model_name.clone(),
         };
 
         // 「Thinking...」を削除して最終回答を表示
@@ -1,9 +1,18 @@
-use std::time::Duration;
+use std::{collections::HashMap, sync::Arc, time::Duration};
 
 use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
 
-use crate::{context::ObserverContext, lmclient::{LMContext, LMTool}};
+use crate::{context::ObserverContext, lmclient::{LMContext, LMTool, ToolInvoker, run_tool}};
+
+/// `GeminiClient::generate` の生成設定
+pub struct GenerateOptions {
+    pub max_output_tokens: u32,
+    /// function calling を含めて回す最大ステップ数
+    pub max_steps: usize,
+    pub tools: Option<Arc<HashMap<String, Arc<dyn LMTool>>>>,
+    pub invoker: Option<ToolInvoker>,
+}
 
 #[derive(Clone)]
 pub struct GeminiClient {
@@ -32,18 +41,18 @@ impl GeminiClient {
         &self,
         ob_ctx: ObserverContext,
         lm_context: &LMContext,
-        max_output_tokens: u32,
-        tools: Option<std::sync::Arc<std::collections::HashMap<String, Box<dyn LMTool>>>>,
+        options: GenerateOptions,
         mut state_send: impl FnMut(String),
     ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        let GenerateOptions { max_output_tokens, max_steps, tools, invoker } = options;
         let (system_instruction, base_contents) = convert_context(lm_context);
 
         let mut accumulated_text = String::new();
         let mut extra_contents: Vec<Content> = Vec::new();
 
-        // function calling ループ（最大10手）
-        for step in 0..10 {
-            state_send(format!("Thinking... (gemini step {}/{})", step + 1, 10));
+        // function calling ループ（最大 max_steps 手）
+        for step in 0..max_steps {
+            state_send(format!("Thinking... (gemini step {}/{})", step + 1, max_steps));
 
             let mut contents = Vec::with_capacity(base_contents.len() + extra_contents.len());
             contents.extend(base_contents.clone());
@@ -123,7 +132,7 @@ impl GeminiClient {
 
                 let output = if let Some(tool_map) = tools.as_ref() {
                     if let Some(tool) = tool_map.get(&name) {
-                        tool.execute(args, ob_ctx.clone()).await.unwrap_or_else(|e| format!("Error: {}", e))
+                        run_tool(tool.as_ref(), args, &ob_ctx, invoker).await.unwrap_or_else(|e| format!("Error: {}", e))
                     } else {
                         format!("Error: tool not found: {}", name)
                     }
@@ -0,0 +1,152 @@
+//! ギルド (サーバ) ごとの設定
+//! config.json は全サーバ共通なので、サーバごとに変えたい項目だけをここで上書きする
+//! 未設定の項目は config.json の値を使う
+
+use std::{collections::BTreeMap, fs, path::PathBuf, sync::Mutex};
+
+use dashmap::DashMap;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use serenity::all::{GuildId, RoleId};
+
+use crate::config::{Config, Models};
+
+/// ギルドごとの上書き (None の項目はグローバルの設定を使う)
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GuildSettings {
+    /// 既定のシステムプロンプト (チャンネルごとの /set_system_prompt が優先)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub system_prompt: Option<String>,
+    /// /model で選んでいない人のモデル
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default_model: Option<String>,
+    /// モデルに使わせるツール (None なら有効なもの全部)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allowed_tools: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rate_limit_window_size: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rate_limit_sec_per_cost: Option<u64>,
+    /// このサーバの設定を変えられるロール (サーバの管理権限を持つ人は常に変えられる)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub admin_roles: Vec<u64>,
+    /// 応答の言語
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    /// チャンネルで BOT を最初から有効にするか
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default_enabled: Option<bool>,
+}
+
+impl GuildSettings {
+    /// /model で選んでいない人のモデル
+    /// サーバの設定が今のモデル一覧に無ければグローバルの既定値
+    pub fn default_model(&self, config: &Config) -> Models {
+        self.default_model
+            .as_deref()
+            .and_then(|name| name.parse::<Models>().ok())
+            .filter(|m| config.models().contains(m))
+            .unwrap_or_else(|| config.default_model.clone())
+    }
+
+    /// レートリミットの (バースト許容量, コストあたりの秒数)
+    /// サーバの設定はグローバルの設定より厳しくはできても緩くはできない
+    pub fn rate_limit(&self, config: &Config) -> (u64, u64) {
+        let (window_size, sec_per_cost) = (config.rale_limit_window_size, config.rate_limit_sec_per_cost);
+        (
+            self.rate_limit_window_size.map_or(window_size, |w| w.min(window_size)),
+            self.rate_limit_sec_per_cost.map_or(sec_per_cost, |s| s.max(sec_per_cost)),
+        )
+    }
+
+    pub fn language<'a>(&'a self, config: &'a Config) -> Option<&'a str> {
+        self.language.as_deref().or(config.language.as_deref())
+    }
+
+    pub fn default_enabled(&self, config: &Config) -> bool {
+        self.default_enabled.unwrap_or(config.default_enabled)
+    }
+
+    /// モデルにこのツールを使わせるか
+    pub fn allows_tool(&self, name: &str) -> bool {
+        self.allowed_tools.as_ref().is_none_or(|tools| tools.iter().any(|t| t == name))
+    }
+
+    /// このロールのどれかを持っていればサーバの設定を変えられるか
+    pub fn has_admin_role(&self, roles: &[RoleId]) -> bool {
+        roles.iter().any(|role| self.admin_roles.contains(&role.get()))
+    }
+}
+
+/// ギルドごとの設定のストア
+/// 変更のたびに JSON ファイルへ書き出す
+pub struct GuildSettingsStore {
+    path: PathBuf,
+    settings: DashMap<GuildId, GuildSettings>,
+    /// 書き出しを 1つずつにする (同じ一時ファイルに同時に書かない、古い内容で上書きしない)
+    save_lock: Mutex<()>,
+}
+
+impl GuildSettingsStore {
+    /// ファイルから読む (無ければ空)
+    /// 壊れているときは上書きで消さないように退避してから空で始める
+    pub fn load(path: impl Into<PathBuf>) -> GuildSettingsStore {
+        let path = path.into();
+        let settings = DashMap::new();
+        match fs::read_to_string(&path) {
+            Ok(text) => match serde_json::from_str::<BTreeMap<u64, GuildSettings>>(&text) {
+                Ok(map) => {
+                    for (guild_id, s) in map {
+                        settings.insert(GuildId::new(guild_id), s);
+                    }
+                    info!("guild settings: loaded {} guild(s) from {}", settings.len(), path.display());
+                }
+                Err(e) => {
+                    let backup = path.with_extension("json.broken");
+                    error!("guild settings: invalid {}: {e}, moved it to {}", path.display(), backup.display());
+                    if let Err(e) = fs::rename(&path, &backup) {
+                        error!("guild settings: cannot move {}: {e}", path.display());
+                    }
+                }
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => warn!("guild settings: cannot read {}: {e}", path.display()),
+        }
+        GuildSettingsStore { path, settings, save_lock: Mutex::new(()) }
+    }
+
+    /// ギルドの設定 (DM など、ギルドの外では空の設定)
+    pub fn get(&self, guild_id: Option<GuildId>) -> GuildSettings {
+        guild_id
+            .and_then(|id| self.settings.get(&id).map(|s| s.clone()))
+            .unwrap_or_default()
+    }
+
+    /// 設定を書き換えて保存する
+    pub fn update(&self, guild_id: GuildId, f: impl FnOnce(&mut GuildSettings)) -> Result<GuildSettings, String> {
+        let updated = {
+            let mut entry = self.settings.entry(guild_id).or_default();
+            f(&mut entry);
+            entry.clone()
+        };
+        if updated == GuildSettings::default() {
+            self.settings.remove(&guild_id);
+        }
+        self.save()?;
+        Ok(updated)
+    }
+
+    /// 一時ファイルに書いてから置き換える (途中で落ちても壊れないように)
+    fn save(&self) -> Result<(), String> {
+        let _guard = self.save_lock.lock().unwrap_or_else(|e| e.into_inner());
+        let map: BTreeMap<u64, GuildSettings> = self.settings.iter().map(|e| (e.key().get(), e.value().clone())).collect();
+        let json = serde_json::to_string_pretty(&map).map_err(|e| format!("cannot serialize guild settings: {e}"))?;
+        if let Some(dir) = self.path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            fs::create_dir_all(dir).map_err(|e| format!("cannot create {}: {e}", dir.display()))?;
+        }
+        let tmp = self.path.with_extension("json.tmp");
+        fs::write(&tmp, json).map_err(|e| format!("cannot write {}: {e}", tmp.display()))?;
+        fs::rename(&tmp, &self.path).map_err(|e| format!("cannot write {}: {e}", self.path.display()))
+    }
+}
@@ -0,0 +1,564 @@
+//! 競プロの提出をテストケースで判定する (AtCoder 風)
+//! 実行はすべて sandbox 経由で行う
+
+use std::{collections::BTreeMap, io::{Cursor, Read}, sync::LazyLock, time::Duration};
+
+use serde::Serialize;
+use tokio::sync::Semaphore;
+
+use crate::{config::ToolConfig, net_guard, sandbox::{Build, Language, Limits, Sandbox}};
+
+/// 同時に判定する提出の数
+static JUDGE_SLOTS: Semaphore = Semaphore::const_new(2);
+/// zip の中のファイル数の上限
+const MAX_ZIP_ENTRIES: usize = 2000;
+/// zip を展開した合計サイズの上限
+const MAX_UNZIPPED_BYTES: usize = 128 * 1024 * 1024;
+/// 添付ファイルを取りに行ってよいホスト (Discord の CDN)
+const ATTACHMENT_HOSTS: &[&str] = &["cdn.discordapp.com", "media.discordapp.net"];
+
+/// 添付ファイル用のクライアント (リダイレクト先も Discord の CDN に限る)
+static DOWNLOAD_CLIENT: LazyLock<reqwest::Client> = LazyLock::new(|| {
+    net_guard::client(reqwest::Client::builder().timeout(Duration::from_secs(30)), check_attachment_url)
+});
+
+/// テストケース 1つ
+#[derive(Debug, Clone)]
+pub struct TestCase {
+    pub name: String,
+    pub input: String,
+    pub expected: String,
+}
+
+/// 判定結果 (AtCoder の表記のまま出す)
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Verdict {
+    AC,
+    WA,
+    TLE,
+    RE,
+    CE,
+}
+
+impl Verdict {
+    pub fn emoji(&self) -> &'static str {
+        match self {
+            Verdict::AC => "🟩",
+            Verdict::WA => "🟥",
+            Verdict::TLE => "🟧",
+            Verdict::RE => "🟪",
+            Verdict::CE => "⬛",
+        }
+    }
+}
+
+/// ケースごとの結果
+#[derive(Debug, Clone, Serialize)]
+pub struct CaseResult {
+    pub name: String,
+    pub verdict: Verdict,
+    pub time_ms: u64,
+    /// WA の差分や RE の stderr など
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+/// 提出全体の結果
+#[derive(Debug, Clone, Serialize)]
+pub struct JudgeReport {
+    pub language: &'static str,
+    /// 全体の判定 (最初に AC 以外になったもの)
+    pub verdict: Verdict,
+    pub max_time_ms: u64,
+    pub time_limit_ms: u64,
+    pub memory_limit_mb: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub compile_error: Option<String>,
+    pub cases: Vec<CaseResult>,
+}
+
+/// 判定の条件
+#[derive(Debug, Clone)]
+pub struct JudgeSettings {
+    pub time_limit_ms: u64,
+    pub memory_mb: u64,
+    /// 実数の許容誤差 (絶対または相対)、None なら文字列として比較
+    pub float_tolerance: Option<f64>,
+    /// 受け付けるケース数の上限
+    pub max_cases: usize,
+}
+
+impl JudgeSettings {
+    /// config.json の `tools.judge` から既定値を読む
+    pub fn from_config(cfg: &ToolConfig) -> JudgeSettings {
+        JudgeSettings {
+            time_limit_ms: cfg.u64_setting("time_limit_ms").unwrap_or(2000),
+            memory_mb: cfg.u64_setting("memory_mb").unwrap_or(1024),
+            float_tolerance: None,
+            max_cases: cfg.u64_setting("max_cases").unwrap_or(50) as usize,
+        }
+    }
+}
+
+/// インラインのテストケースを読む
+/// ケースは `---` だけの行で区切り、入力と期待出力は `===` だけの行で区切る
+/// 改行を含められない場所 (スラッシュコマンドの引数) 向けに、本物の改行が無ければ `\n` を改行として扱う
+pub fn parse_inline(text: &str) -> Result<Vec<TestCase>, String> {
+    let text = if text.contains('\n') { text.to_string() } else { text.replace("\\n", "\n") };
+    let text = text.replace("\r\n", "\n");
+
+    let mut cases = Vec::new();
+    for (i, block) in split_on_marker(&text, "---").into_iter().enumerate() {
+        if block.trim().is_empty() {
+            continue;
+        }
+        let parts = split_on_marker(&block, "===");
+        if parts.len() != 2 {
+            return Err(format!("case {}: separate input and expected output with a line containing only `===`", i + 1));
+        }
+        cases.push(TestCase {
+            name: format!("case{}", cases.len() + 1),
+            input: ensure_trailing_newline(&parts[0]),
+            expected: parts[1].clone(),
+        });
+    }
+    if cases.is_empty() {
+        return Err("no test cases found".to_string());
+    }
+    Ok(cases)
+}
+
+/// 行単位で marker だけの行を区切りとして分割する
+fn split_on_marker(text: &str, marker: &str) -> Vec<String> {
+    let mut parts = vec![String::new()];
+    for line in text.split_inclusive('\n') {
+        if line.trim() == marker {
+            parts.push(String::new());
+        } else {
+            parts.last_mut().unwrap().push_str(line);
+        }
+    }
+    parts
+}
+
+fn ensure_trailing_newline(s: &str) -> String {
+    let s = s.trim_start_matches('\n');
+    if s.ends_with('\n') { s.to_string() } else { format!("{s}\n") }
+}
+
+/// zip のテストケースを読む
+/// `in/NAME` と `out/NAME` (AtCoder の配布形式)、または `NAME.in` と `NAME.out` / `NAME.ans` を組にする
+/// ヘッダのサイズは信用せず、展開しながらケース数と合計サイズの上限を確かめる
+pub fn parse_zip(bytes: &[u8], max_cases: usize) -> Result<Vec<TestCase>, String> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(bytes)).map_err(|e| format!("invalid zip: {e}"))?;
+    if archive.len() > MAX_ZIP_ENTRIES {
+        return Err(format!("too many files in zip ({}, max {MAX_ZIP_ENTRIES})", archive.len()));
+    }
+
+    let mut inputs: BTreeMap<String, String> = BTreeMap::new();
+    let mut outputs: BTreeMap<String, String> = BTreeMap::new();
+    let mut remaining = MAX_UNZIPPED_BYTES;
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i).map_err(|e| format!("invalid zip entry: {e}"))?;
+        if file.is_dir() {
+            continue;
+        }
+        let path = file.name().replace('\\', "/");
+        // macOS の圧縮が入れるゴミは飛ばす
+        if path.starts_with("__MACOSX/") || path.rsplit('/').next().is_some_and(|n| n.starts_with('.')) {
+            continue;
+        }
+        let Some((kind, name)) = classify_entry(&path) else { continue };
+        let cases = match kind {
+            EntryKind::Input => inputs.len(),
+            EntryKind::Output => outputs.len(),
+        };
+        if cases >= max_cases {
+            return Err(format!("too many test cases in zip (max {max_cases})"));
+        }
+        let mut content = Vec::new();
+        (&mut file)
+            .take(remaining as u64 + 1)
+            .read_to_end(&mut content)
+            .map_err(|e| format!("{path}: cannot unzip ({e})"))?;
+        if content.len() > remaining {
+            return Err(format!("test cases are too large when unzipped (max {MAX_UNZIPPED_BYTES} bytes)"));
+        }
+        remaining -= content.len();
+        let content = String::from_utf8(content).map_err(|_| format!("{path}: not a UTF-8 text file"))?;
+        match kind {
+            EntryKind::Input => inputs.insert(name, content),
+            EntryKind::Output => outputs.insert(name, content),
+        };
+    }
+
+    let mut cases = Vec::new();
+    for (name, input) in inputs {
+        let Some(expected) = outputs.remove(&name) else {
+            return Err(format!("no expected output for input `{name}`"));
+        };
+        cases.push(TestCase { name, input, expected });
+    }
+    if let Some(name) = outputs.keys().next() {
+        return Err(format!("no input for expected output `{name}`"));
+    }
+    if cases.is_empty() {
+        return Err("no test cases found in zip (expected in/ and out/ directories, or NAME.in / NAME.out files)".to_string());
+    }
+    Ok(cases)
+}
+
+enum EntryKind {
+    Input,
+    Output,
+}
+
+fn classify_entry(path: &str) -> Option<(EntryKind, String)> {
+    let mut components = path.rsplit('/');
+    let file = components.next()?;
+    let dir = components.next().unwrap_or("");
+    match dir {
+        "in" | "input" | "inputs" => return Some((EntryKind::Input, strip_ext(file, &[".in", ".txt"]))),
+        "out" | "output" | "outputs" | "ans" => return Some((EntryKind::Output, strip_ext(file, &[".out", ".ans", ".txt"]))),
+        _ => {}
+    }
+    if let Some(name) = file.strip_suffix(".in") {
+        return Some((EntryKind::Input, name.to_string()));
+    }
+    if let Some(name) = file.strip_suffix(".out").or_else(|| file.strip_suffix(".ans")) {
+        return Some((EntryKind::Output, name.to_string()));
+    }
+    None
+}
+
+fn strip_ext(file: &str, exts: &[&str]) -> String {
+    exts.iter()
+        .find_map(|ext| file.strip_suffix(ext))
+        .unwrap_or(file)
+        .to_string()
+}
+
+/// Discord の添付ファイルを上限付きでダウンロードする
+/// モデルが渡した URL なので、Discord の CDN 以外 (BOT のいるマシンの中など) には繋がない
+pub async fn download(url: &str, max_bytes: usize) -> Result<Vec<u8>, String> {
+    let parsed = reqwest::Url::parse(url).map_err(|e| format!("invalid URL {url}: {e}"))?;
+    check_attachment_url(&parsed)?;
+    let resp = net_guard::get(&DOWNLOAD_CLIENT, url)
+        .await?
+        .error_for_status()
+        .map_err(|e| format!("failed to download {url}: {e}"))?;
+    net_guard::read_body(resp, max_bytes).await
+}
+
+fn check_attachment_url(url: &reqwest::Url) -> Result<(), String> {
+    let host = url.host_str().unwrap_or("").to_ascii_lowercase();
+    if url.scheme() != "https" || !ATTACHMENT_HOSTS.contains(&host.as_str()) {
+        return Err(format!("only Discord attachment URLs can be downloaded ({})", ATTACHMENT_HOSTS.join(", ")));
+    }
+    Ok(())
+}
+
+/// 出力を比較する (空白の違いは無視、実数は許容誤差つき)
+/// 一致しなければ最初に違った箇所の説明を返す
+pub fn compare_output(actual: &str, expected: &str, float_tolerance: Option<f64>) -> Result<(), String> {
+    let actual_tokens: Vec<&str> = actual.split_whitespace().collect();
+    let expected_tokens: Vec<&str> = expected.split_whitespace().collect();
+
+    for (i, (a, e)) in actual_tokens.iter().zip(&expected_tokens).enumerate() {
+        if a == e {
+            continue;
+        }
+        if let (Some(tol), Ok(av), Ok(ev)) = (float_tolerance, a.parse::<f64>(), e.parse::<f64>())
+            && ((av - ev).abs() <= tol || (av - ev).abs() <= tol * ev.abs())
+        {
+            continue;
+        }
+        return Err(format!("token {}: expected `{}`, got `{}`", i + 1, truncate(e, 40), truncate(a, 40)));
+    }
+    if actual_tokens.len() != expected_tokens.len() {
+        return Err(format!(
+            "expected {} tokens, got {}",
+            expected_tokens.len(),
+            actual_tokens.len()
+        ));
+    }
+    Ok(())
+}
+
+fn truncate(s: &str, max: usize) -> String {
+    if s.chars().count() <= max {
+        s.to_string()
+    } else {
+        format!("{}…", s.chars().take(max).collect::<String>())
+    }
+}
+
+/// 提出をコンパイルして全ケースを実行する
+pub async fn judge(
+    sandbox: &Sandbox,
+    language: Language,
+    source: &str,
+    cases: &[TestCase],
+    settings: &JudgeSettings,
+) -> Result<JudgeReport, String> {
+    if cases.len() > settings.max_cases {
+        return Err(format!("too many test cases ({}, max {})", cases.len(), settings.max_cases));
+    }
+
+    let _slot = JUDGE_SLOTS.acquire().await.map_err(|e| e.to_string())?;
+
+    let mut report = JudgeReport {
+        language: language.name(),
+        verdict: Verdict::AC,
+        max_time_ms: 0,
+        time_limit_ms: settings.time_limit_ms,
+        memory_limit_mb: settings.memory_mb,
+        compile_error: None,
+        cases: Vec::new(),
+    };
+
+    let program = match sandbox.build(language, source).await? {
+        Build::Ready(program) => program,
+        Build::CompileError(out) => {
+            report.verdict = Verdict::CE;
+            report.compile_error = Some(truncate(&format!("{}{}", out.stdout, out.stderr), 1500));
+            return Ok(report);
+        }
+    };
+
+    // CPU 時間は TL を切り上げた秒数、実時間は起動の遅い環境を考えて余裕を持たせる
+    // TLE かどうかは計測した実時間と TL で決める
+    let limits = Limits {
+        cpu_secs: settings.time_limit_ms.div_ceil(1000) + 1,
+        memory_mb: settings.memory_mb,
+        wall_millis: settings.time_limit_ms * 2 + 1000,
+        output_bytes: sandbox.limits.output_bytes.max(4 * 1024 * 1024),
+        file_size_mb: sandbox.limits.file_size_mb,
+        processes: sandbox.limits.processes,
+    };
+    let time_limit = Duration::from_millis(settings.time_limit_ms);
+
+    for case in cases {
+        let out = program.run_with_limits(Some(case.input.as_bytes()), &limits).await?;
+        let time_ms = out.elapsed.as_millis() as u64;
+
+        let (verdict, detail) = if out.timed_out || out.cpu_exceeded() || out.elapsed > time_limit {
+            (Verdict::TLE, None)
+        } else if !out.success() {
+            let stderr = out.stderr.trim();
+            let detail = if stderr.is_empty() {
+                out.status_text()
+            } else {
+                format!("{}: {}", out.status_text(), truncate(stderr.lines().last().unwrap_or(stderr), 200))
+            };
+            (Verdict::RE, Some(detail))
+        } else if out.stdout_truncated {
+            (Verdict::WA, Some("output too large".to_string()))
+        } else {
+            match compare_output(&out.stdout, &case.expected, settings.float_tolerance) {
+                Ok(()) => (Verdict::AC, None),
+                Err(diff) => (Verdict::WA, Some(diff)),
+            }
+        };
+
+        if report.verdict == Verdict::AC && verdict != Verdict::AC {
+            report.verdict = verdict;
+        }
+        report.max_time_ms = report.max_time_ms.max(time_ms);
+        report.cases.push(CaseResult { name: case.name.clone(), verdict, time_ms, detail });
+    }
+
+    Ok(report)
+}
+
+impl JudgeReport {
+    /// Discord に貼る用のテキスト
+    pub fn to_discord_text(&self) -> String {
+        let passed = self.cases.iter().filter(|c| c.verdict == Verdict::AC).count();
+        let mut text = format!(
+            "{} **{:?}** ({}) — {}/{} AC, max {} ms (TL {} ms, ML {} MiB)\n",
+            self.verdict.emoji(),
+            self.verdict,
+            self.language,
+            passed,
+            self.cases.len(),
+            self.max_time_ms,
+            self.time_limit_ms,
+            self.memory_limit_mb,
+        );
+        if let Some(ce) = &self.compile_error {
+            text.push_str(&format!("```\n{}\n```", ce.replace("```", "`\u{200b}``")));
+            return text;
+        }
+        text.push_str("```\n");
+        for case in &self.cases {
+            let line = match &case.detail {
+                Some(detail) => format!("{:<4}{:>6} ms  {}  {}\n", format!("{:?}", case.verdict), case.time_ms, case.name, detail),
+                None => format!("{:<4}{:>6} ms  {}\n", format!("{:?}", case.verdict), case.time_ms, case.name),
+            };
+            // Discord の 2000 文字制限に収める
+            if text.len() + line.len() > 1900 {
+                text.push_str("…\n");
+                break;
+            }
+            text.push_str(&line);
+        }
+        text.push_str("```");
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use zip::{CompressionMethod, ZipWriter, write::SimpleFileOptions};
+
+    use super::*;
+
+    fn zip_of(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+        for (name, content) in files {
+            writer.start_file(*name, options).unwrap();
+            writer.write_all(content).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    fn summary(cases: &[TestCase]) -> Vec<(&str, &str, &str)> {
+        cases.iter().map(|c| (c.name.as_str(), c.input.as_str(), c.expected.as_str())).collect()
+    }
+
+    #[test]
+    fn inline_cases_with_real_newlines() {
+        let cases = parse_inline("1 2\n===\n3\n---\n5 6\r\n===\r\n11\r\n").unwrap();
+        assert_eq!(summary(&cases), [("case1", "1 2\n", "3\n"), ("case2", "5 6\n", "11\n")]);
+    }
+
+    #[test]
+    fn inline_cases_with_escaped_newlines() {
+        // スラッシュコマンドの引数は 1行なので `\n` で書く
+        let cases = parse_inline(r"3\n1 2 3\n===\n6\n---\n1\n5\n===\n5").unwrap();
+        assert_eq!(summary(&cases), [("case1", "3\n1 2 3\n", "6\n"), ("case2", "1\n5\n", "5")]);
+    }
+
+    #[test]
+    fn inline_input_gets_trailing_newline_and_empty_blocks_are_skipped() {
+        let cases = parse_inline("---\n\n42\n===\n42\n---\n  \n").unwrap();
+        assert_eq!(summary(&cases), [("case1", "42\n", "42\n")]);
+    }
+
+    #[test]
+    fn inline_errors() {
+        assert!(parse_inline("1 2\n3\n").unwrap_err().contains("case 1"));
+        assert!(parse_inline("1\n===\n1\n===\n1\n").unwrap_err().contains("==="));
+        assert!(parse_inline("1\n===\n1\n---\n2\n").unwrap_err().contains("case 2"));
+        assert_eq!(parse_inline("\n---\n").unwrap_err(), "no test cases found");
+    }
+
+    #[test]
+    fn zip_in_out_directories() {
+        let bytes = zip_of(&[
+            ("sample/in/01.txt", b"1\n"),
+            ("sample/out/01.txt", b"2\n"),
+            ("sample/in/02.txt", b"3\n"),
+            ("sample/out/02.txt", b"4\n"),
+            ("__MACOSX/sample/in/._01.txt", b"junk"),
+            ("sample/in/.DS_Store", b"junk"),
+            ("README.md", b"ignored"),
+        ]);
+        let cases = parse_zip(&bytes, 10).unwrap();
+        assert_eq!(summary(&cases), [("01", "1\n", "2\n"), ("02", "3\n", "4\n")]);
+    }
+
+    #[test]
+    fn zip_name_extensions() {
+        let bytes = zip_of(&[("a.in", b"1\n"), ("a.ans", b"1\n"), ("b.in", b"2\n"), ("b.out", b"2\n")]);
+        let cases = parse_zip(&bytes, 10).unwrap();
+        assert_eq!(summary(&cases), [("a", "1\n", "1\n"), ("b", "2\n", "2\n")]);
+    }
+
+    #[test]
+    fn zip_unpaired_files() {
+        let bytes = zip_of(&[("a.in", b"1\n")]);
+        assert!(parse_zip(&bytes, 10).unwrap_err().contains("no expected output for input `a`"));
+        let bytes = zip_of(&[("a.out", b"1\n")]);
+        assert!(parse_zip(&bytes, 10).unwrap_err().contains("no input for expected output `a`"));
+        let bytes = zip_of(&[("notes.txt", b"1\n")]);
+        assert!(parse_zip(&bytes, 10).unwrap_err().contains("no test cases found"));
+        assert!(parse_zip(b"not a zip", 10).unwrap_err().contains("invalid zip"));
+    }
+
+    #[test]
+    fn zip_non_utf8() {
+        let bytes = zip_of(&[("a.in", b"\xff\xfe"), ("a.out", b"1\n")]);
+        assert!(parse_zip(&bytes, 10).unwrap_err().contains("not a UTF-8 text file"));
+    }
+
+    #[test]
+    fn zip_case_limit() {
+        let names: Vec<(String, String)> = (0..4).map(|i| (format!("{i}.in"), format!("{i}.out"))).collect();
+        let files: Vec<(&str, &[u8])> = names
+            .iter()
+            .flat_map(|(i, o)| [(i.as_str(), b"1\n".as_slice()), (o.as_str(), b"1\n".as_slice())])
+            .collect();
+        let bytes = zip_of(&files);
+        assert_eq!(parse_zip(&bytes, 4).unwrap().len(), 4);
+        assert!(parse_zip(&bytes, 3).unwrap_err().contains("too many test cases in zip (max 3)"));
+    }
+
+    #[test]
+    fn zip_entry_limit() {
+        let names: Vec<String> = (0..=MAX_ZIP_ENTRIES).map(|i| format!("junk/{i}")).collect();
+        let files: Vec<(&str, &[u8])> = names.iter().map(|n| (n.as_str(), b"".as_slice())).collect();
+        let bytes = zip_of(&files);
+        let err = parse_zip(&bytes, 10).unwrap_err();
+        assert!(err.contains(&format!("too many files in zip ({}, max {MAX_ZIP_ENTRIES})", MAX_ZIP_ENTRIES + 1)), "{err}");
+    }
+
+    #[test]
+    fn zip_unzipped_size_limit() {
+        // 圧縮するとごく小さくなる 0 の並びで合計サイズの上限を 1 バイト超える
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        let options = SimpleFileOptions::default()
+            .compression_method(CompressionMethod::Deflated)
+            .compression_level(Some(1));
+        let chunk = vec![b'0'; 1024 * 1024];
+        writer.start_file("big.in", options).unwrap();
+        for _ in 0..MAX_UNZIPPED_BYTES / chunk.len() {
+            writer.write_all(&chunk).unwrap();
+        }
+        writer.start_file("big.out", options).unwrap();
+        writer.write_all(b"0").unwrap();
+        let bytes = writer.finish().unwrap().into_inner();
+        assert!(bytes.len() < MAX_UNZIPPED_BYTES / 100);
+        let err = parse_zip(&bytes, 10).unwrap_err();
+        assert!(err.contains("too large when unzipped"), "{err}");
+    }
+
+    #[test]
+    fn compare_ignores_whitespace_and_trailing_newline() {
+        assert!(compare_output("1 2\n3\n", "1 2\n3", None).is_ok());
+        assert!(compare_output("1  2\r\n3", "1 2\n3\n", None).is_ok());
+        assert!(compare_output("\n1\t2 3 \n\n", "1 2 3", None).is_ok());
+        assert!(compare_output("", "\n", None).is_ok());
+    }
+
+    #[test]
+    fn compare_reports_first_difference() {
+        assert_eq!(compare_output("1 2 4", "1 2 3", None).unwrap_err(), "token 3: expected `3`, got `4`");
+        assert_eq!(compare_output("1 2", "1 2 3", None).unwrap_err(), "expected 3 tokens, got 2");
+        assert_eq!(compare_output("1 2 3 4", "1 2 3", None).unwrap_err(), "expected 3 tokens, got 4");
+        assert!(compare_output("Yes", "yes", None).is_err());
+    }
+
+    #[test]
+    fn compare_float_tolerance() {
+        assert!(compare_output("0.3333333", "0.333333333", Some(1e-6)).is_ok());
+        assert!(compare_output("1000000.5", "1000000.0", Some(1e-6)).is_ok());
+        assert!(compare_output("0.34", "0.333333333", Some(1e-6)).is_err());
+        assert!(compare_output("0.3333333", "0.333333333", None).is_err());
+    }
+}
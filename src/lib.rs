@@ -1,9 +1,23 @@
+pub mod approval;
+pub mod attachment;
+pub mod check_config;
+pub mod cli;
 pub mod context;
 pub mod commands;
 pub mod config;
+pub mod deep_search;
 pub mod gemini;
+pub mod guild;
 pub mod lmclient;
+pub mod net_guard;
 pub mod channel;
 pub mod events;
 pub mod user;
+pub mod tex;
+pub mod rate_limit;
+pub mod sandbox;
+pub mod secret;
+pub mod site;
+pub mod judge;
+pub mod tool_output;
 pub mod tools;
\ No newline at end of file
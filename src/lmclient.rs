@@ -1,13 +1,22 @@
 use std::{collections::{HashMap, VecDeque}, sync::Arc};
 
-use log::{debug, error, info, warn};
+use log::{debug, error, info, trace, warn};
 use openai_dive::v1::{api::Client as OpenAIClient, resources::response::{items::{FunctionToolCall, FunctionToolCallOutput, InputItemStatus, ReasoningSummaryPart}, request::{ContentInput, ContentItem, ImageDetailLevel, InputItem, InputMessage, ResponseInput, ResponseInputItem, ResponseParametersBuilder}, response::{OutputContent, ResponseOutput, ResponseStreamEvent, Role}, shared::{ResponseTool, ResponseToolChoice}}};
 use serenity::futures::{StreamExt};
 use tokio::sync::mpsc;
 
-use crate::{config::Models, context::ObserverContext, gemini::GeminiClient};
+use serenity::all::{ChannelId, UserId};
+
+use crate::{approval::ApprovalDecision, config::{Config, Models}, context::ObserverContext, gemini::{GeminiClient, GenerateOptions}};
+
+/// 1回の応答で回す最大ステップ数の既定値
+const DEFAULT_MAX_STEPS: usize = 10;
+
+#[derive(Clone)]
 pub struct LMClient {
     backend: LMBackend,
+    /// ツール呼び出しを含めて 1回の応答で回す最大ステップ数
+    max_steps: usize,
 }
 
 #[derive(Clone)]
@@ -23,27 +32,38 @@ impl LMClient {
     pub fn new_openai(client: OpenAIClient) -> Self {
         Self {
             backend: LMBackend::OpenAI(client),
+            max_steps: DEFAULT_MAX_STEPS,
         }
     }
 
     pub fn new_gemini(base_url: String, api_key: String, model_name: String) -> Self {
         Self {
             backend: LMBackend::Gemini(GeminiClient::new(base_url, api_key, model_name)),
+            max_steps: DEFAULT_MAX_STEPS,
         }
     }
 
+    /// 1回の応答で回す最大ステップ数を変える (最後のステップではツールを使わせない)
+    pub fn with_max_steps(mut self, max_steps: usize) -> Self {
+        self.max_steps = max_steps.max(1);
+        self
+    }
+
     #[allow(clippy::too_many_arguments)]
     pub async fn generate_response(
         &self,
         ob_ctx: ObserverContext,
         lm_context: &LMContext,
         max_tokens: Option<u32>,
-        tools: Option<Arc<HashMap<String, Box<dyn LMTool>>>>,
+        tools: Option<Arc<HashMap<String, Arc<dyn LMTool>>>>,
         state_mpsc: Option<mpsc::Sender<String>>,
         delta_mpsc: Option<mpsc::Sender<String>>,
         parameters: Option<ResponseParametersBuilder>,
+        invoker: Option<ToolInvoker>,
     ) -> Result<LMContext, Box<dyn std::error::Error + Send + Sync>> {
-        debug!("Generating response with context: {:?}", lm_context);
+        // 会話の中身は trace だけに出す
+        debug!("Generating response with {} context item(s)", lm_context.buf.len());
+        trace!("Context: {:?}", lm_context);
 
         let tools = tools.unwrap_or_default();
         let max_tokens = max_tokens.unwrap_or(100);
@@ -61,9 +81,13 @@ impl LMClient {
                     }
                 };
 
-                let text = gemini
-                    .generate(ob_ctx, lm_context, max_tokens, Some(tools), |s| state_send(s))
-                    .await?;
+                let options = GenerateOptions {
+                    max_output_tokens: max_tokens,
+                    max_steps: self.max_steps,
+                    tools: Some(tools),
+                    invoker,
+                };
+                let text = gemini.generate(ob_ctx, lm_context, options, state_send).await?;
                 delta_send(text.clone());
 
                 let mut delta_context = LMContext::new();
@@ -99,9 +123,9 @@ impl LMClient {
 
                 let mut token_count = 0;
 
-                for i in 0..10 {
+                for i in 0..self.max_steps {
                     let context = lm_context.generate_context_with(&delta_context);
-                    debug!("Iteration {}: Generated context: {:?}", i, context);
+                    trace!("Iteration {}: Generated context: {:?}", i, context);
                     let parameters = per_parameters
                         .clone()
                         .input(context)
@@ -251,7 +275,7 @@ impl LMClient {
 
                         // ここでtoolを実行
                         if let Some(tool) = tools.get(&name) {
-                            let exec_result = tool.execute(v_args, ob_ctx.clone()).await;
+                            let exec_result = run_tool(tool.as_ref(), v_args, &ob_ctx, invoker).await;
                             debug!("Tool {} executed with result: {:?}", name, exec_result);
                             let output = match exec_result {
                                 Ok(res) => FunctionToolCallOutput {
@@ -275,7 +299,8 @@ impl LMClient {
                         delta_context.add_input_item(InputItem::FunctionToolCallOutput(output));
                     }
 
-                    if i == 8 {
+                    // 最後のステップはツールを使わせずに答えさせる
+                    if i + 2 == self.max_steps {
                         tool_choice = ResponseToolChoice::None;
                     }
                 }
@@ -435,5 +460,86 @@ pub trait LMTool: Send + Sync {
     fn json_schema(&self) -> serde_json::Value;
     fn description(&self) -> String;
     fn name(&self) -> String;
+    /// 大きすぎる出力を退避して切り詰める対象にするか
+    fn shrink_output(&self) -> bool {
+        true
+    }
+    /// 副作用のある呼び出しなら、承認メッセージに載せる説明を返す
+    /// Some を返すと実行前にユーザーの承認を待つ
+    fn approval_summary(&self, _args: &serde_json::Value) -> Option<String> {
+        None
+    }
+    /// 起動時の依存チェック
+    /// Err を返すとツールは登録されない (理由はログに出る)
+    async fn health_check(&self, _config: &Config) -> Result<(), String> {
+        Ok(())
+    }
     async fn execute(&self, args: serde_json::Value, ob_ctx: ObserverContext) -> Result<String, String>;
+    /// 呼び出し元 (チャンネルとユーザー) を使うツールはこちらを上書きする
+    async fn execute_for(&self, args: serde_json::Value, ob_ctx: ObserverContext, _invoker: Option<ToolInvoker>) -> Result<String, String> {
+        self.execute(args, ob_ctx).await
+    }
+}
+
+/// ツール呼び出しの発生元
+/// 承認メッセージの送り先と、承認できるユーザーの判定に使う
+#[derive(Debug, Clone, Copy)]
+pub struct ToolInvoker {
+    pub channel_id: ChannelId,
+    pub user_id: UserId,
+}
+
+/// ツールを実行して出力を後処理する
+/// 承認が必要な呼び出しは承認されるまで待ち、拒否されたらその旨を出力として返す
+/// 大きすぎる出力は ToolOutputs に退避され、先頭ページとハンドルに置き換わる
+pub async fn run_tool(
+    tool: &dyn LMTool,
+    args: serde_json::Value,
+    ob_ctx: &ObserverContext,
+    invoker: Option<ToolInvoker>,
+) -> Result<String, String> {
+    let name = tool.name();
+    let approval_summary = match ob_ctx.config.get().tool(&name).bool_setting("require_approval") {
+        Some(false) => None,
+        Some(true) => Some(
+            tool.approval_summary(&args)
+                .unwrap_or_else(|| format!("```json\n{}\n```", args)),
+        ),
+        None => tool.approval_summary(&args),
+    };
+
+    if let Some(summary) = approval_summary {
+        let invoker = invoker.ok_or_else(|| {
+            "This operation requires user approval, but there is no Discord user to ask.".to_string()
+        })?;
+        match ob_ctx.approvals.request(ob_ctx, invoker, &name, &summary).await? {
+            ApprovalDecision::Approved(user_id) => {
+                info!("Tool call {} approved by {}", name, user_id);
+            }
+            ApprovalDecision::Denied(user_id) => {
+                return Ok(serde_json::json!({
+                    "status": "denied",
+                    "tool": name,
+                    "decided_by": user_id.to_string(),
+                    "message": "The user denied this operation. Do not retry it unless they ask again.",
+                })
+                .to_string());
+            }
+            ApprovalDecision::TimedOut => {
+                return Ok(serde_json::json!({
+                    "status": "timed_out",
+                    "tool": name,
+                    "message": "Nobody approved this operation in time, so it was not executed.",
+                })
+                .to_string());
+            }
+        }
+    }
+
+    let output = tool.execute_for(args, ob_ctx.clone(), invoker).await?;
+    if tool.shrink_output() {
+        Ok(ob_ctx.tool_outputs.shrink(&name, output, invoker.map(|i| i.channel_id)))
+    } else {
+        Ok(output)
+    }
 } 
\ No newline at end of file
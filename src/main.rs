@@ -1,22 +1,66 @@
 use kurosabi::Kurosabi;
-use observer::context::ObserverContext;
-use std::{net::{Ipv4Addr, TcpListener}, process::ExitCode};
+use clap::Parser;
+use observer::{check_config, cli::{self, Cli, Command}, config::Config, context::ObserverContext, secret, site::{self, Site}, tools::web_deploy::{self, ArticleStore}};
+use std::{io::Write, net::{Ipv4Addr, TcpListener}, process::ExitCode};
+use tokio::signal::unix::{SignalKind, signal};
 
 #[tokio::main]
 async fn main() -> ExitCode {
     dotenv::dotenv().ok();
+    let command = Cli::parse().command.unwrap_or(Command::Run);
     // NOTE: serenity 等が `target=tracing::span` でスパンイベントを log に流すことがあり、
     // デフォルト `debug` だと `do_heartbeat;` / `recv_event;` などが大量に出て見づらくなる。
     // RUST_LOG を明示しない場合だけ、`tracing::span` を warn 以上に絞って抑制する。
-    env_logger::try_init_from_env(
-        env_logger::Env::default().default_filter_or("debug,tracing::span=warn"),
-    )
-    .ok();
+    // BOT 以外のサブコマンドは出力にログが混ざらないように warn 以上だけにする
+    let default_filter = match command {
+        Command::Run => "debug,tracing::span=warn",
+        _ => "warn",
+    };
+    // トークンや API キーがログに出ないように、書き出す前に伏せる
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(default_filter))
+        .format(|buf, record| {
+            let style = buf.default_level_style(record.level());
+            writeln!(
+                buf,
+                "[{} {style}{:<5}{style:#} {}] {}",
+                buf.timestamp(),
+                record.level(),
+                record.target(),
+                secret::scrub(&record.args().to_string()),
+            )
+        })
+        .try_init()
+        .ok();
+
+    // サブコマンドを省略したら BOT を起動する
+    match command {
+        Command::Run => run().await,
+        Command::CheckConfig => check_config::run(),
+        Command::ExportContext { channel, output } => cli::export_context(channel, output.as_deref()),
+        Command::ImportContext { channel, input, append } => cli::import_context(channel, input.as_deref(), append),
+        Command::Chat { model, channel } => cli::chat(model, channel).await,
+        Command::Replay { file, model } => cli::replay(&file, model).await,
+    }
+}
+
+/// Web サーバと Discord BOT を起動する
+async fn run() -> ExitCode {
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("error: {e}\nhint: run `observer check-config` to see the resolved configuration");
+            return ExitCode::FAILURE;
+        }
+    };
 
     // コンテキスト初期化
-    let ob_ctx = ObserverContext::new().await;
+    let ob_ctx = ObserverContext::new(config).await;
 
-    let config = ob_ctx.config.clone();
+    let config = ob_ctx.config.get();
+    // config.json を書き換えたら読み直す
+    ob_ctx.watch_config();
+    // 強制終了に備えてチャンネルのデータを定期的に保存する
+    ob_ctx.autosave_chat_contexts();
 
     let bind_ip = Ipv4Addr::from(config.web_server_host);
     if let Err(e) = TcpListener::bind((bind_ip, config.web_server_port)) {
@@ -36,10 +80,129 @@ hint: use an unprivileged port (>= 1024), or set WEB_SERVER_PORT / config.json w
 
     let mut kurosabi = Kurosabi::with_context(ob_ctx.clone());
 
-    kurosabi.get("/latex_expr_render", |mut c| async move {
-        c.res.html(include_str!("../data/latex_render.html"));
-        c
-    });
+    // 公開した記事の API (web_deploy_tool が有効なときだけ)
+    if ob_ctx.tools.get().contains_key(web_deploy::TOOL_NAME) {
+        kurosabi.get("/articles/:year/:month", |mut c| async move {
+            let store = ArticleStore::from_config(&c.c.config.get().tool(web_deploy::TOOL_NAME));
+            let year = c.req.path.get_field("year").unwrap_or_default();
+            let month = c.req.path.get_field("month").unwrap_or_default();
+            let page = c.req.path.get_query("page");
+            let per_page = c.req.path.get_query("per_page");
+            let body = web_deploy::list_articles_json(&store, &year, &month, page.as_deref(), per_page.as_deref());
+            c.res.json(&body);
+            c
+        });
+
+        kurosabi.get("/article/raw/:year/:month/:article", |mut c| async move {
+            let store = ArticleStore::from_config(&c.c.config.get().tool(web_deploy::TOOL_NAME));
+            let year = c.req.path.get_field("year").unwrap_or_default();
+            let month = c.req.path.get_field("month").unwrap_or_default();
+            let article = c.req.path.get_field("article").unwrap_or_default();
+            let article = urlencoding::decode(&article).map(|a| a.into_owned()).unwrap_or(article);
+            match store.read_at(&year, &month, &article) {
+                Some(content) => {
+                    c.res.text(&content);
+                }
+                None => {
+                    c.res.text("記事が見つかりません");
+                    c.res.set_status(404);
+                }
+            }
+            c
+        });
+
+        // 記事サイト
+        kurosabi.get("/blog", |mut c| async move {
+            let q = c.req.path.get_query("q");
+            let site = Site::from_config(&c.c.config.get());
+            match site.render(move |site| site.index_page(q.as_deref())).await {
+                Ok(html) => {
+                    c.res.html(&html);
+                }
+                Err(e) => {
+                    c.res.text(&e);
+                    c.res.set_status(500);
+                }
+            }
+            c
+        });
+
+        kurosabi.get("/blog/:year", |mut c| async move {
+            let site = Site::from_config(&c.c.config.get());
+            let year = c.req.path.get_field("year").unwrap_or_default();
+            match site.render(move |site| site.year_page(&year).ok_or_else(|| site.not_found_page())).await {
+                Ok(Ok(html)) => {
+                    c.res.html(&html);
+                }
+                Ok(Err(not_found)) => {
+                    c.res.html(&not_found);
+                    c.res.set_status(404);
+                }
+                Err(e) => {
+                    c.res.text(&e);
+                    c.res.set_status(500);
+                }
+            }
+            c
+        });
+
+        kurosabi.get("/blog/:year/:month", |mut c| async move {
+            let site = Site::from_config(&c.c.config.get());
+            let year = c.req.path.get_field("year").unwrap_or_default();
+            let month = c.req.path.get_field("month").unwrap_or_default();
+            match site.render(move |site| site.month_page(&year, &month).ok_or_else(|| site.not_found_page())).await {
+                Ok(Ok(html)) => {
+                    c.res.html(&html);
+                }
+                Ok(Err(not_found)) => {
+                    c.res.html(&not_found);
+                    c.res.set_status(404);
+                }
+                Err(e) => {
+                    c.res.text(&e);
+                    c.res.set_status(500);
+                }
+            }
+            c
+        });
+
+        kurosabi.get("/blog/:year/:month/:article", |mut c| async move {
+            let site = Site::from_config(&c.c.config.get());
+            let year = c.req.path.get_field("year").unwrap_or_default();
+            let month = c.req.path.get_field("month").unwrap_or_default();
+            let article = c.req.path.get_field("article").unwrap_or_default();
+            let article = urlencoding::decode(&article).map(|a| a.into_owned()).unwrap_or(article);
+            match site.render(move |site| site.article_page(&year, &month, &article).ok_or_else(|| site.not_found_page())).await {
+                Ok(Ok(html)) => {
+                    c.res.html(&html);
+                }
+                Ok(Err(not_found)) => {
+                    c.res.html(&not_found);
+                    c.res.set_status(404);
+                }
+                Err(e) => {
+                    c.res.text(&e);
+                    c.res.set_status(500);
+                }
+            }
+            c
+        });
+
+        kurosabi.get(site::FEED_PATH, |mut c| async move {
+            let site = Site::from_config(&c.c.config.get());
+            match site.render(|site| site.atom_feed()).await {
+                Ok(xml) => {
+                    c.res.text(&xml);
+                    c.res.header.set("Content-Type", "application/atom+xml; charset=utf-8");
+                }
+                Err(e) => {
+                    c.res.text(&e);
+                    c.res.set_status(500);
+                }
+            }
+            c
+        });
+    }
 
     let server = kurosabi
         .server()
@@ -50,6 +213,15 @@ hint: use an unprivileged port (>= 1024), or set WEB_SERVER_PORT / config.json w
 
     println!("server started. Press Ctrl-C to shutdown...");
 
+    // restart_bot.sh などの pkill (SIGTERM) でも Ctrl-C と同じく保存してから終わる
+    let mut terminate = match signal(SignalKind::terminate()) {
+        Ok(terminate) => Some(terminate),
+        Err(e) => {
+            eprintln!("cannot listen for SIGTERM: {}", e);
+            None
+        }
+    };
+
     tokio::select! {
         _ = server.run_async() => {
             println!("server stopped (run_async returned)");
@@ -57,6 +229,9 @@ hint: use an unprivileged port (>= 1024), or set WEB_SERVER_PORT / config.json w
         _ = tokio::signal::ctrl_c() => {
             println!("received Ctrl-C, shutting down server and browser engine...");
         }
+        Some(_) = async { terminate.as_mut()?.recv().await } => {
+            println!("received SIGTERM, shutting down server and browser engine...");
+        }
     }
 
     // サーバ停止後にエンジンもshutdown
@@ -0,0 +1,160 @@
+//! モデルやユーザーが選んだ URL を取りに行くときの制限
+//! BOT が動いているマシンの中 (localhost、LAN、クラウドのメタデータ) には繋がせない
+//! 名前は `PublicResolver` で解決した先を確かめ、IP を直接書いた URL とリダイレクトは `check_url` で確かめる
+
+use std::{net::{IpAddr, SocketAddr}, sync::Arc};
+
+use reqwest::{Client, ClientBuilder, Response, Url, dns::{Addrs, Name, Resolve, Resolving}, redirect};
+
+/// 追いかけるリダイレクトの回数
+const MAX_REDIRECTS: usize = 5;
+
+/// インターネット上のアドレスか
+pub fn is_public_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => {
+            let [a, b, ..] = v4.octets();
+            !(v4.is_private()
+                || v4.is_loopback()
+                || v4.is_link_local() // 169.254.169.254 (クラウドのメタデータ) もここ
+                || v4.is_unspecified()
+                || v4.is_broadcast()
+                || v4.is_multicast()
+                || v4.is_documentation()
+                || a == 0
+                || (a == 100 && (64..128).contains(&b)) // CGNAT
+                || (a == 198 && (b == 18 || b == 19)) // ベンチマーク用
+                || a >= 240)
+        }
+        IpAddr::V6(v6) => {
+            if let Some(v4) = v6.to_ipv4_mapped() {
+                return is_public_ip(IpAddr::V4(v4));
+            }
+            let first = v6.segments()[0];
+            !(v6.is_loopback()
+                || v6.is_unspecified()
+                || v6.is_multicast()
+                || (first & 0xfe00) == 0xfc00 // ユニークローカル
+                || (first & 0xffc0) == 0xfe80) // リンクローカル
+        }
+    }
+}
+
+/// スキームと、IP を直接書いたホストを確かめる (名前のホストは解決したときに確かめる)
+pub fn check_url(url: &Url) -> Result<(), String> {
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(format!("unsupported URL scheme `{}`", url.scheme()));
+    }
+    let host = url.host_str().ok_or_else(|| format!("{url} has no host"))?;
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    if let Ok(ip) = host.parse::<IpAddr>() {
+        if !is_public_ip(ip) {
+            return Err(format!("{host} is not a public address"));
+        }
+    } else if host.eq_ignore_ascii_case("localhost") || host.to_ascii_lowercase().ends_with(".localhost") {
+        return Err(format!("{host} is not a public address"));
+    }
+    Ok(())
+}
+
+/// 公開アドレスだけを返す名前解決
+/// 解決のたびに確かめるので、リダイレクト先や DNS の書き換えでも内側には繋がらない
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let host = name.as_str().to_string();
+        Box::pin(async move {
+            let public: Vec<SocketAddr> = tokio::net::lookup_host((host.as_str(), 0))
+                .await?
+                .filter(|addr| is_public_ip(addr.ip()))
+                .collect();
+            if public.is_empty() {
+                return Err(format!("{host} does not resolve to a public address").into());
+            }
+            Ok(Box::new(public.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// 内側のアドレスに繋がないクライアントを作る
+/// `check` はリダイレクトの各ホップでも呼ぶ (ツールごとのドメイン制限など)
+pub fn client(builder: ClientBuilder, check: impl Fn(&Url) -> Result<(), String> + Send + Sync + 'static) -> Client {
+    builder
+        .dns_resolver(Arc::new(PublicResolver))
+        .redirect(redirect::Policy::custom(move |attempt| {
+            if attempt.previous().len() >= MAX_REDIRECTS {
+                return attempt.error("too many redirects");
+            }
+            match check_url(attempt.url()).and_then(|_| check(attempt.url())) {
+                Ok(()) => attempt.follow(),
+                Err(e) => attempt.error(e),
+            }
+        }))
+        .build()
+        .expect("reqwest client")
+}
+
+/// URL を確かめてから GET する
+pub async fn get(client: &Client, url: &str) -> Result<Response, String> {
+    let parsed = Url::parse(url).map_err(|e| format!("invalid URL {url}: {e}"))?;
+    check_url(&parsed)?;
+    client
+        .get(parsed)
+        .send()
+        .await
+        .map_err(|e| format!("failed to fetch {url}: {}", error_chain(&e)))
+}
+
+/// 本文を上限付きで読む (Content-Length が無くても読みながら打ち切る)
+pub async fn read_body(mut resp: Response, max_bytes: usize) -> Result<Vec<u8>, String> {
+    let url = resp.url().clone();
+    if resp.content_length().is_some_and(|len| len > max_bytes as u64) {
+        return Err(format!("{url} is too large (max {max_bytes} bytes)"));
+    }
+    let mut body = Vec::new();
+    while let Some(chunk) = resp.chunk().await.map_err(|e| format!("failed to read {url}: {e}"))? {
+        if body.len() + chunk.len() > max_bytes {
+            return Err(format!("{url} is too large (max {max_bytes} bytes)"));
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body)
+}
+
+/// reqwest のエラーは原因 (リダイレクトを断った理由など) を source に持つので繋げて出す
+fn error_chain(e: &dyn std::error::Error) -> String {
+    let mut s = e.to_string();
+    let mut source = e.source();
+    while let Some(cause) = source {
+        s.push_str(&format!(": {cause}"));
+        source = cause.source();
+    }
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn internal_addresses_are_rejected() {
+        for ip in ["127.0.0.1", "10.0.0.1", "172.16.5.4", "192.168.1.1", "169.254.169.254", "100.64.0.1", "0.0.0.0", "::1", "fd00::1", "fe80::1", "::ffff:127.0.0.1"] {
+            assert!(!is_public_ip(ip.parse().unwrap()), "{ip}");
+        }
+        for ip in ["1.1.1.1", "162.159.128.233", "2606:4700:4700::1111"] {
+            assert!(is_public_ip(ip.parse().unwrap()), "{ip}");
+        }
+    }
+
+    #[test]
+    fn urls() {
+        let check = |url: &str| check_url(&Url::parse(url).unwrap());
+        assert!(check("https://cdn.discordapp.com/attachments/1/2/a.zip").is_ok());
+        assert!(check("http://127.0.0.1:8096/").is_err());
+        assert!(check("http://[::1]/").is_err());
+        assert!(check("http://169.254.169.254/latest/meta-data/").is_err());
+        assert!(check("http://localhost/").is_err());
+        assert!(check("file:///etc/passwd").is_err());
+    }
+}
@@ -0,0 +1,185 @@
+//! レートリミットの計算
+//! ユーザーごとに「使い切った時刻」 (rate_line, UNIX 秒) を持ち、使うたびにコスト × sec_per_cost 秒だけ先へ進める
+//! 進めた rate_line が「今 + バースト許容量」を超える使い方は断る
+//! 時間が経つと今の時刻が rate_line に追いつくので、その分だけ使える量が戻る
+
+/// rate_line がこの値なら制限しない (/rate_config unlimit)
+pub const UNLIMITED: u64 = 0;
+/// 制限を戻したときの rate_line (過去の時刻なので満タンになる)
+pub const RESET: u64 = 1;
+/// ユーザーごとに覚えておく使用履歴の件数
+pub const HISTORY_LEN: usize = 10;
+
+/// 今の UNIX 時刻 (秒)
+pub fn now() -> u64 {
+    chrono::Utc::now().timestamp() as u64
+}
+
+/// レートリミットの設定 (サーバごとに上書きされる)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    /// バースト許容量 (秒)
+    pub window_size: u64,
+    /// コスト 1 あたりに消費する秒数
+    pub sec_per_cost: u64,
+}
+
+/// 使ってよいかの判定
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    /// 使ってよい (新しい rate_line)
+    Allowed { rate_line: u64 },
+    /// 足りない (この時刻になれば使える)
+    Limited { retry_at: u64 },
+}
+
+/// 1回の使用の記録
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Usage {
+    /// 使った時刻 (UNIX 秒)
+    pub at: u64,
+    pub model: String,
+    pub cost: u64,
+}
+
+impl RateLimit {
+    /// `(window_size, sec_per_cost)` から作る
+    pub fn new((window_size, sec_per_cost): (u64, u64)) -> RateLimit {
+        RateLimit { window_size, sec_per_cost }
+    }
+
+    /// コストの分だけ消費する秒数
+    pub fn charge(&self, cost: u64) -> u64 {
+        cost.saturating_mul(self.sec_per_cost)
+    }
+
+    /// このコストのモデルを今使ってよいか
+    /// 使い切った時刻が過去なら今から数える
+    pub fn check(&self, rate_line: u64, cost: u64, now: u64) -> Decision {
+        if rate_line == UNLIMITED {
+            return Decision::Allowed { rate_line: UNLIMITED };
+        }
+        let added = rate_line.max(now).saturating_add(self.charge(cost));
+        let limit = now.saturating_add(self.window_size);
+        if added > limit {
+            Decision::Limited { retry_at: now + (added - limit) }
+        } else {
+            Decision::Allowed { rate_line: added }
+        }
+    }
+
+    /// 残りのバースト許容量 (秒、None なら制限なし)
+    pub fn remaining(&self, rate_line: u64, now: u64) -> Option<u64> {
+        if rate_line == UNLIMITED {
+            return None;
+        }
+        Some(self.window_size.saturating_sub(rate_line.saturating_sub(now)))
+    }
+
+    /// 許容量が全部戻る時刻 (None なら制限なし、今以前なら満タン)
+    pub fn full_at(&self, rate_line: u64, now: u64) -> Option<u64> {
+        if rate_line == UNLIMITED {
+            return None;
+        }
+        Some(rate_line.max(now))
+    }
+
+    /// このコストのモデルをあと何回続けて使えるか (None なら制限なし)
+    pub fn uses_left(&self, rate_line: u64, cost: u64, now: u64) -> Option<u64> {
+        let remaining = self.remaining(rate_line, now)?;
+        match self.charge(cost) {
+            0 => None,
+            charge => Some(remaining / charge),
+        }
+    }
+}
+
+/// 秒数を `1h 05m` のように短く書く
+pub fn format_duration(secs: u64) -> String {
+    let (h, m, s) = (secs / 3600, secs % 3600 / 60, secs % 60);
+    if h > 0 {
+        format!("{h}h {m:02}m")
+    } else if m > 0 {
+        format!("{m}m {s:02}s")
+    } else {
+        format!("{s}s")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 1_700_000_000;
+    const LIMIT: RateLimit = RateLimit { window_size: 16_200, sec_per_cost: 900 };
+
+    #[test]
+    fn fresh_user_starts_from_now() {
+        assert_eq!(LIMIT.check(RESET, 2, NOW), Decision::Allowed { rate_line: NOW + 1_800 });
+        assert_eq!(LIMIT.remaining(RESET, NOW), Some(16_200));
+        assert_eq!(LIMIT.full_at(RESET, NOW), Some(NOW));
+    }
+
+    #[test]
+    fn charges_stack_until_the_window_is_used_up() {
+        let mut rate_line = RESET;
+        for _ in 0..18 {
+            match LIMIT.check(rate_line, 1, NOW) {
+                Decision::Allowed { rate_line: next } => rate_line = next,
+                Decision::Limited { .. } => panic!("limited too early"),
+            }
+        }
+        assert_eq!(rate_line, NOW + 16_200);
+        assert_eq!(LIMIT.remaining(rate_line, NOW), Some(0));
+        assert_eq!(LIMIT.check(rate_line, 1, NOW), Decision::Limited { retry_at: NOW + 900 });
+    }
+
+    #[test]
+    fn limited_until_enough_time_passes() {
+        let rate_line = NOW + 16_000;
+        let Decision::Limited { retry_at } = LIMIT.check(rate_line, 1, NOW) else {
+            panic!("should be limited");
+        };
+        assert_eq!(retry_at, NOW + 700);
+        assert_eq!(LIMIT.check(rate_line, 1, retry_at), Decision::Allowed { rate_line: NOW + 16_900 });
+    }
+
+    #[test]
+    fn budget_refills_over_time() {
+        let rate_line = NOW + 3_600;
+        assert_eq!(LIMIT.remaining(rate_line, NOW), Some(12_600));
+        assert_eq!(LIMIT.remaining(rate_line, NOW + 1_800), Some(14_400));
+        assert_eq!(LIMIT.remaining(rate_line, NOW + 7_200), Some(16_200));
+        assert_eq!(LIMIT.full_at(rate_line, NOW), Some(NOW + 3_600));
+    }
+
+    #[test]
+    fn unlimited_users_are_never_charged() {
+        assert_eq!(LIMIT.check(UNLIMITED, 1_000, NOW), Decision::Allowed { rate_line: UNLIMITED });
+        assert_eq!(LIMIT.remaining(UNLIMITED, NOW), None);
+        assert_eq!(LIMIT.full_at(UNLIMITED, NOW), None);
+        assert_eq!(LIMIT.uses_left(UNLIMITED, 1, NOW), None);
+    }
+
+    #[test]
+    fn uses_left_per_model_cost() {
+        assert_eq!(LIMIT.uses_left(RESET, 1, NOW), Some(18));
+        assert_eq!(LIMIT.uses_left(RESET, 4, NOW), Some(4));
+        assert_eq!(LIMIT.uses_left(NOW + 16_000, 1, NOW), Some(0));
+        // コスト 0 のモデルは何回でも使える
+        assert_eq!(LIMIT.uses_left(RESET, 0, NOW), None);
+    }
+
+    #[test]
+    fn huge_costs_do_not_overflow() {
+        assert!(matches!(LIMIT.check(u64::MAX - 1, u64::MAX, NOW), Decision::Limited { .. }));
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(format_duration(0), "0s");
+        assert_eq!(format_duration(59), "59s");
+        assert_eq!(format_duration(900), "15m 00s");
+        assert_eq!(format_duration(16_200), "4h 30m");
+    }
+}
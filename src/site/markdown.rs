@@ -0,0 +1,210 @@
+use std::sync::OnceLock;
+
+use pulldown_cmark::{CodeBlockKind, Event, HeadingLevel, Options, Parser, Tag, TagEnd, html};
+use syntect::{highlighting::ThemeSet, html::highlighted_html_for_string, parsing::SyntaxSet};
+
+use crate::{site::escape_html, tex::{self, MathMode, RenderOptions}};
+
+/// サニタイズ後に差し戻す HTML の目印 (私用領域の文字で囲む)
+const MARK: char = '\u{F8FF}';
+/// コードのハイライトに使うテーマ
+const THEME: &str = "InspiredGitHub";
+
+static SYNTAXES: OnceLock<SyntaxSet> = OnceLock::new();
+static THEMES: OnceLock<ThemeSet> = OnceLock::new();
+
+fn options() -> Options {
+    Options::ENABLE_TABLES
+        | Options::ENABLE_FOOTNOTES
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_TASKLISTS
+        | Options::ENABLE_MATH
+}
+
+/// markdown を HTML にする
+/// 生の HTML やリンクは ammonia で無害化し、こちらで生成したコードと数式だけ後から差し込む
+pub fn render(markdown: &str) -> String {
+    let markdown = markdown.replace(MARK, "");
+    let mut trusted: Vec<String> = Vec::new();
+    let mut events: Vec<Event> = Vec::new();
+    let mut code: Option<(String, String)> = None;
+
+    let mut keep = |html: String, events: &mut Vec<Event>| {
+        events.push(Event::Text(format!("{MARK}{}{MARK}", trusted.len()).into()));
+        trusted.push(html);
+    };
+
+    for event in Parser::new_ext(&markdown, options()) {
+        match event {
+            Event::Start(Tag::CodeBlock(kind)) => {
+                let lang = match kind {
+                    CodeBlockKind::Fenced(info) => info.split_whitespace().next().unwrap_or("").to_string(),
+                    CodeBlockKind::Indented => String::new(),
+                };
+                code = Some((lang, String::new()));
+            }
+            Event::End(TagEnd::CodeBlock) => {
+                if let Some((lang, source)) = code.take() {
+                    keep(highlight(&source, &lang), &mut events);
+                }
+            }
+            Event::Text(text) if code.is_some() => {
+                if let Some((_, source)) = code.as_mut() {
+                    source.push_str(&text);
+                }
+            }
+            Event::InlineMath(expr) => keep(math(&expr, MathMode::Inline), &mut events),
+            Event::DisplayMath(expr) => keep(math(&expr, MathMode::Display), &mut events),
+            other => events.push(other),
+        }
+    }
+
+    let mut raw = String::new();
+    html::push_html(&mut raw, events.into_iter());
+    let mut cleaned = ammonia::clean(&raw);
+    for (i, html) in trusted.iter().enumerate() {
+        cleaned = cleaned.replacen(&format!("{MARK}{i}{MARK}"), html, 1);
+    }
+    cleaned
+}
+
+/// コードブロックをハイライトする (知らない言語はプレーンテキスト)
+fn highlight(source: &str, lang: &str) -> String {
+    let syntaxes = SYNTAXES.get_or_init(SyntaxSet::load_defaults_newlines);
+    let themes = THEMES.get_or_init(ThemeSet::load_defaults);
+    let syntax = syntaxes
+        .find_syntax_by_token(lang)
+        .unwrap_or_else(|| syntaxes.find_syntax_plain_text());
+    match highlighted_html_for_string(source, syntaxes, syntax, &themes.themes[THEME]) {
+        Ok(html) => format!(r#"<div class="code" data-lang="{}">{html}</div>"#, escape_html(lang)),
+        Err(_) => format!(r#"<pre class="code"><code>{}</code></pre>"#, escape_html(source)),
+    }
+}
+
+/// 数式を SVG にして埋め込む (描画できなければ式をそのまま出す)
+fn math(expr: &str, mode: MathMode) -> String {
+    let options = RenderOptions {
+        mode,
+        scale: if mode == MathMode::Inline { 0.55 } else { 0.7 },
+        foreground: "#1f2328".to_string(),
+        background: None,
+    };
+    let class = if mode == MathMode::Inline { "math math-inline" } else { "math math-display" };
+    match tex::renderer().and_then(|r| r.render_svg(expr, &options)) {
+        Ok(svg) => format!(r#"<span class="{class}" role="img" aria-label="{}">{svg}</span>"#, escape_html(expr)),
+        Err(_) => format!(r#"<code class="{class} math-error">{}</code>"#, escape_html(expr)),
+    }
+}
+
+/// 最初の見出し (h1) と最初の段落を平文で取り出す
+pub fn title_and_summary(markdown: &str) -> (Option<String>, String) {
+    let mut title: Option<String> = None;
+    let mut summary = String::new();
+    let mut in_title = false;
+    let mut in_paragraph = false;
+    let mut buf = String::new();
+
+    for event in Parser::new_ext(markdown, options()) {
+        match event {
+            Event::Start(Tag::Heading { level: HeadingLevel::H1, .. }) if title.is_none() => {
+                in_title = true;
+                buf.clear();
+            }
+            Event::End(TagEnd::Heading(HeadingLevel::H1)) if in_title => {
+                in_title = false;
+                title = Some(buf.trim().to_string()).filter(|t| !t.is_empty());
+            }
+            Event::Start(Tag::Paragraph) if summary.is_empty() => {
+                in_paragraph = true;
+                buf.clear();
+            }
+            Event::End(TagEnd::Paragraph) if in_paragraph => {
+                in_paragraph = false;
+                summary = buf.trim().to_string();
+            }
+            Event::Text(t) | Event::Code(t) | Event::InlineMath(t) if in_title || in_paragraph => buf.push_str(&t),
+            Event::SoftBreak | Event::HardBreak if in_title || in_paragraph => buf.push(' '),
+            _ => {}
+        }
+        if title.is_some() && !summary.is_empty() {
+            break;
+        }
+    }
+
+    if summary.chars().count() > 200 {
+        summary = summary.chars().take(200).collect::<String>() + "…";
+    }
+    (title, summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn raw_script_is_removed() {
+        let html = render("hello\n\n<script>alert(1)</script>\n\ninline <script>alert(2)</script> text\n");
+        assert!(!html.contains("<script"), "{html}");
+        assert!(!html.contains("alert(1)"), "{html}");
+        assert!(html.contains("hello"), "{html}");
+    }
+
+    #[test]
+    fn dangerous_attributes_and_links_are_removed() {
+        let html = render(
+            "[click](javascript:alert(1)) <a href=\"javascript:alert(2)\">a</a> \
+             <img src=x onerror=\"alert(3)\"> [ok](https://example.com/)\n",
+        );
+        assert!(!html.contains("javascript:"), "{html}");
+        assert!(!html.contains("onerror"), "{html}");
+        assert!(html.contains(r#"href="https://example.com/""#), "{html}");
+    }
+
+    #[test]
+    fn placeholder_chars_in_source_are_stripped() {
+        // 目印を自分で書いて、差し戻す HTML を別の場所 (属性の中など) に入れさせようとする
+        let source = format!(
+            "{MARK}0{MARK} [x](https://example.com/{MARK}0{MARK}) <a title=\"{MARK}0{MARK}\">t</a>\n\n```rust\nfn main() {{}}\n```\n"
+        );
+        let html = render(&source);
+        assert!(!html.contains(MARK), "{html}");
+        assert_eq!(html.matches(r#"<div class="code""#).count(), 1, "{html}");
+        // 差し戻しはコードブロックの位置だけ
+        let code = html.find(r#"<div class="code""#).unwrap();
+        assert!(html[..code].contains("0 "), "{html}");
+        assert!(!html[..code].contains("<pre"), "{html}");
+    }
+
+    #[test]
+    fn code_blocks_still_render() {
+        let html = render("```rust\nfn main() { println!(\"<script>\"); }\n```\n\n    indented <b>\n");
+        assert!(html.contains(r#"<div class="code" data-lang="rust">"#), "{html}");
+        assert!(html.contains(r#"<div class="code" data-lang="">"#), "{html}");
+        assert!(html.contains("<pre"), "{html}");
+        // コードの中身はエスケープされる
+        assert!(!html.contains("<script"), "{html}");
+        assert!(!html.contains("<b>"), "{html}");
+        assert!(html.contains("&lt;script"), "{html}");
+    }
+
+    #[test]
+    fn math_still_renders() {
+        let html = render("inline $x^2$ and\n\n$$\\frac{1}{2}$$\n");
+        assert!(html.contains(r#"class="math math-inline"#), "{html}");
+        assert!(html.contains(r#"class="math math-display"#), "{html}");
+        assert!(!html.contains(MARK), "{html}");
+    }
+
+    #[test]
+    fn math_source_is_escaped() {
+        let html = render("$</span><script>alert(1)</script>$\n");
+        assert!(!html.contains("<script"), "{html}");
+    }
+
+    #[test]
+    fn title_and_summary_are_plain_text() {
+        let (title, summary) = title_and_summary("# Hello *world*\n\nFirst `code` and $x$.\n\nSecond.\n");
+        assert_eq!(title.as_deref(), Some("Hello world"));
+        assert_eq!(summary, "First code and x.");
+    }
+}
@@ -0,0 +1,438 @@
+//! 公開した記事の閲覧サイト
+//! 年・月ごとの一覧、記事ページ、Atom フィードを HTML / XML の文字列として作る
+//! (ルーティングは main.rs の kurosabi 側)
+
+pub mod markdown;
+
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    fs,
+    path::PathBuf,
+    sync::{LazyLock, Mutex},
+    time::SystemTime,
+};
+
+use chrono::{DateTime, Local, Utc};
+
+use crate::{config::Config, tools::web_deploy::{self, ArticleStore}};
+
+/// フィードに載せる記事数
+const FEED_ENTRIES: usize = 20;
+/// トップページに載せる記事数
+const INDEX_ENTRIES: usize = 30;
+/// 読了時間の目安 (1分あたりの文字数)
+const CHARS_PER_MINUTE: usize = 500;
+
+/// 記事ファイルの (パス, 更新時刻) の一覧。描画済みのページが古いかどうかの判定に使う
+type Fingerprint = Vec<(PathBuf, SystemTime)>;
+
+/// (ページの種類, 記事ディレクトリ, サイト名, ベース URL)
+type CacheKey = (&'static str, PathBuf, String, String);
+
+/// 描画済みのトップページとフィード (記事ファイルが変わるまで使い回す)
+static RENDERED: LazyLock<Mutex<HashMap<CacheKey, (Fingerprint, String)>>> = LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// サイトのパス (記事は `/blog/YYYY/M/記事キー`)
+pub const BLOG_PATH: &str = "/blog";
+/// Atom フィードのパス
+pub const FEED_PATH: &str = "/feed.atom";
+
+pub fn escape_html(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// 記事のパス (`/blog/2025/10/key`)
+pub fn article_path(year: u32, month: u32, key: &str) -> String {
+    format!("{BLOG_PATH}/{year}/{month}/{}", urlencoding::encode(key))
+}
+
+/// 記事 1件の情報
+#[derive(Debug, Clone)]
+pub struct ArticleMeta {
+    pub year: u32,
+    pub month: u32,
+    pub key: String,
+    pub title: String,
+    pub summary: String,
+    pub updated: DateTime<Utc>,
+    pub chars: usize,
+    pub content: String,
+}
+
+impl ArticleMeta {
+    fn load(store: &ArticleStore, year: u32, month: u32, key: &str) -> Option<ArticleMeta> {
+        let path = store.path_of(year, month, key);
+        let content = fs::read_to_string(&path).ok()?;
+        let updated = fs::metadata(&path)
+            .and_then(|m| m.modified())
+            .map(DateTime::<Utc>::from)
+            .unwrap_or_else(|_| Utc::now());
+        let (title, summary) = markdown::title_and_summary(&content);
+        Some(ArticleMeta {
+            year,
+            month,
+            key: key.to_string(),
+            title: title.unwrap_or_else(|| key.to_string()),
+            summary,
+            updated,
+            chars: content.chars().count(),
+            content,
+        })
+    }
+
+    pub fn path(&self) -> String {
+        article_path(self.year, self.month, &self.key)
+    }
+
+    pub fn reading_minutes(&self) -> usize {
+        self.chars.div_ceil(CHARS_PER_MINUTE).max(1)
+    }
+
+    fn local_date(&self) -> String {
+        self.updated.with_timezone(&Local).format("%Y-%m-%d").to_string()
+    }
+}
+
+/// 記事サイト
+pub struct Site {
+    store: ArticleStore,
+    /// 絶対 URL のベース (フィード用)
+    base_url: String,
+    title: String,
+}
+
+impl Site {
+    pub fn from_config(config: &Config) -> Site {
+        let cfg = config.tool(web_deploy::TOOL_NAME);
+        Site {
+            store: ArticleStore::from_config(&cfg),
+            base_url: config.public_base_url(),
+            title: cfg.str_setting("site_title").unwrap_or(&config.assistant_name).to_string(),
+        }
+    }
+
+    /// ファイルの読み込みと描画をブロッキングスレッドで行う
+    pub async fn render<T: Send + 'static>(self, f: impl FnOnce(&Site) -> T + Send + 'static) -> Result<T, String> {
+        tokio::task::spawn_blocking(move || f(&self))
+            .await
+            .map_err(|e| format!("rendering failed: {e}"))
+    }
+
+    fn fingerprint(&self) -> Fingerprint {
+        self.store
+            .months()
+            .into_iter()
+            .flat_map(|(y, m)| {
+                self.store.list(&y.to_string(), &m.to_string()).into_iter().map(move |key| {
+                    let path = self.store.path_of(y, m, &key);
+                    let modified = fs::metadata(&path).and_then(|m| m.modified()).unwrap_or(SystemTime::UNIX_EPOCH);
+                    (path, modified)
+                })
+            })
+            .collect()
+    }
+
+    /// 記事ファイルの更新時刻が前回と同じなら描画済みのものを返す
+    fn cached(&self, kind: &'static str, render: impl FnOnce() -> String) -> String {
+        let fingerprint = self.fingerprint();
+        let key = (kind, self.store.base_dir().to_path_buf(), self.title.clone(), self.base_url.clone());
+        if let Some((cached, text)) = RENDERED.lock().unwrap_or_else(|e| e.into_inner()).get(&key)
+            && *cached == fingerprint
+        {
+            return text.clone();
+        }
+        let text = render();
+        RENDERED
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(key, (fingerprint, text.clone()));
+        text
+    }
+
+    /// 全記事 (新しい順)
+    fn articles(&self) -> Vec<ArticleMeta> {
+        let mut articles: Vec<ArticleMeta> = self
+            .store
+            .months()
+            .into_iter()
+            .flat_map(|(y, m)| {
+                self.store
+                    .list(&y.to_string(), &m.to_string())
+                    .into_iter()
+                    .filter_map(move |key| ArticleMeta::load(&self.store, y, m, &key))
+            })
+            .collect();
+        articles.sort_by_key(|a| std::cmp::Reverse(a.updated));
+        articles
+    }
+
+    fn layout(&self, title: &str, breadcrumbs: &[(String, String)], body: &str) -> String {
+        let mut nav = format!(r#"<a href="{BLOG_PATH}">{}</a>"#, escape_html(&self.title));
+        for (href, label) in breadcrumbs {
+            let _ = write!(nav, r#" / <a href="{}">{}</a>"#, escape_html(href), escape_html(label));
+        }
+        format!(
+            r#"<!DOCTYPE html>
+<html lang="ja">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>{title}</title>
+<link rel="alternate" type="application/atom+xml" title="{site}" href="{FEED_PATH}">
+<style>{STYLE}</style>
+</head>
+<body>
+<header><nav>{nav}</nav><a class="feed" href="{FEED_PATH}">Atom</a></header>
+<main>
+{body}
+</main>
+</body>
+</html>
+"#,
+            title = escape_html(title),
+            site = escape_html(&self.title),
+        )
+    }
+
+    fn article_list(articles: &[&ArticleMeta]) -> String {
+        if articles.is_empty() {
+            return r#"<p class="empty">記事はありません</p>"#.to_string();
+        }
+        let mut html = String::from(r#"<ul class="articles">"#);
+        for a in articles {
+            let _ = write!(
+                html,
+                r#"<li><a href="{}">{}</a><span class="meta">{} · {}分</span><p>{}</p></li>"#,
+                escape_html(&a.path()),
+                escape_html(&a.title),
+                a.local_date(),
+                a.reading_minutes(),
+                escape_html(&a.summary)
+            );
+        }
+        html.push_str("</ul>");
+        html
+    }
+
+    /// 年・月の一覧 (サイドの目次)
+    fn archive(&self) -> String {
+        let mut html = String::from(r#"<section class="archive"><h2>アーカイブ</h2><ul>"#);
+        let mut current_year = None;
+        for (y, m) in self.store.months() {
+            if current_year != Some(y) {
+                if current_year.is_some() {
+                    html.push_str("</li>");
+                }
+                let _ = write!(html, r#"<li><a href="{BLOG_PATH}/{y}">{y}</a> "#);
+                current_year = Some(y);
+            }
+            let count = self.store.list(&y.to_string(), &m.to_string()).len();
+            let _ = write!(html, r#"<a class="month" href="{BLOG_PATH}/{y}/{m}">{m}月 ({count})</a> "#);
+        }
+        if current_year.is_some() {
+            html.push_str("</li>");
+        }
+        html.push_str("</ul></section>");
+        html
+    }
+
+    /// トップページ (`q` があればタイトルと本文を検索)
+    pub fn index_page(&self, query: Option<&str>) -> String {
+        let query = query.map(|q| q.trim()).filter(|q| !q.is_empty());
+        match query {
+            Some(_) => self.render_index(query),
+            None => self.cached("index", || self.render_index(None)),
+        }
+    }
+
+    fn render_index(&self, query: Option<&str>) -> String {
+        let articles = self.articles();
+        let (heading, shown): (String, Vec<&ArticleMeta>) = match query {
+            Some(q) => {
+                let lower = q.to_lowercase();
+                (
+                    format!("「{}」の検索結果", q),
+                    articles
+                        .iter()
+                        .filter(|a| a.title.to_lowercase().contains(&lower) || a.content.to_lowercase().contains(&lower))
+                        .collect(),
+                )
+            }
+            None => ("新着記事".to_string(), articles.iter().take(INDEX_ENTRIES).collect()),
+        };
+        let body = format!(
+            r#"<form class="search" action="{BLOG_PATH}"><input type="search" name="q" value="{}" placeholder="記事を検索"></form>
+<h1>{}</h1>
+{}
+{}"#,
+            escape_html(query.unwrap_or("")),
+            escape_html(&heading),
+            Self::article_list(&shown),
+            self.archive()
+        );
+        self.layout(&self.title, &[], &body)
+    }
+
+    /// 年ごとの一覧
+    pub fn year_page(&self, year: &str) -> Option<String> {
+        let year: u32 = year.parse().ok()?;
+        let articles = self.articles();
+        let mut months: Vec<u32> = articles.iter().filter(|a| a.year == year).map(|a| a.month).collect();
+        if months.is_empty() {
+            return None;
+        }
+        months.sort_unstable_by(|a, b| b.cmp(a));
+        months.dedup();
+
+        let mut body = format!("<h1>{year}年</h1>");
+        for month in months {
+            let in_month: Vec<&ArticleMeta> = articles.iter().filter(|a| a.year == year && a.month == month).collect();
+            let _ = write!(
+                body,
+                r#"<h2><a href="{BLOG_PATH}/{year}/{month}">{month}月</a></h2>{}"#,
+                Self::article_list(&in_month)
+            );
+        }
+        Some(self.layout(&format!("{year}年 - {}", self.title), &[(format!("{BLOG_PATH}/{year}"), format!("{year}"))], &body))
+    }
+
+    /// 月ごとの一覧
+    pub fn month_page(&self, year: &str, month: &str) -> Option<String> {
+        let year: u32 = year.parse().ok()?;
+        let month: u32 = month.trim_start_matches('0').parse().ok()?;
+        let articles = self.articles();
+        let in_month: Vec<&ArticleMeta> = articles.iter().filter(|a| a.year == year && a.month == month).collect();
+        if in_month.is_empty() {
+            return None;
+        }
+        let body = format!("<h1>{year}年{month}月</h1>{}", Self::article_list(&in_month));
+        Some(self.layout(
+            &format!("{year}年{month}月 - {}", self.title),
+            &[
+                (format!("{BLOG_PATH}/{year}"), format!("{year}")),
+                (format!("{BLOG_PATH}/{year}/{month}"), format!("{month}月")),
+            ],
+            &body,
+        ))
+    }
+
+    /// 記事ページ
+    pub fn article_page(&self, year: &str, month: &str, key: &str) -> Option<String> {
+        let year: u32 = year.parse().ok()?;
+        let month: u32 = month.trim_start_matches('0').parse().ok()?;
+        let key = ArticleStore::sanitize_key(key).ok()?;
+        let article = ArticleMeta::load(&self.store, year, month, &key)?;
+
+        let permalink = format!("{}{}", self.base_url, article.path());
+        let raw = format!("/article/raw/{year}/{month}/{}", urlencoding::encode(&key));
+        let body = format!(
+            r#"<article>
+<div class="meta">{} 更新 · 約{}分 · <a href="{}">permalink</a> · <a href="{}">raw</a></div>
+{}
+</article>"#,
+            article.local_date(),
+            article.reading_minutes(),
+            escape_html(&permalink),
+            escape_html(&raw),
+            markdown::render(&article.content)
+        );
+        Some(self.layout(
+            &format!("{} - {}", article.title, self.title),
+            &[
+                (format!("{BLOG_PATH}/{year}"), format!("{year}")),
+                (format!("{BLOG_PATH}/{year}/{month}"), format!("{month}月")),
+                (article.path(), article.title.clone()),
+            ],
+            &body,
+        ))
+    }
+
+    pub fn not_found_page(&self) -> String {
+        self.layout("Not Found", &[], r#"<h1>記事が見つかりません</h1>"#)
+    }
+
+    /// Atom フィード (新しい順に FEED_ENTRIES 件)
+    pub fn atom_feed(&self) -> String {
+        self.cached("feed", || self.render_feed())
+    }
+
+    fn render_feed(&self) -> String {
+        let articles = self.articles();
+        let updated = articles.first().map(|a| a.updated).unwrap_or_else(Utc::now);
+        let mut xml = format!(
+            r#"<?xml version="1.0" encoding="utf-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+<title>{title}</title>
+<id>{base}{BLOG_PATH}</id>
+<link rel="alternate" type="text/html" href="{base}{BLOG_PATH}"/>
+<link rel="self" type="application/atom+xml" href="{base}{FEED_PATH}"/>
+<updated>{updated}</updated>
+<author><name>{title}</name></author>
+"#,
+            title = escape_html(&self.title),
+            base = escape_html(&self.base_url),
+            updated = updated.to_rfc3339(),
+        );
+        for a in articles.iter().take(FEED_ENTRIES) {
+            let url = escape_html(&format!("{}{}", self.base_url, a.path()));
+            let _ = write!(
+                xml,
+                r#"<entry>
+<title>{}</title>
+<id>{url}</id>
+<link rel="alternate" type="text/html" href="{url}"/>
+<updated>{}</updated>
+<summary>{}</summary>
+<content type="html">{}</content>
+</entry>
+"#,
+                escape_html(&a.title),
+                a.updated.to_rfc3339(),
+                escape_html(&a.summary),
+                escape_html(&markdown::render(&a.content))
+            );
+        }
+        xml.push_str("</feed>\n");
+        xml
+    }
+}
+
+const STYLE: &str = r#"
+body { margin: 0; font-family: system-ui, -apple-system, "Hiragino Sans", "Noto Sans JP", sans-serif; color: #1f2328; background: #fafafa; line-height: 1.8; }
+header { display: flex; justify-content: space-between; align-items: center; padding: 0.8rem 1.2rem; background: #fff; border-bottom: 1px solid #e5e7eb; }
+header a { color: inherit; text-decoration: none; }
+header .feed { font-size: 0.85rem; color: #c2410c; }
+main { max-width: 46rem; margin: 0 auto; padding: 1.5rem 1.2rem 4rem; }
+a { color: #0969da; }
+.search input { width: 100%; padding: 0.5rem 0.8rem; font-size: 1rem; border: 1px solid #d0d7de; border-radius: 6px; box-sizing: border-box; }
+.articles { list-style: none; padding: 0; }
+.articles li { padding: 0.8rem 0; border-bottom: 1px solid #eaeef2; }
+.articles li > a { font-size: 1.15rem; font-weight: 600; text-decoration: none; }
+.articles p { margin: 0.3rem 0 0; color: #57606a; font-size: 0.95rem; }
+.meta { color: #6e7781; font-size: 0.85rem; margin-left: 0.6rem; }
+article .meta { margin: 0 0 1rem; }
+.archive ul { padding-left: 1.2rem; }
+.archive .month { font-size: 0.9rem; margin-right: 0.4rem; }
+.empty { color: #6e7781; }
+article img { max-width: 100%; }
+article table { border-collapse: collapse; }
+article th, article td { border: 1px solid #d0d7de; padding: 0.3rem 0.6rem; }
+article blockquote { margin: 0; padding: 0 1rem; color: #57606a; border-left: 0.25rem solid #d0d7de; }
+article code { background: #eff1f3; padding: 0.1rem 0.3rem; border-radius: 4px; font-size: 0.9em; }
+.code pre { padding: 0.8rem 1rem; border-radius: 6px; overflow-x: auto; line-height: 1.5; font-size: 0.9rem; border: 1px solid #e5e7eb; }
+.math-inline svg { vertical-align: middle; }
+.math-display { display: block; text-align: center; overflow-x: auto; margin: 0.8rem 0; }
+.math-error { color: #cf222e; }
+"#;
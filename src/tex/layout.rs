@@ -0,0 +1,666 @@
+//! 数式のレイアウト
+//! 座標はすべて基準フォントサイズを 1.0 とする em 単位、y は下向き、ベースラインが y = 0
+
+use super::{parse::{ColAlign, FracStyle, Node, Variant}, symbols::Class};
+
+/// 数式の軸の高さ (分数線や大型演算子の中心)
+const AXIS: f32 = 0.25;
+
+/// 文字幅の計測
+pub trait Measure {
+    /// サイズ 1.0 のときの文字列の送り幅
+    fn advance(&self, text: &str, variant: Variant) -> f32;
+}
+
+/// 描画要素
+#[derive(Debug, Clone)]
+pub enum Item {
+    /// 文字 (scale_y は縦に伸ばす括弧用)
+    Glyph { x: f32, y: f32, text: String, size: f32, variant: Variant, scale_y: f32 },
+    /// 塗りつぶしの矩形 (y は上端)
+    Rule { x: f32, y: f32, width: f32, height: f32 },
+    /// 折れ線 (根号)
+    Path { points: Vec<(f32, f32)>, stroke: f32 },
+}
+
+/// レイアウト済みの箱
+#[derive(Debug, Clone, Default)]
+pub struct LBox {
+    pub width: f32,
+    pub ascent: f32,
+    pub descent: f32,
+    pub items: Vec<Item>,
+}
+
+impl LBox {
+    /// 子の箱を (dx, dy) にずらして取り込む
+    fn append(&mut self, other: LBox, dx: f32, dy: f32) {
+        self.ascent = self.ascent.max(other.ascent - dy);
+        self.descent = self.descent.max(other.descent + dy);
+        self.width = self.width.max(dx + other.width);
+        self.items.extend(other.items.into_iter().map(|item| match item {
+            Item::Glyph { x, y, text, size, variant, scale_y } => Item::Glyph { x: x + dx, y: y + dy, text, size, variant, scale_y },
+            Item::Rule { x, y, width, height } => Item::Rule { x: x + dx, y: y + dy, width, height },
+            Item::Path { points, stroke } => Item::Path {
+                points: points.into_iter().map(|(x, y)| (x + dx, y + dy)).collect(),
+                stroke,
+            },
+        }));
+    }
+
+    fn space(width: f32) -> LBox {
+        LBox { width, ..Default::default() }
+    }
+}
+
+/// 数式のスタイル (display / text / script / scriptscript)
+#[derive(Debug, Clone, Copy)]
+pub struct Style {
+    pub display: bool,
+    /// 0: 通常, 1: 添字, 2: 添字の添字
+    pub level: u8,
+}
+
+impl Style {
+    pub fn new(display: bool) -> Style {
+        Style { display, level: 0 }
+    }
+
+    pub fn size(&self) -> f32 {
+        match self.level {
+            0 => 1.0,
+            1 => 0.7,
+            _ => 0.5,
+        }
+    }
+
+    fn script(self) -> Style {
+        Style { display: false, level: (self.level + 1).min(2) }
+    }
+
+    fn frac_inner(self, frac_style: FracStyle) -> Style {
+        match frac_style {
+            FracStyle::Display if self.level == 0 => Style { display: true, level: 0 },
+            FracStyle::Text if self.level == 0 => Style { display: false, level: 0 },
+            _ if self.display => Style { display: false, level: self.level },
+            _ => self.script(),
+        }
+    }
+}
+
+pub struct Layouter<'a> {
+    pub measure: &'a dyn Measure,
+}
+
+impl Layouter<'_> {
+    pub fn layout(&self, node: &Node, style: Style) -> LBox {
+        match node {
+            Node::Atom { text, variant, .. } => self.glyph(text, *variant, style.size()),
+            Node::Text { text, variant } => self.glyph(text, *variant, style.size()),
+            Node::Group(list) => self.layout_list(list, style, false),
+            Node::Space(em) => LBox::space(em * style.size()),
+            Node::Frac { num, den, rule, style: frac_style } => self.frac(num, den, *rule, *frac_style, style),
+            Node::Sqrt { index, body } => self.sqrt(index.as_deref(), body, style),
+            Node::Scripts { base, sub, sup } => self.scripts(base, sub.as_deref(), sup.as_deref(), style),
+            Node::BigOp { symbol, text, .. } => self.big_op(symbol, *text, style),
+            Node::Delimited { left, body, right } => {
+                let body = self.layout(body, style);
+                self.delimited(left, body, right, style)
+            }
+            Node::SizedDelim { text, scale, .. } => self.stretched(text, *scale, style.size()),
+            Node::Accent { accent, body } => self.accent(accent, body, style),
+            Node::Overline(body) => {
+                let size = style.size();
+                let mut out = self.layout(body, style);
+                let t = 0.045 * size;
+                let y = -(out.ascent + 0.12 * size);
+                out.items.push(Item::Rule { x: 0.0, y: y - t, width: out.width, height: t });
+                out.ascent = -y + t + 0.05 * size;
+                out
+            }
+            Node::Underline(body) => {
+                let size = style.size();
+                let mut out = self.layout(body, style);
+                let t = 0.045 * size;
+                let y = out.descent + 0.12 * size;
+                out.items.push(Item::Rule { x: 0.0, y, width: out.width, height: t });
+                out.descent = y + t + 0.05 * size;
+                out
+            }
+            Node::Array { rows, cols, paired, left, right } => {
+                let body = self.array(rows, cols, *paired, style);
+                if left.is_none() && right.is_none() {
+                    body
+                } else {
+                    self.delimited(
+                        left.as_deref().unwrap_or(""),
+                        body,
+                        right.as_deref().unwrap_or(""),
+                        style,
+                    )
+                }
+            }
+        }
+    }
+
+    /// 要素を横に並べる (要素の種類に応じて間を空ける)
+    /// leading_ord は align の右側の列のように、前に何かある扱いにしたいとき用
+    fn layout_list(&self, list: &[Node], style: Style, leading_ord: bool) -> LBox {
+        let classes = resolve_classes(list, leading_ord);
+        let mut out = LBox::default();
+        let mut x = 0.0;
+        let mut prev = if leading_ord { Some(Class::Ord) } else { None };
+
+        for (node, class) in list.iter().zip(classes) {
+            if let (Some(p), Some(c)) = (prev, class) {
+                x += spacing(p, c, style) * style.size();
+            }
+            let b = self.layout(node, style);
+            let w = b.width;
+            out.append(b, x, 0.0);
+            x += w;
+            if class.is_some() {
+                prev = class;
+            }
+        }
+        out.width = x;
+        out
+    }
+
+    fn glyph(&self, text: &str, variant: Variant, size: f32) -> LBox {
+        let (ascent, descent) = text_extent(text);
+        LBox {
+            width: self.measure.advance(text, variant) * size,
+            ascent: ascent * size,
+            descent: descent * size,
+            items: vec![Item::Glyph { x: 0.0, y: 0.0, text: text.to_string(), size, variant, scale_y: 1.0 }],
+        }
+    }
+
+    fn frac(&self, num: &Node, den: &Node, rule: bool, frac_style: FracStyle, style: Style) -> LBox {
+        let size = style.size();
+        let inner = style.frac_inner(frac_style);
+        let display = inner.display || (style.display && frac_style == FracStyle::Auto);
+        let num = self.layout(num, inner);
+        let den = self.layout(den, inner);
+
+        let t = if rule { 0.045 * size } else { 0.0 };
+        let gap = if display { 0.16 * size } else { 0.09 * size };
+        let pad = 0.12 * size;
+        let width = num.width.max(den.width) + pad * 2.0;
+
+        let num_shift = (AXIS * size + t / 2.0 + gap + num.descent).max(if display { 0.68 } else { 0.42 } * size);
+        let den_shift = (-AXIS * size + t / 2.0 + gap + den.ascent).max(if display { 0.69 } else { 0.36 } * size);
+
+        let mut out = LBox { width, ..Default::default() };
+        let (nw, dw) = (num.width, den.width);
+        out.append(num, (width - nw) / 2.0, -num_shift);
+        out.append(den, (width - dw) / 2.0, den_shift);
+        if rule {
+            out.items.push(Item::Rule {
+                x: pad / 2.0,
+                y: -AXIS * size - t / 2.0,
+                width: width - pad,
+                height: t,
+            });
+        }
+        out
+    }
+
+    fn sqrt(&self, index: Option<&Node>, body: &Node, style: Style) -> LBox {
+        let size = style.size();
+        let body = self.layout(body, style);
+        let t = 0.045 * size;
+        let gap = if style.display { 0.18 * size } else { 0.12 * size };
+
+        let line_y = -(body.ascent.max(0.7 * size) + gap);
+        let bottom = body.descent.max(0.05 * size) + 0.05 * size;
+        let height = bottom - line_y;
+        let sign_w = (0.5 * size).max(0.12 * height + 0.4 * size);
+
+        // 添字 (\sqrt[n]) は根号の左上に置き、はみ出す分だけ全体を右にずらす
+        let index_box = index.map(|n| self.layout(n, Style { display: false, level: 2 }));
+        let shift = index_box
+            .as_ref()
+            .map(|b| (b.width - 0.55 * sign_w).max(0.0))
+            .unwrap_or(0.0)
+            + 0.05 * size;
+
+        let body_x = shift + sign_w + 0.08 * size;
+        let end_x = body_x + body.width + 0.08 * size;
+        let points = vec![
+            (shift, bottom - 0.42 * height),
+            (shift + 0.22 * sign_w, bottom - 0.5 * height),
+            (shift + 0.5 * sign_w, bottom),
+            (shift + sign_w, line_y),
+            (end_x, line_y),
+        ];
+
+        let mut out = LBox::default();
+        out.append(body, body_x, 0.0);
+        if let Some(index_box) = index_box {
+            let iw = index_box.width;
+            let y = bottom - 0.55 * height - index_box.descent;
+            out.append(index_box, shift + 0.55 * sign_w - iw, y);
+        }
+        out.items.push(Item::Path { points, stroke: t });
+        out.ascent = out.ascent.max(-line_y + t);
+        out.descent = out.descent.max(bottom);
+        out.width = end_x + 0.05 * size;
+        out
+    }
+
+    fn big_op(&self, symbol: &str, text: bool, style: Style) -> LBox {
+        let size = style.size();
+        if text {
+            return self.glyph(symbol, Variant::UPRIGHT, size);
+        }
+        let integral = symbol.starts_with('∫') || symbol.starts_with('∬') || symbol.starts_with('∭') || symbol.starts_with('∮');
+        let scale = match (style.display, integral) {
+            (true, true) => 2.0,
+            (true, false) => 1.5,
+            (false, true) => 1.3,
+            (false, false) => 1.1,
+        };
+        let glyph_size = size * scale;
+        let (ascent, descent) = text_extent(symbol);
+        // 記号の中心を軸に合わせる
+        let center = (ascent - descent) / 2.0 * glyph_size;
+        let y = center - AXIS * size;
+        LBox {
+            width: self.measure.advance(symbol, Variant::UPRIGHT) * glyph_size + if integral { 0.1 * size } else { 0.0 },
+            ascent: ascent * glyph_size - y,
+            descent: descent * glyph_size + y,
+            items: vec![Item::Glyph { x: 0.0, y, text: symbol.to_string(), size: glyph_size, variant: Variant::UPRIGHT, scale_y: 1.0 }],
+        }
+    }
+
+    fn scripts(&self, base: &Node, sub: Option<&Node>, sup: Option<&Node>, style: Style) -> LBox {
+        let size = style.size();
+        let script_style = style.script();
+
+        // display の大型演算子は添字を上下に置く
+        if let Node::BigOp { limits: true, .. } = base
+            && style.display
+        {
+            return self.limits(base, sub, sup, style);
+        }
+
+        let italic = matches!(base, Node::Atom { variant: Variant { italic: true, .. }, .. });
+        let base_box = self.layout(base, style);
+        let sup_box = sup.map(|n| self.layout(n, script_style));
+        let sub_box = sub.map(|n| self.layout(n, script_style));
+        let script_size = script_style.size();
+
+        let mut sup_up = sup_box.as_ref().map(|b| {
+            (base_box.ascent - 0.3 * script_size)
+                .max(if style.display { 0.42 } else { 0.36 } * size)
+                .max(b.descent + 0.22 * size)
+        });
+        let mut sub_down = sub_box.as_ref().map(|b| {
+            (base_box.descent + 0.1 * script_size)
+                .max(0.16 * size)
+                .max(b.ascent - 0.36 * size)
+        });
+
+        // 上下の添字がぶつからないように離す
+        if let (Some(up), Some(down), Some(sp), Some(sb)) = (sup_up, sub_down, sup_box.as_ref(), sub_box.as_ref()) {
+            let clearance = (up - sp.descent) - (sb.ascent - down);
+            let min = 0.16 * size;
+            if clearance < min {
+                sub_down = Some(down + (min - clearance) / 2.0);
+                sup_up = Some(up + (min - clearance) / 2.0);
+            }
+        }
+
+        let base_w = base_box.width;
+        let mut out = LBox::default();
+        out.append(base_box, 0.0, 0.0);
+        let mut width = base_w;
+        if let (Some(b), Some(up)) = (sup_box, sup_up) {
+            let x = base_w + if italic { 0.06 * size } else { 0.0 };
+            width = width.max(x + b.width);
+            out.append(b, x, -up);
+        }
+        if let (Some(b), Some(down)) = (sub_box, sub_down) {
+            width = width.max(base_w + b.width);
+            out.append(b, base_w, down);
+        }
+        out.width = width + 0.04 * size;
+        out
+    }
+
+    /// 大型演算子の上下に添字を置く
+    fn limits(&self, base: &Node, sub: Option<&Node>, sup: Option<&Node>, style: Style) -> LBox {
+        let size = style.size();
+        let script_style = style.script();
+        let op = self.layout(base, style);
+        let sup_box = sup.map(|n| self.layout(n, script_style));
+        let sub_box = sub.map(|n| self.layout(n, script_style));
+
+        let width = op
+            .width
+            .max(sup_box.as_ref().map_or(0.0, |b| b.width))
+            .max(sub_box.as_ref().map_or(0.0, |b| b.width));
+
+        let (op_ascent, op_descent, op_w) = (op.ascent, op.descent, op.width);
+        let mut out = LBox::default();
+        out.append(op, (width - op_w) / 2.0, 0.0);
+        if let Some(b) = sup_box {
+            let y = -(op_ascent + 0.12 * size + b.descent);
+            let bw = b.width;
+            out.append(b, (width - bw) / 2.0, y);
+        }
+        if let Some(b) = sub_box {
+            let y = op_descent + 0.1 * size + b.ascent;
+            let bw = b.width;
+            out.append(b, (width - bw) / 2.0, y);
+        }
+        out.width = width;
+        out
+    }
+
+    /// 中身の高さに合わせて縦に伸ばした括弧で囲む
+    fn delimited(&self, left: &str, body: LBox, right: &str, style: Style) -> LBox {
+        let size = style.size();
+        let half = (body.ascent - AXIS * size).max(body.descent + AXIS * size);
+        let scale = (half * 2.0 * 1.08 / size).max(1.0);
+
+        let mut out = LBox::default();
+        let mut x = 0.0;
+        let l = self.stretched(left, scale, size);
+        let lw = l.width;
+        out.append(l, x, 0.0);
+        x += lw;
+        let bw = body.width;
+        out.append(body, x, 0.0);
+        x += bw;
+        let r = self.stretched(right, scale, size);
+        let rw = r.width;
+        out.append(r, x, 0.0);
+        x += rw;
+        out.width = x;
+        out
+    }
+
+    /// 縦に scale 倍した括弧 (空文字列なら少しだけ空白)
+    fn stretched(&self, text: &str, scale: f32, size: f32) -> LBox {
+        if text.is_empty() {
+            return LBox::space(0.12 * size);
+        }
+        let (ascent, descent) = (0.78, 0.22);
+        let center = (ascent - descent) / 2.0 * size;
+        // 伸ばした後の中心が軸に来るようにベースラインを下げる
+        let y = center * scale - AXIS * size;
+        LBox {
+            width: self.measure.advance(text, Variant::UPRIGHT) * size,
+            ascent: ascent * size * scale - y,
+            descent: descent * size * scale + y,
+            items: vec![Item::Glyph { x: 0.0, y, text: text.to_string(), size, variant: Variant::UPRIGHT, scale_y: scale }],
+        }
+    }
+
+    fn accent(&self, accent: &str, body: &Node, style: Style) -> LBox {
+        let size = style.size();
+        let body_box = self.layout(body, style);
+        let is_arrow = accent == "→";
+        let accent_size = if is_arrow { 0.75 * size } else { size };
+        let accent_w = self.measure.advance(accent, Variant::UPRIGHT) * accent_size;
+        // 記号の下端が本体の少し上に来るように置く (修飾文字はもともと高い位置に描かれる)
+        let baseline = if is_arrow {
+            -(body_box.ascent + 0.02 * size)
+        } else {
+            -(body_box.ascent + 0.04 * size) + 0.52 * size
+        };
+        let italic = matches!(body, Node::Atom { variant: Variant { italic: true, .. }, .. });
+        let skew = if italic { 0.08 * size } else { 0.0 };
+        let x = (body_box.width - accent_w) / 2.0 + skew;
+
+        let body_ascent = body_box.ascent;
+        let body_w = body_box.width;
+        let mut out = LBox::default();
+        out.append(body_box, 0.0, 0.0);
+        out.items.push(Item::Glyph { x, y: baseline, text: accent.to_string(), size: accent_size, variant: Variant::UPRIGHT, scale_y: 1.0 });
+        out.ascent = out.ascent.max(body_ascent + if is_arrow { 0.35 } else { 0.25 } * size);
+        out.width = body_w.max(x + accent_w);
+        out
+    }
+
+    fn array(&self, rows: &[Vec<Node>], cols: &[ColAlign], paired: bool, style: Style) -> LBox {
+        let size = style.size();
+        // align 系は display、行列は text スタイル
+        let cell_style = if paired { style } else { Style { display: false, level: style.level } };
+        let ncols = rows.iter().map(|r| r.len()).max().unwrap_or(0);
+
+        let cells: Vec<Vec<LBox>> = rows
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .enumerate()
+                    .map(|(i, cell)| {
+                        let list = match cell {
+                            Node::Group(list) => list.as_slice(),
+                            other => std::slice::from_ref(other),
+                        };
+                        // align の右側の列は "=" などの前に何かある扱いにして間を空ける
+                        self.layout_list(list, cell_style, paired && i % 2 == 1 && !list.is_empty())
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let mut col_w = vec![0.0f32; ncols];
+        for row in &cells {
+            for (i, cell) in row.iter().enumerate() {
+                col_w[i] = col_w[i].max(cell.width);
+            }
+        }
+        let col_gap = |i: usize| -> f32 {
+            if paired {
+                if i % 2 == 1 { 0.0 } else { 1.0 * size }
+            } else {
+                0.9 * size
+            }
+        };
+
+        let strut_ascent = 0.75 * size;
+        let strut_descent = 0.28 * size;
+        let row_gap = if paired { 0.3 * size } else { 0.2 * size };
+
+        let mut out = LBox::default();
+        let mut y = 0.0;
+        let nrows = cells.len();
+        for (r, row) in cells.into_iter().enumerate() {
+            let ascent = row.iter().map(|c| c.ascent).fold(strut_ascent, f32::max);
+            let descent = row.iter().map(|c| c.descent).fold(strut_descent, f32::max);
+            y += ascent;
+            let mut x = 0.0;
+            for (i, cell) in row.into_iter().enumerate() {
+                if i > 0 {
+                    x += col_gap(i);
+                }
+                let align = cols.get(i).copied().unwrap_or(ColAlign::Center);
+                let dx = match align {
+                    ColAlign::Left => 0.0,
+                    ColAlign::Center => (col_w[i] - cell.width) / 2.0,
+                    ColAlign::Right => col_w[i] - cell.width,
+                };
+                out.append(cell, x + dx, y);
+                x += col_w[i];
+            }
+            y += descent;
+            if r + 1 < nrows {
+                y += row_gap;
+            }
+        }
+
+        let total_w: f32 = col_w.iter().sum::<f32>() + (1..ncols).map(col_gap).sum::<f32>();
+        // 全体の中心を軸に合わせる
+        let shift = -y / 2.0 - AXIS * size;
+        let mut centered = LBox::default();
+        centered.append(out, 0.0, shift);
+        centered.ascent = -shift;
+        centered.descent = y + shift;
+        centered.width = total_w;
+        centered
+    }
+}
+
+/// 要素ごとのスペーシング用の種類を決める
+/// 文脈上二項演算子になれない Bin は Ord 扱いにする (先頭の - など)
+fn resolve_classes(list: &[Node], leading_ord: bool) -> Vec<Option<Class>> {
+    let mut classes: Vec<Option<Class>> = list.iter().map(node_class).collect();
+    let mut prev: Option<Class> = if leading_ord { Some(Class::Ord) } else { None };
+    for i in 0..classes.len() {
+        let Some(c) = classes[i] else { continue };
+        if c == Class::Bin {
+            let next = classes[i + 1..].iter().flatten().next().copied();
+            let bad_prev = matches!(prev, None | Some(Class::Bin | Class::Op | Class::Rel | Class::Open | Class::Punct));
+            let bad_next = matches!(next, None | Some(Class::Rel | Class::Close | Class::Punct));
+            if bad_prev || bad_next {
+                classes[i] = Some(Class::Ord);
+            }
+        }
+        prev = classes[i];
+    }
+    classes
+}
+
+fn node_class(node: &Node) -> Option<Class> {
+    match node {
+        Node::Atom { class, .. } => Some(*class),
+        Node::Space(_) => None,
+        Node::Scripts { base, .. } => node_class(base).or(Some(Class::Ord)),
+        Node::BigOp { .. } => Some(Class::Op),
+        Node::SizedDelim { class, .. } => Some(*class),
+        _ => Some(Class::Ord),
+    }
+}
+
+/// TeX の原子間スペース (em, サイズ 1.0 のとき)
+fn spacing(prev: Class, cur: Class, style: Style) -> f32 {
+    use Class::*;
+    let thin = 3.0 / 18.0;
+    let medium = 4.0 / 18.0;
+    let thick = 5.0 / 18.0;
+    let script = style.level > 0;
+    match (prev, cur) {
+        (Bin, _) | (_, Bin) => if script { 0.0 } else { medium },
+        (Rel, Rel) | (Rel, Punct) => 0.0,
+        (Rel, _) | (_, Rel) => if script { 0.0 } else { thick },
+        (Punct, _) => if script { 0.0 } else { thin },
+        (Op, Ord) | (Op, Op) | (Ord, Op) | (Close, Op) => thin,
+        _ => 0.0,
+    }
+}
+
+/// 文字列のおおよその高さと深さ (サイズ 1.0 のとき)
+fn text_extent(text: &str) -> (f32, f32) {
+    let mut ascent: f32 = 0.0;
+    let mut descent: f32 = 0.0;
+    for c in text.chars() {
+        let (a, d) = char_extent(c);
+        ascent = ascent.max(a);
+        descent = descent.max(d);
+    }
+    (ascent, descent)
+}
+
+fn char_extent(c: char) -> (f32, f32) {
+    match c {
+        ' ' => (0.0, 0.0),
+        'a' | 'c' | 'e' | 'm' | 'n' | 'o' | 'r' | 's' | 'u' | 'v' | 'w' | 'x' | 'z'
+        | 'α' | 'ε' | 'ϵ' | 'ι' | 'κ' | 'ν' | 'ο' | 'π' | 'σ' | 'τ' | 'υ' | 'ω' => (0.46, 0.01),
+        'g' | 'p' | 'q' | 'y' | 'γ' | 'η' | 'μ' | 'ρ' | 'ϱ' | 'χ' | 'ς' => (0.46, 0.22),
+        'j' | 'β' | 'ζ' | 'ξ' | 'φ' | 'ϕ' | 'ψ' | 'f' => (0.75, 0.22),
+        '+' | '−' | '=' | '×' | '÷' | '±' | '∓' | '<' | '>' | '≤' | '≥' | '≠' | '≈' | '∼' | '≡' | '⋅' | '∗' | '∘' => (0.58, 0.08),
+        ',' | ';' => (0.1, 0.16),
+        '.' | '…' => (0.1, 0.0),
+        '⋯' => (0.3, 0.0),
+        '(' | ')' | '[' | ']' | '{' | '}' | '|' | '‖' | '⟨' | '⟩' | '⌊' | '⌋' | '⌈' | '⌉' => (0.78, 0.22),
+        '∑' | '∏' | '∐' | '⋃' | '⋂' | '⨁' | '⨂' | '⋁' | '⋀' => (0.78, 0.22),
+        '∫' | '∬' | '∭' | '∮' => (0.8, 0.2),
+        '′' => (0.75, 0.0),
+        c if c.is_ascii() => (0.72, 0.0),
+        c if (c as u32) >= 0x2E80 => (0.82, 0.12),
+        _ => (0.72, 0.02),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tex::parse::parse;
+
+    /// 1文字 0.5em の等幅
+    struct Fixed;
+
+    impl Measure for Fixed {
+        fn advance(&self, text: &str, _variant: Variant) -> f32 {
+            text.chars().count() as f32 * 0.5
+        }
+    }
+
+    fn layout(expr: &str, display: bool) -> LBox {
+        Layouter { measure: &Fixed }.layout(&parse(expr).unwrap(), Style::new(display))
+    }
+
+    fn glyph_size(lbox: &LBox, wanted: &str) -> f32 {
+        lbox.items
+            .iter()
+            .find_map(|item| match item {
+                Item::Glyph { text, size, .. } if text == wanted => Some(*size),
+                _ => None,
+            })
+            .unwrap_or_else(|| panic!("no glyph {wanted}"))
+    }
+
+    #[test]
+    fn relations_get_thick_spaces() {
+        let lbox = layout("a=b", false);
+        assert!((lbox.width - (1.5 + 2.0 * 5.0 / 18.0)).abs() < 1e-4, "{}", lbox.width);
+        // 添字の中では空けない
+        let scripted = layout("x_{a=b}", false);
+        assert!(scripted.width < 0.5 + 1.5 * 0.7 + 0.1, "{}", scripted.width);
+    }
+
+    #[test]
+    fn fractions_stack_around_the_axis() {
+        let x = layout("x", false);
+        let frac = layout(r"\frac{x}{y}", false);
+        assert!(frac.ascent > x.ascent);
+        assert!(frac.descent > x.descent);
+        // 分子と分母は中央揃えで、横幅は広い方 + 余白
+        assert!((frac.width - (0.5 * 0.7 + 0.24 * 1.0)).abs() < 1e-4, "{}", frac.width);
+        assert!(frac.items.iter().any(|item| matches!(item, Item::Rule { .. })));
+
+        // display では中身も大きいまま
+        let display = layout(r"\frac{x}{y}", true);
+        assert_eq!(glyph_size(&display, "x"), 1.0);
+        assert_eq!(glyph_size(&frac, "x"), 0.7);
+    }
+
+    #[test]
+    fn scripts_shrink() {
+        let lbox = layout("x^{y^z}", false);
+        assert_eq!(glyph_size(&lbox, "x"), 1.0);
+        assert_eq!(glyph_size(&lbox, "y"), 0.7);
+        assert_eq!(glyph_size(&lbox, "z"), 0.5);
+    }
+
+    #[test]
+    fn display_limits_go_above_and_below() {
+        let inline = layout(r"\sum_{i=1}^{n} i", false);
+        let display = layout(r"\sum_{i=1}^{n} i", true);
+        assert!(display.ascent > inline.ascent);
+        assert!(display.descent > inline.descent);
+    }
+
+    #[test]
+    fn matrix_rows_and_delimiters() {
+        let one = layout(r"\begin{pmatrix} a \end{pmatrix}", false);
+        let two = layout(r"\begin{pmatrix} a \\ b \end{pmatrix}", false);
+        assert!(two.ascent + two.descent > one.ascent + one.descent);
+        assert!(two.items.iter().any(|item| matches!(item, Item::Glyph { text, .. } if text == "(")));
+    }
+}
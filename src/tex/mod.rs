@@ -0,0 +1,307 @@
+//! LaTeX 数式の組版 (ブラウザやキャプチャサーバを使わずにプロセス内で完結させる)
+//! 数式の部分集合を解析して自前でレイアウトし、SVG を経由して PNG にする
+
+mod layout;
+mod parse;
+mod symbols;
+
+use std::{collections::HashMap, fmt::Write as _, sync::{Arc, Mutex, OnceLock}};
+
+use resvg::{tiny_skia, usvg::{self, fontdb}};
+
+use layout::{Item, LBox, Layouter, Measure, Style};
+use parse::Variant;
+
+/// 探すセリフ体 (上から順に見つかったものを使う)
+const SERIF_CANDIDATES: &[&str] = &[
+    "DejaVu Serif",
+    "Noto Serif",
+    "Liberation Serif",
+    "Times New Roman",
+    "FreeSerif",
+];
+
+/// 1em あたりのピクセル数 (scale 1.0 のとき)
+const PX_PER_EM: f32 = 32.0;
+/// 受け付ける数式の長さ (文字数)
+pub const MAX_EXPR_CHARS: usize = 4000;
+/// 画像の一辺の上限 (ピクセル)
+const MAX_SIDE_PX: f32 = 8192.0;
+/// 画像の面積の上限 (ピクセル、RGBA で 64 MiB)
+const MAX_PIXELS: f32 = 16_000_000.0;
+
+/// 数式のモード
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MathMode {
+    /// 別行立て ($$...$$)
+    Display,
+    /// 文中 ($...$)
+    Inline,
+}
+
+impl MathMode {
+    pub fn parse(s: &str) -> Option<MathMode> {
+        match s {
+            "display" | "block" => Some(MathMode::Display),
+            "inline" | "text" => Some(MathMode::Inline),
+            _ => None,
+        }
+    }
+}
+
+/// 描画オプション
+#[derive(Debug, Clone)]
+pub struct RenderOptions {
+    pub mode: MathMode,
+    /// 拡大率 (1.0 で 1em = 32px)
+    pub scale: f32,
+    /// 文字色 (#rrggbb)
+    pub foreground: String,
+    /// 背景色 (#rrggbb)、None なら透過
+    pub background: Option<String>,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        RenderOptions {
+            mode: MathMode::Display,
+            scale: 1.0,
+            foreground: "#000000".to_string(),
+            background: Some("#ffffff".to_string()),
+        }
+    }
+}
+
+/// フォントのデータ (ttf-parser で毎回パースする)
+struct FaceData {
+    data: Arc<Vec<u8>>,
+    index: u32,
+}
+
+impl FaceData {
+    fn advance(&self, c: char) -> Option<f32> {
+        let face = ttf_parser::Face::parse(&self.data, self.index).ok()?;
+        let glyph = face.glyph_index(c)?;
+        let adv = face.glyph_hor_advance(glyph)?;
+        Some(adv as f32 / face.units_per_em() as f32)
+    }
+}
+
+/// 文字幅の計測 (主フォントに無い文字は他のフォントから探す)
+struct FontMetrics {
+    regular: FaceData,
+    italic: Option<FaceData>,
+    bold: Option<FaceData>,
+    db: Arc<fontdb::Database>,
+    fallback: Mutex<HashMap<char, f32>>,
+}
+
+impl FontMetrics {
+    fn char_advance(&self, c: char, variant: Variant) -> f32 {
+        let face = match (variant.bold, variant.italic) {
+            (true, _) => self.bold.as_ref(),
+            (false, true) => self.italic.as_ref(),
+            _ => None,
+        }
+        .unwrap_or(&self.regular);
+        if let Some(adv) = face.advance(c).or_else(|| self.regular.advance(c)) {
+            return adv;
+        }
+
+        let mut cache = self.fallback.lock().unwrap();
+        *cache.entry(c).or_insert_with(|| {
+            self.db
+                .faces()
+                .find_map(|info| {
+                    self.db.with_face_data(info.id, |data, index| {
+                        let face = ttf_parser::Face::parse(data, index).ok()?;
+                        let glyph = face.glyph_index(c)?;
+                        Some(face.glyph_hor_advance(glyph)? as f32 / face.units_per_em() as f32)
+                    })?
+                })
+                .unwrap_or(0.6)
+        })
+    }
+}
+
+impl Measure for FontMetrics {
+    fn advance(&self, text: &str, variant: Variant) -> f32 {
+        text.chars().map(|c| self.char_advance(c, variant)).sum()
+    }
+}
+
+/// 数式レンダラ
+pub struct TexRenderer {
+    family: String,
+    db: Arc<fontdb::Database>,
+    metrics: FontMetrics,
+}
+
+impl TexRenderer {
+    /// システムフォントを読み込んで初期化する
+    /// 使えるセリフ体が無ければエラー
+    pub fn new() -> Result<TexRenderer, String> {
+        let mut db = fontdb::Database::new();
+        db.load_system_fonts();
+
+        let family = SERIF_CANDIDATES
+            .iter()
+            .find(|name| db.faces().any(|f| f.families.iter().any(|(fam, _)| fam == *name)))
+            .ok_or_else(|| format!("no serif font found (install one of: {})", SERIF_CANDIDATES.join(", ")))?
+            .to_string();
+        db.set_serif_family(family.clone());
+
+        let load = |style: fontdb::Style, weight: fontdb::Weight| -> Option<FaceData> {
+            let id = db.query(&fontdb::Query {
+                families: &[fontdb::Family::Name(&family)],
+                weight,
+                style,
+                ..Default::default()
+            })?;
+            db.with_face_data(id, |data, index| FaceData { data: Arc::new(data.to_vec()), index })
+        };
+        let regular = load(fontdb::Style::Normal, fontdb::Weight::NORMAL)
+            .ok_or_else(|| format!("failed to load font data for {family}"))?;
+        let italic = load(fontdb::Style::Italic, fontdb::Weight::NORMAL);
+        let bold = load(fontdb::Style::Normal, fontdb::Weight::BOLD);
+
+        let db = Arc::new(db);
+        Ok(TexRenderer {
+            family,
+            db: db.clone(),
+            metrics: FontMetrics { regular, italic, bold, db, fallback: Mutex::new(HashMap::new()) },
+        })
+    }
+
+    /// 使用しているフォント名
+    pub fn font_family(&self) -> &str {
+        &self.family
+    }
+
+    /// 数式を SVG にする
+    /// 大きすぎて画像にできない数式はここで断る
+    pub fn render_svg(&self, expr: &str, options: &RenderOptions) -> Result<String, String> {
+        let chars = expr.chars().count();
+        if chars > MAX_EXPR_CHARS {
+            return Err(format!("expression is too long ({chars} chars, max {MAX_EXPR_CHARS})"));
+        }
+        let node = parse::parse(expr)?;
+        let layouter = Layouter { measure: &self.metrics };
+        let lbox = layouter.layout(&node, Style::new(options.mode == MathMode::Display));
+        let (width, height) = canvas_size(&lbox, options);
+        if !(width <= MAX_SIDE_PX && height <= MAX_SIDE_PX && width * height <= MAX_PIXELS) {
+            return Err(format!("expression is too large to render ({width}x{height}px)"));
+        }
+        Ok(self.to_svg(&lbox, options))
+    }
+
+    /// 数式を PNG にする
+    pub fn render_png(&self, expr: &str, options: &RenderOptions) -> Result<Vec<u8>, String> {
+        // 大きさは render_svg で確かめてあるので、ここで確保する画像も上限に収まる
+        let svg = self.render_svg(expr, options)?;
+
+        let usvg_options = usvg::Options {
+            font_family: self.family.clone(),
+            fontdb: self.db.clone(),
+            ..Default::default()
+        };
+        let tree = usvg::Tree::from_str(&svg, &usvg_options).map_err(|e| format!("failed to build svg tree: {e}"))?;
+
+        let size = tree.size().to_int_size();
+        let mut pixmap = tiny_skia::Pixmap::new(size.width(), size.height())
+            .ok_or("expression is too large to render".to_string())?;
+        resvg::render(&tree, tiny_skia::Transform::default(), &mut pixmap.as_mut());
+        pixmap.encode_png().map_err(|e| format!("failed to encode png: {e}"))
+    }
+
+    fn to_svg(&self, lbox: &LBox, options: &RenderOptions) -> String {
+        let px = pixels_per_em(options);
+        let (width, height) = canvas_size(lbox, options);
+        let origin_x = PAD;
+        let origin_y = PAD + lbox.ascent;
+        let fg = xml_escape(&options.foreground);
+
+        let mut svg = String::new();
+        let _ = write!(
+            svg,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="{height}" viewBox="0 0 {width} {height}">"#
+        );
+        if let Some(bg) = &options.background {
+            let _ = write!(svg, r#"<rect width="100%" height="100%" fill="{}"/>"#, xml_escape(bg));
+        }
+        let _ = write!(
+            svg,
+            r#"<g fill="{fg}" font-family="{}" transform="scale({px}) translate({origin_x} {origin_y})">"#,
+            xml_escape(&self.family)
+        );
+
+        for item in &lbox.items {
+            match item {
+                Item::Glyph { x, y, text, size, variant, scale_y } => {
+                    let style = if variant.italic { r#" font-style="italic""# } else { "" };
+                    let weight = if variant.bold { r#" font-weight="bold""# } else { "" };
+                    if (*scale_y - 1.0).abs() < 0.01 {
+                        let _ = write!(
+                            svg,
+                            r#"<text x="{x:.4}" y="{y:.4}" font-size="{size:.4}"{style}{weight} xml:space="preserve">{}</text>"#,
+                            xml_escape(text)
+                        );
+                    } else {
+                        // 括弧を縦に伸ばす
+                        let _ = write!(
+                            svg,
+                            r#"<text transform="translate({x:.4} {y:.4}) scale(1 {scale_y:.4})" font-size="{size:.4}"{style}{weight}>{}</text>"#,
+                            xml_escape(text)
+                        );
+                    }
+                }
+                Item::Rule { x, y, width, height } => {
+                    let _ = write!(svg, r#"<rect x="{x:.4}" y="{y:.4}" width="{width:.4}" height="{height:.4}"/>"#);
+                }
+                Item::Path { points, stroke } => {
+                    let d = points
+                        .iter()
+                        .enumerate()
+                        .map(|(i, (x, y))| format!("{}{x:.4} {y:.4}", if i == 0 { "M" } else { "L" }))
+                        .collect::<Vec<_>>()
+                        .join(" ");
+                    let _ = write!(
+                        svg,
+                        r#"<path d="{d}" fill="none" stroke="{fg}" stroke-width="{stroke:.4}" stroke-linejoin="round"/>"#
+                    );
+                }
+            }
+        }
+        svg.push_str("</g></svg>");
+        svg
+    }
+}
+
+/// 数式の周りの余白 (em)
+const PAD: f32 = 0.3;
+
+fn pixels_per_em(options: &RenderOptions) -> f32 {
+    PX_PER_EM * options.scale.clamp(0.25, 8.0)
+}
+
+/// 画像の大きさ (ピクセル)
+fn canvas_size(lbox: &LBox, options: &RenderOptions) -> (f32, f32) {
+    let px = pixels_per_em(options);
+    let width = ((lbox.width + PAD * 2.0) * px).ceil().max(1.0);
+    let height = ((lbox.ascent + lbox.descent + PAD * 2.0) * px).ceil().max(1.0);
+    (width, height)
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+static RENDERER: OnceLock<Result<TexRenderer, String>> = OnceLock::new();
+
+/// 共有のレンダラ (初回呼び出し時にフォントを読み込む)
+pub fn renderer() -> Result<&'static TexRenderer, String> {
+    RENDERER.get_or_init(TexRenderer::new).as_ref().map_err(|e| e.clone())
+}
@@ -0,0 +1,763 @@
+//! TeX 数式のパーサ
+//! よく使うサブセット (分数, 根号, 添字, 括弧, アクセント, 行列, align 系環境) を扱う
+
+use super::symbols::{self, Class};
+
+/// 入れ子の深さの上限 ({ } や \frac の引数をたどる再帰でスタックを使い切らないように)
+pub const MAX_DEPTH: usize = 64;
+
+/// 文字の書体
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Variant {
+    pub italic: bool,
+    pub bold: bool,
+}
+
+impl Variant {
+    pub const UPRIGHT: Variant = Variant { italic: false, bold: false };
+    pub const ITALIC: Variant = Variant { italic: true, bold: false };
+}
+
+/// 列の揃え方
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColAlign {
+    Left,
+    Center,
+    Right,
+}
+
+/// 分数のスタイル指定 (\dfrac, \tfrac)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FracStyle {
+    Auto,
+    Display,
+    Text,
+}
+
+#[derive(Debug, Clone)]
+pub enum Node {
+    /// 記号や文字
+    Atom { text: String, class: Class, variant: Variant },
+    /// \text{} の中身 (空白をそのまま残す)
+    Text { text: String, variant: Variant },
+    Group(Vec<Node>),
+    Frac { num: Box<Node>, den: Box<Node>, rule: bool, style: FracStyle },
+    Sqrt { index: Option<Box<Node>>, body: Box<Node> },
+    Scripts { base: Box<Node>, sub: Option<Box<Node>>, sup: Option<Box<Node>> },
+    /// 大型演算子・関数名 (text が true なら立体の文字列として描く)
+    BigOp { symbol: String, limits: bool, text: bool },
+    /// \left ... \right
+    Delimited { left: String, body: Box<Node>, right: String },
+    /// \big( など固定倍率の括弧
+    SizedDelim { text: String, class: Class, scale: f32 },
+    Accent { accent: String, body: Box<Node> },
+    Overline(Box<Node>),
+    Underline(Box<Node>),
+    /// em 単位の空白
+    Space(f32),
+    /// 行列や align 環境
+    Array {
+        rows: Vec<Vec<Node>>,
+        cols: Vec<ColAlign>,
+        /// align 系は列 2つで 1組 (右揃え + 左揃え)
+        paired: bool,
+        left: Option<String>,
+        right: Option<String>,
+    },
+}
+
+/// 数式を解析する
+pub fn parse(expr: &str) -> Result<Node, String> {
+    let mut parser = Parser { chars: expr.chars().collect(), pos: 0, depth: 0 };
+    let first = parser.parse_list()?;
+
+    // トップレベルに \\ や & があれば複数行として扱う
+    let node = match parser.peek_stop() {
+        Stop::Eof => Node::Group(first),
+        Stop::Amp | Stop::RowBreak => {
+            let rows = parser.parse_rows(first, None)?;
+            let paired = rows.iter().any(|r| r.len() > 1);
+            array_node(rows, paired, None, None, None)
+        }
+        Stop::Close => return Err("unmatched '}'".to_string()),
+        Stop::End => return Err("unmatched \\end".to_string()),
+        Stop::Right => return Err("unmatched \\right".to_string()),
+    };
+
+    if parser.pos < parser.chars.len() {
+        return Err(format!("unexpected input at position {}", parser.pos));
+    }
+    Ok(node)
+}
+
+/// parse_list が止まった理由
+#[derive(Debug, PartialEq, Eq)]
+enum Stop {
+    Eof,
+    Close,
+    Amp,
+    RowBreak,
+    End,
+    Right,
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    /// 今たどっている入れ子の深さ
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn skip_ws(&mut self) {
+        while self.peek().is_some_and(|c| c.is_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    /// 次のトークンがコマンドならその名前を返す (消費しない)
+    fn peek_command(&self) -> Option<String> {
+        if self.peek() != Some('\\') {
+            return None;
+        }
+        let mut i = self.pos + 1;
+        let first = *self.chars.get(i)?;
+        if !first.is_ascii_alphabetic() {
+            return Some(first.to_string());
+        }
+        let mut name = String::new();
+        while let Some(&c) = self.chars.get(i) {
+            if !c.is_ascii_alphabetic() {
+                break;
+            }
+            name.push(c);
+            i += 1;
+        }
+        Some(name)
+    }
+
+    fn read_command(&mut self) -> Result<String, String> {
+        let name = self.peek_command().ok_or("expected a command")?;
+        self.pos += 1 + name.chars().count();
+        // `\operatorname*` のような * 付き
+        if name.chars().all(|c| c.is_ascii_alphabetic()) && self.peek() == Some('*') {
+            self.pos += 1;
+            return Ok(format!("{name}*"));
+        }
+        Ok(name)
+    }
+
+    fn peek_stop(&mut self) -> Stop {
+        self.skip_ws();
+        match self.peek() {
+            None => Stop::Eof,
+            Some('}') => Stop::Close,
+            Some('&') => Stop::Amp,
+            Some('\\') => match self.peek_command().as_deref() {
+                Some("\\") | Some("cr") => Stop::RowBreak,
+                Some("end") => Stop::End,
+                Some("right") => Stop::Right,
+                _ => Stop::Eof,
+            },
+            _ => Stop::Eof,
+        }
+    }
+
+    /// 1段深く読む (深すぎればエラー)
+    fn nested<T>(&mut self, read: impl FnOnce(&mut Parser) -> Result<T, String>) -> Result<T, String> {
+        if self.depth >= MAX_DEPTH {
+            return Err(format!("expression is nested too deeply (max {MAX_DEPTH} levels)"));
+        }
+        self.depth += 1;
+        let result = read(self);
+        self.depth -= 1;
+        result
+    }
+
+    /// 止まるべきトークン ( } & \\ \end \right 終端) まで読む
+    fn parse_list(&mut self) -> Result<Vec<Node>, String> {
+        self.nested(Parser::read_list)
+    }
+
+    fn read_list(&mut self) -> Result<Vec<Node>, String> {
+        let mut list: Vec<Node> = Vec::new();
+        loop {
+            self.skip_ws();
+            let Some(c) = self.peek() else { break };
+            match c {
+                '}' | '&' => break,
+                '\\' if matches!(self.peek_command().as_deref(), Some("\\") | Some("cr") | Some("end") | Some("right")) => break,
+                '^' | '_' => {
+                    self.pos += 1;
+                    let arg = self.parse_arg()?;
+                    attach_script(&mut list, c == '^', arg)?;
+                }
+                '\'' => {
+                    self.pos += 1;
+                    let mut primes = String::from("′");
+                    while self.peek() == Some('\'') {
+                        self.pos += 1;
+                        primes.push('′');
+                    }
+                    attach_script(&mut list, true, atom(&primes, Class::Ord, Variant::UPRIGHT))?;
+                }
+                '{' => {
+                    self.pos += 1;
+                    let inner = self.parse_list()?;
+                    self.expect('}')?;
+                    list.push(Node::Group(inner));
+                }
+                '\\' => {
+                    if let Some(node) = self.parse_command(&mut list)? {
+                        list.push(node);
+                    }
+                }
+                _ => {
+                    self.pos += 1;
+                    list.push(char_atom(c));
+                }
+            }
+        }
+        Ok(list)
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        self.skip_ws();
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(format!("expected '{c}'"))
+        }
+    }
+
+    /// `{...}` か 1トークンを引数として読む
+    fn parse_arg(&mut self) -> Result<Node, String> {
+        self.nested(Parser::read_arg)
+    }
+
+    fn read_arg(&mut self) -> Result<Node, String> {
+        self.skip_ws();
+        match self.peek() {
+            None => Err("missing argument".to_string()),
+            Some('{') => {
+                self.pos += 1;
+                let inner = self.parse_list()?;
+                self.expect('}')?;
+                Ok(Node::Group(inner))
+            }
+            Some('\\') => {
+                let mut scratch = Vec::new();
+                match self.parse_command(&mut scratch)? {
+                    Some(node) => Ok(node),
+                    None => Ok(Node::Group(scratch)),
+                }
+            }
+            Some(c) if c == '}' || c == '&' || c == '^' || c == '_' => Err(format!("unexpected '{c}'")),
+            Some(c) => {
+                self.pos += 1;
+                Ok(char_atom(c))
+            }
+        }
+    }
+
+    /// `{...}` の中身を生の文字列で読む (\text 用)
+    fn parse_raw_arg(&mut self) -> Result<String, String> {
+        self.skip_ws();
+        if self.peek() != Some('{') {
+            // 1文字だけ
+            let c = self.peek().ok_or("missing argument")?;
+            self.pos += 1;
+            return Ok(c.to_string());
+        }
+        self.pos += 1;
+        let mut depth = 1;
+        let mut out = String::new();
+        while let Some(c) = self.peek() {
+            self.pos += 1;
+            match c {
+                '{' => depth += 1,
+                '}' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(out);
+                    }
+                }
+                '\\' => {
+                    // \{ \} \\ などのエスケープは中身だけ残す
+                    if let Some(next) = self.peek()
+                        && !next.is_ascii_alphabetic()
+                    {
+                        self.pos += 1;
+                        out.push(next);
+                        continue;
+                    }
+                }
+                _ => {}
+            }
+            out.push(c);
+        }
+        Err("unterminated '{'".to_string())
+    }
+
+    /// `[...]` の省略可能引数
+    fn parse_optional_arg(&mut self) -> Result<Option<Node>, String> {
+        self.skip_ws();
+        if self.peek() != Some('[') {
+            return Ok(None);
+        }
+        self.pos += 1;
+        let mut inner = Vec::new();
+        loop {
+            self.skip_ws();
+            match self.peek() {
+                None => return Err("unterminated '['".to_string()),
+                Some(']') => {
+                    self.pos += 1;
+                    break;
+                }
+                Some(_) => {
+                    let node = self.parse_arg()?;
+                    inner.push(node);
+                }
+            }
+        }
+        Ok(Some(Node::Group(inner)))
+    }
+
+    /// \left などに続く括弧 1つ
+    fn parse_delimiter(&mut self) -> Result<(String, Class), String> {
+        self.skip_ws();
+        match self.peek() {
+            Some('\\') => {
+                let name = self.read_command()?;
+                match symbols::symbol(&name) {
+                    Some((s, class)) => Ok((s.to_string(), class)),
+                    None => Err(format!("unknown delimiter \\{name}")),
+                }
+            }
+            Some('.') => {
+                self.pos += 1;
+                Ok((String::new(), Class::Ord))
+            }
+            Some(c) => {
+                self.pos += 1;
+                let class = match c {
+                    '(' | '[' | '<' => Class::Open,
+                    ')' | ']' | '>' => Class::Close,
+                    _ => Class::Ord,
+                };
+                let s = match c {
+                    '<' => '⟨',
+                    '>' => '⟩',
+                    other => other,
+                };
+                Ok((s.to_string(), class))
+            }
+            None => Err("missing delimiter".to_string()),
+        }
+    }
+
+    /// コマンドを 1つ読む
+    /// 直前の要素を書き換えるもの (\limits など) は list を直接いじって None を返す
+    fn parse_command(&mut self, list: &mut [Node]) -> Result<Option<Node>, String> {
+        let name = self.read_command()?;
+
+        if let Some((s, class)) = symbols::symbol(&name) {
+            // ギリシャ小文字はイタリック
+            let variant = if s.chars().all(|c| ('α'..='ω').contains(&c) || "ϵϑϖϱϕ".contains(c)) {
+                Variant::ITALIC
+            } else {
+                Variant::UPRIGHT
+            };
+            return Ok(Some(atom(s, class, variant)));
+        }
+        if let Some((s, limits)) = symbols::big_operator(&name) {
+            return Ok(Some(Node::BigOp { symbol: s.to_string(), limits, text: false }));
+        }
+        if let Some((s, limits)) = symbols::operator_name(&name) {
+            return Ok(Some(Node::BigOp { symbol: s.to_string(), limits, text: true }));
+        }
+        if let Some(accent) = symbols::accent(&name) {
+            let body = self.parse_arg()?;
+            return Ok(Some(Node::Accent { accent: accent.to_string(), body: Box::new(body) }));
+        }
+
+        let node = match name.as_str() {
+            "frac" | "dfrac" | "tfrac" | "cfrac" => {
+                let num = self.parse_arg()?;
+                let den = self.parse_arg()?;
+                let style = match name.as_str() {
+                    "dfrac" | "cfrac" => FracStyle::Display,
+                    "tfrac" => FracStyle::Text,
+                    _ => FracStyle::Auto,
+                };
+                Node::Frac { num: Box::new(num), den: Box::new(den), rule: true, style }
+            }
+            "binom" | "dbinom" | "tbinom" => {
+                let num = self.parse_arg()?;
+                let den = self.parse_arg()?;
+                let style = match name.as_str() {
+                    "dbinom" => FracStyle::Display,
+                    "tbinom" => FracStyle::Text,
+                    _ => FracStyle::Auto,
+                };
+                Node::Delimited {
+                    left: "(".to_string(),
+                    body: Box::new(Node::Frac { num: Box::new(num), den: Box::new(den), rule: false, style }),
+                    right: ")".to_string(),
+                }
+            }
+            "sqrt" => {
+                let index = self.parse_optional_arg()?;
+                let body = self.parse_arg()?;
+                Node::Sqrt { index: index.map(Box::new), body: Box::new(body) }
+            }
+            "left" => {
+                let (left, _) = self.parse_delimiter()?;
+                let body = self.parse_list()?;
+                if self.peek_stop() != Stop::Right {
+                    return Err("\\left without matching \\right".to_string());
+                }
+                self.read_command()?;
+                let (right, _) = self.parse_delimiter()?;
+                Node::Delimited { left, body: Box::new(Node::Group(body)), right }
+            }
+            "big" | "bigl" | "bigr" | "bigm" => self.sized_delim(1.2)?,
+            "Big" | "Bigl" | "Bigr" | "Bigm" => self.sized_delim(1.8)?,
+            "bigg" | "biggl" | "biggr" | "biggm" => self.sized_delim(2.4)?,
+            "Bigg" | "Biggl" | "Biggr" | "Biggm" => self.sized_delim(3.0)?,
+            "text" | "textrm" | "mbox" | "textnormal" | "textup" => {
+                Node::Text { text: self.parse_raw_arg()?, variant: Variant::UPRIGHT }
+            }
+            "textit" => Node::Text { text: self.parse_raw_arg()?, variant: Variant::ITALIC },
+            "textbf" => Node::Text { text: self.parse_raw_arg()?, variant: Variant { italic: false, bold: true } },
+            "operatorname" | "operatorname*" => {
+                let text = self.parse_raw_arg()?;
+                Node::BigOp { symbol: text, limits: name.ends_with('*'), text: true }
+            }
+            "mathrm" | "mathsf" | "mathtt" | "mathup" => restyle(self.parse_arg()?, &|_| Variant::UPRIGHT),
+            "mathit" => restyle(self.parse_arg()?, &|_| Variant::ITALIC),
+            "mathbf" => restyle(self.parse_arg()?, &|_| Variant { italic: false, bold: true }),
+            "boldsymbol" | "bm" => restyle(self.parse_arg()?, &|v| Variant { italic: v.italic, bold: true }),
+            "mathbb" | "mathcal" | "mathscr" | "mathfrak" => remap(self.parse_arg()?, &name),
+            "overline" | "bar" => Node::Overline(Box::new(self.parse_arg()?)),
+            "underline" => Node::Underline(Box::new(self.parse_arg()?)),
+            "," | "thinspace" => Node::Space(3.0 / 18.0),
+            ":" | ">" | "medspace" => Node::Space(4.0 / 18.0),
+            ";" | "thickspace" => Node::Space(5.0 / 18.0),
+            "!" | "negthinspace" => Node::Space(-3.0 / 18.0),
+            " " | "enspace" => Node::Space(0.5),
+            "quad" => Node::Space(1.0),
+            "qquad" => Node::Space(2.0),
+            "limits" | "nolimits" => {
+                if let Some(Node::BigOp { limits, .. }) = list.last_mut() {
+                    *limits = name == "limits";
+                }
+                return Ok(None);
+            }
+            "label" | "tag" => {
+                self.parse_raw_arg()?;
+                return Ok(None);
+            }
+            "displaystyle" | "textstyle" | "nonumber" | "notag" => return Ok(None),
+            "begin" => self.parse_environment()?,
+            other => return Err(format!("unsupported command \\{other}")),
+        };
+        Ok(Some(node))
+    }
+
+    fn sized_delim(&mut self, scale: f32) -> Result<Node, String> {
+        let (text, class) = self.parse_delimiter()?;
+        Ok(Node::SizedDelim { text, class, scale })
+    }
+
+    /// \begin{...} ... \end{...}
+    fn parse_environment(&mut self) -> Result<Node, String> {
+        let env = self.parse_raw_arg()?;
+        let env = env.trim().to_string();
+
+        let (paired, left, right): (bool, Option<&str>, Option<&str>) = match env.as_str() {
+            "align" | "align*" | "aligned" | "split" | "alignat" | "alignat*" | "flalign" | "flalign*" => {
+                (true, None, None)
+            }
+            "gather" | "gather*" | "gathered" | "equation" | "equation*" | "multline" | "multline*" => {
+                (false, None, None)
+            }
+            "matrix" | "smallmatrix" | "array" => (false, None, None),
+            "pmatrix" => (false, Some("("), Some(")")),
+            "bmatrix" => (false, Some("["), Some("]")),
+            "Bmatrix" => (false, Some("{"), Some("}")),
+            "vmatrix" => (false, Some("|"), Some("|")),
+            "Vmatrix" => (false, Some("‖"), Some("‖")),
+            "cases" => (false, Some("{"), None),
+            other => return Err(format!("unsupported environment {{{other}}}")),
+        };
+
+        // alignat{n} / array{lcr} の引数
+        let mut cols_spec: Option<Vec<ColAlign>> = None;
+        if env.starts_with("alignat") {
+            self.parse_raw_arg()?;
+        }
+        if env == "array" {
+            let spec = self.parse_raw_arg()?;
+            cols_spec = Some(
+                spec.chars()
+                    .filter_map(|c| match c {
+                        'l' => Some(ColAlign::Left),
+                        'c' => Some(ColAlign::Center),
+                        'r' => Some(ColAlign::Right),
+                        _ => None,
+                    })
+                    .collect(),
+            );
+        }
+
+        let first = self.parse_list()?;
+        let rows = self.parse_rows(first, Some(&env))?;
+
+        let cols = if env == "cases" {
+            Some(vec![ColAlign::Left, ColAlign::Left])
+        } else {
+            cols_spec
+        };
+        Ok(array_node(rows, paired, cols, left, right))
+    }
+
+    /// & と \\ で区切られた行を読む
+    /// env が Some なら対応する \end まで、None なら入力の終わりまで
+    fn parse_rows(&mut self, first: Vec<Node>, env: Option<&str>) -> Result<Vec<Vec<Node>>, String> {
+        let mut rows: Vec<Vec<Node>> = Vec::new();
+        let mut row: Vec<Node> = vec![Node::Group(first)];
+        loop {
+            match self.peek_stop() {
+                Stop::Amp => {
+                    self.pos += 1;
+                    row.push(Node::Group(self.parse_list()?));
+                }
+                Stop::RowBreak => {
+                    self.read_command()?;
+                    // \\[2pt] のような行間指定は読み飛ばす
+                    self.skip_ws();
+                    if self.peek() == Some('[') {
+                        while let Some(c) = self.peek() {
+                            self.pos += 1;
+                            if c == ']' {
+                                break;
+                            }
+                        }
+                    }
+                    rows.push(std::mem::take(&mut row));
+                    row.push(Node::Group(self.parse_list()?));
+                }
+                Stop::End => {
+                    let Some(env) = env else {
+                        return Err("unmatched \\end".to_string());
+                    };
+                    self.read_command()?;
+                    let name = self.parse_raw_arg()?;
+                    if name.trim() != env {
+                        return Err(format!("\\begin{{{env}}} closed by \\end{{{}}}", name.trim()));
+                    }
+                    break;
+                }
+                Stop::Eof if self.pos >= self.chars.len() => {
+                    if let Some(env) = env {
+                        return Err(format!("missing \\end{{{env}}}"));
+                    }
+                    break;
+                }
+                Stop::Close => return Err("unmatched '}'".to_string()),
+                Stop::Right => return Err("unmatched \\right".to_string()),
+                Stop::Eof => return Err(format!("unexpected input at position {}", self.pos)),
+            }
+        }
+        // 末尾の空行 (最後の \\ の後ろ) は捨てる
+        let trailing_empty = row.len() == 1 && matches!(&row[0], Node::Group(g) if g.is_empty());
+        if !trailing_empty || rows.is_empty() {
+            rows.push(row);
+        }
+        Ok(rows)
+    }
+}
+
+fn atom(text: &str, class: Class, variant: Variant) -> Node {
+    Node::Atom { text: text.to_string(), class, variant }
+}
+
+/// 地の文字 1つ
+fn char_atom(c: char) -> Node {
+    match c {
+        'a'..='z' | 'A'..='Z' => atom(&c.to_string(), Class::Ord, Variant::ITALIC),
+        '+' => atom("+", Class::Bin, Variant::UPRIGHT),
+        '-' => atom("−", Class::Bin, Variant::UPRIGHT),
+        '*' => atom("∗", Class::Bin, Variant::UPRIGHT),
+        '=' | '<' | '>' | ':' => atom(&c.to_string(), Class::Rel, Variant::UPRIGHT),
+        ',' | ';' => atom(&c.to_string(), Class::Punct, Variant::UPRIGHT),
+        '(' | '[' => atom(&c.to_string(), Class::Open, Variant::UPRIGHT),
+        ')' | ']' => atom(&c.to_string(), Class::Close, Variant::UPRIGHT),
+        '~' => Node::Space(1.0 / 3.0),
+        other => atom(&other.to_string(), Class::Ord, Variant::UPRIGHT),
+    }
+}
+
+/// 直前の要素に添字を付ける
+fn attach_script(list: &mut Vec<Node>, is_sup: bool, arg: Node) -> Result<(), String> {
+    let base = list.pop().unwrap_or(Node::Group(Vec::new()));
+    let node = match base {
+        Node::Scripts { base, sub, sup } => {
+            if is_sup {
+                if sup.is_some() {
+                    return Err("double superscript".to_string());
+                }
+                Node::Scripts { base, sub, sup: Some(Box::new(arg)) }
+            } else {
+                if sub.is_some() {
+                    return Err("double subscript".to_string());
+                }
+                Node::Scripts { base, sub: Some(Box::new(arg)), sup }
+            }
+        }
+        base => {
+            if is_sup {
+                Node::Scripts { base: Box::new(base), sub: None, sup: Some(Box::new(arg)) }
+            } else {
+                Node::Scripts { base: Box::new(base), sub: Some(Box::new(arg)), sup: None }
+            }
+        }
+    };
+    list.push(node);
+    Ok(())
+}
+
+/// 配下の文字の書体を変える (\mathbf など)
+fn restyle(node: Node, f: &dyn Fn(Variant) -> Variant) -> Node {
+    match node {
+        Node::Atom { text, class, variant } => Node::Atom { text, class, variant: f(variant) },
+        Node::Group(list) => Node::Group(list.into_iter().map(|n| restyle(n, f)).collect()),
+        Node::Scripts { base, sub, sup } => Node::Scripts {
+            base: Box::new(restyle(*base, f)),
+            sub,
+            sup,
+        },
+        other => other,
+    }
+}
+
+/// 配下の英字を \mathbb などの Unicode 文字に置き換える
+fn remap(node: Node, font: &str) -> Node {
+    match node {
+        Node::Atom { text, class, .. } => Node::Atom {
+            text: text.chars().map(|c| symbols::styled_char(font, c)).collect(),
+            class,
+            variant: Variant::UPRIGHT,
+        },
+        Node::Group(list) => Node::Group(list.into_iter().map(|n| remap(n, font)).collect()),
+        other => other,
+    }
+}
+
+fn array_node(
+    rows: Vec<Vec<Node>>,
+    paired: bool,
+    cols: Option<Vec<ColAlign>>,
+    left: Option<&str>,
+    right: Option<&str>,
+) -> Node {
+    let n = rows.iter().map(|r| r.len()).max().unwrap_or(0);
+    let cols = cols.unwrap_or_else(|| {
+        (0..n)
+            .map(|i| match (paired, i % 2) {
+                (true, 0) => ColAlign::Right,
+                (true, _) => ColAlign::Left,
+                (false, _) => ColAlign::Center,
+            })
+            .collect()
+    });
+    Node::Array {
+        rows,
+        cols,
+        paired,
+        left: left.map(|s| s.to_string()),
+        right: right.map(|s| s.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn group(node: Node) -> Vec<Node> {
+        match node {
+            Node::Group(list) => list,
+            other => panic!("expected a group, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn fractions_and_scripts() {
+        let list = group(parse(r"\frac{a}{b}^2 + x_i'").unwrap());
+        assert_eq!(list.len(), 3);
+        let Node::Scripts { base, sub: None, sup: Some(_) } = &list[0] else {
+            panic!("expected a superscript, got {:?}", list[0]);
+        };
+        assert!(matches!(**base, Node::Frac { rule: true, style: FracStyle::Auto, .. }));
+        assert!(matches!(&list[1], Node::Atom { text, class: Class::Bin, .. } if text == "+"));
+        // x_i' は添字とプライムが同じ Scripts にまとまる
+        assert!(matches!(&list[2], Node::Scripts { sub: Some(_), sup: Some(_), .. }));
+    }
+
+    #[test]
+    fn environments_and_rows() {
+        let list = group(parse(r"\begin{align} f(x) &= x^2 \\ &= x \cdot x \end{align}").unwrap());
+        let [Node::Array { rows, cols, paired: true, left: None, right: None }] = list.as_slice() else {
+            panic!("expected an align array, got {list:?}");
+        };
+        assert_eq!(rows.len(), 2);
+        assert_eq!(cols, &[ColAlign::Right, ColAlign::Left]);
+
+        let list = group(parse(r"\begin{pmatrix} a & b \\ c & d \\ \end{pmatrix}").unwrap());
+        let [Node::Array { rows, left: Some(left), .. }] = list.as_slice() else {
+            panic!("expected a matrix, got {list:?}");
+        };
+        // 最後の \\ の後ろの空行は数えない
+        assert_eq!(rows.len(), 2);
+        assert_eq!(left, "(");
+
+        // トップレベルの \\ も複数行になる
+        assert!(matches!(parse(r"a \\ b").unwrap(), Node::Array { paired: false, .. }));
+    }
+
+    #[test]
+    fn text_and_fonts() {
+        let list = group(parse(r"\text{if } \mathbb{R} \mathbf{v}").unwrap());
+        assert!(matches!(&list[0], Node::Text { text, .. } if text == "if "));
+        assert!(matches!(&list[1], Node::Group(g) if matches!(&g[0], Node::Atom { text, .. } if text == "ℝ")));
+        assert!(matches!(&list[2], Node::Group(g) if matches!(&g[0], Node::Atom { variant: Variant { bold: true, italic: false }, .. })));
+    }
+
+    #[test]
+    fn errors() {
+        for expr in [r"\frac{a", "a}", r"\left( x", r"\begin{align} x \end{cases}", r"x^", r"x^2^3", r"\nosuchcommand", r"\end{align}"] {
+            assert!(parse(expr).is_err(), "{expr} should fail");
+        }
+    }
+
+    #[test]
+    fn deep_nesting_is_rejected() {
+        let braces = format!("{}x{}", "{".repeat(900), "}".repeat(900));
+        assert!(parse(&braces).unwrap_err().contains("nested too deeply"));
+        let roots = format!("{}x", r"\sqrt".repeat(900));
+        assert!(parse(&roots).unwrap_err().contains("nested too deeply"));
+        let scripts = format!("{}x{}", "x^{".repeat(900), "}".repeat(900));
+        assert!(parse(&scripts).unwrap_err().contains("nested too deeply"));
+
+        // 上限より浅ければ読める
+        let shallow = format!("{}x{}", "{".repeat(MAX_DEPTH - 2), "}".repeat(MAX_DEPTH - 2));
+        assert!(parse(&shallow).is_ok());
+    }
+}
@@ -0,0 +1,289 @@
+//! コマンド名 → 記号の対応表
+
+/// 記号の種類 (前後のスペースの入れ方が変わる)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Class {
+    Ord,
+    Bin,
+    Rel,
+    Open,
+    Close,
+    Punct,
+    /// 大型演算子・関数名
+    Op,
+}
+
+/// `\alpha` などの記号コマンド
+pub fn symbol(name: &str) -> Option<(&'static str, Class)> {
+    use Class::*;
+    let s = match name {
+        // ギリシャ文字 (小文字)
+        "alpha" => ("α", Ord),
+        "beta" => ("β", Ord),
+        "gamma" => ("γ", Ord),
+        "delta" => ("δ", Ord),
+        "epsilon" => ("ϵ", Ord),
+        "varepsilon" => ("ε", Ord),
+        "zeta" => ("ζ", Ord),
+        "eta" => ("η", Ord),
+        "theta" => ("θ", Ord),
+        "vartheta" => ("ϑ", Ord),
+        "iota" => ("ι", Ord),
+        "kappa" => ("κ", Ord),
+        "lambda" => ("λ", Ord),
+        "mu" => ("μ", Ord),
+        "nu" => ("ν", Ord),
+        "xi" => ("ξ", Ord),
+        "omicron" => ("ο", Ord),
+        "pi" => ("π", Ord),
+        "varpi" => ("ϖ", Ord),
+        "rho" => ("ρ", Ord),
+        "varrho" => ("ϱ", Ord),
+        "sigma" => ("σ", Ord),
+        "varsigma" => ("ς", Ord),
+        "tau" => ("τ", Ord),
+        "upsilon" => ("υ", Ord),
+        "phi" => ("ϕ", Ord),
+        "varphi" => ("φ", Ord),
+        "chi" => ("χ", Ord),
+        "psi" => ("ψ", Ord),
+        "omega" => ("ω", Ord),
+        // ギリシャ文字 (大文字)
+        "Gamma" => ("Γ", Ord),
+        "Delta" => ("Δ", Ord),
+        "Theta" => ("Θ", Ord),
+        "Lambda" => ("Λ", Ord),
+        "Xi" => ("Ξ", Ord),
+        "Pi" => ("Π", Ord),
+        "Sigma" => ("Σ", Ord),
+        "Upsilon" => ("Υ", Ord),
+        "Phi" => ("Φ", Ord),
+        "Psi" => ("Ψ", Ord),
+        "Omega" => ("Ω", Ord),
+        // 二項演算子
+        "pm" => ("±", Bin),
+        "mp" => ("∓", Bin),
+        "times" => ("×", Bin),
+        "div" => ("÷", Bin),
+        "cdot" => ("⋅", Bin),
+        "ast" => ("∗", Bin),
+        "star" => ("⋆", Bin),
+        "circ" => ("∘", Bin),
+        "bullet" => ("∙", Bin),
+        "cup" => ("∪", Bin),
+        "cap" => ("∩", Bin),
+        "setminus" => ("∖", Bin),
+        "oplus" => ("⊕", Bin),
+        "ominus" => ("⊖", Bin),
+        "otimes" => ("⊗", Bin),
+        "odot" => ("⊙", Bin),
+        "wedge" | "land" => ("∧", Bin),
+        "vee" | "lor" => ("∨", Bin),
+        "mod" | "bmod" => ("mod", Bin),
+        // 関係
+        "leq" | "le" => ("≤", Rel),
+        "geq" | "ge" => ("≥", Rel),
+        "neq" | "ne" => ("≠", Rel),
+        "approx" => ("≈", Rel),
+        "equiv" => ("≡", Rel),
+        "sim" => ("∼", Rel),
+        "simeq" => ("≃", Rel),
+        "cong" => ("≅", Rel),
+        "propto" => ("∝", Rel),
+        "ll" => ("≪", Rel),
+        "gg" => ("≫", Rel),
+        "in" => ("∈", Rel),
+        "notin" => ("∉", Rel),
+        "ni" => ("∋", Rel),
+        "subset" => ("⊂", Rel),
+        "supset" => ("⊃", Rel),
+        "subseteq" => ("⊆", Rel),
+        "supseteq" => ("⊇", Rel),
+        "perp" => ("⊥", Rel),
+        "parallel" => ("∥", Rel),
+        "mid" => ("∣", Rel),
+        "to" | "rightarrow" => ("→", Rel),
+        "leftarrow" | "gets" => ("←", Rel),
+        "leftrightarrow" => ("↔", Rel),
+        "Rightarrow" => ("⇒", Rel),
+        "Leftarrow" => ("⇐", Rel),
+        "Leftrightarrow" => ("⇔", Rel),
+        "implies" => ("⟹", Rel),
+        "impliedby" => ("⟸", Rel),
+        "iff" => ("⟺", Rel),
+        "mapsto" => ("↦", Rel),
+        "uparrow" => ("↑", Rel),
+        "downarrow" => ("↓", Rel),
+        "longrightarrow" => ("⟶", Rel),
+        "longleftarrow" => ("⟵", Rel),
+        "coloneqq" => ("≔", Rel),
+        // 括弧
+        "langle" => ("⟨", Open),
+        "rangle" => ("⟩", Close),
+        "lfloor" => ("⌊", Open),
+        "rfloor" => ("⌋", Close),
+        "lceil" => ("⌈", Open),
+        "rceil" => ("⌉", Close),
+        "{" | "lbrace" => ("{", Open),
+        "}" | "rbrace" => ("}", Close),
+        "|" | "Vert" => ("‖", Ord),
+        "vert" => ("|", Ord),
+        // その他
+        "infty" => ("∞", Ord),
+        "partial" => ("∂", Ord),
+        "nabla" => ("∇", Ord),
+        "forall" => ("∀", Ord),
+        "exists" => ("∃", Ord),
+        "nexists" => ("∄", Ord),
+        "emptyset" | "varnothing" => ("∅", Ord),
+        "hbar" => ("ℏ", Ord),
+        "ell" => ("ℓ", Ord),
+        "Re" => ("ℜ", Ord),
+        "Im" => ("ℑ", Ord),
+        "aleph" => ("ℵ", Ord),
+        "angle" => ("∠", Ord),
+        "triangle" => ("△", Ord),
+        "neg" | "lnot" => ("¬", Ord),
+        "prime" => ("′", Ord),
+        "degree" => ("°", Ord),
+        "dots" | "ldots" => ("…", Ord),
+        "cdots" => ("⋯", Ord),
+        "vdots" => ("⋮", Ord),
+        "ddots" => ("⋱", Ord),
+        "therefore" => ("∴", Ord),
+        "because" => ("∵", Ord),
+        "checkmark" => ("✓", Ord),
+        "%" => ("%", Ord),
+        "$" => ("$", Ord),
+        "#" => ("#", Ord),
+        "&" => ("&", Ord),
+        "_" => ("_", Ord),
+        _ => return None,
+    };
+    Some(s)
+}
+
+/// 大型演算子 (記号, 上下に添字を置くか)
+pub fn big_operator(name: &str) -> Option<(&'static str, bool)> {
+    let s = match name {
+        "sum" => ("∑", true),
+        "prod" => ("∏", true),
+        "coprod" => ("∐", true),
+        "bigcup" => ("⋃", true),
+        "bigcap" => ("⋂", true),
+        "bigoplus" => ("⨁", true),
+        "bigotimes" => ("⨂", true),
+        "bigvee" => ("⋁", true),
+        "bigwedge" => ("⋀", true),
+        "int" => ("∫", false),
+        "iint" => ("∬", false),
+        "iiint" => ("∭", false),
+        "oint" => ("∮", false),
+        _ => return None,
+    };
+    Some(s)
+}
+
+/// 立体で書く関数名 (名前, 上下に添字を置くか)
+pub fn operator_name(name: &str) -> Option<(&'static str, bool)> {
+    let s = match name {
+        "sin" => ("sin", false),
+        "cos" => ("cos", false),
+        "tan" => ("tan", false),
+        "cot" => ("cot", false),
+        "sec" => ("sec", false),
+        "csc" => ("csc", false),
+        "arcsin" => ("arcsin", false),
+        "arccos" => ("arccos", false),
+        "arctan" => ("arctan", false),
+        "sinh" => ("sinh", false),
+        "cosh" => ("cosh", false),
+        "tanh" => ("tanh", false),
+        "log" => ("log", false),
+        "ln" => ("ln", false),
+        "lg" => ("lg", false),
+        "exp" => ("exp", false),
+        "det" => ("det", true),
+        "dim" => ("dim", false),
+        "ker" => ("ker", false),
+        "deg" => ("deg", false),
+        "gcd" => ("gcd", true),
+        "lcm" => ("lcm", true),
+        "arg" => ("arg", false),
+        "hom" => ("hom", false),
+        "Pr" => ("Pr", true),
+        "lim" => ("lim", true),
+        "limsup" => ("lim sup", true),
+        "liminf" => ("lim inf", true),
+        "max" => ("max", true),
+        "min" => ("min", true),
+        "sup" => ("sup", true),
+        "inf" => ("inf", true),
+        "argmax" => ("argmax", true),
+        "argmin" => ("argmin", true),
+        _ => return None,
+    };
+    Some(s)
+}
+
+/// アクセント記号
+pub fn accent(name: &str) -> Option<&'static str> {
+    let s = match name {
+        "hat" | "widehat" => "ˆ",
+        "tilde" | "widetilde" => "˜",
+        "check" => "ˇ",
+        "breve" => "˘",
+        "acute" => "ˊ",
+        "grave" => "ˋ",
+        "dot" => "˙",
+        "ddot" => "¨",
+        "vec" | "overrightarrow" => "→",
+        _ => return None,
+    };
+    Some(s)
+}
+
+/// 書体ごとの Unicode の並び (大文字の先頭, 小文字の先頭, 数字の先頭, 並びから外れた文字)
+type StyledRange = (u32, Option<u32>, Option<u32>, &'static [(char, char)]);
+
+/// 1文字をフォント指定 (\mathbb など) に応じた Unicode 文字に置き換える
+pub fn styled_char(font: &str, c: char) -> char {
+    let (upper_base, lower_base, digit_base, exceptions): StyledRange = match font {
+        "mathbb" => (
+            0x1D538,
+            Some(0x1D552),
+            Some(0x1D7D8),
+            &[('C', 'ℂ'), ('H', 'ℍ'), ('N', 'ℕ'), ('P', 'ℙ'), ('Q', 'ℚ'), ('R', 'ℝ'), ('Z', 'ℤ')],
+        ),
+        "mathcal" | "mathscr" => (
+            0x1D49C,
+            Some(0x1D4B6),
+            None,
+            &[
+                ('B', 'ℬ'), ('E', 'ℰ'), ('F', 'ℱ'), ('H', 'ℋ'), ('I', 'ℐ'), ('L', 'ℒ'), ('M', 'ℳ'), ('R', 'ℛ'),
+                ('e', 'ℯ'), ('g', 'ℊ'), ('o', 'ℴ'),
+            ],
+        ),
+        "mathfrak" => (
+            0x1D504,
+            Some(0x1D51E),
+            None,
+            &[('C', 'ℭ'), ('H', 'ℌ'), ('I', 'ℑ'), ('R', 'ℜ'), ('Z', 'ℨ')],
+        ),
+        _ => return c,
+    };
+
+    if let Some((_, mapped)) = exceptions.iter().find(|(from, _)| *from == c) {
+        return *mapped;
+    }
+    let mapped = if c.is_ascii_uppercase() {
+        Some(upper_base + (c as u32 - 'A' as u32))
+    } else if c.is_ascii_lowercase() {
+        lower_base.map(|b| b + (c as u32 - 'a' as u32))
+    } else if c.is_ascii_digit() {
+        digit_base.map(|b| b + (c as u32 - '0' as u32))
+    } else {
+        None
+    };
+    mapped.and_then(char::from_u32).unwrap_or(c)
+}
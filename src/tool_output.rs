@@ -0,0 +1,166 @@
+use std::{collections::{VecDeque, hash_map::RandomState}, hash::{BuildHasher, Hasher}, sync::{Mutex, atomic::{AtomicU64, Ordering}}};
+
+use dashmap::DashMap;
+use log::debug;
+use serenity::all::ChannelId;
+
+/// 大きすぎるツール出力の退避先
+/// 上限を超えた出力は全文をここに置いて、モデルには先頭ページとハンドルだけ渡す
+/// 出力は呼び出し元のチャンネルのもので、ほかのチャンネルからは読めない
+/// (非公開チャンネルの履歴などが別のサーバに漏れないように)
+pub struct ToolOutputs {
+    pub entries: DashMap<String, ToolOutput>,
+    /// 古いものから捨てるための挿入順
+    order: Mutex<VecDeque<String>>,
+    /// ハンドルの乱数の元 (連番だけだとほかのハンドルを推測できる)
+    next_id: AtomicU64,
+    random: RandomState,
+    /// 1ページあたりの最大文字数 (これを超えた出力が退避対象)
+    pub page_chars: usize,
+    /// 保持するエントリ数の上限
+    pub capacity: usize,
+}
+
+/// 退避したツール出力
+#[derive(Clone)]
+pub struct ToolOutput {
+    pub tool_name: String,
+    pub content: String,
+    /// 出力したチャンネル (Discord の外からの呼び出しなら None)
+    pub channel_id: Option<ChannelId>,
+}
+
+impl ToolOutput {
+    pub fn total_chars(&self) -> usize {
+        self.content.chars().count()
+    }
+}
+
+impl ToolOutputs {
+    pub fn new(page_chars: usize, capacity: usize) -> ToolOutputs {
+        ToolOutputs {
+            entries: DashMap::new(),
+            order: Mutex::new(VecDeque::new()),
+            next_id: AtomicU64::new(1),
+            random: RandomState::new(),
+            page_chars: page_chars.max(1),
+            capacity: capacity.max(1),
+        }
+    }
+
+    /// 出力が上限を超えていれば全文を退避し、先頭ページ + 続きの読み方の案内に置き換える
+    pub fn shrink(&self, tool_name: &str, output: String, channel_id: Option<ChannelId>) -> String {
+        let total = output.chars().count();
+        if total <= self.page_chars {
+            return output;
+        }
+
+        let pages = total.div_ceil(self.page_chars);
+        let first_page = slice_chars(&output, 0, self.page_chars).to_string();
+        let handle = self.store(tool_name, output, channel_id);
+        debug!("Tool output of {} truncated ({} chars) -> handle {}", tool_name, total, handle);
+
+        format!(
+            "{first_page}\n\n[output truncated: showing page 1/{pages} ({} of {total} chars). \
+             The full output is stored as handle=\"{handle}\". \
+             Call `read_tool_output` with {{\"handle\": \"{handle}\", \"page\": 2}} to read more, \
+             or {{\"handle\": \"{handle}\", \"summarize\": true}} to get a summary.]",
+            self.page_chars
+        )
+    }
+
+    /// 全文を保存してハンドルを返す
+    pub fn store(&self, tool_name: &str, content: String, channel_id: Option<ChannelId>) -> String {
+        let handle = self.new_handle();
+        self.entries.insert(
+            handle.clone(),
+            ToolOutput {
+                tool_name: tool_name.to_string(),
+                content,
+                channel_id,
+            },
+        );
+
+        let mut order = self.order.lock().expect("tool output order lock");
+        order.push_back(handle.clone());
+        while order.len() > self.capacity {
+            if let Some(old) = order.pop_front() {
+                self.entries.remove(&old);
+            }
+        }
+        handle
+    }
+
+    /// 推測できないハンドル (連番をプロセスごとの乱数の鍵でハッシュする)
+    fn new_handle(&self) -> String {
+        let mut hasher = self.random.build_hasher();
+        hasher.write_u64(self.next_id.fetch_add(1, Ordering::Relaxed));
+        format!("out-{:016x}", hasher.finish())
+    }
+
+    /// `channel_id` から出力したものだけを返す
+    pub fn get(&self, handle: &str, channel_id: Option<ChannelId>) -> Option<ToolOutput> {
+        self.entries.get(handle).filter(|e| e.channel_id == channel_id).map(|e| e.clone())
+    }
+
+    /// 1始まりのページを返す (ページ本文, 総ページ数)
+    /// `channel_id` から出力したものでなければ None
+    pub fn page(&self, handle: &str, page: usize, channel_id: Option<ChannelId>) -> Option<(String, usize)> {
+        let entry = self.entries.get(handle).filter(|e| e.channel_id == channel_id)?;
+        let total = entry.total_chars();
+        let pages = total.div_ceil(self.page_chars).max(1);
+        if page == 0 || page > pages {
+            return Some((String::new(), pages));
+        }
+        let start = (page - 1) * self.page_chars;
+        Some((slice_chars(&entry.content, start, self.page_chars).to_string(), pages))
+    }
+}
+
+/// 文字単位で [start, start+len) を切り出す (UTF-8 境界を壊さない)
+pub fn slice_chars(s: &str, start: usize, len: usize) -> &str {
+    let mut indices = s.char_indices().map(|(i, _)| i).chain(std::iter::once(s.len()));
+    let begin = indices.nth(start).unwrap_or(s.len());
+    let end = if len == 0 {
+        begin
+    } else {
+        indices.nth(len - 1).unwrap_or(s.len())
+    };
+    &s[begin..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn outputs_are_scoped_to_the_channel() {
+        let outputs = ToolOutputs::new(4, 10);
+        let (a, b) = (Some(ChannelId::new(1)), Some(ChannelId::new(2)));
+        let handle = outputs.store("discord-tool", "private history".to_string(), a);
+
+        assert_eq!(outputs.get(&handle, a).map(|o| o.content), Some("private history".to_string()));
+        assert!(outputs.get(&handle, b).is_none());
+        assert!(outputs.get(&handle, None).is_none());
+        assert_eq!(outputs.page(&handle, 2, a), Some(("ate ".to_string(), 4)));
+        assert!(outputs.page(&handle, 2, b).is_none());
+    }
+
+    #[test]
+    fn handles_are_not_sequential() {
+        let outputs = ToolOutputs::new(4, 10);
+        let first = outputs.store("browser", "x".to_string(), None);
+        let second = outputs.store("browser", "y".to_string(), None);
+        assert_ne!(first, second);
+        assert_ne!(first, "out-1");
+        assert!(first.starts_with("out-") && first.len() == 4 + 16, "{first}");
+    }
+
+    #[test]
+    fn old_outputs_are_dropped() {
+        let outputs = ToolOutputs::new(4, 2);
+        let handles: Vec<String> = (0..3).map(|i| outputs.store("browser", i.to_string(), None)).collect();
+        assert!(outputs.get(&handles[0], None).is_none());
+        assert!(outputs.get(&handles[2], None).is_some());
+    }
+}
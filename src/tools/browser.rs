@@ -1,15 +1,325 @@
+use std::{collections::HashSet, time::{Duration, Instant}};
+
+use dashmap::DashMap;
 use log::info;
+use reqwest::Url;
+use scraper::{ElementRef, Html, Node, Selector};
+use serde_json::json;
 use wk_371tti_net_crawler::{ScraperAPIBuilder, schema::ScraperResult};
 
-use crate::{context::ObserverContext, lmclient::LMTool};
+use crate::{config::ToolConfig, context::ObserverContext, lmclient::LMTool, net_guard, tool_output::slice_chars};
+
+/// ページ送り用にスクレイプ結果を持っておく時間
+const PAGE_CACHE_TTL: Duration = Duration::from_secs(300);
+/// ページ送り用キャッシュの件数上限
+const PAGE_CACHE_CAPACITY: usize = 32;
+/// 構造解析用に読む HTML の上限
+const MAX_HTML_BYTES: usize = 5 * 1024 * 1024;
+/// 生 HTML を取るときのタイムアウト
+const FETCH_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// ブラウザツール
+/// 本文 (text) はヘッドレスブラウザ経由、構造 (links/metadata/outline/section) は生 HTML を解析して返す
+pub struct Browser {
+    /// 空でなければこのドメイン (とそのサブドメイン) だけ許可
+    allowed_domains: Vec<String>,
+    /// このドメイン (とそのサブドメイン) は拒否
+    blocked_domains: Vec<String>,
+    /// text モードの 1ページあたりの文字数
+    page_chars: usize,
+    /// 生 HTML 取得用 (内側のアドレスとドメインリストに外れるリダイレクトは断る)
+    http: reqwest::Client,
+    /// (url, selector) → スクレイプ結果
+    pages: DashMap<(String, String), (Instant, ScrapedPage)>,
+}
+
+/// ヘッドレスブラウザで取った本文
+#[derive(Clone)]
+struct ScrapedPage {
+    status: String,
+    url: String,
+    text: String,
+}
 
-#[derive(Default)]
-pub struct Browser {}
+impl Default for Browser {
+    fn default() -> Self {
+        Self {
+            allowed_domains: Vec::new(),
+            blocked_domains: Vec::new(),
+            page_chars: 6_000,
+            http: http_client(Vec::new(), Vec::new()),
+            pages: DashMap::new(),
+        }
+    }
+}
 
 impl Browser {
     pub fn new() -> Browser {
         Browser::default()
     }
+
+    /// config.json の `tools.browser` から組み立てる
+    pub fn from_config(cfg: &ToolConfig) -> Browser {
+        let default = Browser::default();
+        let allowed_domains = cfg.str_list_setting("allowed_domains");
+        let blocked_domains = cfg.str_list_setting("blocked_domains");
+        Browser {
+            http: http_client(allowed_domains.clone(), blocked_domains.clone()),
+            allowed_domains,
+            blocked_domains,
+            page_chars: cfg
+                .u64_setting("page_chars")
+                .map(|n| n as usize)
+                .unwrap_or(default.page_chars)
+                .max(500),
+            ..default
+        }
+    }
+
+    /// ドメインリストに照らして URL を開いてよいか
+    fn check_domain(&self, url: &str) -> Result<(), String> {
+        if self.allowed_domains.is_empty() && self.blocked_domains.is_empty() {
+            return Ok(());
+        }
+        let parsed = reqwest::Url::parse(url).map_err(|e| format!("Invalid 'url': {e}"))?;
+        check_domain_lists(&self.allowed_domains, &self.blocked_domains, &parsed)
+    }
+
+    /// ヘッドレスブラウザで本文を取る (短時間キャッシュ付き)
+    async fn scrape_text(&self, url: &str, selector: &str, ob_ctx: &ObserverContext) -> Result<ScrapedPage, String> {
+        let key = (url.to_string(), selector.to_string());
+        if let Some(entry) = self.pages.get(&key)
+            && entry.0.elapsed() < PAGE_CACHE_TTL
+        {
+            return Ok(entry.1.clone());
+        }
+
+        let result = ob_ctx.scraper.scraper(
+            ScraperAPIBuilder::new(url).set_text_selector(selector).build()
+        ).await;
+
+        let page = match result {
+            Ok(ScraperResult::Success { status, url, results }) => ScrapedPage {
+                status: status.to_string(),
+                url: url.to_string(),
+                text: results.text,
+            },
+            Ok(ScraperResult::Failed { error }) => return Err(format!("Scraper failed: {}", error)),
+            Err(e) => return Err(format!("Error during browsing: {}", e)),
+        };
+
+        if self.pages.len() >= PAGE_CACHE_CAPACITY {
+            self.pages.retain(|_, (at, _)| at.elapsed() < PAGE_CACHE_TTL);
+            if self.pages.len() >= PAGE_CACHE_CAPACITY {
+                self.pages.clear();
+            }
+        }
+        self.pages.insert(key, (Instant::now(), page.clone()));
+        Ok(page)
+    }
+
+    /// 構造解析用に生 HTML を取る
+    /// リダイレクトの各ホップもドメインリストと内側のアドレスの制限に通す
+    async fn fetch_html(&self, url: &str) -> Result<(Url, String), String> {
+        let resp = net_guard::get(&self.http, url).await?;
+        let final_url = resp.url().clone();
+        let status = resp.status();
+        if !status.is_success() {
+            return Err(format!("Fetching {url} returned {status}"));
+        }
+        let body = net_guard::read_body(resp, MAX_HTML_BYTES).await?;
+        Ok((final_url, String::from_utf8_lossy(&body).into_owned()))
+    }
+}
+
+/// 生 HTML 取得用のクライアント
+fn http_client(allowed_domains: Vec<String>, blocked_domains: Vec<String>) -> reqwest::Client {
+    let builder = reqwest::Client::builder()
+        .timeout(FETCH_TIMEOUT)
+        .user_agent("Mozilla/5.0 (compatible; observer-bot)");
+    net_guard::client(builder, move |url| check_domain_lists(&allowed_domains, &blocked_domains, url))
+}
+
+/// 許可・拒否のドメインリストに照らす (サブドメインも含む)
+fn check_domain_lists(allowed_domains: &[String], blocked_domains: &[String], url: &Url) -> Result<(), String> {
+    let host = url.host_str().unwrap_or("").to_lowercase();
+    let matches = |domain: &String| {
+        let domain = domain.to_lowercase();
+        host == domain || host.ends_with(&format!(".{domain}"))
+    };
+
+    if blocked_domains.iter().any(matches) {
+        return Err(format!("Domain '{host}' is blocked by configuration"));
+    }
+    if !allowed_domains.is_empty() && !allowed_domains.iter().any(matches) {
+        return Err(format!("Domain '{host}' is not in the allowed domain list"));
+    }
+    Ok(())
+}
+
+/// 見出しタグのレベル (h1 → 1)
+fn heading_level(name: &str) -> Option<u8> {
+    match name {
+        "h1" => Some(1),
+        "h2" => Some(2),
+        "h3" => Some(3),
+        "h4" => Some(4),
+        "h5" => Some(5),
+        "h6" => Some(6),
+        _ => None,
+    }
+}
+
+fn collapse_ws(s: &str) -> String {
+    s.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn element_text(el: &ElementRef) -> String {
+    collapse_ws(&el.text().collect::<String>())
+}
+
+/// ページ内のリンクを アンカーテキスト + 絶対URL で返す
+fn extract_links(html: &Html, base: &Url, limit: usize) -> Vec<serde_json::Value> {
+    let selector = Selector::parse("a[href]").expect("selector");
+    let mut seen = HashSet::new();
+    html.select(&selector)
+        .filter_map(|a| {
+            let href = a.value().attr("href")?.trim();
+            if href.is_empty() || href.starts_with('#') || href.starts_with("javascript:") {
+                return None;
+            }
+            let url = base.join(href).ok()?.to_string();
+            if !seen.insert(url.clone()) {
+                return None;
+            }
+            let mut text = element_text(&a);
+            if text.is_empty() {
+                text = a.value()
+                    .attr("title")
+                    .or_else(|| a.value().attr("aria-label"))
+                    .unwrap_or("")
+                    .to_string();
+            }
+            Some(json!({ "text": text, "url": url }))
+        })
+        .take(limit)
+        .collect()
+}
+
+/// title / description / 公開日などのメタデータ
+fn extract_metadata(html: &Html) -> serde_json::Value {
+    let meta_selector = Selector::parse("meta").expect("selector");
+    let meta = |keys: &[&str]| -> Option<String> {
+        for key in keys {
+            for m in html.select(&meta_selector) {
+                let v = m.value();
+                let name = v.attr("name")
+                    .or_else(|| v.attr("property"))
+                    .or_else(|| v.attr("itemprop"));
+                if name.is_some_and(|n| n.eq_ignore_ascii_case(key))
+                    && let Some(content) = v.attr("content").map(str::trim).filter(|s| !s.is_empty())
+                {
+                    return Some(content.to_string());
+                }
+            }
+        }
+        None
+    };
+    let first_text = |sel: &str| {
+        let selector = Selector::parse(sel).expect("selector");
+        html.select(&selector)
+            .next()
+            .map(|e| element_text(&e))
+            .filter(|s| !s.is_empty())
+    };
+    let first_attr = |sel: &str, attr: &str| {
+        let selector = Selector::parse(sel).expect("selector");
+        html.select(&selector)
+            .next()
+            .and_then(|e| e.value().attr(attr).map(|s| s.trim().to_string()))
+            .filter(|s| !s.is_empty())
+    };
+
+    json!({
+        "title": first_text("title").or_else(|| meta(&["og:title", "twitter:title"])),
+        "description": meta(&["description", "og:description", "twitter:description"]),
+        "site_name": meta(&["og:site_name", "application-name"]),
+        "author": meta(&["author", "article:author"]),
+        "published": meta(&["article:published_time", "datePublished", "date", "dc.date", "pubdate"])
+            .or_else(|| first_attr("time[datetime]", "datetime")),
+        "modified": meta(&["article:modified_time", "dateModified", "og:updated_time"]),
+        "language": first_attr("html", "lang"),
+        "canonical": first_attr("link[rel=canonical]", "href"),
+        "type": meta(&["og:type"]),
+    })
+}
+
+/// 見出しの一覧
+fn extract_outline(html: &Html) -> Vec<serde_json::Value> {
+    let selector = Selector::parse("h1, h2, h3, h4, h5, h6").expect("selector");
+    html.select(&selector)
+        .filter_map(|h| {
+            let level = heading_level(h.value().name())?;
+            let text = element_text(&h);
+            if text.is_empty() {
+                return None;
+            }
+            Some(json!({ "level": level, "text": text, "id": h.value().attr("id") }))
+        })
+        .collect()
+}
+
+/// 見出しから、次の同レベル以上の見出しまでの本文を返す (見出しテキスト, 本文)
+/// heading は見出しテキストの部分一致 (大文字小文字無視) か `#id`
+fn extract_section(html: &Html, heading: &str) -> Option<(String, String)> {
+    let needle = heading.trim().trim_start_matches('#').to_lowercase();
+    let mut target: Option<(u8, String)> = None;
+    let mut out = String::new();
+
+    for node in html.root_element().descendants() {
+        match node.value() {
+            Node::Element(el) => {
+                let Some(level) = heading_level(el.name()) else {
+                    continue;
+                };
+                let text = ElementRef::wrap(node).map(|e| element_text(&e)).unwrap_or_default();
+                match &target {
+                    Some((target_level, _)) if level <= *target_level => break,
+                    Some(_) => {
+                        // 小見出しは markdown 風に残す
+                        out.push_str(&format!("\n{} {}\n", "#".repeat(level as usize), text));
+                    }
+                    None => {
+                        let id_match = el.attr("id").is_some_and(|id| id.eq_ignore_ascii_case(&needle));
+                        if id_match || text.to_lowercase().contains(&needle) {
+                            target = Some((level, text));
+                        }
+                    }
+                }
+            }
+            Node::Text(text) if target.is_some() => {
+                // 見出し自身と script/style の中身は除く
+                let skip = node.ancestors().any(|a| {
+                    a.value().as_element().is_some_and(|e| {
+                        matches!(e.name(), "script" | "style" | "noscript") || heading_level(e.name()).is_some()
+                    })
+                });
+                if skip {
+                    continue;
+                }
+                let t = collapse_ws(text);
+                if !t.is_empty() {
+                    if !out.is_empty() && !out.ends_with('\n') {
+                        out.push(' ');
+                    }
+                    out.push_str(&t);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    target.map(|(_, title)| (title, out.trim().to_string()))
 }
 
 #[async_trait::async_trait]
@@ -22,22 +332,41 @@ impl LMTool for Browser {
                     "type": "string",
                     "description": "The URL of the webpage to browse."
                 },
+                "mode": {
+                    "type": "string",
+                    "description": "What to get. 'text': page text, split into pages (use 'page' to read on). 'links': links with anchor text as JSON. 'metadata': title, description, publish date, etc. 'outline': list of headings. 'section': text under the heading given by 'heading'. Defaults to 'text'.",
+                    "enum": ["text", "links", "metadata", "outline", "section"],
+                    "default": "text"
+                },
+                "page": {
+                    "type": "integer",
+                    "description": "1-based page of the text to read. Used by: text.",
+                    "default": 1
+                },
+                "selector": {
+                    "type": "string",
+                    "description": "CSS selector to extract specific content from the page. Used by: text."
+                },
                 "with_links": {
                     "type": "boolean",
-                    "description": "Whether to follow links on the page.",
+                    "description": "Also return the page's links as JSON. Used by: text.",
                     "default": false
                 },
-                "selector": {
+                "heading": {
                     "type": "string",
-                    "description": "CSS selector to extract specific content from the page."
+                    "description": "Heading text (partial match) or '#id' of the section to jump to. Used by: section."
+                },
+                "limit": {
+                    "type": "integer",
+                    "description": "Max number of links to return. Defaults to 100. Used by: links."
                 }
             },
-            "required": ["url", "with_links"]
+            "required": ["url"]
         })
     }
 
     fn description(&self) -> String {
-        "Browse a webpage and extract content based on a CSS selector.".to_string()
+        "Browse a webpage: read its text page by page, list links, get metadata, list headings, or jump to a section.".to_string()
     }
 
     fn name(&self) -> String {
@@ -49,35 +378,75 @@ impl LMTool for Browser {
         let url = args.get("url")
             .and_then(|v| v.as_str())
             .ok_or("Missing or invalid 'url' parameter".to_string())?;
-        let selector = args.get("selector")
+        let mode = args.get("mode")
             .and_then(|v| v.as_str())
-            .unwrap_or("");
-        let with_links = args.get("with_links")
-            .and_then(|v| v.as_bool())
-            .unwrap_or(false);
+            .unwrap_or("text");
 
-        let result = ob_ctx.scraper.scraper(
-            ScraperAPIBuilder::new(url).set_text_selector(selector).build()
-        ).await;
+        self.check_domain(url)?;
 
-        match result {
-            Ok(scraper_result) => {
-                match scraper_result {
-                    ScraperResult::Success { status, url, results } => {
-                        let text = results.text;
-                        let links = results.links;
-                        if with_links {
-                            // リンクも含めて返す
-                            Ok(format!("Status: {}\nURL: {}\nExtracted Content:\n{}\nLinks:\n{:?}", status, url, text, links))
-                        } else {
-                            // テキストのみ返す
-                            Ok(format!("Status: {}\nURL: {}\nExtracted Content:\n{}", status, url, text))
-                        }
-                    },
-                    ScraperResult::Failed { error } => Err(format!("Scraper failed: {}", error)),
+        match mode {
+            "text" => {
+                let selector = args.get("selector")
+                    .and_then(|v| v.as_str())
+                    .unwrap_or("");
+                let with_links = args.get("with_links")
+                    .and_then(|v| v.as_bool())
+                    .unwrap_or(false);
+                let page = args.get("page")
+                    .and_then(|v| v.as_u64())
+                    .unwrap_or(1)
+                    .max(1) as usize;
+
+                let scraped = self.scrape_text(url, selector, &ob_ctx).await?;
+                let total = scraped.text.chars().count();
+                let pages = total.div_ceil(self.page_chars).max(1);
+                if page > pages {
+                    return Err(format!("Page {page} is out of range (1-{pages})"));
                 }
+                let body = slice_chars(&scraped.text, (page - 1) * self.page_chars, self.page_chars);
+
+                let mut out = format!(
+                    "Status: {}\nURL: {}\nPage: {}/{}\nExtracted Content:\n{}",
+                    scraped.status, scraped.url, page, pages, body
+                );
+                if page < pages {
+                    out.push_str(&format!("\n[more: call again with \"page\": {}]", page + 1));
+                }
+                if with_links {
+                    // リンクも含めて返す
+                    let (base, html) = self.fetch_html(url).await?;
+                    let links = extract_links(&Html::parse_document(&html), &base, 100);
+                    out.push_str(&format!("\nLinks:\n{}", serde_json::Value::Array(links)));
+                }
+                Ok(out)
             }
-            Err(e) => Err(format!("Error during browsing: {}", e)),
+            "links" | "metadata" | "outline" | "section" => {
+                let (base, html) = self.fetch_html(url).await?;
+                let document = Html::parse_document(&html);
+                let result = match mode {
+                    "links" => {
+                        let limit = args.get("limit")
+                            .and_then(|v| v.as_u64())
+                            .unwrap_or(100) as usize;
+                        let links = extract_links(&document, &base, limit);
+                        json!({ "url": base.as_str(), "link_count": links.len(), "links": links })
+                    }
+                    "metadata" => json!({ "url": base.as_str(), "metadata": extract_metadata(&document) }),
+                    "outline" => json!({ "url": base.as_str(), "headings": extract_outline(&document) }),
+                    _ => {
+                        let heading = args.get("heading")
+                            .and_then(|v| v.as_str())
+                            .ok_or("Missing or invalid 'heading' parameter".to_string())?;
+                        let (title, text) = extract_section(&document, heading)
+                            .ok_or_else(|| format!("No heading matching '{heading}'. Use mode 'outline' to list headings."))?;
+                        json!({ "url": base.as_str(), "heading": title, "text": text })
+                    }
+                };
+                Ok(result.to_string())
+            }
+            other => Err(format!(
+                "Unsupported 'mode': {other}. Use one of: text, links, metadata, outline, section."
+            )),
         }
     }
-}
\ No newline at end of file
+}
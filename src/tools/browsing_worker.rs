@@ -0,0 +1,241 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use log::{debug, info};
+use openai_dive::v1::{api::Client as OpenAIClient, resources::response::{request::ResponseParametersBuilder, response::Role}};
+use serde_json::json;
+use tokio::sync::mpsc;
+
+use crate::{config::{Config, ModelProvider, ToolConfig}, context::ObserverContext, guild::GuildSettings, lmclient::{LMClient, LMContext, LMTool, ToolInvoker}, secret, tools::registry};
+
+/// ツール名 (config.json の `tools` のキー)
+pub const TOOL_NAME: &str = "browsing_worker";
+/// サブエージェントに渡すツールの既定値
+const DEFAULT_TOOLS: &[&str] = &["web_search", "browser", "get-location-time", "read_tool_output"];
+/// ツールとして呼ばれたときの最大ステップ数の既定値
+const DEFAULT_TOOL_MAX_STEPS: u64 = 8;
+/// ツールとして呼ばれたときに使ってよい時間 (`timeout_millis` に対する割合, %)
+/// 残りはメインのモデルがレポートを受けて返答する分
+const TOOL_TIME_PERCENT: u64 = 70;
+
+/// 別のコンテキストで調査を回すサブエージェント
+/// 検索やブラウザを何手も使って調べ、最後にまとめたレポートだけを返す
+/// (途中の検索結果やページ本文はメインの会話に残らない)
+pub struct BrowsingWorker {
+    client: LMClient,
+    model: String,
+    /// 調査 1手あたりの最大トークン数
+    max_tokens: u32,
+    /// レポートの最大トークン数
+    report_max_tokens: u32,
+    /// サブエージェントが使えるツール名
+    tool_names: Vec<String>,
+    /// ツールとして呼ばれたとき (通常の応答の中) に使うクライアント
+    /// 応答全体が `timeout_millis` に収まるようステップ数を絞ってある
+    tool_client: LMClient,
+}
+
+impl BrowsingWorker {
+    /// config.json の `tools.browsing_worker` から組み立てる
+    /// `endpoint` / `api_key` / `model` が無ければメインモデルの設定を流用する
+    pub fn from_config(cfg: &ToolConfig, config: &Config) -> BrowsingWorker {
+        let endpoint = cfg.str_setting("endpoint").unwrap_or(&config.main_model_endpoint).to_string();
+        let api_key = cfg
+            .str_setting("api_key")
+            .map(|s| s.to_string())
+            .or_else(|| secret::from_env("BROWSING_WORKER_API_KEY"))
+            .unwrap_or_else(|| config.main_model_api_key.expose().to_string());
+        let model = cfg.str_setting("model").unwrap_or(&config.main_model_name).to_string();
+
+        let client = match config.model_provider {
+            ModelProvider::OpenAI => {
+                let mut openai = OpenAIClient::new(api_key);
+                openai.set_base_url(endpoint.trim_end_matches('/'));
+                LMClient::new_openai(openai)
+            }
+            ModelProvider::GeminiAIStudio => {
+                LMClient::new_gemini(endpoint, api_key, model.clone())
+            }
+        };
+
+        let mut tool_names = cfg.str_list_setting("tools");
+        if tool_names.is_empty() {
+            tool_names = DEFAULT_TOOLS.iter().map(|s| s.to_string()).collect();
+        }
+        // 自分自身を呼ぶと無限に潜るので外す
+        tool_names.retain(|name| name != TOOL_NAME);
+
+        let max_steps = cfg.u64_setting("max_steps").unwrap_or(30);
+        let tool_max_steps = cfg.u64_setting("tool_max_steps").unwrap_or(DEFAULT_TOOL_MAX_STEPS).min(max_steps);
+
+        BrowsingWorker {
+            tool_client: client.clone().with_max_steps(tool_max_steps as usize),
+            client: client.with_max_steps(max_steps as usize),
+            model,
+            max_tokens: cfg.u64_setting("max_tokens").unwrap_or(4000) as u32,
+            report_max_tokens: cfg.u64_setting("report_max_tokens").unwrap_or(8000) as u32,
+            tool_names,
+        }
+    }
+
+    /// サーバで許可されていないツールをサブエージェントからも外す
+    pub fn with_guild(mut self, guild: &GuildSettings) -> BrowsingWorker {
+        self.tool_names.retain(|name| guild.allows_tool(name));
+        self
+    }
+
+    /// サブエージェント用のツール
+    /// メインで有効なもの (config で切っていない、依存チェックを通った) から選ぶので、インスタンスも共有する
+    fn sub_tools(&self, ob_ctx: &ObserverContext) -> Arc<HashMap<String, Arc<dyn LMTool>>> {
+        let tools = registry::subset(&ob_ctx.tools.get(), |name| self.tool_names.iter().any(|n| n == name));
+        debug!("browsing_worker: tools {:?}", tools.keys().collect::<Vec<_>>());
+        Arc::new(tools)
+    }
+
+    /// 調査に使うモデル名
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn parameters(&self) -> ResponseParametersBuilder {
+        ResponseParametersBuilder::default().model(self.model.as_str()).clone()
+    }
+
+    /// 質問について調べてレポートを返す
+    /// `state_tx` には途中経過 (ツール呼び出しなど) が流れる
+    /// `invoker` はサブエージェントのツール呼び出しの発生元 (退避した出力もそのチャンネルのものになる)
+    pub async fn research(
+        &self,
+        question: &str,
+        background: Option<&str>,
+        ob_ctx: &ObserverContext,
+        state_tx: Option<mpsc::Sender<String>>,
+        invoker: Option<ToolInvoker>,
+    ) -> Result<String, String> {
+        self.research_with(&self.client, question, background, ob_ctx, state_tx, invoker).await
+    }
+
+    async fn research_with(
+        &self,
+        client: &LMClient,
+        question: &str,
+        background: Option<&str>,
+        ob_ctx: &ObserverContext,
+        state_tx: Option<mpsc::Sender<String>>,
+        invoker: Option<ToolInvoker>,
+    ) -> Result<String, String> {
+        let tools = self.sub_tools(ob_ctx);
+        let config = ob_ctx.config.get();
+
+        let mut context = LMContext::new();
+        context.add_text(config.deep_search_developer_prompt.clone(), Role::System);
+        let request = match background {
+            Some(background) => format!("{question}\n\nBackground: {background}"),
+            None => question.to_string(),
+        };
+        context.add_text(request, Role::User);
+
+        // 調査: 別コンテキストでツールを回す
+        // (残るのはモデルの発言だけなので、調べた内容は最後にメモとして書かせる)
+        let notes = client
+            .generate_response(
+                ob_ctx.clone(),
+                &context,
+                Some(self.max_tokens),
+                Some(tools),
+                state_tx.clone(),
+                None,
+                Some(self.parameters()),
+                invoker,
+            )
+            .await
+            .map_err(|e| format!("research failed: {e}"))?;
+        context.extend(&notes);
+
+        // レポート: 調べた内容からまとめだけを書かせる
+        if let Some(tx) = state_tx.as_ref() {
+            let _ = tx.try_send("Writing the report...".to_string());
+        }
+        context.add_text(format!("{}\n{}", config.deep_search_generate_prompt, question), Role::User);
+        let report = client
+            .generate_response(ob_ctx.clone(), &context, Some(self.report_max_tokens), None, None, None, Some(self.parameters()), None)
+            .await
+            .map_err(|e| format!("report generation failed: {e}"))?
+            .get_result();
+
+        if report.trim().is_empty() {
+            return Err("the research produced no report".to_string());
+        }
+        Ok(report)
+    }
+}
+
+#[async_trait::async_trait]
+impl LMTool for BrowsingWorker {
+    fn name(&self) -> String {
+        TOOL_NAME.to_string()
+    }
+
+    fn description(&self) -> String {
+        "Delegate a research question to a separate research agent. \
+It searches the web and reads pages over many steps in its own context, then returns only a consolidated report with sources. \
+Use it for questions that need several searches or reading multiple pages; for a quick lookup use web_search directly. \
+It takes a while, so ask one well-formed question per call."
+            .to_string()
+    }
+
+    fn json_schema(&self) -> serde_json::Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "question": {
+                    "type": "string",
+                    "description": "The research question. Be specific about what should be found."
+                },
+                "background": {
+                    "type": "string",
+                    "description": "Optional context from the conversation that helps the research (what the user already knows, constraints, preferred sources)."
+                },
+                "$explain": {
+                    "type": "string",
+                    "description": "A brief explanation of what you are doing with this tool."
+                }
+            },
+            "required": ["question"]
+        })
+    }
+
+    async fn health_check(&self, config: &Config) -> Result<(), String> {
+        let available = registry::specs().iter().any(|spec| {
+            self.tool_names.iter().any(|n| n == spec.name) && config.tool(spec.name).enabled.unwrap_or(spec.default_enabled)
+        });
+        if available {
+            Ok(())
+        } else {
+            Err("no tools available for the research agent".to_string())
+        }
+    }
+
+    async fn execute(&self, args: serde_json::Value, ob_ctx: ObserverContext) -> Result<String, String> {
+        self.execute_for(args, ob_ctx, None).await
+    }
+
+    async fn execute_for(&self, args: serde_json::Value, ob_ctx: ObserverContext, invoker: Option<ToolInvoker>) -> Result<String, String> {
+        let question = args
+            .get("question")
+            .and_then(|v| v.as_str())
+            .filter(|s| !s.trim().is_empty())
+            .ok_or("Missing or invalid 'question' parameter")?;
+        let background = args.get("background").and_then(|v| v.as_str()).filter(|s| !s.trim().is_empty());
+        info!("browsing_worker: researching {:?}", question);
+        // 通常の応答の timeout_millis の中で終わるよう、時間とステップ数を絞る
+        let budget = Duration::from_millis(ob_ctx.config.get().timeout_millis * TOOL_TIME_PERCENT / 100);
+        tokio::time::timeout(budget, self.research_with(&self.tool_client, question, background, &ob_ctx, None, invoker))
+            .await
+            .unwrap_or_else(|_| {
+                Err(format!(
+                    "research timed out after {}s; ask a narrower question, or suggest /deep_search for long research",
+                    budget.as_secs()
+                ))
+            })
+    }
+}
@@ -0,0 +1,138 @@
+use log::info;
+use serde_json::json;
+use tokio::sync::Semaphore;
+
+use crate::{config::{Config, ToolConfig}, context::ObserverContext, lmclient::LMTool, sandbox::{Build, Language, RunOutput, Sandbox}};
+
+/// 受け付けるソースコードの最大バイト数
+const MAX_SOURCE_BYTES: usize = 64 * 1024;
+/// 受け付ける標準入力の最大バイト数
+const MAX_STDIN_BYTES: usize = 1024 * 1024;
+
+/// Python / Rust / C++ のコードをサンドボックスで実行するツール
+pub struct CodeExec {
+    sandbox: Sandbox,
+    /// 同時に動かす数の上限
+    slots: Semaphore,
+}
+
+impl CodeExec {
+    /// config.json の `tools.code_exec` から組み立てる
+    pub fn from_config(cfg: &ToolConfig) -> CodeExec {
+        CodeExec {
+            sandbox: Sandbox::from_config(cfg),
+            slots: Semaphore::new(cfg.u64_setting("max_concurrent").unwrap_or(2).max(1) as usize),
+        }
+    }
+}
+
+/// 実行結果を JSON にする (切り詰めがあれば notice を付ける)
+pub fn run_output_json(out: &RunOutput) -> serde_json::Value {
+    let mut notice = Vec::new();
+    if out.stdout_truncated {
+        notice.push("stdout was truncated");
+    }
+    if out.stderr_truncated {
+        notice.push("stderr was truncated");
+    }
+    json!({
+        "status": out.status_text(),
+        "exit_code": out.exit_code,
+        "signal": out.signal,
+        "timed_out": out.timed_out,
+        "elapsed_ms": out.elapsed.as_millis() as u64,
+        "stdout": out.stdout,
+        "stderr": out.stderr,
+        "truncated": out.stdout_truncated || out.stderr_truncated,
+        "notice": if notice.is_empty() { None } else { Some(notice.join(", ")) },
+    })
+}
+
+#[async_trait::async_trait]
+impl LMTool for CodeExec {
+    fn name(&self) -> String {
+        "code_exec".to_string()
+    }
+
+    fn description(&self) -> String {
+        format!(
+            "Run a Python, Rust or C++ program in a sandbox and get stdout, stderr and the exit status. \
+No network access. Limits: {}s CPU, {} MiB memory, {} ms wall clock, {} bytes of output per stream. \
+Files can only be written in the temporary working directory.",
+            self.sandbox.limits.cpu_secs,
+            self.sandbox.limits.memory_mb,
+            self.sandbox.limits.wall_millis,
+            self.sandbox.limits.output_bytes,
+        )
+    }
+
+    async fn health_check(&self, _config: &Config) -> Result<(), String> {
+        self.sandbox.self_test().await
+    }
+
+    fn json_schema(&self) -> serde_json::Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "language": {
+                    "type": "string",
+                    "description": "Language of the code.",
+                    "enum": ["python", "rust", "cpp"]
+                },
+                "code": {
+                    "type": "string",
+                    "description": "Full source code. Rust and C++ need a main function. Rust is built with edition 2021 and -O, C++ with -std=gnu++17 -O2."
+                },
+                "stdin": {
+                    "type": "string",
+                    "description": "Optional text given to standard input."
+                },
+                "$explain": {
+                    "type": "string",
+                    "description": "A brief explanation of what you are doing with this tool."
+                }
+            },
+            "required": ["language", "code"]
+        })
+    }
+
+    async fn execute(&self, args: serde_json::Value, _ob_ctx: ObserverContext) -> Result<String, String> {
+        info!("CodeExec::execute called with language: {:?}", args.get("language"));
+        let language = args.get("language")
+            .and_then(|v| v.as_str())
+            .ok_or("Missing or invalid 'language' parameter".to_string())?;
+        let language = Language::parse(language)
+            .ok_or(format!("Unsupported language '{language}' (use python, rust or cpp)"))?;
+        let code = args.get("code")
+            .and_then(|v| v.as_str())
+            .filter(|s| !s.trim().is_empty())
+            .ok_or("Missing or invalid 'code' parameter".to_string())?;
+        if code.len() > MAX_SOURCE_BYTES {
+            return Err(format!("'code' is too large ({} bytes, max {MAX_SOURCE_BYTES})", code.len()));
+        }
+        let stdin = args.get("stdin").and_then(|v| v.as_str());
+        if stdin.is_some_and(|s| s.len() > MAX_STDIN_BYTES) {
+            return Err(format!("'stdin' is too large (max {MAX_STDIN_BYTES} bytes)"));
+        }
+
+        let _slot = self.slots.acquire().await.map_err(|e| e.to_string())?;
+
+        let program = match self.sandbox.build(language, code).await? {
+            Build::Ready(program) => program,
+            Build::CompileError(out) => {
+                return Ok(json!({
+                    "status": "compile_error",
+                    "language": language.name(),
+                    "compile": run_output_json(&out),
+                })
+                .to_string());
+            }
+        };
+
+        let out = program.run(stdin.map(|s| s.as_bytes())).await?;
+
+        let mut result = run_output_json(&out);
+        result["language"] = json!(language.name());
+        Ok(result.to_string())
+    }
+}
@@ -1,24 +1,58 @@
 use std::str::FromStr;
 
+use chrono::{DateTime, NaiveDate, Utc};
 use openai_dive::v1::resources::response::response::Role;
+use regex::{Regex, RegexBuilder};
 use serde_json::json;
 use serenity::all::{
-    Builder, ChannelId, ChannelType, CreateMessage, CreateThread, EditMessage, GetMessages, Message, MessageId, ReactionType
+    Builder, ChannelId, ChannelType, Colour, CreateEmbed, CreateEmbedAuthor, CreateEmbedFooter, CreateMessage, CreatePoll,
+    CreatePollAnswer, CreateThread, EditMessage, Embed, Emoji, GetMessages, Guild, GuildChannel, GuildId, Member, Message,
+    MessageId, Poll, PollMediaEmoji, ReactionType, Role as GuildRole, RoleId, Timestamp, UserId
 };
+use serenity::builder::create_poll;
 
-use crate::lmclient::LMTool;
+use crate::{config::ToolConfig, context::ObserverContext, lmclient::LMTool};
 
-pub struct DiscordTool;
+/// search_messages で depth 未指定のときに遡るメッセージ数
+const DEFAULT_SEARCH_DEPTH: u64 = 500;
+/// search_messages で遡れるメッセージ数の既定の上限
+const DEFAULT_MAX_SEARCH_DEPTH: u64 = 5000;
+/// search_messages で返す件数の上限
+const MAX_SEARCH_RESULTS: u64 = 50;
+/// fuzzy 検索で一致とみなす類似度
+const FUZZY_THRESHOLD: f64 = 0.75;
+/// 1メッセージに付けられる embed の数
+const MAX_EMBEDS: usize = 10;
+/// 投票の期間の上限 (時間)
+const MAX_POLL_HOURS: u64 = 768;
+/// 承認プロンプトに載せる embed や投票の文字列の長さ
+const SUMMARY_TEXT_CHARS: usize = 200;
+/// Discord の snowflake の起点 (2015-01-01T00:00:00Z, ミリ秒)
+const DISCORD_EPOCH_MILLIS: i64 = 1_420_070_400_000;
+
+pub struct DiscordTool {
+    /// search_messages で遡れるメッセージ数の上限
+    max_search_depth: u64,
+}
 
 impl Default for DiscordTool {
     fn default() -> Self {
-        Self
+        Self {
+            max_search_depth: DEFAULT_MAX_SEARCH_DEPTH,
+        }
     }
 }
 
 impl DiscordTool {
     pub fn new() -> DiscordTool {
-        Self
+        Self::default()
+    }
+
+    /// config.json の `tools.discord-tool` から組み立てる
+    pub fn from_config(cfg: &ToolConfig) -> DiscordTool {
+        Self {
+            max_search_depth: cfg.u64_setting("max_search_depth").unwrap_or(DEFAULT_MAX_SEARCH_DEPTH).max(1),
+        }
     }
 
     fn get_str_arg<'a>(args: &'a serde_json::Value, key: &'a str) -> Result<&'a str, String> {
@@ -26,6 +60,534 @@ impl DiscordTool {
             .and_then(|v| v.as_str())
             .ok_or_else(|| format!("Missing or invalid '{key}' parameter"))
     }
+
+    fn get_message_id_arg(args: &serde_json::Value, key: &str) -> Result<Option<MessageId>, String> {
+        args.get(key)
+            .and_then(|v| v.as_str())
+            .filter(|s| !s.is_empty())
+            .map(|s| MessageId::from_str(s).map_err(|e| format!("Invalid '{key}': {e}")))
+            .transpose()
+    }
+
+    /// チャンネルの属するギルドを引く (キャッシュに無ければ HTTP)
+    async fn guild_id_of(ob_ctx: &ObserverContext, channel_id: ChannelId) -> Result<GuildId, String> {
+        let discord = ob_ctx.discord_client.open();
+        let channel = channel_id
+            .to_channel((&discord.cache, discord.http.as_ref()))
+            .await
+            .map_err(|e| format!("Failed to fetch channel: {e}"))?;
+        channel
+            .guild()
+            .map(|c| c.guild_id)
+            .ok_or_else(|| "The channel is not in a guild".to_string())
+    }
+
+    /// 権限の計算に使うチャンネル (スレッドは親チャンネルの権限に従う)
+    fn permission_target<'a>(guild: &'a Guild, channel: &'a GuildChannel) -> &'a GuildChannel {
+        match channel.thread_metadata {
+            Some(_) => channel.parent_id.and_then(|p| guild.channels.get(&p)).unwrap_or(channel),
+            None => channel,
+        }
+    }
+
+    /// bot 自身がチャンネルを見られるか
+    fn bot_can_view(guild: &Guild, channel: &GuildChannel, bot_id: UserId) -> bool {
+        let Some(bot) = guild.members.get(&bot_id) else {
+            return true;
+        };
+        guild.user_permissions_in(Self::permission_target(guild, channel), bot).view_channel()
+    }
+
+    /// チャンネルのギルドのカスタム絵文字 (DM なら空)
+    async fn guild_emojis(ob_ctx: &ObserverContext, channel_id: ChannelId) -> Vec<Emoji> {
+        let Ok(guild_id) = Self::guild_id_of(ob_ctx, channel_id).await else {
+            return Vec::new();
+        };
+        let discord = ob_ctx.discord_client.open();
+        let cached: Option<Vec<Emoji>> = discord.cache.guild(guild_id).map(|g| g.emojis.values().cloned().collect());
+        match cached {
+            Some(emojis) => emojis,
+            None => guild_id.emojis(&discord.http).await.unwrap_or_default(),
+        }
+    }
+
+    /// リアクションの指定を解決する
+    /// Unicode の絵文字, `<:name:id>` / `<a:name:id>`, `:name:` / `name` (ギルドの絵文字を名前で引く), 絵文字 ID を受ける
+    async fn resolve_reaction(ob_ctx: &ObserverContext, channel_id: ChannelId, input: &str) -> Result<ReactionType, String> {
+        let input = input.trim();
+        if input.starts_with('<') {
+            return ReactionType::try_from(input)
+                .map_err(|_| format!("Invalid custom emoji '{input}'. Use <:name:id> or <a:name:id>."));
+        }
+        if !input.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ':') {
+            return Ok(ReactionType::Unicode(input.to_string()));
+        }
+
+        let name = input.trim_matches(':');
+        let emojis = Self::guild_emojis(ob_ctx, channel_id).await;
+        match find_emoji(&emojis, name) {
+            Some(e) => Ok(ReactionType::Custom {
+                animated: e.animated,
+                id: e.id,
+                name: Some(e.name.clone()),
+            }),
+            None => match name.parse::<u64>() {
+                Ok(id) if id > 0 => Ok(ReactionType::Custom {
+                    animated: false,
+                    id: id.into(),
+                    name: None,
+                }),
+                _ => Err(format!("Custom emoji '{name}' not found in this guild. Use list_emojis to see the available ones.")),
+            },
+        }
+    }
+}
+
+/// ID か名前 (大文字小文字を区別して一致するものを優先) で絵文字を探す
+fn find_emoji<'a>(emojis: &'a [Emoji], name: &str) -> Option<&'a Emoji> {
+    emojis
+        .iter()
+        .find(|e| e.id.to_string() == name || e.name == name)
+        .or_else(|| emojis.iter().find(|e| e.name.eq_ignore_ascii_case(name)))
+}
+
+/// 本文中の `:name:` をギルドのカスタム絵文字に置き換える (見つからないものはそのまま)
+fn expand_emoji_shortcodes(content: &str, emojis: &[Emoji]) -> String {
+    if emojis.is_empty() || !content.contains(':') {
+        return content.to_string();
+    }
+    let re = Regex::new(r"<a?:\w+:\d+>|:(\w{2,32}):").expect("valid regex");
+    re.replace_all(content, |caps: &regex::Captures| match caps.get(1).and_then(|name| find_emoji(emojis, name.as_str())) {
+        Some(emoji) => emoji.to_string(),
+        None => caps[0].to_string(),
+    })
+    .into_owned()
+}
+
+/// `#RRGGBB`, `0xRRGGBB` または整数
+fn parse_color(v: &serde_json::Value) -> Result<Colour, String> {
+    if let Some(n) = v.as_u64() {
+        return Ok(Colour::new(n as u32 & 0xFF_FFFF));
+    }
+    let s = v.as_str().ok_or("Invalid embed 'color'")?;
+    let hex = s.trim().trim_start_matches('#').trim_start_matches("0x");
+    u32::from_str_radix(hex, 16)
+        .ok()
+        .filter(|_| hex.len() == 6)
+        .map(Colour::new)
+        .ok_or_else(|| format!("Invalid embed 'color' '{s}'. Use #RRGGBB."))
+}
+
+/// 文字数の上限を確かめて文字列を取り出す
+fn limited_str<'a>(v: &'a serde_json::Value, key: &str, max_chars: usize, what: &str) -> Result<Option<&'a str>, String> {
+    match v.get(key).and_then(|v| v.as_str()).filter(|s| !s.is_empty()) {
+        Some(s) if s.chars().count() > max_chars => Err(format!("{what} '{key}' is longer than {max_chars} characters")),
+        other => Ok(other),
+    }
+}
+
+/// embed の JSON 表現から CreateEmbed を組み立てる
+fn build_embed(v: &serde_json::Value) -> Result<CreateEmbed, String> {
+    let mut embed = CreateEmbed::new();
+    if let Some(title) = limited_str(v, "title", 256, "embed")? {
+        embed = embed.title(title);
+    }
+    if let Some(description) = limited_str(v, "description", 4096, "embed")? {
+        embed = embed.description(description);
+    }
+    if let Some(url) = limited_str(v, "url", 2048, "embed")? {
+        embed = embed.url(url);
+    }
+    if let Some(color) = v.get("color").filter(|c| !c.is_null()) {
+        embed = embed.colour(parse_color(color)?);
+    }
+    if let Some(author) = limited_str(v, "author", 256, "embed")? {
+        embed = embed.author(CreateEmbedAuthor::new(author));
+    }
+    if let Some(footer) = limited_str(v, "footer", 2048, "embed")? {
+        embed = embed.footer(CreateEmbedFooter::new(footer));
+    }
+    if let Some(image) = limited_str(v, "image_url", 2048, "embed")? {
+        embed = embed.image(image);
+    }
+    if let Some(thumbnail) = limited_str(v, "thumbnail_url", 2048, "embed")? {
+        embed = embed.thumbnail(thumbnail);
+    }
+    if let Some(timestamp) = limited_str(v, "timestamp", 64, "embed")? {
+        let timestamp = Timestamp::parse(timestamp).map_err(|e| format!("Invalid embed 'timestamp': {e}"))?;
+        embed = embed.timestamp(timestamp);
+    }
+    if let Some(fields) = v.get("fields").and_then(|f| f.as_array()) {
+        if fields.len() > 25 {
+            return Err("An embed can have at most 25 fields".to_string());
+        }
+        for field in fields {
+            let name = limited_str(field, "name", 256, "embed field")?.ok_or("Embed fields need 'name'")?;
+            let value = limited_str(field, "value", 1024, "embed field")?.ok_or("Embed fields need 'value'")?;
+            embed = embed.field(name, value, field.get("inline").and_then(|i| i.as_bool()).unwrap_or(false));
+        }
+    }
+    Ok(embed)
+}
+
+/// `embeds` (配列) か `embed` (1つ) の JSON を取り出す
+fn embed_values(args: &serde_json::Value) -> Vec<&serde_json::Value> {
+    match (args.get("embeds").and_then(|v| v.as_array()), args.get("embed")) {
+        (Some(embeds), _) => embeds.iter().collect(),
+        (None, Some(embed)) if embed.is_object() => vec![embed],
+        _ => Vec::new(),
+    }
+}
+
+/// `embeds` (配列) か `embed` (1つ) から embed を組み立てる
+fn build_embeds(args: &serde_json::Value) -> Result<Vec<CreateEmbed>, String> {
+    let values = embed_values(args);
+    if values.len() > MAX_EMBEDS {
+        return Err(format!("A message can have at most {MAX_EMBEDS} embeds"));
+    }
+    values.into_iter().map(build_embed).collect()
+}
+
+/// 投票の JSON 表現から CreatePoll を組み立てる
+fn build_poll(v: &serde_json::Value, emojis: &[Emoji]) -> Result<CreatePoll<create_poll::Ready>, String> {
+    let question = limited_str(v, "question", 300, "poll")?.ok_or("Poll needs 'question'")?;
+    let answers = v
+        .get("answers")
+        .and_then(|a| a.as_array())
+        .filter(|a| !a.is_empty())
+        .ok_or("Poll needs 'answers' (1-10 items)")?;
+    if answers.len() > 10 {
+        return Err("A poll can have at most 10 answers".to_string());
+    }
+
+    let answers = answers
+        .iter()
+        .map(|a| {
+            let (text, emoji) = match a.as_str() {
+                Some(text) => (text, None),
+                None => (
+                    a.get("text").and_then(|t| t.as_str()).ok_or("Poll answers need 'text'")?,
+                    a.get("emoji").and_then(|e| e.as_str()).filter(|e| !e.is_empty()),
+                ),
+            };
+            if text.chars().count() > 55 {
+                return Err(format!("Poll answer '{text}' is longer than 55 characters"));
+            }
+            let mut answer = CreatePollAnswer::new().text(text);
+            if let Some(emoji) = emoji {
+                let custom = match ReactionType::try_from(emoji) {
+                    Ok(ReactionType::Custom { id, .. }) => Some(id),
+                    _ => find_emoji(emojis, emoji.trim_matches(':')).map(|e| e.id),
+                };
+                answer = answer.emoji(match custom {
+                    Some(id) => PollMediaEmoji::Id(id),
+                    None => PollMediaEmoji::Name(emoji.to_string()),
+                });
+            }
+            Ok(answer)
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    let hours = v.get("duration_hours").and_then(|d| d.as_u64()).unwrap_or(24).clamp(1, MAX_POLL_HOURS);
+    let mut poll = CreatePoll::new()
+        .question(question)
+        .answers(answers)
+        .duration(std::time::Duration::from_secs(hours * 60 * 60));
+    if v.get("allow_multiselect").and_then(|m| m.as_bool()).unwrap_or(false) {
+        poll = poll.allow_multiselect();
+    }
+    Ok(poll)
+}
+
+fn embed_json(e: &Embed) -> serde_json::Value {
+    json!({
+        "title": e.title,
+        "description": e.description,
+        "url": e.url,
+        "fields": e.fields.iter().map(|f| json!({ "name": f.name, "value": f.value, "inline": f.inline })).collect::<Vec<_>>(),
+        "footer": e.footer.as_ref().map(|f| f.text.clone()),
+    })
+}
+
+fn poll_json(p: &Poll) -> serde_json::Value {
+    let counts = p.results.as_ref().map(|r| &r.answer_counts);
+    json!({
+        "question": p.question.text,
+        "allow_multiselect": p.allow_multiselect,
+        "expiry": p.expiry.map(|t| t.to_string()),
+        "finalized": p.results.as_ref().is_some_and(|r| r.is_finalized),
+        "answers": p.answers.iter().map(|a| json!({
+            "answer_id": a.answer_id.get(),
+            "text": a.poll_media.text,
+            "votes": counts.and_then(|c| c.iter().find(|c| c.id == a.answer_id)).map(|c| c.count).unwrap_or(0),
+        })).collect::<Vec<_>>(),
+    })
+}
+
+/// 結果に載せるメッセージの形
+fn message_json(m: &Message) -> serde_json::Value {
+    json!({
+        "message_id": m.id.to_string(),
+        "author_id": m.author.id.to_string(),
+        "author_name": m.author.name,
+        "content": m.content,
+        "timestamp": m.timestamp.to_string(),
+        "attachments": m.attachments.iter().map(|a| a.url.clone()).collect::<Vec<_>>(),
+    })
+}
+
+fn channel_json(c: &GuildChannel) -> serde_json::Value {
+    let mut value = json!({
+        "channel_id": c.id.to_string(),
+        "name": c.name,
+        "type": c.kind.name(),
+        "parent_id": c.parent_id.map(|p| p.to_string()),
+        "position": c.position,
+    });
+    if let Some(topic) = c.topic.as_ref().filter(|t| !t.is_empty()) {
+        value["topic"] = json!(topic);
+    }
+    if let Some(meta) = &c.thread_metadata {
+        value["owner_id"] = json!(c.owner_id.map(|o| o.to_string()));
+        value["message_count"] = json!(c.message_count);
+        value["member_count"] = json!(c.member_count);
+        value["archived"] = json!(meta.archived);
+        value["locked"] = json!(meta.locked);
+    }
+    value
+}
+
+fn role_json(r: &GuildRole) -> serde_json::Value {
+    json!({
+        "role_id": r.id.to_string(),
+        "name": r.name,
+        "color": format!("#{}", r.colour.hex()),
+        "position": r.position,
+        "hoist": r.hoist,
+        "mentionable": r.mentionable,
+        "managed": r.managed,
+        "permissions": r.permissions.get_permission_names(),
+    })
+}
+
+fn member_json(m: &Member, roles: &[serde_json::Value]) -> serde_json::Value {
+    json!({
+        "user_id": m.user.id.to_string(),
+        "name": m.user.name,
+        "global_name": m.user.global_name,
+        "nick": m.nick,
+        "display_name": m.display_name(),
+        "bot": m.user.bot,
+        "joined_at": m.joined_at.map(|t| t.to_string()),
+        "premium_since": m.premium_since.map(|t| t.to_string()),
+        "avatar_url": m.face(),
+        "roles": roles,
+    })
+}
+
+/// 本文の照合方法
+enum MatchMode {
+    /// 大文字小文字を無視した部分一致
+    Contains(String),
+    /// 大文字小文字も含めた部分一致
+    Exact(String),
+    Regex(Regex),
+    /// 単語ごとの編集距離で多少の表記揺れを許す
+    Fuzzy(Vec<String>),
+}
+
+impl MatchMode {
+    fn name(&self) -> &'static str {
+        match self {
+            MatchMode::Contains(_) => "contains",
+            MatchMode::Exact(_) => "exact",
+            MatchMode::Regex(_) => "regex",
+            MatchMode::Fuzzy(_) => "fuzzy",
+        }
+    }
+
+    /// 一致すればスコア (0.0 - 1.0) を返す
+    fn score(&self, content: &str) -> Option<f64> {
+        match self {
+            MatchMode::Contains(q) => content.to_lowercase().contains(q).then_some(1.0),
+            MatchMode::Exact(q) => content.contains(q.as_str()).then_some(1.0),
+            MatchMode::Regex(re) => re.is_match(content).then_some(1.0),
+            MatchMode::Fuzzy(terms) => {
+                let lower = content.to_lowercase();
+                let words: Vec<&str> = lower.split(|c: char| !c.is_alphanumeric()).filter(|w| !w.is_empty()).collect();
+                let total: f64 = terms
+                    .iter()
+                    .map(|term| {
+                        if lower.contains(term.as_str()) {
+                            return 1.0;
+                        }
+                        words.iter().map(|w| similarity(term, w)).fold(0.0, f64::max)
+                    })
+                    .sum();
+                let score = total / terms.len() as f64;
+                (score >= FUZZY_THRESHOLD).then_some(score)
+            }
+        }
+    }
+}
+
+/// 編集距離を長さで正規化した類似度
+fn similarity(a: &str, b: &str) -> f64 {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let longest = a.len().max(b.len());
+    if longest == 0 {
+        return 1.0;
+    }
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.iter().enumerate() {
+        let mut cur = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == cb { 0 } else { 1 };
+            cur[j + 1] = (prev[j] + cost).min(prev[j + 1] + 1).min(cur[j] + 1);
+        }
+        prev = cur;
+    }
+    1.0 - prev[b.len()] as f64 / longest as f64
+}
+
+/// `2024-05-01` か RFC 3339 の日時 (日付のみは UTC の 0時)
+fn parse_date_arg(args: &serde_json::Value, key: &str) -> Result<Option<DateTime<Utc>>, String> {
+    let Some(s) = args.get(key).and_then(|v| v.as_str()).filter(|s| !s.is_empty()) else {
+        return Ok(None);
+    };
+    if let Ok(dt) = DateTime::parse_from_rfc3339(s) {
+        return Ok(Some(dt.with_timezone(&Utc)));
+    }
+    NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .map(|d| Some(d.and_hms_opt(0, 0, 0).expect("midnight").and_utc()))
+        .map_err(|_| format!("Invalid '{key}': use YYYY-MM-DD or RFC 3339"))
+}
+
+/// その時刻より前のメッセージを指す snowflake
+fn snowflake_at(time: DateTime<Utc>) -> MessageId {
+    let millis = (time.timestamp_millis() - DISCORD_EPOCH_MILLIS).max(1) as u64;
+    MessageId::new(millis << 22)
+}
+
+/// search_messages の絞り込み条件
+struct SearchFilter {
+    mode: Option<MatchMode>,
+    /// ユーザー ID かユーザー名 (小文字)
+    author: Option<String>,
+    after: Option<DateTime<Utc>>,
+    before: Option<DateTime<Utc>>,
+    has_attachments: Option<bool>,
+    has_link: Option<bool>,
+}
+
+impl SearchFilter {
+    fn from_args(args: &serde_json::Value) -> Result<SearchFilter, String> {
+        let query = args.get("query").and_then(|v| v.as_str()).filter(|s| !s.is_empty());
+        let mode = match (query, args.get("match_mode").and_then(|v| v.as_str()).unwrap_or("contains")) {
+            (None, _) => None,
+            (Some(q), "contains") => Some(MatchMode::Contains(q.to_lowercase())),
+            (Some(q), "exact") => Some(MatchMode::Exact(q.to_string())),
+            (Some(q), "regex") => Some(MatchMode::Regex(
+                RegexBuilder::new(q)
+                    .case_insensitive(true)
+                    .size_limit(1 << 20)
+                    .build()
+                    .map_err(|e| format!("Invalid regex in 'query': {e}"))?,
+            )),
+            (Some(q), "fuzzy") => Some(MatchMode::Fuzzy(q.to_lowercase().split_whitespace().map(|s| s.to_string()).collect())),
+            (_, other) => return Err(format!("Unsupported 'match_mode': {other}. Use contains, exact, regex or fuzzy.")),
+        };
+        let author = args
+            .get("author")
+            .and_then(|v| v.as_str())
+            .map(|s| s.trim().trim_start_matches("<@").trim_start_matches('!').trim_end_matches('>').trim_start_matches('@').to_lowercase())
+            .filter(|s| !s.is_empty());
+
+        let filter = SearchFilter {
+            mode,
+            author,
+            after: parse_date_arg(args, "after_date")?,
+            before: parse_date_arg(args, "before_date")?,
+            has_attachments: args.get("has_attachments").and_then(|v| v.as_bool()),
+            has_link: args.get("has_link").and_then(|v| v.as_bool()),
+        };
+        if filter.mode.is_none()
+            && filter.author.is_none()
+            && filter.after.is_none()
+            && filter.before.is_none()
+            && filter.has_attachments.is_none()
+            && filter.has_link.is_none()
+        {
+            return Err("Give 'query' or at least one filter (author, after_date, before_date, has_attachments, has_link)".to_string());
+        }
+        Ok(filter)
+    }
+
+    /// 条件に合えばスコアを返す
+    fn score(&self, m: &Message) -> Option<f64> {
+        if let Some(author) = &self.author {
+            let matched = m.author.id.to_string() == *author
+                || m.author.name.to_lowercase() == *author
+                || m.author.global_name.as_ref().is_some_and(|g| g.to_lowercase() == *author);
+            if !matched {
+                return None;
+            }
+        }
+        let unix = m.timestamp.unix_timestamp();
+        if self.after.is_some_and(|after| unix < after.timestamp()) || self.before.is_some_and(|before| unix >= before.timestamp()) {
+            return None;
+        }
+        if self.has_attachments.is_some_and(|want| want == m.attachments.is_empty()) {
+            return None;
+        }
+        if let Some(want) = self.has_link {
+            let has_link = m.content.contains("https://") || m.content.contains("http://") || m.embeds.iter().any(|e| e.url.is_some());
+            if want != has_link {
+                return None;
+            }
+        }
+        match &self.mode {
+            Some(mode) => mode.score(&m.content),
+            None => Some(1.0),
+        }
+    }
+}
+
+/// 承認プロンプト用に長い文字列を切り詰める
+fn summary_text(s: &str) -> String {
+    if s.chars().count() > SUMMARY_TEXT_CHARS {
+        format!("{}…", s.chars().take(SUMMARY_TEXT_CHARS).collect::<String>())
+    } else {
+        s.to_string()
+    }
+}
+
+/// 承認プロンプト用の embed の要約 (タイトルと本文)
+fn embeds_summary(args: &serde_json::Value) -> String {
+    let mut summary = String::new();
+    for embed in embed_values(args) {
+        let text = |key: &str| embed.get(key).and_then(|v| v.as_str()).map(summary_text);
+        summary.push_str("\n[embed]");
+        if let Some(title) = text("title") {
+            summary.push_str(&format!(" **{title}**"));
+        }
+        if let Some(description) = text("description") {
+            summary.push_str(&format!("\n{}", description.lines().map(|l| format!("> {l}")).collect::<Vec<_>>().join("\n")));
+        }
+    }
+    summary
+}
+
+/// 承認プロンプト用の投票の要約 (質問と選択肢)
+fn poll_summary(poll: &serde_json::Value) -> String {
+    let question = poll.get("question").and_then(|q| q.as_str()).unwrap_or("?");
+    let mut summary = format!("\n[poll] {}", summary_text(question));
+    for answer in poll.get("answers").and_then(|a| a.as_array()).into_iter().flatten() {
+        let text = answer.as_str().or_else(|| answer.get("text").and_then(|t| t.as_str())).unwrap_or("?");
+        summary.push_str(&format!("\n- {}", summary_text(text)));
+    }
+    summary
 }
 
 #[async_trait::async_trait]
@@ -35,7 +597,53 @@ impl LMTool for DiscordTool {
     }
 
     fn description(&self) -> String {
-        "Interact with Discord: add/remove reactions, create threads, send/edit/fetch messages, and search messages in a channel.".to_string()
+        "Interact with Discord: add/remove reactions (Unicode or custom guild emoji), create threads, send/edit/fetch messages \
+with rich embeds and native polls, end polls, list custom emoji, \
+search deep into channel history with filters (author, date range, attachments, links; exact, regex or fuzzy matching), \
+list the guild's channels and active threads, read history before/after a message with pagination, pin/unpin messages, \
+and look up members and roles."
+            .to_string()
+    }
+
+    fn approval_summary(&self, args: &serde_json::Value) -> Option<String> {
+        let operation = args.get("operation").and_then(|v| v.as_str())?;
+        let channel = args.get("channel_id").and_then(|v| v.as_str()).unwrap_or("?");
+        let quote = |key: &str| {
+            args.get(key)
+                .and_then(|v| v.as_str())
+                .map(|s| s.lines().map(|l| format!("> {l}")).collect::<Vec<_>>().join("\n"))
+                .unwrap_or_default()
+        };
+        match operation {
+            "send_message" => {
+                let mut summary = format!("Send a message to <#{channel}>:\n{}", quote("content"));
+                summary.push_str(&embeds_summary(args));
+                if let Some(poll) = args.get("poll") {
+                    summary.push_str(&poll_summary(poll));
+                }
+                Some(summary)
+            }
+            "edit_message" => Some(format!(
+                "Edit message `{}` in <#{channel}> to:\n{}{}",
+                args.get("message_id").and_then(|v| v.as_str()).unwrap_or("?"),
+                quote("content"),
+                embeds_summary(args)
+            )),
+            "create_thread" => Some(format!(
+                "Create a thread `{}` in <#{channel}>",
+                args.get("name").and_then(|v| v.as_str()).unwrap_or("?")
+            )),
+            "end_poll" => Some(format!(
+                "End the poll `{}` in <#{channel}>",
+                args.get("message_id").and_then(|v| v.as_str()).unwrap_or("?")
+            )),
+            "pin_message" | "unpin_message" => Some(format!(
+                "{} message `{}` in <#{channel}>",
+                if operation == "pin_message" { "Pin" } else { "Unpin" },
+                args.get("message_id").and_then(|v| v.as_str()).unwrap_or("?")
+            )),
+            _ => None,
+        }
     }
 
     fn json_schema(&self) -> serde_json::Value {
@@ -52,20 +660,29 @@ impl LMTool for DiscordTool {
                         "send_message",
                         "edit_message",
                         "fetch_message",
-                        "search_messages"
+                        "search_messages",
+                        "fetch_history",
+                        "list_channels",
+                        "list_active_threads",
+                        "pin_message",
+                        "unpin_message",
+                        "get_member",
+                        "list_roles",
+                        "end_poll",
+                        "list_emojis"
                     ]
                 },
                 "channel_id": {
                     "type": "string",
-                    "description": "ID of the target channel. Required for all operations."
+                    "description": "ID of the target channel. Required for all operations (for list_channels, list_active_threads, get_member and list_roles, any channel of the guild)."
                 },
                 "message_id": {
                     "type": "string",
-                    "description": "ID of the target message. Used by: add/remove_reaction, create_thread(from message), send_message(reply_to), edit_message, fetch_message."
+                    "description": "ID of the target message. Used by: add/remove_reaction, create_thread(from message), send_message(reply_to), edit_message, fetch_message, pin_message, unpin_message, end_poll."
                 },
                 "reaction": {
                     "type": "string",
-                    "description": "Emoji for reactions. Unicode (e.g. 🫠,😱,👍,👈,🤔) or custom emoji ID. Used by: add_reaction, remove_reaction."
+                    "description": "Emoji for reactions. Unicode (e.g. 🫠,😱,👍,👈,🤔), a custom emoji as <:name:id> / <a:name:id>, or a guild emoji name (e.g. 'blobcat'; see list_emojis). Used by: add_reaction, remove_reaction."
                 },
                 "name": {
                     "type": "string",
@@ -78,7 +695,60 @@ impl LMTool for DiscordTool {
                 },
                 "content": {
                     "type": "string",
-                    "description": "Message content. Used by: send_message, edit_message."
+                    "description": "Message content. Guild emoji can be written as :name:. Optional if 'embeds' or 'poll' is given. Used by: send_message, edit_message."
+                },
+                "embeds": {
+                    "type": "array",
+                    "description": "Rich embeds (max 10). Used by: send_message, edit_message (replaces the embeds; [] removes them).",
+                    "items": {
+                        "type": "object",
+                        "properties": {
+                            "title": { "type": "string" },
+                            "description": { "type": "string", "description": "Markdown body (max 4096 characters)." },
+                            "url": { "type": "string", "description": "Link of the title." },
+                            "color": { "type": "string", "description": "Side bar color as #RRGGBB." },
+                            "author": { "type": "string" },
+                            "footer": { "type": "string" },
+                            "image_url": { "type": "string" },
+                            "thumbnail_url": { "type": "string" },
+                            "timestamp": { "type": "string", "description": "RFC 3339 time shown in the footer." },
+                            "fields": {
+                                "type": "array",
+                                "description": "Up to 25 fields.",
+                                "items": {
+                                    "type": "object",
+                                    "properties": {
+                                        "name": { "type": "string" },
+                                        "value": { "type": "string" },
+                                        "inline": { "type": "boolean" }
+                                    },
+                                    "required": ["name", "value"]
+                                }
+                            }
+                        }
+                    }
+                },
+                "poll": {
+                    "type": "object",
+                    "description": "Native Discord poll. Used by: send_message.",
+                    "properties": {
+                        "question": { "type": "string", "description": "Max 300 characters." },
+                        "answers": {
+                            "type": "array",
+                            "description": "1-10 answers (max 55 characters each).",
+                            "items": {
+                                "type": "object",
+                                "properties": {
+                                    "text": { "type": "string" },
+                                    "emoji": { "type": "string", "description": "Unicode emoji or guild emoji name." }
+                                },
+                                "required": ["text"]
+                            }
+                        },
+                        "duration_hours": { "type": "integer", "description": "1-768. Defaults to 24." },
+                        "allow_multiselect": { "type": "boolean" }
+                    },
+                    "required": ["question", "answers"]
                 },
                 "reply_to": {
                     "type": "string",
@@ -86,11 +756,64 @@ impl LMTool for DiscordTool {
                 },
                 "query": {
                     "type": "string",
-                    "description": "Keyword to search in message content. Used by: search_messages."
+                    "description": "Text to search in message content. Optional if a filter is given. Used by: search_messages."
+                },
+                "match_mode": {
+                    "type": "string",
+                    "description": "How 'query' is matched. 'contains' (case-insensitive, default), 'exact' (case-sensitive), 'regex', or 'fuzzy' (tolerates typos). Used by: search_messages.",
+                    "enum": ["contains", "exact", "regex", "fuzzy"]
+                },
+                "author": {
+                    "type": "string",
+                    "description": "Only messages by this user (ID, mention or user name). Used by: search_messages."
+                },
+                "after_date": {
+                    "type": "string",
+                    "description": "Only messages at or after this date (YYYY-MM-DD in UTC or RFC 3339). Used by: search_messages."
+                },
+                "before_date": {
+                    "type": "string",
+                    "description": "Only messages before this date (YYYY-MM-DD in UTC or RFC 3339). Used by: search_messages."
+                },
+                "has_attachments": {
+                    "type": "boolean",
+                    "description": "Only messages with (true) or without (false) attachments. Used by: search_messages."
+                },
+                "has_link": {
+                    "type": "boolean",
+                    "description": "Only messages with (true) or without (false) a link. Used by: search_messages."
+                },
+                "depth": {
+                    "type": "integer",
+                    "description": "How many messages to scan back through history. Defaults to 500. Used by: search_messages."
+                },
+                "max_results": {
+                    "type": "integer",
+                    "description": "Max number of matches to return (1–50). Defaults to 20. Scanning stops once this many are found; page on with next_before. Used by: search_messages."
                 },
                 "limit": {
                     "type": "integer",
-                    "description": "Max number of recent messages to scan (1–100). Defaults to 50. Used by: search_messages."
+                    "description": "Max number of messages (1–100). Defaults to 50. Used by: fetch_history."
+                },
+                "before": {
+                    "type": "string",
+                    "description": "Fetch messages older than this message ID. Use 'next_before' of the previous result to page back. Used by: fetch_history, search_messages (start point)."
+                },
+                "after": {
+                    "type": "string",
+                    "description": "Fetch messages newer than this message ID. Use 'next_after' of the previous result to page forward. Used by: fetch_history."
+                },
+                "around": {
+                    "type": "string",
+                    "description": "Fetch messages around this message ID. Used by: fetch_history."
+                },
+                "user_id": {
+                    "type": "string",
+                    "description": "ID of the user. Used by: get_member."
+                },
+                "role_id": {
+                    "type": "string",
+                    "description": "ID of a role to show (all roles if omitted). Used by: list_roles."
                 }
             },
             "required": ["operation", "channel_id"]
@@ -100,7 +823,7 @@ impl LMTool for DiscordTool {
     async fn execute(
         &self,
         args: serde_json::Value,
-        ob_ctx: crate::context::ObserverContext,
+        ob_ctx: ObserverContext,
     ) -> Result<String, String> {
         let operation = args
             .get("operation")
@@ -124,11 +847,12 @@ impl LMTool for DiscordTool {
                 let message_id = MessageId::from_str(message_id_str)
                     .map_err(|e| format!("Invalid 'message_id': {e}"))?;
 
+                let reaction_type = Self::resolve_reaction(&ob_ctx, channel_id, reaction).await?;
                 channel_id
                     .create_reaction(
                         http,
                         message_id,
-                        ReactionType::Unicode(reaction.to_string()),
+                        reaction_type,
                     )
                     .await
                     .map_err(|e| format!("Failed to add reaction: {e}"))?;
@@ -149,11 +873,12 @@ impl LMTool for DiscordTool {
                 let message_id = MessageId::from_str(message_id_str)
                     .map_err(|e| format!("Invalid 'message_id': {e}"))?;
 
+                let reaction_type = Self::resolve_reaction(&ob_ctx, channel_id, reaction).await?;
                 channel_id
                     .delete_reaction_emoji(
                         http,
                         message_id,
-                        ReactionType::Unicode(reaction.to_string()),
+                        reaction_type,
                     )
                     .await
                     .map_err(|e| format!("Failed to remove reaction: {e}"))?;
@@ -223,11 +948,23 @@ impl LMTool for DiscordTool {
             // Send message
             // --------------------
             "send_message" => {
-                let content = Self::get_str_arg(&args, "content")?;
+                let content = args.get("content").and_then(|v| v.as_str()).filter(|s| !s.is_empty());
+                let embeds = build_embeds(&args)?;
+                let poll = args.get("poll").filter(|p| p.is_object());
+                if content.is_none() && embeds.is_empty() && poll.is_none() {
+                    return Err("Missing 'content', 'embeds' or 'poll' parameter".to_string());
+                }
 
                 let reply_to_str = args.get("reply_to").and_then(|v| v.as_str());
 
-                let mut builder = CreateMessage::new().content(content);
+                let emojis = Self::guild_emojis(&ob_ctx, channel_id).await;
+                let mut builder = CreateMessage::new().embeds(embeds);
+                if let Some(content) = content {
+                    builder = builder.content(expand_emoji_shortcodes(content, &emojis));
+                }
+                if let Some(poll) = poll {
+                    builder = builder.poll(build_poll(poll, &emojis)?);
+                }
 
                 if let Some(reply_id_str) = reply_to_str {
                     let reply_id = MessageId::from_str(reply_id_str).map_err(|e| {
@@ -257,12 +994,23 @@ impl LMTool for DiscordTool {
             // --------------------
             "edit_message" => {
                 let message_id_str = Self::get_str_arg(&args, "message_id")?;
-                let content = Self::get_str_arg(&args, "content")?;
+                let content = args.get("content").and_then(|v| v.as_str()).filter(|s| !s.is_empty());
+                let has_embeds = args.get("embeds").is_some() || args.get("embed").is_some();
+                if content.is_none() && !has_embeds {
+                    return Err("Missing 'content' or 'embeds' parameter".to_string());
+                }
 
                 let message_id = MessageId::from_str(message_id_str)
                     .map_err(|e| format!("Invalid 'message_id': {e}"))?;
 
-                let builder = EditMessage::new().content(content);
+                let mut builder = EditMessage::new();
+                if let Some(content) = content {
+                    let emojis = Self::guild_emojis(&ob_ctx, channel_id).await;
+                    builder = builder.content(expand_emoji_shortcodes(content, &emojis));
+                }
+                if has_embeds {
+                    builder = builder.embeds(build_embeds(&args)?);
+                }
 
                 let msg = channel_id
                     .edit_message(&http, message_id, builder)
@@ -293,7 +1041,7 @@ impl LMTool for DiscordTool {
                     .await
                     .map_err(|e| format!("Failed to fetch message: {e}"))?;
 
-                let result = json!({
+                let mut result = json!({
                     "status": "ok",
                     "operation": operation,
                     "channel_id": channel_id_str,
@@ -303,6 +1051,12 @@ impl LMTool for DiscordTool {
                     "content": msg.content,
                     "timestamp": msg.timestamp.to_string(),
                 });
+                if !msg.embeds.is_empty() {
+                    result["embeds"] = json!(msg.embeds.iter().map(embed_json).collect::<Vec<_>>());
+                }
+                if let Some(poll) = &msg.poll {
+                    result["poll"] = poll_json(poll);
+                }
 
                 Ok(result.to_string())
             }
@@ -311,44 +1065,349 @@ impl LMTool for DiscordTool {
             // Search messages
             // --------------------
             "search_messages" => {
-                let query = Self::get_str_arg(&args, "query")?;
+                let filter = SearchFilter::from_args(&args)?;
+                let depth = args
+                    .get("depth")
+                    .or_else(|| args.get("limit"))
+                    .and_then(|v| v.as_u64())
+                    .unwrap_or(DEFAULT_SEARCH_DEPTH)
+                    .clamp(1, self.max_search_depth);
+                let max_results = args
+                    .get("max_results")
+                    .and_then(|v| v.as_u64())
+                    .unwrap_or(20)
+                    .clamp(1, MAX_SEARCH_RESULTS) as usize;
+                // jump URL 用 (DM なら None)
+                let guild_id = Self::guild_id_of(&ob_ctx, channel_id).await.ok();
+
+                // before_date があればその時刻から遡り始める
+                let mut cursor = match Self::get_message_id_arg(&args, "before")? {
+                    Some(id) => Some(id),
+                    None => filter.before.map(snowflake_at),
+                };
+                let mut scanned: u64 = 0;
+                let mut reached_end = false;
+                let mut matched: Vec<(f64, Message)> = Vec::new();
 
+                while scanned < depth && matched.len() < max_results {
+                    let page = (depth - scanned).min(100) as u8;
+                    let mut builder = GetMessages::new().limit(page);
+                    if let Some(cursor) = cursor {
+                        builder = builder.before(cursor);
+                    }
+                    let mut messages: Vec<Message> = channel_id
+                        .messages(&http, builder)
+                        .await
+                        .map_err(|e| format!("Failed to fetch messages: {e}"))?;
+                    messages.sort_by(|a, b| b.id.cmp(&a.id));
+
+                    // 上限に達したらそこで止め、見たところまでを cursor にする (残りは次の呼び出しで拾う)
+                    let mut stopped = false;
+                    let fetched = messages.len();
+                    for m in messages {
+                        if matched.len() >= max_results {
+                            stopped = true;
+                            break;
+                        }
+                        scanned += 1;
+                        cursor = Some(m.id);
+                        if filter.after.is_some_and(|after| m.timestamp.unix_timestamp() < after.timestamp()) {
+                            reached_end = true;
+                            continue;
+                        }
+                        if let Some(score) = filter.score(&m) {
+                            matched.push((score, m));
+                        }
+                    }
+                    if !stopped && fetched < page as usize {
+                        reached_end = true;
+                    }
+                    if reached_end {
+                        break;
+                    }
+                }
+
+                // fuzzy は類似度順、それ以外は新しい順
+                matched.sort_by(|a, b| b.0.total_cmp(&a.0).then(b.1.id.cmp(&a.1.id)));
+                let messages: Vec<serde_json::Value> = matched
+                    .iter()
+                    .map(|(score, m)| {
+                        let mut value = message_json(m);
+                        value["jump_url"] = json!(m.id.link(channel_id, guild_id));
+                        if matches!(filter.mode, Some(MatchMode::Fuzzy(_))) {
+                            value["score"] = json!((score * 100.0).round() / 100.0);
+                        }
+                        value
+                    })
+                    .collect();
+
+                let result = json!({
+                    "status": "ok",
+                    "operation": operation,
+                    "channel_id": channel_id_str,
+                    "query": args.get("query").and_then(|v| v.as_str()),
+                    "match_mode": filter.mode.as_ref().map(|m| m.name()),
+                    "scanned_count": scanned,
+                    "reached_end": reached_end,
+                    "next_before": if reached_end { None } else { cursor.map(|c| c.to_string()) },
+                    "matched_count": messages.len(),
+                    "messages": messages,
+                });
+
+                Ok(result.to_string())
+            }
+
+            // --------------------
+            // History: before / after / around
+            // --------------------
+            "fetch_history" => {
                 let limit = args
                     .get("limit")
                     .and_then(|v| v.as_u64())
                     .unwrap_or(50)
-                    .min(100) as u8;
+                    .clamp(1, 100) as u8;
 
-                let messages: Vec<Message> = channel_id
-                    .messages(
-                        &http,
-                        GetMessages::new().limit(limit),
-                    )
+                let mut builder = GetMessages::new().limit(limit);
+                let before = Self::get_message_id_arg(&args, "before")?;
+                let after = Self::get_message_id_arg(&args, "after")?;
+                let around = Self::get_message_id_arg(&args, "around")?;
+                match (before, after, around) {
+                    (Some(id), None, None) => builder = builder.before(id),
+                    (None, Some(id), None) => builder = builder.after(id),
+                    (None, None, Some(id)) => builder = builder.around(id),
+                    (None, None, None) => {}
+                    _ => return Err("Use only one of 'before', 'after' or 'around'".to_string()),
+                }
+
+                let mut messages: Vec<Message> = channel_id
+                    .messages(&http, builder)
                     .await
                     .map_err(|e| format!("Failed to fetch messages: {e}"))?;
+                // 古い順に並べる
+                messages.sort_by_key(|m| m.id);
 
-                let lower_query = query.to_lowercase();
-                let matched: Vec<serde_json::Value> = messages
-                    .into_iter()
-                    .filter(|m| m.content.to_lowercase().contains(&lower_query))
-                    .map(|m| {
-                        json!({
-                            "message_id": m.id.to_string(),
-                            "author_id": m.author.id.to_string(),
-                            "author_name": m.author.name,
-                            "content": m.content,
-                            "timestamp": m.timestamp.to_string(),
-                        })
-                    })
-                    .collect();
+                let result = json!({
+                    "status": "ok",
+                    "operation": operation,
+                    "channel_id": channel_id_str,
+                    "count": messages.len(),
+                    "has_more": messages.len() == limit as usize,
+                    "next_before": messages.first().map(|m| m.id.to_string()),
+                    "next_after": messages.last().map(|m| m.id.to_string()),
+                    "messages": messages.iter().map(message_json).collect::<Vec<_>>(),
+                });
+
+                Ok(result.to_string())
+            }
+
+            // --------------------
+            // Poll: end
+            // --------------------
+            "end_poll" => {
+                let message_id_str = Self::get_str_arg(&args, "message_id")?;
+                let message_id = MessageId::from_str(message_id_str)
+                    .map_err(|e| format!("Invalid 'message_id': {e}"))?;
+
+                let msg = channel_id
+                    .end_poll(&http, message_id)
+                    .await
+                    .map_err(|e| format!("Failed to end poll: {e}"))?;
+
+                let result = json!({
+                    "status": "ok",
+                    "operation": operation,
+                    "channel_id": channel_id_str,
+                    "message_id": message_id_str,
+                    "poll": msg.poll.as_deref().map(poll_json),
+                });
+
+                Ok(result.to_string())
+            }
+
+            // --------------------
+            // Custom emoji
+            // --------------------
+            "list_emojis" => {
+                let mut emojis = Self::guild_emojis(&ob_ctx, channel_id).await;
+                emojis.sort_by(|a, b| a.name.cmp(&b.name));
+
+                let result = json!({
+                    "status": "ok",
+                    "operation": operation,
+                    "channel_id": channel_id_str,
+                    "emojis": emojis
+                        .iter()
+                        .filter(|e| e.available)
+                        .map(|e| json!({
+                            "name": e.name,
+                            "emoji_id": e.id.to_string(),
+                            "animated": e.animated,
+                            "markup": e.to_string(),
+                        }))
+                        .collect::<Vec<_>>(),
+                });
+
+                Ok(result.to_string())
+            }
+
+            // --------------------
+            // Channels / threads
+            // --------------------
+            "list_channels" | "list_active_threads" => {
+                let guild_id = Self::guild_id_of(&ob_ctx, channel_id).await?;
+                let cache = ob_ctx.discord_client.open().cache.clone();
+                let bot_id = cache.current_user().id;
+
+                let (channels, threads) = {
+                    let guild = cache
+                        .guild(guild_id)
+                        .ok_or_else(|| "The guild is not in the cache yet. Try again later.".to_string())?;
+
+                    let mut channels: Vec<&GuildChannel> = guild
+                        .channels
+                        .values()
+                        .filter(|c| Self::bot_can_view(&guild, c, bot_id))
+                        .collect();
+                    channels.sort_by_key(|c| (c.kind != ChannelType::Category, c.position, c.id));
+                    let threads: Vec<serde_json::Value> = guild
+                        .threads
+                        .iter()
+                        .filter(|t| t.thread_metadata.is_some_and(|m| !m.archived))
+                        .filter(|t| Self::bot_can_view(&guild, t, bot_id))
+                        .map(channel_json)
+                        .collect();
+                    (channels.into_iter().map(channel_json).collect::<Vec<_>>(), threads)
+                };
+
+                let mut result = json!({
+                    "status": "ok",
+                    "operation": operation,
+                    "channel_id": channel_id_str,
+                    "guild_id": guild_id.to_string(),
+                    "threads": threads,
+                });
+                if operation == "list_channels" {
+                    result["channels"] = json!(channels);
+                }
+
+                Ok(result.to_string())
+            }
+
+            // --------------------
+            // Pin / unpin
+            // --------------------
+            "pin_message" | "unpin_message" => {
+                let message_id_str = Self::get_str_arg(&args, "message_id")?;
+                let message_id = MessageId::from_str(message_id_str)
+                    .map_err(|e| format!("Invalid 'message_id': {e}"))?;
+
+                if operation == "pin_message" {
+                    channel_id
+                        .pin(&http, message_id)
+                        .await
+                        .map_err(|e| format!("Failed to pin message: {e}"))?;
+                } else {
+                    channel_id
+                        .unpin(&http, message_id)
+                        .await
+                        .map_err(|e| format!("Failed to unpin message: {e}"))?;
+                }
+
+                let result = json!({
+                    "status": "ok",
+                    "operation": operation,
+                    "channel_id": channel_id_str,
+                    "message_id": message_id_str,
+                });
+
+                Ok(result.to_string())
+            }
+
+            // --------------------
+            // Member info
+            // --------------------
+            "get_member" => {
+                let user_id_str = Self::get_str_arg(&args, "user_id")?;
+                let user_id =
+                    UserId::from_str(user_id_str).map_err(|e| format!("Invalid 'user_id': {e}"))?;
+                let guild_id = Self::guild_id_of(&ob_ctx, channel_id).await?;
+                let cache = ob_ctx.discord_client.open().cache.clone();
+
+                // メンバーの intent が無いのでキャッシュに無ければ HTTP で取る
+                let cached = cache.guild(guild_id).and_then(|g| g.members.get(&user_id).cloned());
+                let member = match cached {
+                    Some(member) => member,
+                    None => guild_id
+                        .member(&http, user_id)
+                        .await
+                        .map_err(|e| format!("Failed to fetch member: {e}"))?,
+                };
+
+                let (roles, permissions) = match cache.guild(guild_id) {
+                    Some(guild) => {
+                        let mut roles: Vec<&GuildRole> = member.roles.iter().filter_map(|r| guild.roles.get(r)).collect();
+                        roles.sort_by_key(|r| std::cmp::Reverse(r.position));
+                        (
+                            roles.into_iter().map(role_json).collect::<Vec<_>>(),
+                            guild
+                                .channels
+                                .get(&channel_id)
+                                .or_else(|| guild.threads.iter().find(|t| t.id == channel_id))
+                                .map(|c| guild.user_permissions_in(Self::permission_target(&guild, c), &member).get_permission_names()),
+                        )
+                    }
+                    None => (member.roles.iter().map(|r| json!({ "role_id": r.to_string() })).collect(), None),
+                };
+
+                let result = json!({
+                    "status": "ok",
+                    "operation": operation,
+                    "channel_id": channel_id_str,
+                    "guild_id": guild_id.to_string(),
+                    "member": member_json(&member, &roles),
+                    "channel_permissions": permissions,
+                });
+
+                Ok(result.to_string())
+            }
+
+            // --------------------
+            // Role info
+            // --------------------
+            "list_roles" => {
+                let role_id = args
+                    .get("role_id")
+                    .and_then(|v| v.as_str())
+                    .filter(|s| !s.is_empty())
+                    .map(|s| RoleId::from_str(s).map_err(|e| format!("Invalid 'role_id': {e}")))
+                    .transpose()?;
+                let guild_id = Self::guild_id_of(&ob_ctx, channel_id).await?;
+                let cache = ob_ctx.discord_client.open().cache.clone();
+
+                let cached: Option<Vec<GuildRole>> = cache.guild(guild_id).map(|g| g.roles.values().cloned().collect());
+                let mut roles = match cached {
+                    Some(roles) => roles,
+                    None => guild_id
+                        .roles(&http)
+                        .await
+                        .map_err(|e| format!("Failed to fetch roles: {e}"))?
+                        .into_values()
+                        .collect(),
+                };
+                if let Some(role_id) = role_id {
+                    roles.retain(|r| r.id == role_id);
+                    if roles.is_empty() {
+                        return Err(format!("Role {role_id} not found"));
+                    }
+                }
+                roles.sort_by_key(|r| std::cmp::Reverse(r.position));
 
                 let result = json!({
                     "status": "ok",
                     "operation": operation,
                     "channel_id": channel_id_str,
-                    "query": query,
-                    "matched_count": matched.len(),
-                    "messages": matched,
+                    "guild_id": guild_id.to_string(),
+                    "roles": roles.iter().map(role_json).collect::<Vec<_>>(),
                 });
 
                 Ok(result.to_string())
@@ -357,7 +1416,9 @@ impl LMTool for DiscordTool {
             other => Err(format!(
                 "Unsupported 'operation': {other}. \
                  Use one of: add_reaction, remove_reaction, create_thread, \
-                 send_message, edit_message, fetch_message, search_messages."
+                 send_message, edit_message, fetch_message, search_messages, \
+                 fetch_history, list_channels, list_active_threads, pin_message, \
+                 unpin_message, get_member, list_roles, end_poll, list_emojis."
             )),
         }
     }
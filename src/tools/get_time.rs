@@ -1,11 +1,47 @@
 
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Datelike, Duration, FixedOffset, Months, SecondsFormat, NaiveDate, NaiveDateTime, NaiveTime, Offset, TimeDelta, TimeZone, Utc, Weekday};
 use chrono_tz::Tz;
 use log::info;
-use std::collections::HashMap;
+use serde_json::json;
 
 use crate::{context::ObserverContext, lmclient::LMTool};
 
+/// 場所の指定を解決したタイムゾーン
+#[derive(Debug, Clone, Copy)]
+pub enum Zone {
+    Named(Tz),
+    Fixed(FixedOffset),
+}
+
+impl Zone {
+    fn name(&self) -> String {
+        match self {
+            Zone::Named(tz) => tz.name().to_string(),
+            Zone::Fixed(offset) => format!("UTC{}", offset),
+        }
+    }
+
+    fn local_at(&self, utc: &DateTime<Utc>) -> DateTime<FixedOffset> {
+        match self {
+            Zone::Named(tz) => utc.with_timezone(tz).fixed_offset(),
+            Zone::Fixed(offset) => utc.with_timezone(offset),
+        }
+    }
+
+    /// 現地の日時を UTC にする (夏時間の切り替えで曖昧/存在しない時刻は前側に寄せる)
+    fn resolve_local(&self, local: NaiveDateTime) -> Result<DateTime<Utc>, String> {
+        let resolved = match self {
+            Zone::Named(tz) => tz.from_local_datetime(&local).earliest().map(|d| d.with_timezone(&Utc)).or_else(|| {
+                // 存在しない時刻 (春の切り替え) は 1時間後ろにずらす
+                let later = local.checked_add_signed(Duration::hours(1))?;
+                tz.from_local_datetime(&later).earliest().map(|d| d.with_timezone(&Utc))
+            }),
+            Zone::Fixed(offset) => offset.from_local_datetime(&local).single().map(|d| d.with_timezone(&Utc)),
+        };
+        resolved.ok_or(format!("invalid local time {local} in {}", self.name()))
+    }
+}
+
 #[derive(Default)]
 pub struct GetTime {}
 
@@ -15,87 +51,435 @@ impl GetTime {
     }
 }
 
+/// 国コード -> 代表的なタイムゾーン (複数ある国は他のゾーンも返す)
+fn country_zones(country_code: &str) -> Option<Vec<Tz>> {
+    use chrono_tz::{Africa, America, Asia, Australia, Europe, Pacific};
+    let zones = match country_code.to_ascii_uppercase().as_str() {
+        "AE" => vec![Asia::Dubai],
+        "AR" => vec![America::Argentina::Buenos_Aires],
+        "AT" => vec![Europe::Vienna],
+        "AU" => vec![Australia::Sydney, Australia::Melbourne, Australia::Brisbane, Australia::Adelaide, Australia::Perth],
+        "BE" => vec![Europe::Brussels],
+        "BG" => vec![Europe::Sofia],
+        "BO" => vec![America::La_Paz],
+        "BR" => vec![America::Sao_Paulo, America::Manaus, America::Noronha],
+        "CA" => vec![America::Toronto, America::Vancouver, America::Edmonton, America::Winnipeg, America::Halifax, America::St_Johns],
+        "CH" => vec![Europe::Zurich],
+        "CL" => vec![America::Santiago],
+        "CN" => vec![Asia::Shanghai],
+        "CO" => vec![America::Bogota],
+        "CZ" => vec![Europe::Prague],
+        "DE" => vec![Europe::Berlin],
+        "DK" => vec![Europe::Copenhagen],
+        "EC" => vec![America::Guayaquil],
+        "EG" => vec![Africa::Cairo],
+        "ES" => vec![Europe::Madrid],
+        "FI" => vec![Europe::Helsinki],
+        "FR" => vec![Europe::Paris],
+        "GB" | "UK" => vec![Europe::London],
+        "GR" => vec![Europe::Athens],
+        "HK" => vec![Asia::Hong_Kong],
+        "HU" => vec![Europe::Budapest],
+        "ID" => vec![Asia::Jakarta, Asia::Makassar, Asia::Jayapura],
+        "IE" => vec![Europe::Dublin],
+        "IL" => vec![Asia::Jerusalem],
+        "IN" => vec![Asia::Kolkata],
+        "IR" => vec![Asia::Tehran],
+        "IT" => vec![Europe::Rome],
+        "JP" => vec![Asia::Tokyo],
+        "KR" => vec![Asia::Seoul],
+        "MX" => vec![America::Mexico_City, America::Tijuana, America::Cancun],
+        "MY" => vec![Asia::Kuala_Lumpur],
+        "NG" => vec![Africa::Lagos],
+        "NL" => vec![Europe::Amsterdam],
+        "NO" => vec![Europe::Oslo],
+        "NZ" => vec![Pacific::Auckland],
+        "PE" => vec![America::Lima],
+        "PH" => vec![Asia::Manila],
+        "PK" => vec![Asia::Karachi],
+        "PL" => vec![Europe::Warsaw],
+        "PT" => vec![Europe::Lisbon],
+        "PY" => vec![America::Asuncion],
+        "QA" => vec![Asia::Qatar],
+        "RO" => vec![Europe::Bucharest],
+        "RU" => vec![Europe::Moscow, Asia::Yekaterinburg, Asia::Novosibirsk, Asia::Vladivostok],
+        "SA" => vec![Asia::Riyadh],
+        "SE" => vec![Europe::Stockholm],
+        "SG" => vec![Asia::Singapore],
+        "TH" => vec![Asia::Bangkok],
+        "TR" => vec![Europe::Istanbul],
+        "TW" => vec![Asia::Taipei],
+        "UA" => vec![Europe::Kyiv],
+        "US" => vec![America::New_York, America::Chicago, America::Denver, America::Los_Angeles, America::Anchorage, Pacific::Honolulu],
+        "UY" => vec![America::Montevideo],
+        "VE" => vec![America::Caracas],
+        "VN" => vec![Asia::Ho_Chi_Minh],
+        "ZA" => vec![Africa::Johannesburg],
+        _ => return None,
+    };
+    Some(zones)
+}
+
+/// IANA の名前になっていない都市名・日本語名・略称
+fn city_alias(name: &str) -> Option<Tz> {
+    use chrono_tz::{America, Asia, Australia, Europe, Pacific, UTC};
+    let tz = match name {
+        "utc" | "gmt" | "z" | "協定世界時" => UTC,
+        "jst" | "日本" | "東京" | "大阪" | "京都" | "名古屋" | "札幌" | "福岡" | "osaka" | "kyoto" | "nagoya" | "sapporo" | "fukuoka" | "yokohama" | "tsukuba" | "つくば" => Asia::Tokyo,
+        "kst" | "ソウル" => Asia::Seoul,
+        "北京" | "上海" | "beijing" | "peking" | "shenzhen" | "guangzhou" => Asia::Shanghai,
+        "台北" => Asia::Taipei,
+        "香港" => Asia::Hong_Kong,
+        "シンガポール" => Asia::Singapore,
+        "ist" | "delhi" | "new delhi" | "mumbai" | "bangalore" | "bengaluru" | "chennai" => Asia::Kolkata,
+        "ロンドン" | "bst" => Europe::London,
+        "パリ" | "cet" | "cest" => Europe::Paris,
+        "ベルリン" | "munich" | "frankfurt" | "hamburg" => Europe::Berlin,
+        "モスクワ" => Europe::Moscow,
+        "ニューヨーク" | "est" | "edt" | "boston" | "washington" | "washington dc" | "miami" | "atlanta" | "philadelphia" => America::New_York,
+        "cst" | "cdt" | "dallas" | "houston" | "austin" => America::Chicago,
+        "mst" | "mdt" | "salt lake city" => America::Denver,
+        "phoenix" => America::Phoenix,
+        "ロサンゼルス" | "サンフランシスコ" | "シアトル" | "pst" | "pdt" | "san francisco" | "seattle" | "san jose" | "silicon valley" | "las vegas" | "san diego" => America::Los_Angeles,
+        "hawaii" | "ハワイ" | "hst" => Pacific::Honolulu,
+        "シドニー" | "canberra" | "aest" => Australia::Sydney,
+        "montreal" | "ottawa" => America::Toronto,
+        _ => return None,
+    };
+    Some(tz)
+}
+
+/// `UTC+9`, `+09:00`, `GMT-5:30` のような固定オフセット
+fn parse_fixed_offset(s: &str) -> Option<FixedOffset> {
+    let rest = s
+        .strip_prefix("utc")
+        .or_else(|| s.strip_prefix("gmt"))
+        .unwrap_or(s)
+        .trim();
+    let (sign, rest) = match rest.chars().next()? {
+        '+' => (1, &rest[1..]),
+        '-' | '−' => (-1, &rest[rest.chars().next()?.len_utf8()..]),
+        _ => return None,
+    };
+    // 数字と ':' 以外 ("UTC+9時" など) はオフセットとして読まない (下で 1バイトずつ切り出すので先に弾く)
+    if rest.is_empty() || !rest.chars().all(|c| c.is_ascii_digit() || c == ':') {
+        return None;
+    }
+    let (h, m) = match rest.split_once(':') {
+        Some((h, m)) => (h.parse::<i32>().ok()?, m.parse::<i32>().ok()?),
+        None if rest.len() == 4 => (rest[..2].parse::<i32>().ok()?, rest[2..].parse::<i32>().ok()?),
+        None => (rest.parse::<i32>().ok()?, 0),
+    };
+    if h > 14 || m >= 60 {
+        return None;
+    }
+    FixedOffset::east_opt(sign * (h * 3600 + m * 60))
+}
+
+/// 場所の指定 (IANA 名, 都市名, 国コード, UTC オフセット, 略称) をタイムゾーンにする
+/// 国コードで複数ゾーンある国は代表以外のゾーンも返す
+pub fn resolve_zone(location: &str) -> Result<(Zone, Vec<Tz>), String> {
+    let trimmed = location.trim();
+    if trimmed.is_empty() {
+        return Err("empty location".to_string());
+    }
+
+    // IANA 名そのもの (大文字小文字は無視)
+    if let Ok(tz) = trimmed.parse::<Tz>() {
+        return Ok((Zone::Named(tz), vec![]));
+    }
+    if let Some(tz) = chrono_tz::TZ_VARIANTS.iter().find(|tz| tz.name().eq_ignore_ascii_case(trimmed)) {
+        return Ok((Zone::Named(*tz), vec![]));
+    }
+
+    let lower = trimmed.to_lowercase();
+    if let Some(offset) = parse_fixed_offset(&lower) {
+        return Ok((Zone::Fixed(offset), vec![]));
+    }
+    if let Some(tz) = city_alias(&lower) {
+        return Ok((Zone::Named(tz), vec![]));
+    }
+    if trimmed.len() == 2
+        && let Some(zones) = country_zones(trimmed)
+    {
+        return Ok((Zone::Named(zones[0]), zones[1..].to_vec()));
+    }
+
+    // IANA 名の最後の部分を都市名として探す (例: "new york" → America/New_York)
+    let key = lower.replace([' ', '-'], "_");
+    if let Some(tz) = chrono_tz::TZ_VARIANTS
+        .iter()
+        .find(|tz| tz.name().rsplit('/').next().is_some_and(|city| city.to_lowercase() == key))
+    {
+        return Ok((Zone::Named(*tz), vec![]));
+    }
+
+    Err(format!(
+        "Unknown location '{location}'. Use an IANA zone (e.g. 'Asia/Tokyo'), a city name, a 2-letter country code or a UTC offset (e.g. 'UTC+9')"
+    ))
+}
+
+fn parse_weekday(s: &str) -> Option<Weekday> {
+    match s {
+        "monday" | "mon" | "月" | "月曜" | "月曜日" => Some(Weekday::Mon),
+        "tuesday" | "tue" | "tues" | "火" | "火曜" | "火曜日" => Some(Weekday::Tue),
+        "wednesday" | "wed" | "水" | "水曜" | "水曜日" => Some(Weekday::Wed),
+        "thursday" | "thu" | "thurs" | "木" | "木曜" | "木曜日" => Some(Weekday::Thu),
+        "friday" | "fri" | "金" | "金曜" | "金曜日" => Some(Weekday::Fri),
+        "saturday" | "sat" | "土" | "土曜" | "土曜日" => Some(Weekday::Sat),
+        "sunday" | "sun" | "日" | "日曜" | "日曜日" => Some(Weekday::Sun),
+        _ => None,
+    }
+}
+
+fn parse_clock(s: &str) -> Option<NaiveTime> {
+    ["%H:%M:%S", "%H:%M", "%I:%M%p", "%I%p"]
+        .iter()
+        .find_map(|f| NaiveTime::parse_from_str(&s.to_uppercase(), f).ok())
+}
+
+/// 時刻の指定を UTC にする
+/// ISO 8601 / `YYYY-MM-DD HH:MM` / 日付のみ / 時刻のみ / UNIX 秒 / now, today, tomorrow, yesterday /
+/// `next monday`, `last friday`, `monday` (今日以降で最初の) に、後ろに時刻を付けてもよい
+pub fn parse_time(input: &str, zone: &Zone, now: DateTime<Utc>) -> Result<DateTime<Utc>, String> {
+    let s = input.trim();
+    let lower = s.to_lowercase();
+    let local_now = zone.local_at(&now).naive_local();
+
+    if lower.is_empty() || lower == "now" {
+        return Ok(now);
+    }
+    if let Ok(dt) = DateTime::parse_from_rfc3339(s) {
+        return Ok(dt.with_timezone(&Utc));
+    }
+    if let Ok(secs) = s.parse::<i64>() {
+        return DateTime::from_timestamp(secs, 0).ok_or(format!("invalid unix timestamp {secs}"));
+    }
+    for f in ["%Y-%m-%dT%H:%M:%S", "%Y-%m-%dT%H:%M", "%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M", "%Y/%m/%d %H:%M:%S", "%Y/%m/%d %H:%M"] {
+        if let Ok(naive) = NaiveDateTime::parse_from_str(s, f) {
+            return zone.resolve_local(naive);
+        }
+    }
+    for f in ["%Y-%m-%d", "%Y/%m/%d"] {
+        if let Ok(date) = NaiveDate::parse_from_str(s, f) {
+            return zone.resolve_local(date.and_time(NaiveTime::MIN));
+        }
+    }
+    if let Some(time) = parse_clock(&lower) {
+        return zone.resolve_local(local_now.date().and_time(time));
+    }
+
+    // 相対的な日付 (+ 任意の時刻)
+    let mut words: Vec<&str> = lower.split_whitespace().collect();
+    let time = match words.last().and_then(|w| parse_clock(w)) {
+        Some(t) => {
+            words.pop();
+            Some(t)
+        }
+        None => None,
+    };
+    let today = local_now.date();
+    let date = match words.as_slice() {
+        ["today"] | ["今日"] => Some(today),
+        ["tomorrow"] | ["明日"] => today.succ_opt(),
+        ["yesterday"] | ["昨日"] => today.pred_opt(),
+        ["next", day] => parse_weekday(day).map(|wd| {
+            let ahead = (wd.num_days_from_monday() as i64 - today.weekday().num_days_from_monday() as i64).rem_euclid(7);
+            today + Duration::days(if ahead == 0 { 7 } else { ahead })
+        }),
+        ["last", day] => parse_weekday(day).map(|wd| {
+            let back = (today.weekday().num_days_from_monday() as i64 - wd.num_days_from_monday() as i64).rem_euclid(7);
+            today - Duration::days(if back == 0 { 7 } else { back })
+        }),
+        ["this", day] | [day] => parse_weekday(day).map(|wd| {
+            let ahead = (wd.num_days_from_monday() as i64 - today.weekday().num_days_from_monday() as i64).rem_euclid(7);
+            today + Duration::days(ahead)
+        }),
+        [] if time.is_some() => Some(today),
+        _ => None,
+    };
+    match date {
+        Some(date) => zone.resolve_local(date.and_time(time.unwrap_or(NaiveTime::MIN))),
+        None => Err(format!(
+            "Could not parse time '{input}'. Use ISO 8601 (e.g. '2025-04-01 15:00'), a unix timestamp, 'now', 'tomorrow 9:00' or 'next monday'"
+        )),
+    }
+}
+
+/// `+3 weeks`, `3 weeks 2 days`, `-90 minutes`, `1 month` のような期間を現地時間で足す
+/// 日以上の単位は現地の暦で足すので、夏時間をまたいでも時刻は変わらない
+pub fn apply_offset(base: DateTime<Utc>, zone: &Zone, offset: &str) -> Result<DateTime<Utc>, String> {
+    let lower = offset.to_lowercase().replace(',', " ");
+    let lower = lower.replace(" and ", " ");
+    let lower = lower.trim().trim_start_matches("in ").trim_end_matches(" later").trim_end_matches(" after");
+    let (mut sign, mut rest) = (1i64, lower.trim());
+    let negated = rest.ends_with(" ago") || rest.ends_with(" before");
+    if negated {
+        rest = rest.trim_end_matches(" ago").trim_end_matches(" before");
+        sign = -1;
+    }
+
+    // 数字と単位を分離する ("3weeks" も "3 weeks" も受ける)
+    let mut tokens: Vec<String> = Vec::new();
+    for word in rest.split_whitespace() {
+        let split = word
+            .char_indices()
+            .find(|(i, c)| *i > 0 && c.is_alphabetic() && word[..*i].chars().all(|c| c.is_ascii_digit() || c == '+' || c == '-'))
+            .map(|(i, _)| i);
+        match split {
+            Some(i) => {
+                tokens.push(word[..i].to_string());
+                tokens.push(word[i..].to_string());
+            }
+            None => tokens.push(word.to_string()),
+        }
+    }
+    if tokens.is_empty() || !tokens.len().is_multiple_of(2) {
+        return Err(format!("Could not parse offset '{offset}' (e.g. '+3 weeks', '2 days 4 hours', '-90 minutes')"));
+    }
+
+    // 大きすぎる数は日時の範囲を超えるので、足すたびに確かめてエラーにする
+    let out_of_range = || format!("Offset '{offset}' is out of range");
+    let mut local = zone.local_at(&base).naive_local();
+    let mut exact = Duration::zero();
+    for pair in tokens.chunks(2) {
+        let n: i64 = pair[0]
+            .trim_start_matches('+')
+            .parse()
+            .map_err(|_| format!("Invalid number '{}' in offset", pair[0]))?;
+        let n = n.checked_mul(sign).ok_or_else(out_of_range)?;
+        let unit = pair[1].as_str();
+        match unit {
+            "y" | "yr" | "yrs" | "year" | "years" => local = add_months(local, n.checked_mul(12).ok_or_else(out_of_range)?)?,
+            "mo" | "mon" | "month" | "months" => local = add_months(local, n)?,
+            "w" | "wk" | "wks" | "week" | "weeks" | "d" | "day" | "days" => {
+                let delta = if unit.starts_with('w') { TimeDelta::try_weeks(n) } else { TimeDelta::try_days(n) };
+                local = delta.and_then(|d| local.checked_add_signed(d)).ok_or_else(out_of_range)?;
+            }
+            "h" | "hr" | "hrs" | "hour" | "hours" | "m" | "min" | "mins" | "minute" | "minutes" | "s" | "sec" | "secs" | "second" | "seconds" => {
+                let delta = match unit.chars().next() {
+                    Some('h') => TimeDelta::try_hours(n),
+                    Some('m') => TimeDelta::try_minutes(n),
+                    _ => TimeDelta::try_seconds(n),
+                };
+                exact = delta.and_then(|d| exact.checked_add(&d)).ok_or_else(out_of_range)?;
+            }
+            unit => return Err(format!("Unknown unit '{unit}' in offset")),
+        }
+    }
+    zone.resolve_local(local)?.checked_add_signed(exact).ok_or_else(out_of_range)
+}
+
+fn add_months(local: NaiveDateTime, months: i64) -> Result<NaiveDateTime, String> {
+    let m = Months::new(u32::try_from(months.unsigned_abs()).map_err(|_| "date out of range".to_string())?);
+    if months >= 0 { local.checked_add_months(m) } else { local.checked_sub_months(m) }
+        .ok_or("date out of range".to_string())
+}
+
+/// 時刻の詳細 (曜日, 週番号, Discord のタイムスタンプ記法など)
+pub fn describe(utc: DateTime<Utc>, zone: &Zone) -> serde_json::Value {
+    let local = zone.local_at(&utc);
+    let ts = utc.timestamp();
+    let week = local.iso_week();
+    json!({
+        "zone": zone.name(),
+        "local_time": local.format("%Y-%m-%d %H:%M:%S").to_string(),
+        "iso8601": local.to_rfc3339_opts(SecondsFormat::Secs, true),
+        "utc_offset": local.offset().fix().to_string(),
+        "weekday": local.format("%A").to_string(),
+        "iso_week": format!("{}-W{:02}", week.year(), week.week()),
+        "day_of_year": local.ordinal(),
+        "unix": ts,
+        "discord": {
+            "short_time": format!("<t:{ts}:t>"),
+            "long_time": format!("<t:{ts}:T>"),
+            "short_date": format!("<t:{ts}:d>"),
+            "long_date": format!("<t:{ts}:D>"),
+            "short_date_time": format!("<t:{ts}:f>"),
+            "long_date_time": format!("<t:{ts}:F>"),
+            "relative": format!("<t:{ts}:R>"),
+        },
+    })
+}
+
+fn format_duration(d: Duration) -> String {
+    let sign = if d < Duration::zero() { "-" } else { "" };
+    let total = d.num_seconds().abs();
+    let (days, rem) = (total / 86_400, total % 86_400);
+    format!("{sign}{}d {:02}:{:02}:{:02}", days, rem / 3600, rem % 3600 / 60, rem % 60)
+}
+
 impl GetTime {
     /// 国コードを元に現在の時刻を取得する
     pub fn get_time_by_country(&self, country_code: &str) -> Result<String, String> {
-        // 国コード -> タイムゾーンの対応表
-        let country_to_tz: HashMap<&str, Tz> = [
-            ("AE", chrono_tz::Asia::Dubai),
-            ("AR", chrono_tz::America::Argentina::Buenos_Aires),
-            ("AT", chrono_tz::Europe::Vienna),
-            ("AU", chrono_tz::Australia::Sydney),
-            ("BE", chrono_tz::Europe::Brussels),
-            ("BG", chrono_tz::Europe::Sofia),
-            ("BO", chrono_tz::America::La_Paz),
-            ("BR", chrono_tz::America::Sao_Paulo),
-            ("CA", chrono_tz::America::Toronto),
-            ("CH", chrono_tz::Europe::Zurich),
-            ("CL", chrono_tz::America::Santiago),
-            ("CN", chrono_tz::Asia::Shanghai),
-            ("CO", chrono_tz::America::Bogota),
-            ("CZ", chrono_tz::Europe::Prague),
-            ("DE", chrono_tz::Europe::Berlin),
-            ("DK", chrono_tz::Europe::Copenhagen),
-            ("EC", chrono_tz::America::Guayaquil),
-            ("EG", chrono_tz::Africa::Cairo),
-            ("ES", chrono_tz::Europe::Madrid),
-            ("FI", chrono_tz::Europe::Helsinki),
-            ("FR", chrono_tz::Europe::Paris),
-            ("GB", chrono_tz::Europe::London),
-            ("GR", chrono_tz::Europe::Athens),
-            ("HK", chrono_tz::Asia::Hong_Kong),
-            ("HU", chrono_tz::Europe::Budapest),
-            ("ID", chrono_tz::Asia::Jakarta),
-            ("IE", chrono_tz::Europe::Dublin),
-            ("IL", chrono_tz::Asia::Jerusalem),
-            ("IN", chrono_tz::Asia::Kolkata),
-            ("IR", chrono_tz::Asia::Tehran),
-            ("IT", chrono_tz::Europe::Rome),
-            ("JP", chrono_tz::Asia::Tokyo),
-            ("KR", chrono_tz::Asia::Seoul),
-            ("MX", chrono_tz::America::Mexico_City),
-            ("MY", chrono_tz::Asia::Kuala_Lumpur),
-            ("NG", chrono_tz::Africa::Lagos),
-            ("NL", chrono_tz::Europe::Amsterdam),
-            ("NO", chrono_tz::Europe::Oslo),
-            ("NZ", chrono_tz::Pacific::Auckland),
-            ("PE", chrono_tz::America::Lima),
-            ("PH", chrono_tz::Asia::Manila),
-            ("PK", chrono_tz::Asia::Karachi),
-            ("PL", chrono_tz::Europe::Warsaw),
-            ("PT", chrono_tz::Europe::Lisbon),
-            ("PY", chrono_tz::America::Asuncion),
-            ("QA", chrono_tz::Asia::Qatar),
-            ("RO", chrono_tz::Europe::Bucharest),
-            ("RU", chrono_tz::Europe::Moscow),
-            ("SA", chrono_tz::Asia::Riyadh),
-            ("SE", chrono_tz::Europe::Stockholm),
-            ("SG", chrono_tz::Asia::Singapore),
-            ("TH", chrono_tz::Asia::Bangkok),
-            ("TR", chrono_tz::Europe::Istanbul),
-            ("TW", chrono_tz::Asia::Taipei),
-            ("UA", chrono_tz::Europe::Kyiv),
-            ("US", chrono_tz::America::New_York),
-            ("UY", chrono_tz::America::Montevideo),
-            ("VE", chrono_tz::America::Caracas),
-            ("VN", chrono_tz::Asia::Ho_Chi_Minh),
-            ("ZA", chrono_tz::Africa::Johannesburg),
-        ]
-        .iter()
-        .cloned()
-        .collect();
-
-        // 対応するタイムゾーンを取得
-        let tz = country_to_tz
-            .get(country_code)
-            .ok_or(format!("Unsupported country code: {}", country_code))?;
+        let zones = country_zones(country_code).ok_or(format!("Unsupported country code: {}", country_code))?;
+        let tz = zones[0];
 
         // 現在の UTC 時間を取得し、指定のタイムゾーンに変換
         let utc_now: DateTime<Utc> = Utc::now();
-        let local_time = utc_now.with_timezone(tz);
+        let local_time = utc_now.with_timezone(&tz);
 
         Ok(format!("The current time in {} ({}) is: {}", country_code, tz, local_time))
     }
+
+    fn run(&self, args: &serde_json::Value, now: DateTime<Utc>) -> Result<serde_json::Value, String> {
+        let str_arg = |key: &str| args.get(key).and_then(|v| v.as_str()).map(|s| s.trim()).filter(|s| !s.is_empty());
+        let operation = str_arg("operation").unwrap_or("now");
+        let location = str_arg("location").or(str_arg("country_code")).unwrap_or("UTC");
+        let (zone, other_zones) = resolve_zone(location)?;
+        let base = parse_time(str_arg("time").unwrap_or("now"), &zone, now)?;
+
+        let mut result = match operation {
+            "now" | "info" => json!({ "time": describe(base, &zone) }),
+            "convert" => {
+                let to = str_arg("to_location").ok_or("'convert' needs 'to_location'".to_string())?;
+                let (to_zone, _) = resolve_zone(to)?;
+                json!({
+                    "from": describe(base, &zone),
+                    "to": describe(base, &to_zone),
+                })
+            }
+            "add" => {
+                let offset = str_arg("offset").ok_or("'add' needs 'offset' (e.g. '+3 weeks')".to_string())?;
+                let result = apply_offset(base, &zone, offset)?;
+                json!({
+                    "base": describe(base, &zone),
+                    "offset": offset,
+                    "result": describe(result, &zone),
+                })
+            }
+            "diff" => {
+                let end = str_arg("end_time").ok_or("'diff' needs 'end_time'".to_string())?;
+                let end = parse_time(end, &zone, now)?;
+                let d = end - base;
+                let local_start = zone.local_at(&base).date_naive();
+                let local_end = zone.local_at(&end).date_naive();
+                json!({
+                    "start": describe(base, &zone),
+                    "end": describe(end, &zone),
+                    "difference": format_duration(d),
+                    "total_seconds": d.num_seconds(),
+                    "total_minutes": d.num_minutes(),
+                    "total_hours": d.num_seconds() as f64 / 3600.0,
+                    "total_days": d.num_seconds() as f64 / 86_400.0,
+                    "calendar_days": (local_end - local_start).num_days(),
+                    "weeks": d.num_days() / 7,
+                })
+            }
+            other => return Err(format!("Unknown operation '{other}' (use now, convert, add, diff or info)")),
+        };
+
+        if !other_zones.is_empty() {
+            result["note"] = json!(format!(
+                "'{}' spans several time zones; used {}. Others: {}",
+                location,
+                zone.name(),
+                other_zones.iter().map(|tz| tz.name()).collect::<Vec<_>>().join(", ")
+            ));
+        }
+        result["status"] = json!("ok");
+        Ok(result)
+    }
 }
 
 #[async_trait::async_trait]
@@ -105,32 +489,143 @@ impl LMTool for GetTime {
     }
 
     fn description(&self) -> String {
-        "Get the current time of the location based on the country code".to_string()
+        "Time zone and date tool. Get the current time of a place (IANA zone, city name, country code or UTC offset), \
+convert a time between zones, add offsets like '3 weeks after next Monday', compute the difference between two times, \
+and get the weekday, ISO week number and Discord timestamp markup (<t:...>) for any time."
+            .to_string()
     }
 
     fn json_schema(&self) -> serde_json::Value {
         serde_json::json!({
             "type": "object",
             "properties": {
+                "operation": {
+                    "type": "string",
+                    "description": "now (default): describe 'time' in 'location'. convert: show 'time' in 'location' and 'to_location'. add: add 'offset' to 'time'. diff: difference from 'time' to 'end_time'. info: same as now.",
+                    "enum": ["now", "convert", "add", "diff", "info"]
+                },
+                "location": {
+                    "type": "string",
+                    "description": "IANA zone ('Asia/Tokyo'), city ('New York', 'Osaka'), ISO 3166-1 alpha-2 country code ('JP') or UTC offset ('UTC+9'). Default UTC. 'time' is interpreted here."
+                },
+                "time": {
+                    "type": "string",
+                    "description": "Base time: 'now' (default), ISO 8601 ('2025-04-01 15:00'), a date, a clock time ('9:30'), a unix timestamp, 'tomorrow 9:00', 'next monday', 'last friday 18:00'."
+                },
+                "to_location": {
+                    "type": "string",
+                    "description": "Target zone for 'convert'."
+                },
+                "offset": {
+                    "type": "string",
+                    "description": "For 'add': e.g. '+3 weeks', '2 days 4 hours', '-90 minutes', '1 month', '10 days ago'."
+                },
+                "end_time": {
+                    "type": "string",
+                    "description": "For 'diff': the end time (same formats as 'time')."
+                },
                 "country_code": {
                     "type": "string",
-                    "description": "ISO 3166-1 alpha-2 country code (e.g., 'US', 'JP', 'FR')"
+                    "description": "Deprecated: same as 'location' with a country code."
                 },
                 "$explain": {
                     "type": "string",
                     "description": "A brief explanation of what you are doing with this tool."
                 }
-            },
-            "required": ["country_code"]
+            }
         })
     }
 
     async fn execute(&self, args: serde_json::Value, _ob_ctx: ObserverContext) -> Result<String, String> {
         info!("GetTime::run called with args: {:?}", args);
-        let country_code = args.get("country_code")
-            .and_then(|v| v.as_str())
-            .ok_or("Missing or invalid 'country_code' parameter".to_string())?;
+        self.run(&args, Utc::now()).map(|v| v.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2025-03-07 (金) 12:00 UTC
+    fn now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2025, 3, 7, 12, 0, 0).unwrap()
+    }
+
+    fn tokyo() -> Zone {
+        Zone::Named(chrono_tz::Asia::Tokyo)
+    }
+
+    #[test]
+    fn fixed_offsets() {
+        let secs = |s: &str| parse_fixed_offset(s).map(|o| o.local_minus_utc());
+        assert_eq!(secs("utc+9"), Some(9 * 3600));
+        assert_eq!(secs("gmt-5:30"), Some(-(5 * 3600 + 30 * 60)));
+        assert_eq!(secs("+0930"), Some(9 * 3600 + 30 * 60));
+        assert_eq!(secs("utc−3"), Some(-3 * 3600));
+        assert_eq!(secs("utc+15"), None);
+        assert_eq!(secs("utc+9:60"), None);
+        assert_eq!(secs("utc"), None);
+        assert_eq!(secs("utc+"), None);
+        // 数字以外が混ざっていても落ちない
+        assert_eq!(secs("utc+9時"), None);
+        assert_eq!(secs("+ä9"), None);
+        assert_eq!(secs("+12時3"), None);
+    }
+
+    #[test]
+    fn zones() {
+        assert!(matches!(resolve_zone("Asia/Tokyo"), Ok((Zone::Named(chrono_tz::Asia::Tokyo), _))));
+        assert!(matches!(resolve_zone("new york"), Ok((Zone::Named(chrono_tz::America::New_York), _))));
+        assert!(matches!(resolve_zone("UTC+9"), Ok((Zone::Fixed(_), _))));
+        let (_, others) = resolve_zone("US").unwrap();
+        assert!(!others.is_empty());
+        assert!(resolve_zone("UTC+9時").is_err());
+        assert!(resolve_zone("nowhere at all").is_err());
+    }
+
+    #[test]
+    fn relative_times() {
+        let local = |s: &str| {
+            let utc = parse_time(s, &tokyo(), now()).unwrap();
+            tokyo().local_at(&utc).format("%Y-%m-%d %H:%M").to_string()
+        };
+        assert_eq!(local("now"), "2025-03-07 21:00");
+        assert_eq!(local("tomorrow 9:00"), "2025-03-08 09:00");
+        assert_eq!(local("next friday"), "2025-03-14 00:00");
+        assert_eq!(local("friday"), "2025-03-07 00:00");
+        assert_eq!(local("last monday 18:30"), "2025-03-03 18:30");
+        assert_eq!(local("2025-04-01 15:00"), "2025-04-01 15:00");
+        assert!(parse_time("someday", &tokyo(), now()).is_err());
+    }
+
+    #[test]
+    fn offsets() {
+        let add = |offset: &str| apply_offset(now(), &Zone::Named(chrono_tz::Europe::Berlin), offset);
+        assert_eq!(add("+3 weeks").unwrap(), now() + Duration::weeks(3));
+        assert_eq!(add("2 days 4 hours").unwrap(), now() + Duration::days(2) + Duration::hours(4));
+        assert_eq!(add("90 minutes ago").unwrap(), now() - Duration::minutes(90));
+        assert_eq!(add("1 month").unwrap(), Utc.with_ymd_and_hms(2025, 4, 7, 11, 0, 0).unwrap());
+        // 夏時間 (3/30) をまたいでも現地の時刻はそのまま
+        assert_eq!(add("1 month").unwrap().with_timezone(&chrono_tz::Europe::Berlin).format("%H:%M").to_string(), "13:00");
+        assert!(add("3 fortnights").is_err());
+        assert!(add("3").is_err());
+    }
 
-        self.get_time_by_country(country_code)
+    #[test]
+    fn huge_offsets_are_errors() {
+        let add = |offset: &str| apply_offset(now(), &tokyo(), offset);
+        for offset in [
+            "9223372036854775807 weeks",
+            "9223372036854775807 days",
+            "9223372036854775807 seconds",
+            "9223372036854775807 years",
+            "-9223372036854775808 days ago",
+            "4294967296 months",
+            "300000 years",
+            "9223372036854775807 hours 9223372036854775807 hours",
+        ] {
+            assert!(add(offset).is_err(), "{offset}");
+        }
+        assert!(add_months(now().naive_utc(), i64::MIN).is_err());
     }
 }
@@ -0,0 +1,225 @@
+use std::{str::FromStr, time::{Duration, Instant}};
+
+use dashmap::DashMap;
+use log::{debug, info};
+use openai_dive::v1::{api::Client as OpenAIClient, resources::response::{request::{ImageDetailLevel, ResponseParametersBuilder}, response::Role}};
+use serde_json::json;
+use serenity::all::{ChannelId, MessageId};
+
+use crate::{config::{Config, ModelProvider, ToolConfig}, context::ObserverContext, lmclient::{LMClient, LMContext, LMTool}, secret};
+
+/// キャッシュに持つ画像数の上限
+const CACHE_CAPACITY: usize = 256;
+/// キャッシュの有効期間
+const CACHE_TTL: Duration = Duration::from_secs(60 * 60);
+
+const CAPTION_PROMPT: &str = "Describe this image for someone who cannot see it. \
+Reply with only a JSON object of the form \
+{\"caption\": string, \"ocr_text\": string, \"objects\": [string]}. \
+`caption` is a detailed description (scene, people, style, notable details; for screenshots of code, charts or problems, explain what they show). \
+`ocr_text` is all readable text in the image, verbatim, keeping line breaks (empty string if none). \
+`objects` lists the main objects or elements. \
+Write `caption` in the same language as the text in the image if any, otherwise in Japanese.";
+
+/// 画像をビジョン対応のサブモデルに渡して説明文・OCR・物体の一覧を得るツール
+pub struct ImageCaptioner {
+    client: LMClient,
+    model: String,
+    detail: ImageDetailLevel,
+    max_tokens: u32,
+    /// 画像 URL (クエリを除いたもの) → 結果の JSON
+    cache: DashMap<String, (Instant, String)>,
+}
+
+impl ImageCaptioner {
+    /// config.json の `tools.image_captioner` から組み立てる
+    /// `endpoint` / `api_key` が無ければ OpenAI 互換のメインモデルの設定を流用する
+    pub fn from_config(cfg: &ToolConfig, config: &Config) -> Result<ImageCaptioner, String> {
+        let endpoint = match cfg.str_setting("endpoint") {
+            Some(endpoint) => endpoint.to_string(),
+            None if config.model_provider == ModelProvider::OpenAI => config.main_model_endpoint.clone(),
+            None => return Err("no vision model endpoint configured".to_string()),
+        };
+        let api_key = cfg
+            .str_setting("api_key")
+            .map(|s| s.to_string())
+            .or_else(|| secret::from_env("IMAGE_CAPTIONER_API_KEY"))
+            .unwrap_or_else(|| config.main_model_api_key.expose().to_string());
+
+        let mut openai = OpenAIClient::new(api_key);
+        openai.set_base_url(endpoint.trim_end_matches('/'));
+
+        Ok(ImageCaptioner {
+            client: LMClient::new_openai(openai),
+            model: cfg.str_setting("model").unwrap_or("gpt-5-mini").to_string(),
+            detail: match cfg.str_setting("detail").unwrap_or("high") {
+                "low" => ImageDetailLevel::Low,
+                "auto" => ImageDetailLevel::Auto,
+                _ => ImageDetailLevel::High,
+            },
+            max_tokens: cfg.u64_setting("max_tokens").unwrap_or(2000) as u32,
+            cache: DashMap::new(),
+        })
+    }
+
+    /// 画像 1枚を説明させる (結果は JSON 文字列)
+    pub async fn caption(&self, image_url: &str, question: Option<&str>, ob_ctx: &ObserverContext) -> Result<String, String> {
+        // Discord の CDN は URL の期限がクエリに付くので外してキーにする
+        let key = format!("{}\u{1f}{}", image_url.split('?').next().unwrap_or(image_url), question.unwrap_or(""));
+        if let Some(entry) = self.cache.get(&key)
+            && entry.0.elapsed() < CACHE_TTL
+        {
+            debug!("image_captioner cache hit: {}", image_url);
+            return Ok(entry.1.clone());
+        }
+
+        let prompt = match question {
+            Some(q) => format!("{CAPTION_PROMPT}\nAlso pay particular attention to: {q}"),
+            None => CAPTION_PROMPT.to_string(),
+        };
+        let mut context = LMContext::new();
+        context.add_text_with_image(prompt, image_url.to_string(), Role::User, self.detail.clone());
+
+        let params = ResponseParametersBuilder::default().model(self.model.as_str()).clone();
+        let result = self
+            .client
+            .generate_response(ob_ctx.clone(), &context, Some(self.max_tokens), None, None, None, Some(params), None)
+            .await
+            .map_err(|e| format!("vision model request failed: {e}"))?
+            .get_result();
+
+        let parsed = parse_caption(&result);
+        let output = json!({
+            "status": "ok",
+            "image_url": image_url,
+            "caption": parsed.get("caption").and_then(|v| v.as_str()).unwrap_or(result.trim()),
+            "ocr_text": parsed.get("ocr_text").and_then(|v| v.as_str()).unwrap_or(""),
+            "objects": parsed.get("objects").cloned().unwrap_or(json!([])),
+        })
+        .to_string();
+
+        if self.cache.len() >= CACHE_CAPACITY {
+            self.cache.retain(|_, (at, _)| at.elapsed() < CACHE_TTL);
+            let oldest = self.cache.iter().min_by_key(|e| e.value().0).map(|e| e.key().clone());
+            if self.cache.len() >= CACHE_CAPACITY
+                && let Some(oldest) = oldest
+            {
+                self.cache.remove(&oldest);
+            }
+        }
+        self.cache.insert(key, (Instant::now(), output.clone()));
+
+        Ok(output)
+    }
+}
+
+/// モデルの出力から JSON オブジェクトを取り出す (コードブロックで囲まれていても拾う)
+fn parse_caption(text: &str) -> serde_json::Value {
+    let start = text.find('{');
+    let end = text.rfind('}');
+    match (start, end) {
+        (Some(s), Some(e)) if s < e => serde_json::from_str(&text[s..=e]).unwrap_or(json!({})),
+        _ => json!({}),
+    }
+}
+
+#[async_trait::async_trait]
+impl LMTool for ImageCaptioner {
+    fn name(&self) -> String {
+        "image_captioner".to_string()
+    }
+
+    fn description(&self) -> String {
+        "Look at an image with a vision model and get a detailed caption, the text in it (OCR) and a list of objects. \
+Give an image URL, or a Discord channel_id and message_id to read that message's image attachments."
+            .to_string()
+    }
+
+    fn json_schema(&self) -> serde_json::Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "image_url": {
+                    "type": "string",
+                    "description": "URL of the image."
+                },
+                "channel_id": {
+                    "type": "string",
+                    "description": "Channel of the message with image attachments (use with message_id)."
+                },
+                "message_id": {
+                    "type": "string",
+                    "description": "Message with image attachments (use with channel_id)."
+                },
+                "question": {
+                    "type": "string",
+                    "description": "Optional thing to focus on (e.g. 'read the formula', 'what error is shown')."
+                },
+                "$explain": {
+                    "type": "string",
+                    "description": "A brief explanation of what you are doing with this tool."
+                }
+            }
+        })
+    }
+
+    async fn execute(&self, args: serde_json::Value, ob_ctx: ObserverContext) -> Result<String, String> {
+        info!("ImageCaptioner::execute called with args: {:?}", args);
+        let question = args.get("question").and_then(|v| v.as_str()).filter(|s| !s.trim().is_empty());
+
+        if let Some(url) = args.get("image_url").and_then(|v| v.as_str()).filter(|s| !s.is_empty()) {
+            return self.caption(url, question, &ob_ctx).await;
+        }
+
+        let channel_id = args.get("channel_id").and_then(|v| v.as_str());
+        let message_id = args.get("message_id").and_then(|v| v.as_str());
+        let (Some(channel_id), Some(message_id)) = (channel_id, message_id) else {
+            return Err("Give 'image_url', or both 'channel_id' and 'message_id'".to_string());
+        };
+        let channel_id = ChannelId::from_str(channel_id).map_err(|e| format!("Invalid 'channel_id': {e}"))?;
+        let message_id = MessageId::from_str(message_id).map_err(|e| format!("Invalid 'message_id': {e}"))?;
+
+        let http = ob_ctx
+            .discord_client
+            .try_open()
+            .ok_or_else(|| "Discord is not connected, give 'image_url' instead".to_string())?
+            .http
+            .clone();
+        let msg = channel_id
+            .message(&http, message_id)
+            .await
+            .map_err(|e| format!("Failed to fetch message: {e}"))?;
+
+        let urls: Vec<String> = msg
+            .attachments
+            .iter()
+            .filter(|att| is_image_attachment(att.content_type.as_deref(), &att.filename))
+            .map(|att| att.url.clone())
+            .chain(msg.embeds.iter().filter_map(|e| e.image.as_ref().map(|i| i.url.clone())))
+            .collect();
+        if urls.is_empty() {
+            return Err("The message has no image attachments".to_string());
+        }
+
+        let mut images = Vec::new();
+        for url in urls {
+            let result = self.caption(&url, question, &ob_ctx).await?;
+            images.push(serde_json::from_str::<serde_json::Value>(&result).unwrap_or(json!(result)));
+        }
+        Ok(json!({
+            "status": "ok",
+            "message_id": message_id.to_string(),
+            "images": images,
+        })
+        .to_string())
+    }
+}
+
+/// content_type (無ければ拡張子) で画像か判定する
+pub fn is_image_attachment(content_type: Option<&str>, filename: &str) -> bool {
+    if let Some(ct) = content_type {
+        return ct.starts_with("image/");
+    }
+    let filename = filename.to_ascii_lowercase();
+    [".png", ".jpg", ".jpeg", ".webp", ".gif"].iter().any(|ext| filename.ends_with(ext))
+}
@@ -0,0 +1,169 @@
+use log::info;
+use serde_json::json;
+
+use crate::{config::{Config, ToolConfig}, context::ObserverContext, judge::{self, JudgeSettings, TestCase}, lmclient::LMTool, sandbox::{Language, Sandbox}};
+
+/// ダウンロードするソースの最大バイト数
+pub const MAX_SOURCE_BYTES: usize = 256 * 1024;
+/// ダウンロードするテストケース zip の最大バイト数
+pub const MAX_TESTS_ZIP_BYTES: usize = 32 * 1024 * 1024;
+
+/// 競プロの提出をテストケースで判定するツール
+pub struct Judge {
+    sandbox: Sandbox,
+    settings: JudgeSettings,
+    /// time_limit_ms として指定できる上限
+    max_time_limit_ms: u64,
+}
+
+impl Judge {
+    /// config.json の `tools.judge` から組み立てる
+    pub fn from_config(cfg: &ToolConfig) -> Judge {
+        Judge {
+            sandbox: Sandbox::from_config(cfg),
+            settings: JudgeSettings::from_config(cfg),
+            max_time_limit_ms: cfg.u64_setting("max_time_limit_ms").unwrap_or(10_000),
+        }
+    }
+
+    pub fn sandbox(&self) -> &Sandbox {
+        &self.sandbox
+    }
+
+    /// 呼び出しごとの TL / 誤差の指定を反映した設定
+    pub fn settings(&self, time_limit_ms: Option<u64>, float_tolerance: Option<f64>) -> JudgeSettings {
+        JudgeSettings {
+            time_limit_ms: time_limit_ms
+                .unwrap_or(self.settings.time_limit_ms)
+                .clamp(100, self.max_time_limit_ms),
+            float_tolerance: float_tolerance.filter(|t| *t > 0.0),
+            ..self.settings.clone()
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl LMTool for Judge {
+    fn name(&self) -> String {
+        "judge".to_string()
+    }
+
+    fn description(&self) -> String {
+        "Judge a competitive programming solution (Python, Rust or C++) against test cases in a sandbox without network. \
+Returns per-case verdicts (AC, WA, TLE, RE, CE) with run times. Give the source inline or as a Discord attachment URL, \
+and test cases inline or as a zip URL (in/ and out/ directories, or NAME.in / NAME.out)."
+            .to_string()
+    }
+
+    async fn health_check(&self, _config: &Config) -> Result<(), String> {
+        self.sandbox.self_test().await
+    }
+
+    fn json_schema(&self) -> serde_json::Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "language": {
+                    "type": "string",
+                    "description": "Language of the solution. Guessed from the source file name if omitted.",
+                    "enum": ["python", "rust", "cpp"]
+                },
+                "source": {
+                    "type": "string",
+                    "description": "Source code of the solution."
+                },
+                "source_url": {
+                    "type": "string",
+                    "description": "URL of the source file attachment on Discord (use instead of 'source')."
+                },
+                "tests": {
+                    "type": "array",
+                    "description": "Test cases.",
+                    "items": {
+                        "type": "object",
+                        "properties": {
+                            "input": { "type": "string" },
+                            "output": { "type": "string", "description": "Expected output." }
+                        },
+                        "required": ["input", "output"]
+                    }
+                },
+                "tests_zip_url": {
+                    "type": "string",
+                    "description": "URL of a zip of test cases attached on Discord (use instead of 'tests')."
+                },
+                "time_limit_ms": {
+                    "type": "integer",
+                    "description": "Time limit per case in milliseconds (default 2000)."
+                },
+                "float_tolerance": {
+                    "type": "number",
+                    "description": "Accept real numbers within this absolute or relative error (e.g. 1e-6)."
+                },
+                "$explain": {
+                    "type": "string",
+                    "description": "A brief explanation of what you are doing with this tool."
+                }
+            }
+        })
+    }
+
+    async fn execute(&self, args: serde_json::Value, _ob_ctx: ObserverContext) -> Result<String, String> {
+        info!("Judge::execute called with language: {:?}", args.get("language"));
+        let source_url = args.get("source_url").and_then(|v| v.as_str()).filter(|s| !s.is_empty());
+
+        let source = match (args.get("source").and_then(|v| v.as_str()), source_url) {
+            (Some(source), _) if !source.trim().is_empty() => source.to_string(),
+            (_, Some(url)) => {
+                let bytes = judge::download(url, MAX_SOURCE_BYTES).await?;
+                String::from_utf8(bytes).map_err(|_| "source file is not UTF-8 text".to_string())?
+            }
+            _ => return Err("Missing 'source' or 'source_url' parameter".to_string()),
+        };
+
+        let language = match args.get("language").and_then(|v| v.as_str()) {
+            Some(lang) => Language::parse(lang).ok_or(format!("Unsupported language '{lang}' (use python, rust or cpp)"))?,
+            None => source_url
+                .and_then(|url| Language::from_filename(url.split(['?', '#']).next().unwrap_or(url)))
+                .ok_or("Missing 'language' parameter".to_string())?,
+        };
+
+        let cases: Vec<TestCase> = if let Some(tests) = args.get("tests").and_then(|v| v.as_array()) {
+            tests
+                .iter()
+                .enumerate()
+                .map(|(i, t)| {
+                    let input = t.get("input").and_then(|v| v.as_str());
+                    let output = t.get("output").and_then(|v| v.as_str());
+                    match (input, output) {
+                        (Some(input), Some(output)) => Ok(TestCase {
+                            name: format!("case{}", i + 1),
+                            input: if input.ends_with('\n') { input.to_string() } else { format!("{input}\n") },
+                            expected: output.to_string(),
+                        }),
+                        _ => Err(format!("tests[{i}] needs 'input' and 'output'")),
+                    }
+                })
+                .collect::<Result<_, _>>()?
+        } else if let Some(url) = args.get("tests_zip_url").and_then(|v| v.as_str()) {
+            judge::parse_zip(&judge::download(url, MAX_TESTS_ZIP_BYTES).await?, self.settings.max_cases)?
+        } else {
+            return Err("Missing 'tests' or 'tests_zip_url' parameter".to_string());
+        };
+        if cases.is_empty() {
+            return Err("No test cases given".to_string());
+        }
+
+        let settings = self.settings(
+            args.get("time_limit_ms").and_then(|v| v.as_u64()),
+            args.get("float_tolerance").and_then(|v| v.as_f64()),
+        );
+        let report = judge::judge(&self.sandbox, language, &source, &cases, &settings).await?;
+
+        Ok(json!({
+            "status": "ok",
+            "report": report,
+        })
+        .to_string())
+    }
+}
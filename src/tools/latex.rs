@@ -1,76 +1,66 @@
-use std::{str::FromStr, time::Duration};
+use std::str::FromStr;
 
 use serde_json::json;
 use serenity::all::{ChannelId, CreateAttachment, CreateMessage, MessageId};
-use reqwest::header::CONTENT_TYPE;
-use urlencoding::encode;
 
-use crate::{context::ObserverContext, lmclient::LMTool};
+use crate::{config::{Config, ToolConfig}, context::ObserverContext, lmclient::LMTool, tex::{self, MathMode, RenderOptions}};
 
-pub struct LatexExprRenderTool;
-
-impl Default for LatexExprRenderTool {
-    fn default() -> Self {
-        Self
-    }
+#[derive(Default)]
+pub struct LatexExprRenderTool {
+    /// config.json の `tools.latex_expr_render` から読んだ描画オプション
+    options: RenderOptions,
 }
 
 impl LatexExprRenderTool {
     pub fn new() -> LatexExprRenderTool {
-        Self
+        Self::default()
     }
 
-    pub async fn render(expr: &str, ob_ctx: &ObserverContext) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
-        let expr_encoded = encode(expr);
-        let url = format!("http://{host}:{port}/latex_expr_render#{expr}",
-            host=ob_ctx.config.web_server_local_ip.iter().map(|b| b.to_string()).collect::<Vec<String>>().join("."),
-            port=ob_ctx.config.web_server_port,
-            expr=expr_encoded
-        );
+    pub fn from_config(cfg: &ToolConfig) -> LatexExprRenderTool {
+        Self { options: Self::options_from_config(cfg) }
+    }
 
-        let base = ob_ctx.config.scraper_base_url.trim_end_matches('/');
-        let capture_url = format!(
-            "{base}/capture?url={}&selector={}&wait={}",
-            encode(&url),
-            encode(".capture"),
-            200
-        );
+    /// `scale` / `foreground` / `background` ("transparent" で透過) を読む
+    pub fn options_from_config(cfg: &ToolConfig) -> RenderOptions {
+        let default = RenderOptions::default();
+        RenderOptions {
+            mode: default.mode,
+            scale: cfg.f64_setting("scale").map(|v| v as f32).unwrap_or(default.scale),
+            foreground: cfg.str_setting("foreground").map(|s| s.to_string()).unwrap_or(default.foreground),
+            background: match cfg.str_setting("background") {
+                Some("transparent") | Some("none") => None,
+                Some(bg) => Some(bg.to_string()),
+                None => default.background,
+            },
+        }
+    }
 
-        let client = reqwest::Client::builder()
-            .timeout(Duration::from_millis(ob_ctx.config.latex_capture_timeout_millis))
-            .build()?;
+    /// 数式を PNG にする (プロセス内で組版する)
+    /// 組版とエンコードは重いので blocking スレッドで行う
+    pub async fn render(expr: &str, options: &RenderOptions) -> Result<Vec<u8>, String> {
+        Self::check_len(expr)?;
+        let (expr, options) = (expr.to_string(), options.clone());
+        tokio::task::spawn_blocking(move || tex::renderer()?.render_png(&expr, &options))
+            .await
+            .map_err(|e| format!("render task failed: {e}"))?
+    }
 
-        let resp = client
-            .get(capture_url)
-            .send()
+    /// 数式を SVG にする
+    pub async fn render_svg(expr: &str, options: &RenderOptions) -> Result<String, String> {
+        Self::check_len(expr)?;
+        let (expr, options) = (expr.to_string(), options.clone());
+        tokio::task::spawn_blocking(move || tex::renderer()?.render_svg(&expr, &options))
             .await
-            .map_err(|e| {
-                format!(
-                    "failed to call capture server `{}` (set SCRAPER_BASE_URL or config.json `scraper_base_url`): {e}",
-                    base
-                )
-            })?;
-        let status = resp.status();
-        let content_type = resp
-            .headers()
-            .get(CONTENT_TYPE)
-            .and_then(|v| v.to_str().ok())
-            .unwrap_or("")
-            .to_string();
-
-        if !status.is_success() || content_type.starts_with("text/") {
-            let text = resp.text().await.unwrap_or_else(|_| "<failed to read body>".to_string());
-            return Err(format!(
-                "capture API returned {} (content-type: {}): {}",
-                status,
-                content_type,
-                text
-            )
-            .into());
-        }
+            .map_err(|e| format!("render task failed: {e}"))?
+    }
 
-        let bytes = resp.bytes().await?;
-        Ok(bytes.to_vec())
+    /// 長すぎる数式は組版に回す前に断る
+    fn check_len(expr: &str) -> Result<(), String> {
+        let chars = expr.chars().count();
+        if chars > tex::MAX_EXPR_CHARS {
+            return Err(format!("expression is too long ({chars} chars, max {})", tex::MAX_EXPR_CHARS));
+        }
+        Ok(())
     }
 }
 
@@ -84,7 +74,16 @@ impl LMTool for LatexExprRenderTool {
         "Render LaTeX expressions to images and send to Discord.".to_string()
     }
 
+    async fn health_check(&self, _config: &Config) -> Result<(), String> {
+        // フォントが見つからなければここで落ちる
+        tex::renderer().map(|_| ())
+    }
+
     fn json_schema(&self) -> serde_json::Value {
+        let expression = format!(
+            "The LaTeX math expression to render (without surrounding $ or $$, at most {} chars). Supports \\frac, \\sqrt, sub/superscripts, \\sum/\\int with limits, \\left...\\right, matrices, cases and align.",
+            tex::MAX_EXPR_CHARS
+        );
         serde_json::json!({
             "type": "object",
             "properties": {
@@ -98,7 +97,17 @@ impl LMTool for LatexExprRenderTool {
                 },
                 "expression": {
                     "type": "string",
-                    "description": "The LaTeX expression to render."
+                    "description": expression
+                },
+                "mode": {
+                    "type": "string",
+                    "description": "display (default, like $$...$$) or inline (like $...$).",
+                    "enum": ["display", "inline"]
+                },
+                "format": {
+                    "type": "string",
+                    "description": "Image format to send (default png).",
+                    "enum": ["png", "svg"]
                 }
             },
             "required": ["expression", "channel_id"]
@@ -108,7 +117,7 @@ impl LMTool for LatexExprRenderTool {
     async fn execute(
         &self,
         args: serde_json::Value,
-        ob_ctx: crate::context::ObserverContext,
+        ob_ctx: ObserverContext,
     ) -> Result<String, String> {
         // --- 引数パース ---
         let channel_id_str = args
@@ -126,6 +135,12 @@ impl LMTool for LatexExprRenderTool {
             .and_then(|v| v.as_str())
             .filter(|s| !s.is_empty());
 
+        let mode = match args.get("mode").and_then(|v| v.as_str()) {
+            Some(m) => MathMode::parse(m).ok_or(format!("Invalid 'mode': {m}"))?,
+            None => MathMode::Display,
+        };
+        let format = args.get("format").and_then(|v| v.as_str()).unwrap_or("png");
+
         let channel_id = ChannelId::from_str(channel_id_str)
             .map_err(|e| format!("Invalid 'channel_id': {e}"))?;
 
@@ -139,15 +154,25 @@ impl LMTool for LatexExprRenderTool {
         };
 
         // --- LaTeX → 画像レンダリング ---
-        let png_bytes = Self::render(expr, &ob_ctx)
-            .await
-            .map_err(|e| format!("Failed to render LaTeX expression: {e}"))?;
+        let options = RenderOptions { mode, ..self.options.clone() };
+        let attachment = match format {
+            "png" => CreateAttachment::bytes(
+                Self::render(expr, &options).await.map_err(|e| format!("Failed to render LaTeX expression: {e}"))?,
+                "latex.png",
+            ),
+            "svg" => CreateAttachment::bytes(
+                Self::render_svg(expr, &options)
+                    .await
+                    .map_err(|e| format!("Failed to render LaTeX expression: {e}"))?
+                    .into_bytes(),
+                "latex.svg",
+            ),
+            other => return Err(format!("Invalid 'format': {other}")),
+        };
 
         // --- Discord 送信 ---
         let http = ob_ctx.discord_client.open().http.clone();
 
-        let attachment = CreateAttachment::bytes(png_bytes, "latex.png");
-
         let mut builder = CreateMessage::new()
             .add_file(attachment);
 
@@ -166,6 +191,7 @@ impl LMTool for LatexExprRenderTool {
             "message_id": msg.id.to_string(),
             "channel_id": channel_id.to_string(),
             "expression": expr,
+            "format": format,
         });
 
         Ok(result.to_string())
@@ -1,12 +1,15 @@
 pub mod get_time;
 pub mod browser;
+pub mod browsing_worker;
+pub mod code_exec;
 pub mod discord;
+pub mod image_captioner;
+pub mod judge;
 pub mod latex;
-// pub mod www_search;
+pub mod read_output;
+pub mod registry;
+pub mod web_deploy;
+pub mod web_search;
 // pub mod web_scraper;
 // pub mod memory;
 // pub mod text_len;
-#[cfg(feature = "web-deploy-tool")]
-pub mod web_deploy;
-// pub mod image_captioner;
-// pub mod browsing_worker;
\ No newline at end of file
@@ -0,0 +1,130 @@
+use log::info;
+use openai_dive::v1::resources::response::response::Role;
+use serde_json::json;
+
+use crate::{context::ObserverContext, lmclient::{LMContext, LMTool, ToolInvoker}, tool_output::slice_chars};
+
+/// 要約に回す最大文字数 (これを超えた分は切り捨てて要約する)
+const SUMMARIZE_MAX_CHARS: usize = 48_000;
+
+/// 退避されたツール出力を読むためのツール
+#[derive(Default)]
+pub struct ReadToolOutput {}
+
+impl ReadToolOutput {
+    pub fn new() -> ReadToolOutput {
+        ReadToolOutput::default()
+    }
+
+    /// サブモデルで全文を要約する
+    async fn summarize(content: &str, focus: Option<&str>, ob_ctx: &ObserverContext) -> Result<String, String> {
+        let total = content.chars().count();
+        let body = slice_chars(content, 0, SUMMARIZE_MAX_CHARS);
+
+        let mut context = LMContext::new();
+        context.add_text(
+            format!(
+                "Summarize the following tool output concisely, keeping facts, numbers, names and URLs that matter.{}\
+                 Reply in the same language as the content.",
+                focus.map(|f| format!(" Focus on: {f}.")).unwrap_or_default()
+            ),
+            Role::System,
+        );
+        context.add_text(body.to_string(), Role::User);
+
+        let result = ob_ctx
+            .lm_client
+            .generate_response(ob_ctx.clone(), &context, Some(1000), None, None, None, None, None)
+            .await
+            .map_err(|e| format!("Failed to summarize: {e}"))?;
+
+        let mut summary = result.get_result();
+        if total > SUMMARIZE_MAX_CHARS {
+            summary.push_str(&format!(
+                "\n[note: only the first {SUMMARIZE_MAX_CHARS} of {total} chars were summarized]"
+            ));
+        }
+        Ok(summary)
+    }
+}
+
+#[async_trait::async_trait]
+impl LMTool for ReadToolOutput {
+    fn name(&self) -> String {
+        "read_tool_output".to_string()
+    }
+
+    fn description(&self) -> String {
+        "Read a truncated tool output by its handle, page by page, or get a summary of the whole output.".to_string()
+    }
+
+    fn json_schema(&self) -> serde_json::Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "handle": {
+                    "type": "string",
+                    "description": "Handle of the stored output (e.g. 'out-3f9a0c2e5b7d1486'), shown in the truncation notice."
+                },
+                "page": {
+                    "type": "integer",
+                    "description": "1-based page number to read. Defaults to 2 (the page after the one already shown)."
+                },
+                "summarize": {
+                    "type": "boolean",
+                    "description": "If true, return a summary of the whole output instead of a page.",
+                    "default": false
+                },
+                "focus": {
+                    "type": "string",
+                    "description": "Optional topic to focus on when summarizing."
+                }
+            },
+            "required": ["handle"]
+        })
+    }
+
+    fn shrink_output(&self) -> bool {
+        // 自分の出力を再退避すると読めなくなるので対象外
+        false
+    }
+
+    async fn execute(&self, args: serde_json::Value, ob_ctx: ObserverContext) -> Result<String, String> {
+        self.execute_for(args, ob_ctx, None).await
+    }
+
+    /// 読めるのは同じチャンネルで退避された出力だけ
+    async fn execute_for(&self, args: serde_json::Value, ob_ctx: ObserverContext, invoker: Option<ToolInvoker>) -> Result<String, String> {
+        info!("ReadToolOutput::execute called with args: {:?}", args);
+        let channel_id = invoker.map(|i| i.channel_id);
+        let handle = args.get("handle")
+            .and_then(|v| v.as_str())
+            .ok_or("Missing or invalid 'handle' parameter".to_string())?;
+        let summarize = args.get("summarize")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false);
+
+        let entry = ob_ctx.tool_outputs.get(handle, channel_id)
+            .ok_or_else(|| format!("Unknown or expired handle: {handle}"))?;
+
+        if summarize {
+            let focus = args.get("focus").and_then(|v| v.as_str());
+            let summary = Self::summarize(&entry.content, focus, &ob_ctx).await?;
+            return Ok(format!("Summary of {} output ({}):\n{}", entry.tool_name, handle, summary));
+        }
+
+        let page = args.get("page")
+            .and_then(|v| v.as_u64())
+            .unwrap_or(2) as usize;
+        let (text, pages) = ob_ctx.tool_outputs.page(handle, page, channel_id)
+            .ok_or_else(|| format!("Unknown or expired handle: {handle}"))?;
+        if text.is_empty() {
+            return Err(format!("Page {page} is out of range (1-{pages})"));
+        }
+
+        Ok(format!(
+            "[{} output {}: page {}/{}]\n{}",
+            entry.tool_name, handle, page, pages, text
+        ))
+    }
+}
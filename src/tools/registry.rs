@@ -0,0 +1,164 @@
+use std::{collections::HashMap, sync::{Arc, RwLock}};
+
+use log::{info, warn};
+
+use crate::{config::{Config, ToolConfig}, lmclient::LMTool, tools};
+
+/// ツール固有の設定からツールを組み立てる関数
+pub type ToolBuilder = fn(&ToolConfig, &Config) -> Result<Box<dyn LMTool>, String>;
+
+/// 登録可能なツールの定義
+pub struct ToolSpec {
+    /// ツール名 (config.json の `tools` のキーと LMTool::name が一致する)
+    pub name: &'static str,
+    /// config に記述がないときに有効にするか
+    pub default_enabled: bool,
+    /// Discord に接続していないと使えないか (オフラインの `observer chat` では外す)
+    pub needs_discord: bool,
+    /// ツール固有の設定からツールを組み立てる
+    pub build: ToolBuilder,
+}
+
+/// 既知のツールの一覧
+/// ツールを追加するときはここに足す
+pub fn specs() -> Vec<ToolSpec> {
+    vec![
+        ToolSpec {
+            name: "get-location-time",
+            default_enabled: true,
+            needs_discord: false,
+            build: |_, _| Ok(Box::new(tools::get_time::GetTime::new())),
+        },
+        ToolSpec {
+            name: "browser",
+            default_enabled: true,
+            needs_discord: false,
+            build: |cfg, _| Ok(Box::new(tools::browser::Browser::from_config(cfg))),
+        },
+        ToolSpec {
+            name: "discord-tool",
+            default_enabled: true,
+            needs_discord: true,
+            build: |cfg, _| Ok(Box::new(tools::discord::DiscordTool::from_config(cfg))),
+        },
+        ToolSpec {
+            name: "latex_expr_render",
+            default_enabled: true,
+            needs_discord: true,
+            build: |cfg, _| Ok(Box::new(tools::latex::LatexExprRenderTool::from_config(cfg))),
+        },
+        ToolSpec {
+            name: "web_search",
+            default_enabled: true,
+            needs_discord: false,
+            build: |cfg, _| Ok(Box::new(tools::web_search::WebSearch::from_config(cfg)?)),
+        },
+        ToolSpec {
+            name: "code_exec",
+            default_enabled: true,
+            needs_discord: false,
+            build: |cfg, _| Ok(Box::new(tools::code_exec::CodeExec::from_config(cfg))),
+        },
+        ToolSpec {
+            name: "judge",
+            default_enabled: true,
+            needs_discord: false,
+            build: |cfg, _| Ok(Box::new(tools::judge::Judge::from_config(cfg))),
+        },
+        ToolSpec {
+            name: "image_captioner",
+            default_enabled: true,
+            needs_discord: false,
+            build: |cfg, config| Ok(Box::new(tools::image_captioner::ImageCaptioner::from_config(cfg, config)?)),
+        },
+        ToolSpec {
+            name: "web_deploy_tool",
+            default_enabled: false,
+            needs_discord: false,
+            build: |cfg, config| Ok(Box::new(tools::web_deploy::WebDeploy::from_config(cfg, config))),
+        },
+        ToolSpec {
+            name: "browsing_worker",
+            default_enabled: false,
+            needs_discord: false,
+            build: |cfg, config| Ok(Box::new(tools::browsing_worker::BrowsingWorker::from_config(cfg, config))),
+        },
+        ToolSpec {
+            name: "read_tool_output",
+            default_enabled: true,
+            needs_discord: false,
+            build: |_, _| Ok(Box::new(tools::read_output::ReadToolOutput::new())),
+        },
+    ]
+}
+
+/// 実行中に差し替えられるツール一覧 (設定の読み直しで組み立て直す)
+/// 読む側は `get` で今の一覧の Arc を取り出して使う
+pub struct SharedTools {
+    inner: RwLock<Arc<HashMap<String, Arc<dyn LMTool>>>>,
+}
+
+impl SharedTools {
+    pub fn new(tools: HashMap<String, Arc<dyn LMTool>>) -> SharedTools {
+        SharedTools {
+            inner: RwLock::new(Arc::new(tools)),
+        }
+    }
+
+    pub fn get(&self) -> Arc<HashMap<String, Arc<dyn LMTool>>> {
+        self.inner.read().expect("RWlock").clone()
+    }
+
+    pub fn set(&self, tools: HashMap<String, Arc<dyn LMTool>>) {
+        *self.inner.write().expect("RWlock") = Arc::new(tools);
+    }
+}
+
+/// config からツールを組み立てる
+/// 無効化されたもの、組み立てや依存チェックに失敗したものは理由をログに出して外す
+pub async fn build_tools(config: &Config) -> HashMap<String, Arc<dyn LMTool>> {
+    let specs = specs();
+
+    for name in config.tools.keys() {
+        if !specs.iter().any(|s| s.name == name) {
+            warn!("tools: unknown tool `{}` in config (ignored)", name);
+        }
+    }
+
+    let mut tools: HashMap<String, Arc<dyn LMTool>> = HashMap::new();
+    for spec in specs {
+        let tool_cfg = config.tool(spec.name);
+        if !tool_cfg.enabled.unwrap_or(spec.default_enabled) {
+            info!("tools: {} disabled by config", spec.name);
+            continue;
+        }
+
+        let tool = match (spec.build)(&tool_cfg, config) {
+            Ok(tool) => tool,
+            Err(e) => {
+                warn!("tools: {} → {} disabled", e, spec.name);
+                continue;
+            }
+        };
+
+        if let Err(e) = tool.health_check(config).await {
+            warn!("tools: {} → {} disabled", e, spec.name);
+            continue;
+        }
+
+        info!("tools: {} enabled", spec.name);
+        tools.insert(tool.name(), Arc::from(tool));
+    }
+
+    tools
+}
+
+/// 名前で絞り込んだツール
+/// インスタンスは元のものを共有する (キャッシュや同時実行数の制限もそのまま)
+pub fn subset(tools: &HashMap<String, Arc<dyn LMTool>>, allowed: impl Fn(&str) -> bool) -> HashMap<String, Arc<dyn LMTool>> {
+    tools
+        .iter()
+        .filter(|(name, _)| allowed(name))
+        .map(|(name, tool)| (name.clone(), tool.clone()))
+        .collect()
+}
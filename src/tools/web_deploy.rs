@@ -1,236 +1,225 @@
-use actix_web::{App, HttpResponse, HttpServer, Responder, web};
-use call_agent::chat::function::Tool;
+use std::{fs, path::{Path, PathBuf}};
+
 use chrono::{Datelike, Local};
-use log::debug;
-use std::collections::HashMap;
-use std::path::{Path, PathBuf};
-use std::sync::Arc;
-use std::{fs, thread};
-use tokio::runtime::Runtime;
-use tokio::sync::RwLock;
+use log::{debug, info};
+use serde_json::json;
 
-use crate::prefix::DOMAIN;
-use actix_web::HttpRequest;
-use std::fs::File;
-use std::io::{Read, Seek, SeekFrom};
+use crate::{config::{Config, ToolConfig}, context::ObserverContext, lmclient::LMTool, site};
 
-const BASE_DIR: &str = "./data";
+/// ツール名 (config.json の `tools` のキー)
+pub const TOOL_NAME: &str = "web_deploy_tool";
+/// 記事を置くディレクトリの既定値 (`YYYY/M/記事キー` の形で保存する)
+const DEFAULT_DATA_DIR: &str = "./data";
+/// 記事 1件の最大バイト数
+const MAX_ARTICLE_BYTES: usize = 512 * 1024;
 
-#[derive(Clone)]
-pub struct WebDeploy {
-    file_map: Arc<RwLock<HashMap<String, PathBuf>>>, // 記事キー → ファイルパス
+/// `data/YYYY/M/記事キー` に置いた記事のストア
+/// サーバ側とツール側で同じディレクトリを見るだけなので状態は持たない
+#[derive(Debug, Clone)]
+pub struct ArticleStore {
+    base_dir: PathBuf,
 }
 
-async fn list_articles_by_month(
-    path: web::Path<(String, String)>,
-    query: web::Query<HashMap<String, String>>,
-) -> impl Responder {
-    let (year, month) = path.into_inner();
-    let normalized_month = month.trim_start_matches('0');
-    let base_path = format!("{}/{}/{}", BASE_DIR, year, normalized_month);
-    let page: usize = query.get("page").and_then(|p| p.parse().ok()).unwrap_or(1);
-    let per_page: usize = query
-        .get("per_page")
-        .and_then(|p| p.parse().ok())
-        .unwrap_or(10);
-
-    let mut articles = Vec::new();
-    if let Ok(files) = fs::read_dir(&base_path) {
-        for file_entry in files.flatten() {
-            if let Some(article_name) = file_entry.file_name().to_str() {
-                articles.push(article_name.to_string());
-            }
-        }
+impl ArticleStore {
+    pub fn new(base_dir: impl Into<PathBuf>) -> ArticleStore {
+        ArticleStore { base_dir: base_dir.into() }
     }
 
-    let start = (page - 1) * per_page;
-    let end = start + per_page;
-    let paginated_articles = articles[start.min(articles.len())..end.min(articles.len())].to_vec();
-
-    HttpResponse::Ok().json(serde_json::json!({ "articles": paginated_articles }))
-}
+    /// config.json の `tools.web_deploy_tool` から組み立てる
+    pub fn from_config(cfg: &ToolConfig) -> ArticleStore {
+        Self::new(cfg.str_setting("data_dir").unwrap_or(DEFAULT_DATA_DIR))
+    }
 
-async fn get_article_raw(
-    path: web::Path<(String, String, String)>,
-    req: HttpRequest,
-) -> impl Responder {
-    let (year, month, article) = path.into_inner();
-    let normalized_month = month.trim_start_matches('0');
-    let file_path = format!("{}/{}/{}/{}", BASE_DIR, year, normalized_month, article);
+    /// 記事キーからファイル名に使えない文字を除く
+    pub fn sanitize_key(raw: &str) -> Result<String, String> {
+        let key: String = raw
+            .trim()
+            .chars()
+            .filter(|&c| !matches!(c, '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*') && !c.is_control())
+            .collect();
+        let key = key.trim_matches('.').to_string();
+        if key.is_empty() {
+            return Err("The provided 'key' parameter contains only invalid characters".to_string());
+        }
+        Ok(key)
+    }
 
-    if let Ok(mut file) = File::open(&file_path) {
-        let metadata = file.metadata().ok();
-        let file_size = metadata.map(|m| m.len()).unwrap_or(0);
+    /// 年と月のディレクトリ名を正規化する (月は先頭の 0 を外す)
+    fn month_dir(&self, year: &str, month: &str) -> Option<PathBuf> {
+        let year: u32 = year.parse().ok()?;
+        let month: u32 = month.trim_start_matches('0').parse().ok()?;
+        if !(1..=12).contains(&month) {
+            return None;
+        }
+        Some(self.base_dir.join(year.to_string()).join(month.to_string()))
+    }
 
-        if let Some(range_header) = req.headers().get("Range")
-            && let Ok(range) = range_header.to_str()
-                && let Some(range) = range.strip_prefix("bytes=") {
-                    let parts: Vec<&str> = range.split('-').collect();
-                    if let (Some(start_str), Some(end_str)) = (parts.first(), parts.get(1))
-                        && let (Ok(start), Ok(end)) =
-                            (start_str.parse::<u64>(), end_str.parse::<u64>())
-                            && start < file_size && end < file_size {
-                                let mut buffer = vec![0; (end - start + 1) as usize];
-                                file.seek(SeekFrom::Start(start)).ok();
-                                file.read_exact(&mut buffer).ok();
-                                return HttpResponse::PartialContent()
-                                    .insert_header((
-                                        "Content-Range",
-                                        format!("bytes {}-{}/{}", start, end, file_size),
-                                    ))
-                                    .body(buffer);
-                            }
+    /// 記事のある (年, 月) を新しい順に返す
+    pub fn months(&self) -> Vec<(u32, u32)> {
+        let mut months = Vec::new();
+        for year in read_dir_names(&self.base_dir) {
+            let Ok(y) = year.parse::<u32>() else { continue };
+            for month in read_dir_names(&self.base_dir.join(&year)) {
+                if let Ok(m) = month.parse::<u32>()
+                    && (1..=12).contains(&m)
+                {
+                    months.push((y, m));
                 }
-
-        let mut buffer = Vec::new();
-        file.read_to_end(&mut buffer).ok();
-        return HttpResponse::Ok().body(buffer);
+            }
+        }
+        months.sort_unstable_by(|a, b| b.cmp(a));
+        months
     }
 
-    HttpResponse::NotFound().body("記事が見つかりません")
-}
+    /// その月の記事キー (名前順)
+    pub fn list(&self, year: &str, month: &str) -> Vec<String> {
+        let Some(dir) = self.month_dir(year, month) else {
+            return Vec::new();
+        };
+        let mut articles: Vec<String> = fs::read_dir(dir)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .filter(|e| e.path().is_file())
+            .filter_map(|e| e.file_name().to_str().map(|s| s.to_string()))
+            .collect();
+        articles.sort();
+        articles
+    }
 
-async fn root_page() -> impl Responder {
-    let file_path = "./data/index.html";
-    if let Ok(content) = fs::read_to_string(file_path) {
-        HttpResponse::Ok().content_type("text/html").body(content)
-    } else {
-        HttpResponse::NotFound().body("ルートページが見つかりません")
+    /// 記事を置くディレクトリ
+    pub fn base_dir(&self) -> &Path {
+        &self.base_dir
     }
-}
 
-async fn favicon() -> impl Responder {
-    let file_path = "./data/favicon.ico";
-    if let Ok(mut file) = File::open(file_path) {
-        let mut buffer = Vec::new();
-        file.read_to_end(&mut buffer).ok();
-        return HttpResponse::Ok().content_type("image/x-icon").body(buffer);
+    /// 記事ファイルのパス
+    pub fn path_of(&self, year: u32, month: u32, key: &str) -> PathBuf {
+        self.base_dir.join(year.to_string()).join(month.to_string()).join(key)
     }
-    HttpResponse::NotFound().body("Favicon not found")
-}
 
-impl WebDeploy {
-    pub async fn new() -> Self {
-        let file_map = Arc::new(RwLock::new(HashMap::new()));
-        let base_dir = Path::new(BASE_DIR);
-        if base_dir.exists() {
-            for y in fs::read_dir(base_dir).expect("Failed to read directory") {
-                let y = y.expect("Failed to read directory entry");
-                let y_path = y.path();
-                if y_path.is_dir() {
-                    for m in fs::read_dir(y_path).expect("Failed to read directory") {
-                        let m = m.expect("Failed to read directory entry");
-                        let m_path = m.path();
-                        if m_path.is_dir() {
-                            for articles in fs::read_dir(m_path).expect("Failed to read directory")
-                            {
-                                let articles = articles.expect("Failed to read directory entry");
-                                let articles_path = articles.path();
-                                if articles_path.is_file() {
-                                    let relative_path =
-                                        articles_path.strip_prefix(BASE_DIR).unwrap();
-                                    let key = relative_path
-                                        .to_string_lossy()
-                                        .split(&['/', '\\'][..])
-                                        .collect::<Vec<&str>>()[2]
-                                        .to_string();
-                                    debug!("loading article: {}", key);
-                                    file_map.write().await.insert(key, articles_path.clone());
-                                }
-                            }
-                        }
-                    }
-                }
-            }
-        } else {
-            fs::create_dir_all(base_dir).expect("Failed to create directory");
-        }
+    /// 記事キーから (年, 月, パス) を探す
+    pub fn find(&self, key: &str) -> Option<(u32, u32, PathBuf)> {
+        self.months().into_iter().find_map(|(y, m)| {
+            let path = self.path_of(y, m, key);
+            path.is_file().then_some((y, m, path))
+        })
+    }
 
-        Self { file_map }
+    /// 年・月・記事キーを指定して本文を読む
+    pub fn read_at(&self, year: &str, month: &str, key: &str) -> Option<String> {
+        let key = Self::sanitize_key(key).ok()?;
+        fs::read_to_string(self.month_dir(year, month)?.join(key)).ok()
     }
 
-    pub async fn get_article(&self, key: &str) -> Result<String, String> {
-        let file_map = self.file_map.read().await;
-        let path = file_map.get(key).ok_or("Article not found")?;
-        let content = fs::read_to_string(path)
-            .map(|s| s.to_string())
-            .map_err(|_| "Failed loading article")?;
-        Ok(content)
+    pub fn read(&self, key: &str) -> Result<String, String> {
+        let (_, _, path) = self.find(key).ok_or("Article not found")?;
+        fs::read_to_string(path).map_err(|_| "Failed loading article".to_string())
     }
 
-    pub async fn found_article(&self, key: &str) -> bool {
-        self.file_map.read().await.contains_key(key)
+    /// 記事を書き込む (同じキーの記事があればその場所を上書きする)
+    /// 保存した (年, 月) を返す
+    pub fn write(&self, key: &str, content: &str) -> Result<(u32, u32), String> {
+        let (year, month, path) = match self.find(key) {
+            Some(found) => found,
+            None => {
+                let now = Local::now();
+                let (year, month) = (now.year() as u32, now.month());
+                (year, month, self.path_of(year, month, key))
+            }
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|e| format!("Failed to create directory: {e}"))?;
+        }
+        fs::write(&path, content).map_err(|e| format!("Failed to write file: {e}"))?;
+        debug!("article written: {}", path.display());
+        Ok((year, month))
     }
+}
 
-    pub async fn create_article(&self, key: &str, content: &str) -> Result<String, String> {
-        let file_map = Arc::clone(&self.file_map);
-        let base_dir = Path::new(BASE_DIR);
-        let now = Local::now();
-        let year = now.year().to_string();
-        let month = now.month().to_string();
-        let key_path = base_dir.join(&year).join(&month).join(key);
+fn read_dir_names(dir: &Path) -> Vec<String> {
+    fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|e| e.path().is_dir())
+        .filter_map(|e| e.file_name().to_str().map(|s| s.to_string()))
+        .collect()
+}
 
-        fs::create_dir_all(key_path.parent().unwrap()).map_err(|_| "Failed to create directory")?;
-        fs::write(&key_path, content).map_err(|_| "Failed to write file")?;
+/// `GET /articles/:year/:month` の応答 (ページ送り付きの記事キー一覧)
+pub fn list_articles_json(store: &ArticleStore, year: &str, month: &str, page: Option<&str>, per_page: Option<&str>) -> String {
+    let page: usize = page.and_then(|p| p.parse().ok()).filter(|p| *p >= 1).unwrap_or(1);
+    let per_page: usize = per_page.and_then(|p| p.parse().ok()).unwrap_or(10).clamp(1, 100);
+    let articles = store.list(year, month);
+    let start = ((page - 1) * per_page).min(articles.len());
+    let end = (start + per_page).min(articles.len());
+    json!({
+        "articles": &articles[start..end],
+        "total": articles.len(),
+        "page": page,
+        "per_page": per_page,
+    })
+    .to_string()
+}
 
-        file_map
-            .write()
-            .await
-            .insert(key.to_string(), key_path.clone());
-        Ok(format!(
-            "https://{}/?view=article&year={}&month={}&article={}",
-            *DOMAIN, year, month, key
-        ))
+/// 記事を公開・取得するツール
+pub struct WebDeploy {
+    store: ArticleStore,
+    /// 記事の URL のベース (`server_domain` から作る)
+    base_url: String,
+}
+
+impl WebDeploy {
+    pub fn from_config(cfg: &ToolConfig, config: &Config) -> WebDeploy {
+        WebDeploy {
+            store: ArticleStore::from_config(cfg),
+            base_url: config.public_base_url(),
+        }
     }
 
-    pub fn start_server(&self, bind: String) {
-        let map = Arc::clone(&self.file_map);
-        thread::spawn(move || {
-            let rt = Runtime::new().expect("Failed to create runtime");
-            rt.block_on(async move {
-                HttpServer::new(move || {
-                    App::new()
-                        .app_data(web::Data::new(Arc::clone(&map)))
-                        .route("/", web::get().to(root_page))
-                        .route(
-                            "/articles/{year}/{month}",
-                            web::get().to(list_articles_by_month),
-                        )
-                        .route(
-                            "/article/raw/{year}/{month}/{article}",
-                            web::get().to(get_article_raw),
-                        )
-                        .route("/favicon.ico", web::get().to(favicon))
-                })
-                .bind(bind.as_str())
-                .expect("Failed to bind server")
-                .run()
-                .await
-                .expect("Server run failed");
-            });
-        });
+    /// 記事のパーマリンク
+    pub fn article_url(&self, year: u32, month: u32, key: &str) -> String {
+        format!("{}{}", self.base_url, site::article_path(year, month, key))
     }
 }
 
-impl Tool for WebDeploy {
-    fn def_name(&self) -> &str {
-        "web_deploy_tool"
+#[async_trait::async_trait]
+impl LMTool for WebDeploy {
+    fn name(&self) -> String {
+        TOOL_NAME.to_string()
     }
 
-    fn def_description(&self) -> &str {
+    fn description(&self) -> String {
         "A tool to deploy articles to the web.
-        The article should be crafted to provide a reading experience of approximately 1 to 5 minutes.
-        Also, please provide an appropriate source of information."
+The article should be crafted to provide a reading experience of approximately 1 to 5 minutes.
+Write it in markdown: start with a '# Title' line and a short first paragraph (used as the summary in the index and feed). \
+Code blocks are highlighted and $...$ / $$...$$ math is rendered.
+Also, please provide an appropriate source of information."
+            .to_string()
     }
 
-    fn def_parameters(&self) -> serde_json::Value {
-        serde_json::json!({
+    fn approval_summary(&self, args: &serde_json::Value) -> Option<String> {
+        if args.get("action").and_then(|v| v.as_str()) != Some("create") {
+            return None;
+        }
+        let key = args.get("key").and_then(|v| v.as_str()).unwrap_or("?");
+        let chars = args.get("content").and_then(|v| v.as_str()).map(|c| c.chars().count()).unwrap_or(0);
+        Some(format!("Publish the article `{key}` ({chars} chars) on {}", self.base_url))
+    }
+
+    async fn health_check(&self, _config: &Config) -> Result<(), String> {
+        fs::create_dir_all(&self.store.base_dir)
+            .map_err(|e| format!("cannot create article directory {}: {e}", self.store.base_dir.display()))
+    }
+
+    fn json_schema(&self) -> serde_json::Value {
+        json!({
             "type": "object",
             "properties": {
                 "action": {
                     "type": "string",
                     "enum": ["get", "create", "found"],
-                    "description": "Action to perform: 'get' (retrieve an article), 'create' (add a new article), 'found' (check if an article exists)"
+                    "description": "Action to perform: 'get' (retrieve an article), 'create' (add a new article, or update an existing one), 'found' (check if an article exists)"
                 },
                 "key": {
                     "type": "string",
@@ -238,69 +227,58 @@ impl Tool for WebDeploy {
                 },
                 "content": {
                     "type": "string",
-                    "description": "Content of the article",
+                    "description": "Content of the article (markdown). Required for 'create'."
                 },
                 "$explain": {
                     "type": "string",
                     "description": "A brief explanation of what you are doing with this tool."
-                },
-            },
-            "required": ["action", "key"],
-            "if": {
-                "properties": {
-                    "action": { "const": "create" }
                 }
             },
-            "then": {
-                "required": ["content"]
-            }
+            "required": ["action", "key"]
         })
     }
 
-    fn run(&self, args: serde_json::Value) -> Result<String, String> {
-        let self_clone = self.clone();
+    async fn execute(&self, args: serde_json::Value, _ob_ctx: ObserverContext) -> Result<String, String> {
+        info!("WebDeploy::execute called with action: {:?}", args.get("action"));
+        let action = args
+            .get("action")
+            .and_then(|v| v.as_str())
+            .ok_or("Missing or invalid 'action' parameter")?;
+        let raw_key = args
+            .get("key")
+            .and_then(|v| v.as_str())
+            .ok_or("Missing or invalid 'key' parameter")?;
+        let key = ArticleStore::sanitize_key(raw_key)?;
 
-        let args_clone = args.clone();
-        
-
-        thread::spawn(move || {
-            let rt = Runtime::new().expect("Failed to create runtime");
-
-            let action = args_clone
-                .get("action")
-                .and_then(|v| v.as_str())
-                .ok_or("Missing or invalid 'action' parameter")?;
-            let raw_key = args_clone
-                .get("key")
-                .and_then(|v| v.as_str())
-                .ok_or("Missing or invalid 'key' parameter")?;
-            let sanitized_key: String = raw_key
-                .chars()
-                .filter(|&c| !matches!(c, '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*'))
-                .collect();
-            if sanitized_key.is_empty() {
-                return Err(
-                    "The provided 'key' parameter contains only invalid characters".to_string(),
-                );
+        // ファイル IO はブロッキングなので別スレッドで
+        let store = self.store.clone();
+        match action {
+            "get" => tokio::task::spawn_blocking(move || store.read(&key))
+                .await
+                .map_err(|e| format!("article task failed: {e}"))?,
+            "found" => {
+                let found = tokio::task::spawn_blocking(move || store.find(&key).is_some())
+                    .await
+                    .map_err(|e| format!("article task failed: {e}"))?;
+                Ok(found.to_string())
             }
-            let key = sanitized_key;
-            match action {
-                "get" => rt.block_on(async { self_clone.get_article(&key).await }),
-                "create" => rt.block_on(async {
-                    let content = args_clone
-                        .get("content")
-                        .and_then(|v| v.as_str())
-                        .ok_or("Missing or invalid 'content' parameter")?;
-                    self_clone.create_article(&key, content).await
-                }),
-                "found" => rt.block_on(async {
-                    let found = self_clone.found_article(&key).await;
-                    Ok(found.to_string())
-                }),
-                _ => Err("Invalid action".to_string()),
+            "create" => {
+                let content = args
+                    .get("content")
+                    .and_then(|v| v.as_str())
+                    .filter(|s| !s.trim().is_empty())
+                    .ok_or("Missing or invalid 'content' parameter")?
+                    .to_string();
+                if content.len() > MAX_ARTICLE_BYTES {
+                    return Err(format!("The article is too large (max {MAX_ARTICLE_BYTES} bytes)"));
+                }
+                let write_key = key.clone();
+                let (year, month) = tokio::task::spawn_blocking(move || store.write(&write_key, &content))
+                    .await
+                    .map_err(|e| format!("article task failed: {e}"))??;
+                Ok(self.article_url(year, month, &key))
             }
-        })
-        .join()
-        .map_err(|_| "Thread panicked".to_string())?
+            other => Err(format!("Invalid action '{other}'. Use get, create or found.")),
+        }
     }
 }
@@ -0,0 +1,359 @@
+use std::{collections::HashMap, time::{Duration, Instant}};
+
+use dashmap::DashMap;
+use log::{debug, info};
+use serde::Serialize;
+use serde_json::json;
+
+use crate::{config::ToolConfig, context::ObserverContext, lmclient::LMTool, secret};
+
+/// キャッシュに持つクエリ数の上限
+const CACHE_CAPACITY: usize = 256;
+
+/// 検索結果 1件
+#[derive(Debug, Clone, Serialize)]
+pub struct SearchResult {
+    pub title: String,
+    pub url: String,
+    pub snippet: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub date: Option<String>,
+}
+
+/// 検索の条件
+#[derive(Debug, Clone)]
+pub struct SearchQuery {
+    pub query: String,
+    pub max_results: usize,
+    /// 言語コード (例: ja, en)
+    pub language: Option<String>,
+    /// day / week / month / year
+    pub time_range: Option<String>,
+}
+
+impl SearchQuery {
+    fn cache_key(&self) -> String {
+        format!(
+            "{}\u{1f}{}\u{1f}{}\u{1f}{}",
+            self.query,
+            self.max_results,
+            self.language.as_deref().unwrap_or(""),
+            self.time_range.as_deref().unwrap_or("")
+        )
+    }
+}
+
+/// 検索バックエンドのアダプタ
+#[async_trait::async_trait]
+pub trait SearchBackend: Send + Sync {
+    fn name(&self) -> &'static str;
+    async fn search(&self, http: &reqwest::Client, query: &SearchQuery) -> Result<Vec<SearchResult>, String>;
+}
+
+/// SearXNG (`/search?format=json`)
+pub struct SearxngBackend {
+    endpoint: String,
+}
+
+#[async_trait::async_trait]
+impl SearchBackend for SearxngBackend {
+    fn name(&self) -> &'static str {
+        "searxng"
+    }
+
+    async fn search(&self, http: &reqwest::Client, query: &SearchQuery) -> Result<Vec<SearchResult>, String> {
+        let mut params = vec![
+            ("q", query.query.clone()),
+            ("format", "json".to_string()),
+        ];
+        if let Some(lang) = &query.language {
+            params.push(("language", lang.clone()));
+        }
+        if let Some(range) = &query.time_range {
+            params.push(("time_range", range.clone()));
+        }
+
+        let body: serde_json::Value = http
+            .get(format!("{}/search", self.endpoint))
+            .query(&params)
+            .send()
+            .await
+            .map_err(|e| format!("searxng request failed: {e}"))?
+            .error_for_status()
+            .map_err(|e| format!("searxng returned an error: {e}"))?
+            .json()
+            .await
+            .map_err(|e| format!("searxng returned invalid JSON: {e}"))?;
+
+        let results = body
+            .get("results")
+            .and_then(|v| v.as_array())
+            .ok_or("searxng response has no 'results'".to_string())?;
+
+        Ok(results
+            .iter()
+            .filter_map(|r| {
+                Some(SearchResult {
+                    title: str_field(r, "title").unwrap_or_default(),
+                    url: str_field(r, "url")?,
+                    snippet: str_field(r, "content").unwrap_or_default(),
+                    date: str_field(r, "publishedDate"),
+                })
+            })
+            .take(query.max_results)
+            .collect())
+    }
+}
+
+/// 任意の JSON 検索 API
+/// 結果配列の場所と各フィールド名を設定で指定する (Brave Search, Google CSE など)
+pub struct JsonApiBackend {
+    endpoint: String,
+    query_param: String,
+    count_param: Option<String>,
+    api_key: Option<String>,
+    /// API キーをヘッダで渡す場合のヘッダ名 (例: X-Subscription-Token)
+    api_key_header: Option<String>,
+    /// API キーをクエリで渡す場合のパラメータ名 (例: key)
+    api_key_param: Option<String>,
+    extra_params: HashMap<String, String>,
+    /// 結果配列へのドット区切りパス (例: web.results)
+    results_path: String,
+    title_field: String,
+    url_field: String,
+    snippet_field: String,
+    date_field: String,
+}
+
+#[async_trait::async_trait]
+impl SearchBackend for JsonApiBackend {
+    fn name(&self) -> &'static str {
+        "json_api"
+    }
+
+    async fn search(&self, http: &reqwest::Client, query: &SearchQuery) -> Result<Vec<SearchResult>, String> {
+        let mut params: Vec<(String, String)> = vec![(self.query_param.clone(), query.query.clone())];
+        if let Some(count_param) = &self.count_param {
+            params.push((count_param.clone(), query.max_results.to_string()));
+        }
+        if let (Some(param), Some(key)) = (&self.api_key_param, &self.api_key) {
+            params.push((param.clone(), key.clone()));
+        }
+        params.extend(self.extra_params.iter().map(|(k, v)| (k.clone(), v.clone())));
+
+        let mut req = http.get(&self.endpoint).query(&params);
+        if let (Some(header), Some(key)) = (&self.api_key_header, &self.api_key) {
+            req = req.header(header.as_str(), key.as_str());
+        }
+
+        let body: serde_json::Value = req
+            .send()
+            .await
+            .map_err(|e| format!("search API request failed: {e}"))?
+            .error_for_status()
+            .map_err(|e| format!("search API returned an error: {e}"))?
+            .json()
+            .await
+            .map_err(|e| format!("search API returned invalid JSON: {e}"))?;
+
+        let results = json_path(&body, &self.results_path)
+            .and_then(|v| v.as_array())
+            .ok_or_else(|| format!("search API response has no '{}'", self.results_path))?;
+
+        Ok(results
+            .iter()
+            .filter_map(|r| {
+                Some(SearchResult {
+                    title: str_field(r, &self.title_field).unwrap_or_default(),
+                    url: str_field(r, &self.url_field)?,
+                    snippet: str_field(r, &self.snippet_field).unwrap_or_default(),
+                    date: str_field(r, &self.date_field),
+                })
+            })
+            .take(query.max_results)
+            .collect())
+    }
+}
+
+/// ドット区切りのパスで JSON を辿る
+fn json_path<'a>(value: &'a serde_json::Value, path: &str) -> Option<&'a serde_json::Value> {
+    path.split('.')
+        .filter(|s| !s.is_empty())
+        .try_fold(value, |v, key| v.get(key))
+}
+
+fn str_field(value: &serde_json::Value, path: &str) -> Option<String> {
+    json_path(value, path)
+        .and_then(|v| v.as_str())
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+}
+
+/// Web 検索ツール
+pub struct WebSearch {
+    backend: Box<dyn SearchBackend>,
+    http: reqwest::Client,
+    cache: DashMap<String, (Instant, Vec<SearchResult>)>,
+    cache_ttl: Duration,
+    default_max_results: usize,
+}
+
+impl WebSearch {
+    /// config.json の `tools.web_search` から組み立てる
+    pub fn from_config(cfg: &ToolConfig) -> Result<WebSearch, String> {
+        let endpoint = cfg
+            .str_setting("endpoint")
+            .ok_or("no search endpoint configured")?
+            .trim_end_matches('/')
+            .to_string();
+
+        let backend: Box<dyn SearchBackend> = match cfg.str_setting("backend").unwrap_or("searxng") {
+            "searxng" => Box::new(SearxngBackend { endpoint }),
+            "json_api" => Box::new(JsonApiBackend {
+                endpoint,
+                query_param: cfg.str_setting("query_param").unwrap_or("q").to_string(),
+                count_param: cfg.str_setting("count_param").map(|s| s.to_string()),
+                api_key: cfg
+                    .str_setting("api_key")
+                    .map(|s| s.to_string())
+                    .or_else(|| secret::from_env("WEB_SEARCH_API_KEY")),
+                api_key_header: cfg.str_setting("api_key_header").map(|s| s.to_string()),
+                api_key_param: cfg.str_setting("api_key_param").map(|s| s.to_string()),
+                extra_params: cfg
+                    .settings
+                    .get("extra_params")
+                    .and_then(|v| v.as_object())
+                    .map(|o| {
+                        o.iter()
+                            .filter_map(|(k, v)| v.as_str().map(|v| (k.clone(), v.to_string())))
+                            .collect()
+                    })
+                    .unwrap_or_default(),
+                results_path: cfg.str_setting("results_path").unwrap_or("results").to_string(),
+                title_field: cfg.str_setting("title_field").unwrap_or("title").to_string(),
+                url_field: cfg.str_setting("url_field").unwrap_or("url").to_string(),
+                snippet_field: cfg.str_setting("snippet_field").unwrap_or("snippet").to_string(),
+                date_field: cfg.str_setting("date_field").unwrap_or("date").to_string(),
+            }),
+            other => return Err(format!("unknown search backend '{other}'")),
+        };
+
+        let http = reqwest::Client::builder()
+            .timeout(Duration::from_millis(cfg.u64_setting("timeout_millis").unwrap_or(10_000)))
+            .build()
+            .map_err(|e| format!("failed to build http client: {e}"))?;
+
+        Ok(WebSearch {
+            backend,
+            http,
+            cache: DashMap::new(),
+            cache_ttl: Duration::from_secs(cfg.u64_setting("cache_ttl_secs").unwrap_or(600)),
+            default_max_results: cfg.u64_setting("max_results").unwrap_or(8) as usize,
+        })
+    }
+
+    /// キャッシュ付き検索 (結果, キャッシュヒットか)
+    pub async fn search(&self, query: &SearchQuery) -> Result<(Vec<SearchResult>, bool), String> {
+        let key = query.cache_key();
+        if let Some(entry) = self.cache.get(&key)
+            && entry.0.elapsed() < self.cache_ttl
+        {
+            debug!("web_search cache hit: {}", query.query);
+            return Ok((entry.1.clone(), true));
+        }
+
+        let results = self.backend.search(&self.http, query).await?;
+
+        // 期限切れを掃除してもまだ多ければ一番古いものを捨てる
+        if self.cache.len() >= CACHE_CAPACITY {
+            self.cache.retain(|_, (at, _)| at.elapsed() < self.cache_ttl);
+            let oldest = self
+                .cache
+                .iter()
+                .min_by_key(|e| e.value().0)
+                .map(|e| e.key().clone());
+            if self.cache.len() >= CACHE_CAPACITY
+                && let Some(oldest) = oldest
+            {
+                self.cache.remove(&oldest);
+            }
+        }
+        self.cache.insert(key, (Instant::now(), results.clone()));
+
+        Ok((results, false))
+    }
+}
+
+#[async_trait::async_trait]
+impl LMTool for WebSearch {
+    fn name(&self) -> String {
+        "web_search".to_string()
+    }
+
+    fn description(&self) -> String {
+        "Search the web and get structured results (title, url, snippet, date). Use `browser` to read a result page.".to_string()
+    }
+
+    fn json_schema(&self) -> serde_json::Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "query": {
+                    "type": "string",
+                    "description": "Search query."
+                },
+                "max_results": {
+                    "type": "integer",
+                    "description": "Max number of results (1-20)."
+                },
+                "language": {
+                    "type": "string",
+                    "description": "Optional language code to prefer (e.g. 'ja', 'en')."
+                },
+                "time_range": {
+                    "type": "string",
+                    "description": "Optional recency filter.",
+                    "enum": ["day", "week", "month", "year"]
+                },
+                "$explain": {
+                    "type": "string",
+                    "description": "A brief explanation of what you are doing with this tool."
+                }
+            },
+            "required": ["query"]
+        })
+    }
+
+    async fn execute(&self, args: serde_json::Value, _ob_ctx: ObserverContext) -> Result<String, String> {
+        info!("WebSearch::execute called with args: {:?}", args);
+        let query = args.get("query")
+            .and_then(|v| v.as_str())
+            .map(|s| s.trim())
+            .filter(|s| !s.is_empty())
+            .ok_or("Missing or invalid 'query' parameter".to_string())?;
+        let max_results = args.get("max_results")
+            .and_then(|v| v.as_u64())
+            .map(|n| n as usize)
+            .unwrap_or(self.default_max_results)
+            .clamp(1, 20);
+
+        let search_query = SearchQuery {
+            query: query.to_string(),
+            max_results,
+            language: args.get("language").and_then(|v| v.as_str()).map(|s| s.to_string()),
+            time_range: args.get("time_range").and_then(|v| v.as_str()).map(|s| s.to_string()),
+        };
+
+        let (results, cached) = self.search(&search_query).await?;
+
+        Ok(json!({
+            "status": "ok",
+            "backend": self.backend.name(),
+            "query": query,
+            "cached": cached,
+            "result_count": results.len(),
+            "results": results,
+        })
+        .to_string())
+    }
+}
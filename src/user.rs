@@ -1,7 +1,9 @@
+use std::collections::VecDeque;
+
 use dashmap::DashMap;
 use serenity::all::UserId;
 
-use crate::config::Models;
+use crate::{config::Models, rate_limit::{self, HISTORY_LEN, Usage}};
 
 /// ユーザー情報のプール
 pub struct UserContexts {
@@ -20,18 +22,28 @@ impl Default for UserContexts {
 #[derive(Clone)]
 pub struct UserContext {
     pub user_id: UserId,
-    pub main_model: Models,
+    /// /model で選んだモデル (None ならサーバかグローバルの既定値)
+    pub main_model: Option<Models>,
+    /// レートリミットの消費状況 (計算は rate_limit.rs)
     pub rate_line: u64,
+    /// 最近の使用履歴 (新しいものが後ろ)
+    pub usage: VecDeque<Usage>,
 }
 
 impl UserContext {
     pub fn new(user_id: UserId) -> UserContext {
         UserContext {
             user_id,
-            main_model: Models::O4Mini,
-            rate_line: 1,
+            main_model: None,
+            rate_line: rate_limit::RESET,
+            usage: VecDeque::new(),
         }
     }
+
+    /// 使うモデル (選んでいなければ `default`)
+    pub fn model_or(&self, default: Models) -> Models {
+        self.main_model.clone().unwrap_or(default)
+    }
 }
 
 impl UserContexts {
@@ -46,7 +58,7 @@ impl UserContexts {
             .clone()
     }
 
-    pub fn set_model(&self, user_id: UserId, model: Models) {
+    pub fn set_model(&self, user_id: UserId, model: Option<Models>) {
         self.contexts
             .entry(user_id)
             .or_insert_with(|| UserContext::new(user_id))
@@ -59,4 +71,13 @@ impl UserContexts {
             .or_insert_with(|| UserContext::new(user_id))
             .rate_line = rate_line;
     }
+
+    /// 使用履歴に足す (古いものから捨てる)
+    pub fn record_usage(&self, user_id: UserId, usage: Usage) {
+        let mut entry = self.contexts.entry(user_id).or_insert_with(|| UserContext::new(user_id));
+        entry.usage.push_back(usage);
+        while entry.usage.len() > HISTORY_LEN {
+            entry.usage.pop_front();
+        }
+    }
 }
\ No newline at end of file